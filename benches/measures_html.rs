@@ -0,0 +1,144 @@
+//! Measures the heap allocations and time of parsing and rendering a large score.
+//!
+//! Run with `cargo bench --bench measures_html`. Besides `parse_mscx_score` and `generate_measures_html`, it reports
+//! what one copy of the parsed measures costs: the allocations `generate_measures_html` saved when it stopped taking
+//! the measures by value (and the parser stopped cloning every measure's chords).
+
+// Only the parsing and rendering functions are exercised; the rest of the compiled-in modules is unused here, and so
+// are the imports of their test modules when this target is built as a test (which has no test harness to run them)
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/templates/mod.rs"]
+mod templates;
+#[path = "../src/utils/mod.rs"]
+mod utils;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use templates::parser::{generate_measures_html, parse_mscx_score, LabelStyle, MeasureLayout};
+use utils::scales::{get_handpan_scale, NoteNaming, TranspositionWeights};
+
+/// The number of measures of the generated fixture.
+const FIXTURE_MEASURES: usize = 2_000;
+
+/// How many times each step is run; the reported figures are per run.
+const RUNS: usize = 5;
+
+/// The number of heap allocations (including reallocations) made so far.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of bytes requested from the heap so far.
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting every allocation.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Builds a single-part score of `measure_count` 4/4 measures, each with three quarter notes and a two-note chord on
+/// D Kurd.
+fn large_fixture(measure_count: usize) -> String {
+    let chord = |notes: &[(u8, i8)]| {
+        let notes: String = notes
+            .iter()
+            .map(|(pitch, tpc)| format!("<Note><pitch>{}</pitch><tpc>{}</tpc></Note>", pitch, tpc))
+            .collect();
+        format!(
+            "<Chord><durationType>quarter</durationType>{}</Chord>",
+            notes
+        )
+    };
+    let measure = format!(
+        "<Measure><voice>{}{}{}{}</voice></Measure>",
+        chord(&[(62, 16)]),
+        chord(&[(64, 18)]),
+        chord(&[(65, 13)]),
+        chord(&[(57, 17), (69, 17)])
+    );
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+         <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Piano</trackName></Part>\
+         <Staff id=\"1\"><Measure><voice><TimeSig><sigN>4</sigN><sigD>4</sigD></TimeSig></voice></Measure>{}</Staff>\
+         </Score></museScore>",
+        measure.repeat(measure_count)
+    )
+}
+
+/// Runs `step` `RUNS` times and prints its average allocations, allocated bytes and time.
+fn report<T>(name: &str, mut step: impl FnMut() -> T) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        std::hint::black_box(step());
+    }
+    let elapsed = started.elapsed() / RUNS as u32;
+    println!(
+        "{:<24} {:>10} allocations {:>12} bytes {:>10.2?}",
+        name,
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / RUNS,
+        (ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes) / RUNS,
+        elapsed
+    );
+}
+
+fn main() {
+    let xml = large_fixture(FIXTURE_MEASURES);
+    let scale_notes = get_handpan_scale("d_kurd_9")
+        .expect("D Kurd is a built-in scale")
+        .1;
+    let buffer_svg = utils::svg::load_svg_for_scale(scale_notes.len(), None)
+        .expect("run from the crate root so static/img is found");
+    let parse = || {
+        parse_mscx_score(
+            &xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .expect("the fixture parses")
+        .0
+    };
+    let measures = parse();
+
+    println!("{} measures, {} runs each", measures.len(), RUNS);
+    report("parse_mscx_score", parse);
+    report("generate_measures_html", || {
+        generate_measures_html(
+            &measures,
+            &buffer_svg,
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        )
+    });
+    report("copy of the measures", || measures.clone());
+}
@@ -0,0 +1,57 @@
+#![no_main]
+// Only the parsing functions are exercised; the rest of the compiled-in modules is unused here
+#![allow(dead_code)]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/templates/mod.rs"]
+mod templates;
+#[path = "../../src/utils/mod.rs"]
+mod utils;
+
+use templates::parser::{parse_mscx_metadata, parse_mscx_parts, parse_mscx_score};
+use utils::scales::TranspositionWeights;
+
+// Feeds arbitrary bytes to the MSCX parsers: they may return errors but must never panic or hang
+fuzz_target!(|data: &[u8]| {
+    let xml_content = String::from_utf8_lossy(data);
+    let Some((_, scale_notes, _)) = utils::scales::get_handpan_scale("d_kurd_9") else {
+        return;
+    };
+
+    let _ = parse_mscx_metadata(&xml_content);
+    let mut part_ids = match parse_mscx_parts(&xml_content) {
+        Ok(parts) => parts.into_iter().map(|(id, _, _)| id).collect(),
+        Err(_) => Vec::new(),
+    };
+    part_ids.push(1);
+    part_ids.sort_unstable();
+    part_ids.dedup();
+
+    for part_id in part_ids.into_iter().take(4) {
+        for lenient in [false, true] {
+            let _ = parse_mscx_score(
+                &xml_content,
+                part_id,
+                None,
+                &scale_notes,
+                None,
+                TranspositionWeights::default(),
+                0,
+                lenient,
+                None,
+            );
+            let _ = parse_mscx_score(
+                &xml_content,
+                part_id,
+                Some(2),
+                &scale_notes,
+                Some(12),
+                TranspositionWeights::default(),
+                0,
+                lenient,
+                Some(8),
+            );
+        }
+    }
+});
@@ -0,0 +1,1078 @@
+use crate::handlers::generate::{
+    acquire_generate_slot, build_arrangement, generate_slots, release_generate_slot, GenerateForm,
+};
+use crate::handlers::upload::upload_slots;
+use crate::templates::parser::{
+    metadata_from_tags, parse_key_signature, parse_mscx_meta_tags, parse_mscx_score,
+    parse_part_transposition, transpose_report, unused_fields, ScoreMeasure, ScoreTooLarge,
+};
+use crate::utils::config::upload_dir;
+use crate::utils::file::{read_mscx, resolve_upload_path};
+use crate::utils::scales::{
+    get_handpan_scale, scale_families, scale_key, scales_in_family, scales_list, spell_note,
+    TransposeMode, TranspositionWeights, DEFAULT_TRANSPOSE_RANGE,
+};
+use crate::utils::svg::{color_svg_fields, label_svg_fields, load_svg_for_scale, mirror_svg};
+use actix_web::{
+    web::{Path, Query},
+    HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+
+/// The version of the JSON API, reported in every `ApiEnvelope`.
+///
+/// It is bumped whenever a response changes in a way that breaks existing clients (a field is removed, renamed or
+/// changes type); adding a field does not bump it.
+pub const API_VERSION: u32 = 1;
+
+/// The envelope wrapping every JSON response of the `/api/*` endpoints.
+///
+/// Fields:
+/// - `api_version`: The `API_VERSION` the response follows, so clients can detect incompatibilities.
+/// - `data`: The endpoint's own response body.
+/// - `warnings`: Notices about the request that did not prevent it from succeeding, such as ignored options or the
+///   problems `parse_mscx_score` worked around in the score (empty when there are none).
+#[derive(Serialize)]
+pub struct ApiEnvelope<T: Serialize> {
+    pub api_version: u32,
+    pub data: T,
+    pub warnings: Vec<String>,
+}
+
+impl<T: Serialize> ApiEnvelope<T> {
+    /// Wraps a response body and its warnings with the current `API_VERSION`.
+    pub fn new(data: T, warnings: Vec<String>) -> Self {
+        ApiEnvelope {
+            api_version: API_VERSION,
+            data,
+            warnings,
+        }
+    }
+}
+
+/// Builds a `200 OK` JSON response with `data` wrapped in an `ApiEnvelope`.
+///
+/// # Parameters
+/// - `data`: The endpoint's response body.
+/// - `warnings`: The warnings to report alongside it.
+///
+/// # Returns
+/// An `HttpResponse` with the serialized envelope.
+pub fn api_ok<T: Serialize>(data: T, warnings: Vec<String>) -> HttpResponse {
+    HttpResponse::Ok().json(ApiEnvelope::new(data, warnings))
+}
+
+/// Handles GET requests to `/api/scale/{id}/svg`, returning the handpan layout for a scale.
+///
+/// This function:
+///
+/// 1. **Finds the Scale**: Looks up the scale by its key, returning `404 Not Found` if it doesn't exist.
+/// 2. **Loads the Layout**: Loads the handpan SVG matching the scale's note count.
+/// 3. **Labels the Fields**: Writes each field's note name (from `spell_note`) onto the layout.
+///
+/// # Parameters
+/// - `path`: The scale key (or deprecated numeric ID) from the URL.
+///
+/// # Returns
+/// An `HttpResponse` with the labeled `image/svg+xml` content, or an error response.
+pub async fn handle_scale_svg(path: Path<String>) -> HttpResponse {
+    let (_, scale_notes, scale_tpc) = match get_handpan_scale(&path.into_inner()) {
+        Some(scale_data) => scale_data,
+        None => return HttpResponse::NotFound().body("Unknown scale id"),
+    };
+
+    let svg_content = match load_svg_for_scale(scale_notes.len(), None) {
+        Ok(svg_content) => svg_content,
+        Err(e) => {
+            log::error!("Failed to load SVG: {}", e);
+            return HttpResponse::InternalServerError().body("Server error");
+        }
+    };
+
+    let labels = scale_notes
+        .iter()
+        .zip(scale_tpc.iter())
+        .map(|(&midi_note, &tpc_note)| {
+            let (note, octave) = spell_note(midi_note, tpc_note);
+            format!("{}{}", note, octave)
+        })
+        .collect::<Vec<String>>();
+
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(label_svg_fields(&svg_content, &labels))
+}
+
+/// The query parameters accepted by `/api/scales`.
+///
+/// Fields:
+/// - `family`: An optional scale family (e.g. `Kurd`) to list the scales of, compared case-insensitively.
+#[derive(Deserialize)]
+pub struct ScalesQuery {
+    family: Option<String>,
+}
+
+/// A scale variant listed by `/api/scales`.
+///
+/// Fields:
+/// - `id`: The scale key, as accepted by `/generate` and the other endpoints.
+/// - `name`: The scale name.
+/// - `family`: The family the scale belongs to.
+/// - `note_count`: The number of fields, ding included.
+#[derive(Serialize)]
+pub struct ScaleSummary {
+    id: String,
+    name: String,
+    family: String,
+    note_count: usize,
+}
+
+/// The JSON body returned by `/api/scales`.
+///
+/// Fields:
+/// - `families`: Every scale family of the catalog, so clients can build a family filter.
+/// - `scales`: The listed scale variants, sorted by note count.
+#[derive(Serialize)]
+pub struct ScaleCatalog {
+    families: Vec<String>,
+    scales: Vec<ScaleSummary>,
+}
+
+/// Handles GET requests to `/api/scales`, listing the available scales, optionally within one family.
+///
+/// This function:
+///
+/// 1. **Selects the Scales**: Takes every scale of the catalog, or only those of `family` when it is given.
+/// 2. **Summarizes Them**: Reports each scale's key, name, family and note count, along with the list of families.
+///
+/// An unknown family yields an empty list and a warning rather than an error.
+///
+/// # Parameters
+/// - `query`: The `ScalesQuery` parameters.
+///
+/// # Returns
+/// - `200 OK` with a `ScaleCatalog` JSON body wrapped in an `ApiEnvelope`.
+pub async fn handle_scales(query: Query<ScalesQuery>) -> HttpResponse {
+    let mut warnings = Vec::new();
+    let scales = match query
+        .family
+        .as_deref()
+        .filter(|family| !family.trim().is_empty())
+    {
+        Some(family) => {
+            let scales = scales_in_family(family);
+            if scales.is_empty() {
+                warnings.push(format!("Unknown scale family '{}'", family));
+            }
+            scales
+        }
+        None => scales_list(),
+    };
+
+    let scales = scales
+        .into_iter()
+        .map(|(_, key, name, family, notes, _)| ScaleSummary {
+            id: key,
+            name,
+            family,
+            note_count: notes.len(),
+        })
+        .collect();
+
+    api_ok(
+        ScaleCatalog {
+            families: scale_families(),
+            scales,
+        },
+        warnings,
+    )
+}
+
+/// The query parameters accepted by `/api/compare`.
+///
+/// Fields:
+/// - `mscx_path`: The file path to the uploaded MSCX file.
+/// - `part_id`: The ID of the part to compare.
+/// - `scale_a`: The key of the first scale.
+/// - `scale_b`: The key of the second scale.
+/// - `transpose`: An optional transposition in semitones applied to both arrangements (default `0`).
+/// - `auto_transpose`: When `true`, each scale gets its own best transposition instead.
+#[derive(Deserialize)]
+pub struct CompareQuery {
+    mscx_path: String,
+    part_id: u32,
+    scale_a: String,
+    scale_b: String,
+    #[serde(default)]
+    transpose: i32,
+    #[serde(default)]
+    auto_transpose: bool,
+}
+
+/// How one of the compared scales fits the piece.
+///
+/// Fields:
+/// - `id`: The scale key.
+/// - `name`: The scale name.
+/// - `transposition`: The transposition applied for this scale, in semitones.
+/// - `fit_percent`: The share of notes (rests excluded) that land on a field of the scale.
+/// - `unused_fields`: The fields of the scale that the piece never plays, which can be left aside while learning it.
+#[derive(Serialize)]
+pub struct ScaleFit {
+    id: String,
+    name: String,
+    transposition: i32,
+    fit_percent: f64,
+    unused_fields: Vec<UnusedField>,
+}
+
+/// A field of the handpan that an arrangement never plays.
+///
+/// Fields:
+/// - `index`: The field index in the scale (`0` being the ding).
+/// - `note`: The note name of the field, with its octave (e.g. `"A3"`).
+#[derive(Serialize)]
+pub struct UnusedField {
+    index: usize,
+    note: String,
+}
+
+/// The comparison of a single note between the two arrangements.
+///
+/// Fields:
+/// - `measure`: The 1-based measure number.
+/// - `chord`: The 0-based position of the chord within the measure.
+/// - `note_a` / `note_b`: The note name in each arrangement.
+/// - `in_scale`: One of `both`, `a`, `b` or `neither`.
+#[derive(Serialize)]
+pub struct NoteComparison {
+    measure: u32,
+    chord: usize,
+    note_a: String,
+    note_b: String,
+    in_scale: &'static str,
+}
+
+/// The JSON body returned by `/api/compare`.
+#[derive(Serialize)]
+pub struct ScaleComparison {
+    scale_a: ScaleFit,
+    scale_b: ScaleFit,
+    notes: Vec<NoteComparison>,
+}
+
+/// Aligns the notes of two arrangements of the same part by position and classifies each one.
+///
+/// Both arrangements come from the same staff, so their measures, chords and notes line up one to one;
+/// rests are skipped.
+///
+/// # Parameters
+/// - `measures_a`: The measures parsed against the first scale.
+/// - `measures_b`: The measures parsed against the second scale.
+///
+/// # Returns
+/// A `Vec<NoteComparison>` with one entry per note, in score order.
+pub fn compare_arrangements(
+    measures_a: &[ScoreMeasure],
+    measures_b: &[ScoreMeasure],
+) -> Vec<NoteComparison> {
+    let mut notes = Vec::new();
+    for (measure_a, measure_b) in measures_a.iter().zip(measures_b) {
+        for (chord_index, (chord_a, chord_b)) in
+            measure_a.chords.iter().zip(&measure_b.chords).enumerate()
+        {
+            for (note_a, note_b) in chord_a.iter().zip(chord_b) {
+                if note_a.note == "Rest" {
+                    continue;
+                }
+                let in_scale = match (note_a.note_index.is_some(), note_b.note_index.is_some()) {
+                    (true, true) => "both",
+                    (true, false) => "a",
+                    (false, true) => "b",
+                    (false, false) => "neither",
+                };
+                notes.push(NoteComparison {
+                    measure: measure_a.number,
+                    chord: chord_index,
+                    note_a: note_a.note.clone(),
+                    note_b: note_b.note.clone(),
+                    in_scale,
+                });
+            }
+        }
+    }
+    notes
+}
+
+/// Handles GET requests to `/api/compare`, comparing how two scales fit the same part.
+///
+/// This function:
+///
+/// 1. **Rate Limiting**: Shares the concurrency limit of the generate requests.
+/// 2. **Reads the Score**: Resolves `mscx_path` with `resolve_upload_path`, so only files inside the upload directory
+///    can be read, then opens and reads it.
+/// 3. **Parses Twice**: Runs `parse_mscx_score` once per scale with the same part and transposition options.
+/// 4. **Compares**: Aligns the notes by position and computes each scale's fit percentage and unused fields.
+///
+/// # Parameters
+/// - `query`: The `CompareQuery` parameters.
+///
+/// # Returns
+/// - `200 OK` with a `ScaleComparison` JSON body wrapped in an `ApiEnvelope`.
+/// - `400 Bad Request` for an unknown scale, `404 Not Found` for an unknown file, or another error response if the
+///   score can't be read or parsed.
+pub async fn handle_compare(query: Query<CompareQuery>) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = compare_scales(query.into_inner()).await;
+
+    release_generate_slot();
+    response
+}
+
+/// Lists the warnings of a JSON response about its transposition options, from
+/// `TransposeMode::ignored_transpose_warning`.
+fn transpose_warnings(auto_transpose: bool, transpose: i32) -> Vec<String> {
+    let mode = if auto_transpose {
+        TransposeMode::Auto
+    } else {
+        TransposeMode::Manual
+    };
+    mode.ignored_transpose_warning(transpose)
+        .map(|warning| warning.to_string())
+        .into_iter()
+        .collect()
+}
+
+/// Reads the score and builds the `ScaleComparison` response for `handle_compare`.
+async fn compare_scales(query: CompareQuery) -> HttpResponse {
+    let mscx_path = match resolve_upload_path(&query.mscx_path, upload_dir()) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().body("Unknown MSCX file"),
+    };
+    let file = match File::open(&mscx_path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open MSCX file: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to open MSCX file");
+        }
+    };
+    let mscx_content = match read_mscx(file).await {
+        Ok(content) => content,
+        Err(e) => {
+            log::error!("Failed to read MSCX content: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to read MSCX content");
+        }
+    };
+
+    let mut arrangements = Vec::new();
+    let mut warnings = transpose_warnings(query.auto_transpose, query.transpose);
+    for (index, scale) in [&query.scale_a, &query.scale_b].into_iter().enumerate() {
+        let (name, scale_notes, scale_tpc) = match get_handpan_scale(scale) {
+            Some(scale_data) => scale_data,
+            None => return HttpResponse::BadRequest().body("Unknown scale"),
+        };
+        match parse_mscx_score(
+            &mscx_content,
+            query.part_id,
+            None,
+            &scale_notes,
+            query.auto_transpose.then_some(DEFAULT_TRANSPOSE_RANGE),
+            TranspositionWeights::default(),
+            query.transpose,
+            true,
+            None,
+        ) {
+            Ok((measures, transposition, parse_warnings)) => {
+                // Both passes read the same part, so its parse warnings are only reported once
+                if index == 0 {
+                    warnings.extend(parse_warnings);
+                }
+                let unused = unused_fields(&measures, scale_notes.len())
+                    .into_iter()
+                    .map(|index| {
+                        let (note, octave) = spell_note(scale_notes[index], scale_tpc[index]);
+                        UnusedField {
+                            index,
+                            note: format!("{}{}", note, octave),
+                        }
+                    })
+                    .collect::<Vec<UnusedField>>();
+                arrangements.push((
+                    scale_key(&name, scale_notes.len()),
+                    name,
+                    measures,
+                    transposition,
+                    unused,
+                ))
+            }
+            Err(e) if e.is::<ScoreTooLarge>() => {
+                log::error!("Rejected score: {}", e);
+                return HttpResponse::BadRequest().body(e.to_string());
+            }
+            Err(e) => {
+                log::error!("Failed to parse MSCX: {:?}", e);
+                return HttpResponse::InternalServerError().body("Failed to parse MSCX");
+            }
+        }
+    }
+
+    let (id_b, name_b, measures_b, transposition_b, unused_b) = arrangements.pop().unwrap();
+    let (id_a, name_a, measures_a, transposition_a, unused_a) = arrangements.pop().unwrap();
+    let notes = compare_arrangements(&measures_a, &measures_b);
+
+    let fit_percent = |in_a: bool| {
+        if notes.is_empty() {
+            return 0.0;
+        }
+        let fitting = notes
+            .iter()
+            .filter(|n| n.in_scale == "both" || n.in_scale == if in_a { "a" } else { "b" })
+            .count();
+        (fitting as f64 * 1000.0 / notes.len() as f64).round() / 10.0
+    };
+
+    api_ok(
+        ScaleComparison {
+            scale_a: ScaleFit {
+                id: id_a,
+                name: name_a,
+                transposition: transposition_a,
+                fit_percent: fit_percent(true),
+                unused_fields: unused_a,
+            },
+            scale_b: ScaleFit {
+                id: id_b,
+                name: name_b,
+                transposition: transposition_b,
+                fit_percent: fit_percent(false),
+                unused_fields: unused_b,
+            },
+            notes,
+        },
+        warnings,
+    )
+}
+
+/// The number of measures returned by `/api/preview` when `measures` is not given.
+const DEFAULT_PREVIEW_MEASURES: usize = 4;
+
+/// The largest number of measures `/api/preview` returns, whatever `measures` asks for.
+const MAX_PREVIEW_MEASURES: usize = 32;
+
+/// The query parameters accepted by `/api/preview`.
+///
+/// Fields:
+/// - `mscx_path`: The file path to the uploaded MSCX file.
+/// - `part_id`: The ID of the part to preview.
+/// - `scale`: The key of the scale the notes are matched against.
+/// - `measures`: How many measures to return (default `DEFAULT_PREVIEW_MEASURES`, at most `MAX_PREVIEW_MEASURES`).
+/// - `transpose`: An optional transposition in semitones (default `0`).
+/// - `auto_transpose`: When `true`, the best transposition for the previewed measures is used instead.
+#[derive(Deserialize)]
+pub struct PreviewQuery {
+    mscx_path: String,
+    part_id: u32,
+    scale: String,
+    measures: Option<usize>,
+    #[serde(default)]
+    transpose: i32,
+    #[serde(default)]
+    auto_transpose: bool,
+}
+
+/// The JSON body returned by `/api/preview`.
+///
+/// Fields:
+/// - `transposition`: The transposition applied, in semitones.
+/// - `measures`: The first measures of the part, as parsed by `parse_mscx_score`.
+///
+/// A manual transposition ignored by auto-transpose is reported in the envelope's `warnings`, along with the parse
+/// warnings of the returned measures.
+#[derive(Serialize)]
+pub struct ScorePreview {
+    transposition: i32,
+    measures: Vec<ScoreMeasure>,
+}
+
+/// Handles GET requests to `/api/preview`, returning the first measures of a part for a quick preview.
+///
+/// This function:
+///
+/// 1. **Rate Limiting**: Shares the concurrency limit of the generate requests.
+/// 2. **Reads the Score**: Resolves `mscx_path` with `resolve_upload_path`, so only files inside the upload directory
+///    can be read, then opens and reads it.
+/// 3. **Parses the Beginning**: Runs `parse_mscx_score` with a measure count, so parsing stops once the requested
+///    measures are read instead of going through the whole score.
+///
+/// # Parameters
+/// - `query`: The `PreviewQuery` parameters.
+///
+/// # Returns
+/// - `200 OK` with a `ScorePreview` JSON body wrapped in an `ApiEnvelope`.
+/// - `400 Bad Request` for an unknown scale or a measure count of `0`, `404 Not Found` for an unknown file, or another
+///   error response if the score can't be read or parsed.
+pub async fn handle_preview(query: Query<PreviewQuery>) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = preview_score(query.into_inner()).await;
+
+    release_generate_slot();
+    response
+}
+
+/// Reads the score and builds the `ScorePreview` response for `handle_preview`.
+async fn preview_score(query: PreviewQuery) -> HttpResponse {
+    let measure_count = match query.measures.unwrap_or(DEFAULT_PREVIEW_MEASURES) {
+        0 => return HttpResponse::BadRequest().body("At least one measure must be requested"),
+        count => count.min(MAX_PREVIEW_MEASURES),
+    };
+
+    let (_, scale_notes, _) = match get_handpan_scale(&query.scale) {
+        Some(scale_data) => scale_data,
+        None => return HttpResponse::BadRequest().body("Unknown scale"),
+    };
+
+    let mscx_path = match resolve_upload_path(&query.mscx_path, upload_dir()) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().body("Unknown MSCX file"),
+    };
+    let file = match File::open(&mscx_path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open MSCX file: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to open MSCX file");
+        }
+    };
+    let mscx_content = match read_mscx(file).await {
+        Ok(content) => content,
+        Err(e) => {
+            log::error!("Failed to read MSCX content: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to read MSCX content");
+        }
+    };
+
+    match parse_mscx_score(
+        &mscx_content,
+        query.part_id,
+        None,
+        &scale_notes,
+        query.auto_transpose.then_some(DEFAULT_TRANSPOSE_RANGE),
+        TranspositionWeights::default(),
+        query.transpose,
+        true,
+        Some(measure_count),
+    ) {
+        Ok((measures, transposition, parse_warnings)) => api_ok(
+            ScorePreview {
+                transposition,
+                measures,
+            },
+            transpose_warnings(query.auto_transpose, query.transpose)
+                .into_iter()
+                .chain(parse_warnings)
+                .collect(),
+        ),
+        Err(e) if e.is::<ScoreTooLarge>() => {
+            log::error!("Rejected score: {}", e);
+            HttpResponse::BadRequest().body(e.to_string())
+        }
+        Err(e) => {
+            log::error!("Failed to parse MSCX: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to parse MSCX")
+        }
+    }
+}
+
+/// Handles GET requests to `/api/measure/{n}/svg`, returning the handpan with the fields of one measure colored, so
+/// an interactive lesson can step through a part measure by measure.
+///
+/// This function:
+///
+/// 1. **Rate Limiting**: Shares the concurrency limit of the generate requests.
+/// 2. **Checks the Path**: Resolves `mscx_path` with `resolve_upload_path`, so only files inside the upload
+///    directory can be read.
+/// 3. **Arranges the Part**: Runs `build_arrangement` with the query, so the measure is transposed, rounded and drawn
+///    exactly as on the generate page (transpose mode, ding target, weights, out-of-scale rounding, layout set...).
+/// 4. **Colors the Fields**: Picks measure `n` from the arrangement and colors every field played in it with
+///    `color_svg_fields`, by the duration of its first note. Rests and unplayed notes leave the layout untouched.
+///
+/// # Parameters
+/// - `path`: The 1-based measure number from the URL.
+/// - `query`: The same `GenerateForm` parameters as `/generate`.
+///
+/// # Returns
+/// - `200 OK` with the `image/svg+xml` content.
+/// - `400 Bad Request` for measure `0` or invalid generate parameters, `404 Not Found` for an unknown file or a
+///   measure missing from the arrangement, or another error response if the score can't be read or parsed.
+pub async fn handle_measure_svg(path: Path<usize>, query: Query<GenerateForm>) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = measure_svg(path.into_inner(), query.into_inner()).await;
+
+    release_generate_slot();
+    response
+}
+
+/// Arranges the part and builds the SVG response for `handle_measure_svg`.
+async fn measure_svg(measure_number: usize, form: GenerateForm) -> HttpResponse {
+    if measure_number == 0 {
+        return HttpResponse::BadRequest().body("Measures are numbered from 1");
+    }
+    if resolve_upload_path(form.mscx_path(), upload_dir()).is_none() {
+        return HttpResponse::NotFound().body("Unknown MSCX file");
+    }
+
+    let arrangement = match build_arrangement(form).await {
+        Ok(arrangement) => arrangement,
+        Err(response) => return response,
+    };
+    let Some(measure) = arrangement
+        .measures
+        .iter()
+        .find(|measure| measure.number as usize == measure_number)
+    else {
+        return HttpResponse::NotFound().body(format!(
+            "Measure {} not found: the arrangement has {} measures",
+            measure_number,
+            arrangement.measures.len()
+        ));
+    };
+
+    let svg_content =
+        match load_svg_for_scale(arrangement.scale_len, arrangement.layout_set.as_deref()) {
+            Ok(svg_content) if arrangement.mirror => mirror_svg(&svg_content),
+            Ok(svg_content) => svg_content,
+            Err(e) => {
+                log::error!("Failed to load SVG: {}", e);
+                return HttpResponse::InternalServerError().body("Server error");
+            }
+        };
+
+    // Color the fields of the measure's played notes, in play order
+    let fields: Vec<(usize, String)> = measure
+        .chords
+        .iter()
+        .flatten()
+        .filter(|note| note.is_played(arrangement.play_only_inscale))
+        .filter_map(|note| note.note_index.map(|index| (index, note.duration.clone())))
+        .collect();
+
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(color_svg_fields(&svg_content, &fields))
+}
+
+/// The query parameters accepted by `/api/metadata`.
+///
+/// Fields:
+/// - `mscx_path`: The path of an extracted MSCX file inside the upload directory.
+#[derive(Deserialize)]
+pub struct MetadataQuery {
+    mscx_path: String,
+}
+
+/// The JSON body returned by `/api/metadata`.
+///
+/// Fields:
+/// - `title` / `composer` / `arranger`: The convenience fields of `parse_mscx_metadata` (`"Unknown"` when missing).
+/// - `tags`: Every non-empty metaTag of the score by name, e.g. `lyricist`, `copyright` or `creationDate`.
+#[derive(Serialize)]
+pub struct ScoreMetadata {
+    title: String,
+    composer: String,
+    arranger: String,
+    tags: BTreeMap<String, String>,
+}
+
+impl ScoreMetadata {
+    /// Reads the metadata of a score with `parse_mscx_meta_tags`.
+    pub fn from_mscx(mscx_content: &str) -> Self {
+        let tags = parse_mscx_meta_tags(mscx_content);
+        let (title, composer, arranger) = metadata_from_tags(&tags);
+        ScoreMetadata {
+            title,
+            composer,
+            arranger,
+            tags,
+        }
+    }
+}
+
+/// Handles GET requests to `/api/metadata`, returning the title, composer and arranger of an uploaded score.
+///
+/// This function:
+///
+/// 1. **Checks the Path**: Resolves `mscx_path` with `resolve_upload_path`, so only files inside the upload
+///    directory can be read.
+/// 2. **Reads the Score**: Reads the MSCX content.
+/// 3. **Parses the Metadata**: Reuses `parse_mscx_meta_tags`, the same parser that fills the upload page.
+///
+/// # Parameters
+/// - `query`: The `MetadataQuery` parameters.
+///
+/// # Returns
+/// - `200 OK` with a `ScoreMetadata` JSON body wrapped in an `ApiEnvelope`.
+/// - `404 Not Found` if the path does not name a file in the upload directory.
+/// - `500 Internal Server Error` if the file can't be read.
+pub async fn handle_metadata(query: Query<MetadataQuery>) -> HttpResponse {
+    let mscx_path = match resolve_upload_path(&query.mscx_path, upload_dir()) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().body("Unknown MSCX file"),
+    };
+
+    let file = match File::open(&mscx_path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open MSCX file: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to open MSCX file");
+        }
+    };
+    let mscx_content = match read_mscx(file).await {
+        Ok(content) => content,
+        Err(e) => {
+            log::error!("Failed to read MSCX content: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to read MSCX content");
+        }
+    };
+
+    api_ok(ScoreMetadata::from_mscx(&mscx_content), Vec::new())
+}
+
+/// The query parameters accepted by `/api/transpose-report`.
+///
+/// Fields:
+/// - `mscx_path`: The path of an extracted MSCX file inside the upload directory.
+/// - `part_id`: The ID of the part to report on.
+/// - `scale`: The key of the scale the notes are matched against.
+/// - `transpose`: An optional transposition in semitones (default `0`).
+/// - `auto_transpose`: When `true`, the best transposition is searched for instead.
+#[derive(Deserialize)]
+pub struct TransposeReportQuery {
+    mscx_path: String,
+    part_id: u32,
+    scale: String,
+    #[serde(default)]
+    transpose: i32,
+    #[serde(default)]
+    auto_transpose: bool,
+}
+
+/// Handles GET requests to `/api/transpose-report`, describing what a transposition does to the fit of a part.
+///
+/// This function:
+///
+/// 1. **Rate Limiting**: Shares the concurrency limit of the generate requests.
+/// 2. **Checks the Path**: Resolves `mscx_path` with `resolve_upload_path`, so only files inside the upload
+///    directory can be read.
+/// 3. **Parses Twice**: Parses the part as written and with the requested (or automatic) transposition.
+/// 4. **Compares**: Builds the `TransposeReport` of the two passes with `transpose_report`.
+///
+/// # Parameters
+/// - `query`: The `TransposeReportQuery` parameters.
+///
+/// # Returns
+/// - `200 OK` with a `TransposeReport` JSON body wrapped in an `ApiEnvelope`.
+/// - `400 Bad Request` for an unknown scale, `404 Not Found` for an unknown file, or another error response if the
+///   score can't be read or parsed.
+pub async fn handle_transpose_report(query: Query<TransposeReportQuery>) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = report_transposition(query.into_inner()).await;
+
+    release_generate_slot();
+    response
+}
+
+/// Reads the score and builds the `TransposeReport` response for `handle_transpose_report`.
+async fn report_transposition(query: TransposeReportQuery) -> HttpResponse {
+    let (_, scale_notes, _) = match get_handpan_scale(&query.scale) {
+        Some(scale_data) => scale_data,
+        None => return HttpResponse::BadRequest().body("Unknown scale"),
+    };
+
+    let mscx_path = match resolve_upload_path(&query.mscx_path, upload_dir()) {
+        Some(path) => path,
+        None => return HttpResponse::NotFound().body("Unknown MSCX file"),
+    };
+    let file = match File::open(&mscx_path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open MSCX file: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to open MSCX file");
+        }
+    };
+    let mscx_content = match read_mscx(file).await {
+        Ok(content) => content,
+        Err(e) => {
+            log::error!("Failed to read MSCX content: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to read MSCX content");
+        }
+    };
+
+    let passes = parse_mscx_score(
+        &mscx_content,
+        query.part_id,
+        None,
+        &scale_notes,
+        None,
+        TranspositionWeights::default(),
+        0,
+        true,
+        None,
+    )
+    .and_then(|(written, _, _)| {
+        let transposed = parse_mscx_score(
+            &mscx_content,
+            query.part_id,
+            None,
+            &scale_notes,
+            query.auto_transpose.then_some(DEFAULT_TRANSPOSE_RANGE),
+            TranspositionWeights::default(),
+            query.transpose,
+            true,
+            None,
+        )?;
+        Ok((written, transposed))
+    });
+
+    match passes {
+        Ok((written, (transposed, transposition, parse_warnings))) => api_ok(
+            transpose_report(
+                &written,
+                &transposed,
+                transposition,
+                parse_key_signature(&mscx_content, query.part_id),
+                parse_part_transposition(&mscx_content, query.part_id).0,
+            ),
+            transpose_warnings(query.auto_transpose, query.transpose)
+                .into_iter()
+                .chain(parse_warnings)
+                .collect(),
+        ),
+        Err(e) if e.is::<ScoreTooLarge>() => {
+            log::error!("Rejected score: {}", e);
+            HttpResponse::BadRequest().body(e.to_string())
+        }
+        Err(e) => {
+            log::error!("Failed to parse MSCX: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to parse MSCX")
+        }
+    }
+}
+
+/// The JSON body returned by `/api/capacity`.
+///
+/// Fields:
+/// - `uploads_in_use` / `uploads_max`: The upload slots taken and available in total.
+/// - `generates_in_use` / `generates_max`: The slots shared by `/generate`, the exports and the parsing API endpoints.
+#[derive(Serialize)]
+pub struct Capacity {
+    uploads_in_use: usize,
+    uploads_max: usize,
+    generates_in_use: usize,
+    generates_max: usize,
+}
+
+/// Handles GET requests to `/api/capacity`, reporting how many upload and generate slots are taken.
+///
+/// Clients can poll it and wait for a free slot instead of retrying into `429 Too Many Requests`. The endpoint
+/// takes no slot itself.
+///
+/// # Returns
+/// - `200 OK` with a `Capacity` JSON body wrapped in an `ApiEnvelope`.
+pub async fn handle_capacity() -> HttpResponse {
+    let (uploads_in_use, uploads_max) = upload_slots();
+    let (generates_in_use, generates_max) = generate_slots();
+    api_ok(
+        Capacity {
+            uploads_in_use,
+            uploads_max,
+            generates_in_use,
+            generates_max,
+        },
+        Vec::new(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn measure_svg_follows_the_generate_parameters() {
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = dir.path().join("score.mscx");
+        // A single D4, the fifth field of D Kurd 9, one octave above the ding
+        std::fs::write(
+            &score,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Piano</trackName></Part>\
+             <Staff id=\"1\"><Measure><voice><Chord><durationType>quarter</durationType>\
+             <Note><pitch>62</pitch><tpc>16</tpc></Note></Chord></voice></Measure></Staff></Score></museScore>",
+        )
+        .unwrap();
+        let svg = |fields: &[(&str, &str)]| {
+            let mut form = serde_json::json!({
+                "mscx_path": score.to_str().unwrap(),
+                "part_name": "Piano",
+                "part_id": 1,
+                "scale": "d_kurd_9",
+            });
+            for (name, value) in fields {
+                form[*name] = serde_json::Value::from(*value);
+            }
+            let form: GenerateForm = serde_json::from_value(form).unwrap();
+            async move {
+                let response = measure_svg(1, form).await;
+                assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+                let body = actix_web::body::to_bytes(response.into_body())
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let layout = load_svg_for_scale(9, None).unwrap();
+
+        assert_eq!(
+            svg(&[]).await,
+            color_svg_fields(&layout, &[(4, "quarter".to_string())])
+        );
+        // Putting the D4 on the ding moves it down an octave, onto the centre field
+        assert_eq!(
+            svg(&[("ding_target", "D4")]).await,
+            color_svg_fields(&layout, &[(0, "quarter".to_string())])
+        );
+        assert_eq!(
+            svg(&[("mirror", "1")]).await,
+            color_svg_fields(&mirror_svg(&layout), &[(4, "quarter".to_string())])
+        );
+    }
+
+    #[actix_web::test]
+    async fn scale_svg_is_labeled_with_the_scale_notes() {
+        use actix_web::{test, web, App};
+
+        let app = test::init_service(
+            App::new().route("/api/scale/{id}/svg", web::get().to(handle_scale_svg)),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/api/scale/d_kurd_9/svg")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(actix_web::http::header::CONTENT_TYPE)
+                .unwrap(),
+            "image/svg+xml"
+        );
+        let body = test::read_body(response).await;
+        let labels = ["D3", "A3", "B♭3", "C4", "D4", "E4", "F4", "G4", "A4"].map(String::from);
+        assert_eq!(
+            body,
+            label_svg_fields(&load_svg_for_scale(9, None).unwrap(), &labels)
+        );
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/api/scale/no_such_scale/svg")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn metadata_is_read_from_an_uploaded_score() {
+        use actix_web::{test, web, App};
+
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = dir.path().join("score.mscx");
+        std::fs::write(
+            &score,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <metaTag name=\"workTitle\">Greensleeves</metaTag><metaTag name=\"composer\">Traditional</metaTag>\
+             </Score></museScore>",
+        )
+        .unwrap();
+        let outside_dir = tempfile::tempdir().unwrap();
+        let outside = outside_dir.path().join("score.mscx");
+        std::fs::copy(&score, &outside).unwrap();
+        let app =
+            test::init_service(App::new().route("/api/metadata", web::get().to(handle_metadata)))
+                .await;
+        let uri = |path: &std::path::Path| format!("/api/metadata?mscx_path={}", path.display());
+
+        let request = test::TestRequest::get().uri(&uri(&score)).to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response["data"]["title"], "Greensleeves");
+        assert_eq!(response["data"]["composer"], "Traditional");
+        assert_eq!(response["data"]["arranger"], "Unknown");
+        assert_eq!(response["data"]["tags"]["workTitle"], "Greensleeves");
+
+        let request = test::TestRequest::get().uri(&uri(&outside)).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn responses_are_wrapped_in_a_versioned_envelope() {
+        let response = api_ok(
+            serde_json::json!({ "transposition": 2 }),
+            vec!["auto-transpose was used; manual transpose ignored".to_string()],
+        );
+        assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let envelope: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            envelope,
+            serde_json::json!({
+                "api_version": API_VERSION,
+                "data": { "transposition": 2 },
+                "warnings": ["auto-transpose was used; manual transpose ignored"],
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn capacity_counts_a_generate_request_in_flight() {
+        use crate::handlers::generate::GenerateSlotGuard;
+
+        let capacity = || async {
+            let body = actix_web::body::to_bytes(handle_capacity().await.into_body())
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+        };
+
+        assert!(acquire_generate_slot());
+        let slot = GenerateSlotGuard;
+        let busy = capacity().await;
+        // Other tests may hold slots too, but never more than the maximum
+        assert!(busy["generates_in_use"].as_u64().unwrap() >= 1);
+        assert!(busy["generates_in_use"].as_u64() <= busy["generates_max"].as_u64());
+        assert_eq!(busy["uploads_max"], upload_slots().1);
+        drop(slot);
+    }
+}
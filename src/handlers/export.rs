@@ -0,0 +1,567 @@
+use crate::handlers::generate::{
+    acquire_generate_slot, build_arrangement, release_generate_slot, render_arrangement_html,
+    Arrangement, GenerateForm, GenerateJson,
+};
+use crate::templates::html::sanitize_html;
+use crate::templates::parser::first_phrase;
+use crate::utils::config::upload_dir;
+use crate::utils::file::resolve_upload_path;
+use crate::utils::svg::{load_svg_for_scale, mirror_svg, number_svg_fields};
+use crate::utils::{midi::export_midi, musicxml::export_musicxml, tab::export_text_tab};
+use actix_web::{
+    http::header::{ContentDisposition, CONTENT_LENGTH},
+    web::{Form, Json},
+    Either, HttpResponse,
+};
+use std::io::{Cursor, Write};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+/// The stylesheet inlined into the HTML export, so the page renders without the server.
+const STYLESHEET_PATH: &str = "static/style.css";
+
+/// The follow-along script inlined into the HTML export, moving a play cursor along the notes.
+const FOLLOW_SCRIPT_PATH: &str = "static/scripts/follow.js";
+
+/// The largest number of chords numbered on the sheet of `/export/numbered-sheet`, so the numbers stay readable.
+const NUMBERED_SHEET_MAX_STEPS: usize = 16;
+
+/// Builds a download file name from the work title and part name, keeping only characters that are safe in a header.
+///
+/// # Parameters
+/// - `arrangement`: The exported arrangement. Its title is left out when the score has none.
+/// - `extension`: The file extension, without the dot.
+///
+/// # Returns
+/// A file name such as `Greensleeves_-_Flute_1.mid`, falling back to `handflow` when nothing usable remains.
+pub fn export_file_name(arrangement: &Arrangement, extension: &str) -> String {
+    let base_name = if arrangement.title.is_empty() || arrangement.title == "Unknown" {
+        arrangement.part_name.clone()
+    } else {
+        format!("{} - {}", arrangement.title, arrangement.part_name)
+    };
+    let stem: String = base_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = stem.trim_matches('_');
+    let stem = if stem.is_empty() { "handflow" } else { stem };
+    format!("{}.{}", stem, extension)
+}
+
+/// Wraps an exported file in a `200 OK` download response.
+///
+/// The `Content-Type`, `Content-Length` and `Content-Disposition` headers are all derived from the payload,
+/// so every export route answers the same way.
+///
+/// # Parameters
+/// - `payload`: The bytes of the exported file.
+/// - `content_type`: The MIME type of the file (e.g. `audio/midi`).
+/// - `file_name`: The sanitized download name, as returned by `export_file_name`.
+///
+/// # Returns
+/// The `HttpResponse` sending the file as an attachment.
+pub fn download_response(payload: Vec<u8>, content_type: &str, file_name: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((CONTENT_LENGTH, payload.len()))
+        .insert_header(ContentDisposition::attachment(file_name))
+        .body(payload)
+}
+
+/// Builds the arrangement of an export request with `build_arrangement`, once its score is found in the upload
+/// directory.
+///
+/// # Parameters
+/// - `form`: The generate parameters of the export.
+///
+/// # Returns
+/// - `Ok(Arrangement)` with the arranged part.
+/// - `Err(HttpResponse)` with `404 Not Found` if `mscx_path` does not name a file in the upload directory, or the
+///   error response of the failing step otherwise.
+async fn build_export_arrangement(form: GenerateForm) -> Result<Arrangement, HttpResponse> {
+    if resolve_upload_path(form.mscx_path(), upload_dir()).is_none() {
+        return Err(HttpResponse::NotFound().body("Unknown MSCX file"));
+    }
+    build_arrangement(form).await
+}
+
+/// Handles POST requests to `/export/midi`, returning the selected part as a Standard MIDI File.
+///
+/// The request accepts exactly the same parameters as `/generate`. Each note-on event carries the
+/// velocity parsed from the score's dynamics, accents and per-note velocities, and glissandos are filled in with
+/// chromatic runs when `fill_glissandos` is set. With `play_only_inscale`, notes that are not on the handpan are
+/// left out. The track plays with the handpan sound, or with the part's own instrument program when `part_program`
+/// is set and the score declares one.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with an `audio/midi` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_midi(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => download_response(
+            export_midi(
+                &arrangement.measures,
+                arrangement.play_only_inscale,
+                arrangement.fill_glissandos,
+                arrangement.midi_program,
+            ),
+            "audio/midi",
+            export_file_name(&arrangement, "mid"),
+        ),
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Handles POST requests to `/export/musicxml`, returning the selected part as a MusicXML file.
+///
+/// The request accepts exactly the same parameters as `/generate`.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with an `application/vnd.recordare.musicxml+xml` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_musicxml(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => download_response(
+            export_musicxml(
+                &arrangement.title,
+                &arrangement.part_name,
+                &arrangement.measures,
+                arrangement.play_only_inscale,
+            )
+            .into_bytes(),
+            "application/vnd.recordare.musicxml+xml",
+            export_file_name(&arrangement, "musicxml"),
+        ),
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Builds the plain-text tab of an arrangement with `export_text_tab`.
+fn arrangement_text_tab(arrangement: &Arrangement) -> String {
+    export_text_tab(
+        &arrangement.title,
+        &arrangement.part_name,
+        &arrangement.scale_name,
+        &arrangement.scale_notes,
+        arrangement.transposed_value,
+        &arrangement.measures,
+        arrangement.play_only_inscale,
+    )
+}
+
+/// Handles POST requests to `/export/text`, returning the selected part as a plain-text tab.
+///
+/// The request accepts exactly the same parameters as `/generate`; with `play_only_inscale`, notes that are not on the
+/// handpan are left out.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with a `text/plain` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_text(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => download_response(
+            arrangement_text_tab(&arrangement).into_bytes(),
+            "text/plain; charset=utf-8",
+            export_file_name(&arrangement, "txt"),
+        ),
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Renders an arrangement as a self-contained HTML page.
+///
+/// This function:
+///
+/// 1. **Body**: Renders every measure (without pagination) with `render_arrangement_html`.
+/// 2. **Stylesheet**: Inlines `static/style.css`, so the page looks the same when opened from disk.
+/// 3. **Follow-Along**: Inlines `static/scripts/follow.js` with a play button and tempo field, moving a cursor along
+///    the notes from their `data-start` and `data-duration` beats.
+/// 4. **Document**: Wraps them in a complete HTML document titled after the work and part.
+///
+/// # Parameters
+/// - `arrangement`: The arrangement to export.
+///
+/// # Returns
+/// - `Ok(String)` with the HTML document.
+/// - `Err(HttpResponse)` with the error response to send if the page cannot be rendered.
+pub async fn export_html(arrangement: &Arrangement) -> Result<String, HttpResponse> {
+    let body = render_arrangement_html(arrangement, &arrangement.measures, "")
+        .map_err(|message| HttpResponse::InternalServerError().body(message))?;
+    let stylesheet = match tokio::fs::read_to_string(STYLESHEET_PATH).await {
+        Ok(stylesheet) => stylesheet,
+        Err(e) => {
+            log::warn!("Failed to read stylesheet for the HTML export: {:?}", e);
+            String::new()
+        }
+    };
+    let follow_script = match tokio::fs::read_to_string(FOLLOW_SCRIPT_PATH).await {
+        Ok(script) => script,
+        Err(e) => {
+            log::warn!(
+                "Failed to read follow-along script for the HTML export: {:?}",
+                e
+            );
+            String::new()
+        }
+    };
+    let title = sanitize_html(&format!(
+        "{} - {}",
+        arrangement.title, arrangement.part_name
+    ));
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<div class=\"container\">\n<h1 class=\"title\">{}</h1>\n<div class=\"autoPlay\"><button id=\"followPlay\">▶ Follow</button> <label for=\"followBpm\">BPM:</label> <input type=\"number\" id=\"followBpm\" min=\"20\" max=\"300\" value=\"120\"></div>\n{}\n</div>\n<script>\n{}\n</script>\n</body>\n</html>\n",
+        title, stylesheet, title, body, follow_script
+    ))
+}
+
+/// Handles POST requests to `/export/html`, returning the selected part as a self-contained HTML page.
+///
+/// The request accepts exactly the same parameters as `/generate`.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with a `text/html` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_html(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => match export_html(&arrangement).await {
+            Ok(html) => download_response(
+                html.into_bytes(),
+                "text/html; charset=utf-8",
+                export_file_name(&arrangement, "html"),
+            ),
+            Err(response) => response,
+        },
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Handles POST requests to `/export/numbered-sheet`, returning a "follow the numbers" sheet of the selected part.
+///
+/// This function:
+///
+/// 1. **Builds the Arrangement**: Accepts exactly the same parameters as `/generate`.
+/// 2. **Collects the Phrase**: Takes the chords of the first phrase with `first_phrase`, up to
+///    `NUMBERED_SHEET_MAX_STEPS` of them.
+/// 3. **Numbers the Fields**: Renders the handpan once with every played field colored and numbered in play order by
+///    `number_svg_fields`.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with an `image/svg+xml` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_numbered_sheet(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => {
+            match load_svg_for_scale(arrangement.scale_len, arrangement.layout_set.as_deref()) {
+                Ok(svg_content) => download_response(
+                    number_svg_fields(
+                        &if arrangement.mirror {
+                            mirror_svg(&svg_content)
+                        } else {
+                            svg_content
+                        },
+                        &first_phrase(&arrangement.measures, NUMBERED_SHEET_MAX_STEPS),
+                    )
+                    .into_bytes(),
+                    "image/svg+xml",
+                    export_file_name(&arrangement, "svg"),
+                ),
+                Err(e) => {
+                    log::error!("Failed to load SVG: {}", e);
+                    HttpResponse::InternalServerError().body("Server error")
+                }
+            }
+        }
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Packs named files into an in-memory ZIP archive.
+///
+/// # Parameters
+/// - `files`: The entry names and contents, in the order they are written.
+///
+/// # Returns
+/// The bytes of the archive, or the `zip` error if it cannot be written.
+pub fn build_zip(files: &[(String, Vec<u8>)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Handles POST requests to `/export/bundle`, returning every export format of the selected part in one ZIP.
+///
+/// The arrangement is built once and exported as HTML, MIDI, MusicXML and a text tab, each entry named after the
+/// sanitized title and part like the individual exports. The request accepts exactly the same parameters as
+/// `/generate`.
+///
+/// # Parameters
+/// - `form`: The generate parameters, either as a JSON body or as form-encoded data.
+///
+/// # Returns
+/// - `200 OK` with an `application/zip` attachment.
+/// - `404 Not Found` if the score is not in the upload directory.
+/// - `429 Too Many Requests` or the error response of the failing step otherwise.
+pub async fn handle_export_bundle(
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> HttpResponse {
+    if !acquire_generate_slot() {
+        return HttpResponse::TooManyRequests().body("Too many requests in progress");
+    }
+
+    let response = match build_export_arrangement(form.into()).await {
+        Ok(arrangement) => bundle_response(&arrangement).await,
+        Err(response) => response,
+    };
+
+    release_generate_slot();
+    response
+}
+
+/// Builds the ZIP download of `/export/bundle` for an arrangement.
+async fn bundle_response(arrangement: &Arrangement) -> HttpResponse {
+    let html = match export_html(arrangement).await {
+        Ok(html) => html,
+        Err(response) => return response,
+    };
+    let files = vec![
+        (export_file_name(arrangement, "html"), html.into_bytes()),
+        (
+            export_file_name(arrangement, "mid"),
+            export_midi(
+                &arrangement.measures,
+                arrangement.play_only_inscale,
+                arrangement.fill_glissandos,
+                arrangement.midi_program,
+            ),
+        ),
+        (
+            export_file_name(arrangement, "musicxml"),
+            export_musicxml(
+                &arrangement.title,
+                &arrangement.part_name,
+                &arrangement.measures,
+                arrangement.play_only_inscale,
+            )
+            .into_bytes(),
+        ),
+        (
+            export_file_name(arrangement, "txt"),
+            arrangement_text_tab(arrangement).into_bytes(),
+        ),
+    ];
+
+    match build_zip(&files) {
+        Ok(bundle) => download_response(
+            bundle,
+            "application/zip",
+            export_file_name(arrangement, "zip"),
+        ),
+        Err(e) => {
+            log::error!("Failed to build the export bundle: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to build the export bundle")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Writes a one-measure piano score of D4, F4, A4 and D4 quarter notes into `dir`.
+    fn write_score(dir: &Path) -> std::path::PathBuf {
+        let score = dir.join("score.mscx");
+        let notes: String = [(62, 16), (65, 13), (69, 17), (62, 16)]
+            .iter()
+            .map(|(pitch, tpc)| {
+                format!(
+                    "<Chord><durationType>quarter</durationType><Note><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+                    pitch, tpc
+                )
+            })
+            .collect();
+        std::fs::write(
+            &score,
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+                 <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Piano</trackName></Part>\
+                 <Staff id=\"1\"><Measure><voice>{}</voice></Measure></Staff></Score></museScore>",
+                notes
+            ),
+        )
+        .unwrap();
+        score
+    }
+
+    /// The form-encoded export request for the piano part of `mscx_path` on D Kurd 9.
+    fn export_form(mscx_path: &Path) -> Either<Json<GenerateJson>, Form<GenerateForm>> {
+        Either::Right(Form(
+            serde_json::from_value(serde_json::json!({
+                "mscx_path": mscx_path.to_str().unwrap(),
+                "part_name": "Piano",
+                "part_id": 1,
+                "scale": "d_kurd_9",
+            }))
+            .unwrap(),
+        ))
+    }
+
+    #[actix_web::test]
+    async fn bundle_contains_every_export_format() {
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let response = handle_export_bundle(export_form(&write_score(dir.path()))).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+
+        let archive = zip::ZipArchive::new(Cursor::new(body.to_vec())).unwrap();
+        let mut entries: Vec<&str> = archive.file_names().collect();
+        entries.sort_unstable();
+        assert_eq!(
+            entries,
+            ["Piano.html", "Piano.mid", "Piano.musicxml", "Piano.txt"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn numbered_sheet_numbers_the_fields_in_play_order() {
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let response = handle_export_numbered_sheet(export_form(&write_score(dir.path()))).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let svg = String::from_utf8(body.to_vec()).unwrap();
+
+        let labels: Vec<&str> = svg
+            .split("<text class=\"field-label\"")
+            .skip(1)
+            .map(|label| {
+                let text = &label[label.find('>').unwrap() + 1..];
+                &text[..text.find("</text>").unwrap()]
+            })
+            .collect();
+        // The D4, F4 and A4 fields, the D4 being played again last
+        assert_eq!(labels, ["1, 4", "2", "3"]);
+    }
+
+    #[actix_web::test]
+    async fn midi_download_has_length_type_and_file_name_headers() {
+        use actix_web::{http::header, test, web, App};
+
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = write_score(dir.path());
+        let app = test::init_service(
+            App::new().route("/export/midi", web::post().to(handle_export_midi)),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/export/midi")
+            .set_json(serde_json::json!({
+                "mscx_path": score.to_str().unwrap(),
+                "part_name": "Piano",
+                "part_id": 1,
+                "scale": "d_kurd_9",
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+        let headers = response.headers().clone();
+        assert_eq!(headers.get(header::CONTENT_TYPE).unwrap(), "audio/midi");
+        assert_eq!(
+            headers.get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"Piano.mid\""
+        );
+        let body = test::read_body(response).await;
+        assert!(body.starts_with(b"MThd"));
+        assert_eq!(
+            headers.get(header::CONTENT_LENGTH).unwrap(),
+            body.len().to_string().as_str()
+        );
+    }
+
+    #[actix_web::test]
+    async fn exports_refuse_scores_outside_the_upload_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = handle_export_bundle(export_form(&write_score(dir.path()))).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+}
@@ -1,6 +1,27 @@
-use crate::utils::{file::read_mscx, scales::get_handpan_scale};
-use actix_web::{web::Form, Error, HttpRequest, HttpResponse};
+use crate::templates::parser::{
+    note_sequence, per_measure_transpositions, pitch_histogram, transpose_report, LabelStyle,
+    MeasureLayout, ScoreMeasure, ScoreTooLarge, TransposeReport,
+};
+use crate::utils::config::{template_path, upload_dir};
+use crate::utils::metrics::{self, Endpoint, RejectionReason};
+use crate::utils::midi::{duration_to_beats, HANDPAN_PROGRAM};
+use crate::utils::{
+    file::{read_mscx, resolve_upload_path, touch_upload},
+    scales::{
+        ding_target_transposition, ding_transposition, get_handpan_scale, parse_note_name,
+        tpc_for_pitch_class, AccidentalPreference, NoteNaming, OutOfScaleRounding, TransposeMode,
+        TranspositionWeights, DEFAULT_TRANSPOSE_RANGE, MAX_TRANSPOSE_RANGE,
+        MAX_TRANSPOSITION_WEIGHT,
+    },
+    staff::export_unicode_staff,
+};
+use actix_web::{
+    web::{Bytes, Form, Json, Query},
+    Either, Error, HttpRequest, HttpResponse,
+};
+use futures_util::Stream;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -15,59 +36,687 @@ static GENERATE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 /// "Too Many Requests" response.
 const MAX_GENERATES: usize = 100;
 
+/// How many rendered pieces (the page head, a measure or the tail) may wait for a slow client while the generate page
+/// is streamed.
+const STREAM_BUFFER_CHUNKS: usize = 16;
+
+/// The largest number of times a practice loop may repeat the selected measures.
+const MAX_LOOP_COUNT: usize = 16;
+
+/// The number of voices MuseScore writes per staff, the highest `voice` a request may select.
+const MAX_VOICES: usize = 4;
+
 /// A data structure representing the form data submitted with a generate request.
 ///
 /// Fields:
 /// - `mscx_path`: The file path to the MSCX file to be processed.
 /// - `part_name`: The name of the musical part being processed.
 /// - `part_id`: The ID of the specific part within the MSCX file to be processed.
-/// - `scale`: The index of the scale to be used in the generation process.
+/// - `scale`: The key of the scale to be used in the generation process (e.g. `d_kurd_10`; a numeric ID is still accepted but deprecated).
 /// - `auto_transpose`: An optional flag indicating whether auto-transposition should be applied.
 /// - `play_only_inscale`: An optional flag indicating whether only in-scale notes should be played.
 /// - `transpose`: An optional value specifying the number of semitones by which the notes should be transposed.
+/// - `scale_accidentals`: An optional spelling (`sharps` or `flats`) for the displayed scale notes.
+/// - `lenient`: An optional flag (default on) to skip unparseable measures instead of failing; `"0"` disables it.
+/// - `show_rests`: An optional flag (default on) to render rest cells; `"0"` hides them.
+/// - `layout`: An optional measure layout (`stacked` or `compact`); anything else uses the stacked layout.
+/// - `show_intervals`: An optional flag (`"1"`) to label each melody note with the interval from the previous one.
+/// - `split_hands`: An optional flag (`"1"`) to tag each in-scale note with a suggested hand and color the left and
+///   right hand notes apart.
+/// - `start_measure`: An optional 1-based first measure to keep (default: the first measure).
+/// - `end_measure`: An optional 1-based last measure to keep, inclusive (default: the last measure).
+/// - `loop_count`: An optional number of times (1–16, default 1) the kept measures are repeated for practice.
+/// - `transpose_mode`: An optional mode (`manual`, `auto`, `ding` or `per_measure`) overriding `auto_transpose`. With
+///   `ding`, the part is shifted so its lowest note lands on the ding.
+/// - `transpose_range`: An optional reach (0–24, default 12) of the auto-transpose search, in semitones either way.
+///   A wider search can shift a piece by two octaves but takes proportionally longer.
+/// - `match_weight`: An optional weight (0–100, default 1) of every note the auto-transpose search lands on a field.
+/// - `penalty_weight`: An optional weight (0–100, default 1) of the auto-transpose penalties, mostly the notes pushed
+///   out of the handpan's span. Raising `match_weight` over it favors the shift with the most notes in the scale.
+/// - `measure_order`: An optional order (`forward` or `reverse`) in which the measures are rendered; anything else
+///   keeps the written order.
+/// - `rhythm`: An optional `"off"` to render a pitch map: every note with the same duration and color, without time
+///   signatures or rests.
+/// - `note_naming`: An optional naming (`letters`, `solfege` or `fixed_do`) for the displayed scale notes and note
+///   labels; anything else uses letters.
+/// - `label_style`: An optional `field_number` to label the notes with the number of their handpan field (the ding is
+///   `0`) instead of their name; out-of-scale notes show their nearest field and delta.
+/// - `fill_glissandos`: An optional flag (`"1"`) to play glissandos as chromatic runs in the MIDI export.
+/// - `per_measure_transpose`: An experimental flag (`"1"`) giving every measure its own best transposition, shown in
+///   its header. It overrides `transpose_mode` and `auto_transpose`.
+/// - `out_of_scale_rounding`: An optional choice (`nearest`, `below` or `above`) of the field an out-of-scale note is
+///   mapped to; anything else uses the nearest field.
+/// - `merge_short_rests`: An optional duration (e.g. `eighth`); rests shorter than it are absorbed into the chord
+///   before them for a legato practice view. Anything else keeps every rest.
+/// - `part_program`: An optional flag (`"1"`) to play the MIDI export with the part's own instrument program instead
+///   of the handpan sound.
+/// - `ding_target`: An optional note name (e.g. `C4`) to put on the ding; it selects the `ding` transpose mode
+///   (unless `per_measure_transpose` is set) and shifts the piece's most prominent occurrence of that note onto the
+///   ding instead of its lowest note.
+/// - `mirror`: An optional flag (`"1"`) to mirror the handpan layout horizontally, for left-handed or mirror-tuned
+///   instruments; the suggested hands of `split_hands` are swapped to match.
+/// - `voice`: An optional 1-based voice of the part's staff (e.g. `1`) to arrange on its own, such as the melody of a
+///   two-voice piano staff; every voice is read when it is missing or empty.
+/// - `layout_set`: An optional alternate handpan layout set (e.g. `brandX`) from the `HANDFLOW_LAYOUT_SETS` allowlist,
+///   drawing the arrangement on that maker's field arrangement; the default layout is used when the set has none for
+///   the scale's note count.
+/// - `only_problem_measures`: An optional flag (`"1"`) to render only the measures with at least one out-of-scale
+///   note, keeping their original numbers, to judge quickly whether a scale suits the piece.
 #[derive(Deserialize)]
 pub struct GenerateForm {
     mscx_path: String,
     part_name: String,
     part_id: u32,
-    scale: usize,
+    scale: String,
     auto_transpose: Option<String>,
     play_only_inscale: Option<String>,
     transpose: Option<String>,
+    scale_accidentals: Option<String>,
+    lenient: Option<String>,
+    show_rests: Option<String>,
+    layout: Option<String>,
+    show_intervals: Option<String>,
+    split_hands: Option<String>,
+    start_measure: Option<String>,
+    end_measure: Option<String>,
+    loop_count: Option<String>,
+    transpose_mode: Option<String>,
+    transpose_range: Option<String>,
+    measure_order: Option<String>,
+    rhythm: Option<String>,
+    note_naming: Option<String>,
+    label_style: Option<String>,
+    fill_glissandos: Option<String>,
+    per_measure_transpose: Option<String>,
+    out_of_scale_rounding: Option<String>,
+    merge_short_rests: Option<String>,
+    part_program: Option<String>,
+    ding_target: Option<String>,
+    mirror: Option<String>,
+    layout_set: Option<String>,
+    voice: Option<String>,
+    only_problem_measures: Option<String>,
+    match_weight: Option<String>,
+    penalty_weight: Option<String>,
 }
 
-/// Handles the generation of musical content based on an uploaded MSCX file and user-provided parameters.
+/// The JSON counterpart of `GenerateForm`, accepted when the request is sent as `application/json`.
 ///
-/// This function performs the following tasks:
+/// The flags are plain booleans and the transposition a number, which are mapped back onto the
+/// presence/string semantics of the form fields so both content types produce identical output.
+#[derive(Deserialize)]
+pub struct GenerateJson {
+    mscx_path: String,
+    part_name: String,
+    part_id: u32,
+    scale: JsonScale,
+    #[serde(default)]
+    auto_transpose: bool,
+    #[serde(default)]
+    play_only_inscale: bool,
+    #[serde(default)]
+    transpose: i32,
+    scale_accidentals: Option<String>,
+    #[serde(default = "default_enabled")]
+    lenient: bool,
+    #[serde(default = "default_enabled")]
+    show_rests: bool,
+    layout: Option<String>,
+    #[serde(default)]
+    show_intervals: bool,
+    #[serde(default)]
+    split_hands: bool,
+    start_measure: Option<usize>,
+    end_measure: Option<usize>,
+    loop_count: Option<usize>,
+    transpose_mode: Option<String>,
+    transpose_range: Option<usize>,
+    measure_order: Option<String>,
+    rhythm: Option<String>,
+    note_naming: Option<String>,
+    label_style: Option<String>,
+    #[serde(default)]
+    fill_glissandos: bool,
+    #[serde(default)]
+    per_measure_transpose: bool,
+    out_of_scale_rounding: Option<String>,
+    merge_short_rests: Option<String>,
+    #[serde(default)]
+    part_program: bool,
+    ding_target: Option<String>,
+    #[serde(default)]
+    mirror: bool,
+    layout_set: Option<String>,
+    voice: Option<usize>,
+    #[serde(default)]
+    only_problem_measures: bool,
+    match_weight: Option<f64>,
+    penalty_weight: Option<f64>,
+}
+
+/// The `scale` of a JSON request: a scale key, or the deprecated numeric ID sent as a number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonScale {
+    Key(String),
+    Id(usize),
+}
+
+/// Lenient parsing and rest cells are enabled unless a JSON client explicitly turns them off.
+fn default_enabled() -> bool {
+    true
+}
+
+impl GenerateForm {
+    /// Returns the path of the MSCX file, as sent by the client.
+    pub fn mscx_path(&self) -> &str {
+        &self.mscx_path
+    }
+}
+
+impl From<Either<Json<GenerateJson>, Form<GenerateForm>>> for GenerateForm {
+    fn from(form: Either<Json<GenerateJson>, Form<GenerateForm>>) -> Self {
+        match form {
+            Either::Left(json) => json.into_inner().into(),
+            Either::Right(form) => form.into_inner(),
+        }
+    }
+}
+
+impl From<GenerateJson> for GenerateForm {
+    fn from(json: GenerateJson) -> Self {
+        GenerateForm {
+            mscx_path: json.mscx_path,
+            part_name: json.part_name,
+            part_id: json.part_id,
+            scale: match json.scale {
+                JsonScale::Key(key) => key,
+                JsonScale::Id(id) => id.to_string(),
+            },
+            auto_transpose: json.auto_transpose.then(|| "on".to_string()),
+            play_only_inscale: Some(if json.play_only_inscale { "1" } else { "0" }.to_string()),
+            transpose: Some(json.transpose.to_string()),
+            scale_accidentals: json.scale_accidentals,
+            lenient: Some(if json.lenient { "1" } else { "0" }.to_string()),
+            show_rests: Some(if json.show_rests { "1" } else { "0" }.to_string()),
+            layout: json.layout,
+            show_intervals: Some(if json.show_intervals { "1" } else { "0" }.to_string()),
+            split_hands: Some(if json.split_hands { "1" } else { "0" }.to_string()),
+            start_measure: json.start_measure.map(|v| v.to_string()),
+            end_measure: json.end_measure.map(|v| v.to_string()),
+            loop_count: json.loop_count.map(|v| v.to_string()),
+            transpose_mode: json.transpose_mode,
+            transpose_range: json.transpose_range.map(|v| v.to_string()),
+            measure_order: json.measure_order,
+            rhythm: json.rhythm,
+            note_naming: json.note_naming,
+            label_style: json.label_style,
+            fill_glissandos: Some(if json.fill_glissandos { "1" } else { "0" }.to_string()),
+            per_measure_transpose: Some(
+                if json.per_measure_transpose { "1" } else { "0" }.to_string(),
+            ),
+            out_of_scale_rounding: json.out_of_scale_rounding,
+            merge_short_rests: json.merge_short_rests,
+            part_program: Some(if json.part_program { "1" } else { "0" }.to_string()),
+            ding_target: json.ding_target,
+            mirror: Some(if json.mirror { "1" } else { "0" }.to_string()),
+            layout_set: json.layout_set,
+            voice: json.voice.map(|v| v.to_string()),
+            only_problem_measures: Some(
+                if json.only_problem_measures { "1" } else { "0" }.to_string(),
+            ),
+            match_weight: json.match_weight.map(|v| v.to_string()),
+            penalty_weight: json.penalty_weight.map(|v| v.to_string()),
+        }
+    }
+}
+
+/// The result of parsing the selected part against a handpan scale, shared by the HTML view and the exports.
 ///
-/// 1. **Rate Limiting**: Checks the current number of active generate requests against a maximum limit. If the limit is exceeded, returns a "Too Many Requests" response.
-/// 2. **Form Processing**: Extracts and processes parameters from the form, including the path to the MSCX file, part name, part ID, scale, and various options for transposition and note filtering.
-/// 3. **File Handling**: Attempts to open and read the MSCX file specified in the form. If the file cannot be opened or read, an error response is returned.
-/// 4. **Scale Selection**: Retrieves the handpan scale based on the provided scale index. If the scale is invalid, an error response is returned.
-/// 5. **Template Loading**: Loads the HTML template used for generating the response. If the template cannot be opened or read, an error response is returned.
-/// 6. **MSCX Parsing**: Parses the MSCX content to extract musical measures, applying any necessary transpositions and scale constraints.
-/// 7. **SVG Handling**: Loads an SVG representation of the scale. If the SVG cannot be loaded, an error response is returned.
-/// 8. **HTML Generation**: Generates HTML content representing the musical measures and integrates it with the loaded template.
-/// 9. **Response Construction**: Replaces placeholders in the template with the generated content and returns the final HTML response to the client.
+/// Fields:
+/// - `title`: The work title from the score metadata (`"Unknown"` when missing).
+/// - `part_name`: The name of the musical part being processed.
+/// - `scale_name`: The scale name followed by its note count.
+/// - `scale_notes`: The scale's note names, comma-separated.
+/// - `scale_len`: The number of notes in the scale.
+/// - `measures`: The parsed measures of the selected part.
+/// - `transposed_value`: The transposition that was finally applied, in semitones.
+/// - `instrument_transposition`: A description of the part's written-to-sounding transposition.
+/// - `play_only_inscale`: Whether only in-scale notes should be played.
+/// - `show_rests`: Whether rest cells are rendered in the HTML view.
+/// - `layout`: How measures are laid out in the HTML view.
+/// - `show_rhythm`: Whether durations, time signatures and rests are shown; `false` renders a pitch map.
+/// - `note_naming`: The names the note labels are displayed with (`scale_notes` is already named with it).
+/// - `label_style`: Whether the note labels show the note names or the field numbers.
+/// - `fill_glissandos`: Whether the MIDI export plays glissandos as chromatic runs.
+/// - `midi_program`: The program the MIDI export plays with: the part's own program when requested and declared, the
+///   handpan sound otherwise.
+/// - `mirror`: Whether the handpan layout is drawn mirrored.
+/// - `layout_set`: The allowlisted alternate layout set to draw the handpan with, or `None` for the default layouts.
+/// - `warnings`: Notices about request options that were not applied, shown above the measures.
+/// - `parse_warnings`: The problems `parse_mscx_score` worked around in the score, listed in a collapsible section.
+/// - `transpose_report`: What the applied transposition changed, compared with the part as written.
+pub struct Arrangement {
+    pub title: String,
+    pub part_name: String,
+    pub scale_name: String,
+    pub scale_notes: String,
+    pub scale_len: usize,
+    pub measures: Vec<ScoreMeasure>,
+    pub transposed_value: i32,
+    pub instrument_transposition: String,
+    pub play_only_inscale: bool,
+    pub show_rests: bool,
+    pub layout: MeasureLayout,
+    pub show_rhythm: bool,
+    pub note_naming: NoteNaming,
+    pub label_style: LabelStyle,
+    pub fill_glissandos: bool,
+    pub midi_program: u8,
+    pub mirror: bool,
+    pub layout_set: Option<String>,
+    pub warnings: Vec<String>,
+    pub parse_warnings: Vec<String>,
+    pub transpose_report: TransposeReport,
+}
+
+/// The query parameters accepted by `/generate`.
+///
+/// Fields:
+/// - `page`: The 1-based page of measures to render (default `1`).
+/// - `format`: `sequence` to return only the played note names as plain text instead of the HTML page, or `staff` to
+///   return the part drawn on Unicode staff lines.
+#[derive(Deserialize)]
+pub struct PageQuery {
+    page: Option<usize>,
+    format: Option<String>,
+}
+
+/// Describes which slice of a long score a generated page contains.
+///
+/// Fields:
+/// - `total_measures`: The number of measures in the selected part.
+/// - `page`: The 1-based page number.
+/// - `page_count`: The number of pages the part is split into.
+/// - `has_more`: Whether a page follows this one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pagination {
+    pub total_measures: usize,
+    pub page: usize,
+    pub page_count: usize,
+    pub has_more: bool,
+}
+
+/// Splits the measures into pages and keeps only the requested one.
+///
+/// The first measure of a page that doesn't start with a time signature inherits the one in effect,
+/// so the page can be rendered on its own.
 ///
 /// # Parameters
-/// - `_req`: The incoming `HttpRequest`.
-/// - `form`: The form data submitted by the client, wrapped in `Form<GenerateForm>`.
+/// - `measures`: All the measures of the selected part.
+/// - `page`: The 1-based page to keep.
+/// - `per_page`: The maximum number of measures per page.
 ///
 /// # Returns
-/// - `Result<HttpResponse, Error>`: The final HTML response or an error if any step fails.
-pub async fn handle_generate(
-    _req: HttpRequest,
-    form: Form<GenerateForm>,
-) -> Result<HttpResponse, Error> {
-    // Increment the generate counter and check if the maximum number of concurrent requests is exceeded
+/// `Some((measures, pagination))` for an existing page (page 1 always exists), or `None` if `page` is out of range.
+pub fn paginate_measures(
+    measures: Vec<ScoreMeasure>,
+    page: usize,
+    per_page: usize,
+) -> Option<(Vec<ScoreMeasure>, Pagination)> {
+    let per_page = per_page.max(1);
+    let total_measures = measures.len();
+    let page_count = total_measures.div_ceil(per_page).max(1);
+    if page == 0 || page > page_count {
+        return None;
+    }
+
+    let start = (page - 1) * per_page;
+    let page_measures = slice_measures(measures, start, per_page);
+
+    Some((
+        page_measures,
+        Pagination {
+            total_measures,
+            page,
+            page_count,
+            has_more: page < page_count,
+        },
+    ))
+}
+
+/// Keeps `count` measures from `start` (0-based), carrying the time signature in effect over to the first one.
+///
+/// # Parameters
+/// - `measures`: All the measures of the selected part.
+/// - `start`: The index of the first measure to keep.
+/// - `count`: The maximum number of measures to keep.
+///
+/// # Returns
+/// The kept measures, which can be rendered on their own.
+fn slice_measures(measures: Vec<ScoreMeasure>, start: usize, count: usize) -> Vec<ScoreMeasure> {
+    let active_time_signature = measures[..start.min(measures.len())]
+        .iter()
+        .rev()
+        .find(|m| !m.time_signature.is_empty())
+        .map(|m| m.time_signature.clone());
+
+    let mut kept: Vec<ScoreMeasure> = measures.into_iter().skip(start).take(count).collect();
+    if let (Some(first), Some(time_signature)) = (kept.first_mut(), active_time_signature) {
+        if first.time_signature.is_empty() {
+            first.time_signature = time_signature;
+        }
+    }
+    kept
+}
+
+/// Keeps only a range of measures, e.g. the passage to drill in a practice loop.
+///
+/// # Parameters
+/// - `measures`: All the measures of the selected part.
+/// - `start_measure`: The 1-based first measure to keep, or `None` for the first measure.
+/// - `end_measure`: The 1-based last measure to keep (inclusive), or `None` for the last measure.
+///
+/// # Returns
+/// `Some(measures)` for a valid range (all of them when neither bound is given), or `None` if the range is empty or
+/// goes past the end of the part.
+pub fn select_measure_range(
+    measures: Vec<ScoreMeasure>,
+    start_measure: Option<usize>,
+    end_measure: Option<usize>,
+) -> Option<Vec<ScoreMeasure>> {
+    if start_measure.is_none() && end_measure.is_none() {
+        return Some(measures);
+    }
+    let start = start_measure.unwrap_or(1);
+    let end = end_measure.unwrap_or(measures.len());
+    if start == 0 || start > end || end > measures.len() {
+        return None;
+    }
+    Some(slice_measures(measures, start - 1, end - start + 1))
+}
+
+/// Keeps only the measures with at least one out-of-scale note, the problem spots of a piece on the chosen scale.
+///
+/// Measures keep their original `number`. Time signatures are only stored on the measure where they change, so every
+/// kept measure whose meter differs from the one kept before it gets its time signature written out again.
+///
+/// # Parameters
+/// - `measures`: The measures to filter, in written order.
+///
+/// # Returns
+/// The measures with a note whose `delta` is not `0`, which may be none.
+pub fn keep_problem_measures(measures: Vec<ScoreMeasure>) -> Vec<ScoreMeasure> {
+    let mut active_time_signature = String::new();
+    let mut previous = String::new();
+    let mut kept = Vec::new();
+    for mut measure in measures {
+        if !measure.time_signature.is_empty() {
+            active_time_signature = measure.time_signature.clone();
+        }
+        let has_problem = measure
+            .chords
+            .iter()
+            .flatten()
+            .any(|note| note.note != "Rest" && note.delta != 0);
+        if !has_problem {
+            continue;
+        }
+        measure.time_signature = if active_time_signature != previous {
+            active_time_signature.clone()
+        } else {
+            String::new()
+        };
+        previous = active_time_signature.clone();
+        kept.push(measure);
+    }
+    kept
+}
+
+/// Repeats the measures for a practice loop, marking the first measure of each pass with its `repetition`.
+///
+/// # Parameters
+/// - `measures`: The measures of one pass.
+/// - `loop_count`: How many passes to play. With `1` (or `0`) the measures are returned unchanged.
+///
+/// # Returns
+/// The measures of every pass, one after the other.
+pub fn repeat_measures(measures: Vec<ScoreMeasure>, loop_count: usize) -> Vec<ScoreMeasure> {
+    if loop_count <= 1 {
+        return measures;
+    }
+    let mut repeated = Vec::with_capacity(measures.len() * loop_count);
+    for pass in 1..=loop_count {
+        let start = repeated.len();
+        repeated.extend(measures.iter().cloned());
+        if let Some(first) = repeated.get_mut(start) {
+            first.repetition = Some((pass, loop_count));
+        }
+    }
+    repeated
+}
+
+/// The order in which the measures of an arrangement are rendered.
+///
+/// - **`Forward`**: The default; measures are played as written.
+/// - **`Reverse`**: The last measure comes first, for retrograde practice drills. Measures keep their original
+///   numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MeasureOrder {
+    #[default]
+    Forward,
+    Reverse,
+}
+
+impl MeasureOrder {
+    /// Parses a form value (`"forward"` or `"reverse"`).
+    ///
+    /// # Returns
+    /// The matching order, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "forward" => Some(MeasureOrder::Forward),
+            "reverse" => Some(MeasureOrder::Reverse),
+            _ => None,
+        }
+    }
+}
+
+/// Puts the measures in the requested order.
+///
+/// Time signatures are only stored on the measure where they change, so in reverse every measure whose meter
+/// differs from the one rendered before it gets its time signature written out again.
+///
+/// # Parameters
+/// - `measures`: The measures in written order.
+/// - `order`: The order to render them in.
+///
+/// # Returns
+/// The reordered measures, keeping their original `number`.
+pub fn order_measures(mut measures: Vec<ScoreMeasure>, order: MeasureOrder) -> Vec<ScoreMeasure> {
+    if order == MeasureOrder::Forward {
+        return measures;
+    }
+
+    // Resolve the meter of every measure before the changes lose their meaning
+    let mut active_time_signature = String::new();
+    let time_signatures: Vec<String> = measures
+        .iter()
+        .map(|measure| {
+            if !measure.time_signature.is_empty() {
+                active_time_signature = measure.time_signature.clone();
+            }
+            active_time_signature.clone()
+        })
+        .collect();
+
+    measures.reverse();
+    let mut previous = String::new();
+    for (measure, time_signature) in measures.iter_mut().zip(time_signatures.into_iter().rev()) {
+        measure.time_signature = if time_signature != previous {
+            time_signature.clone()
+        } else {
+            String::new()
+        };
+        previous = time_signature;
+    }
+    measures
+}
+
+/// Parses an optional numeric form field, treating an empty value as absent.
+///
+/// # Returns
+/// `Ok(None)` when the field is missing or empty, `Ok(Some(value))` for a number, or `Err(())` otherwise.
+fn parse_count_field(value: Option<String>) -> Result<Option<usize>, ()> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| ()),
+    }
+}
+
+/// Parses an optional auto-transpose weight, treating an empty value as absent.
+///
+/// # Returns
+/// `Ok(None)` when the field is missing or empty, `Ok(Some(weight))` for a number from `0` to
+/// `MAX_TRANSPOSITION_WEIGHT`, or `Err(())` otherwise.
+fn parse_weight_field(value: Option<&str>) -> Result<Option<f64>, ()> {
+    match value.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => match value.parse::<f64>() {
+            Ok(weight) if (0.0..=MAX_TRANSPOSITION_WEIGHT).contains(&weight) => Ok(Some(weight)),
+            _ => Err(()),
+        },
+    }
+}
+
+/// Builds the `400 Bad Request` response for an auto-transpose weight rejected by `parse_weight_field`.
+///
+/// # Parameters
+/// - `name`: Which weight it is (`match` or `penalty`).
+/// - `value`: The value that was sent.
+fn invalid_weight_response(name: &str, value: Option<String>) -> HttpResponse {
+    HttpResponse::BadRequest().body(format!(
+        "Invalid {} weight '{}': expected a number from 0 to {}",
+        name,
+        value.unwrap_or_default().trim(),
+        MAX_TRANSPOSITION_WEIGHT
+    ))
+}
+
+/// Renders the page navigation shown above and below the measures of a paginated score.
+///
+/// # Parameters
+/// - `pagination`: The pagination of the rendered page.
+/// - `per_page`: The maximum number of measures per page.
+///
+/// # Returns
+/// The navigation HTML, or an empty string when the score fits on a single page.
+fn generate_pagination_html(pagination: &Pagination, per_page: usize) -> String {
+    if pagination.page_count <= 1 {
+        return String::new();
+    }
+
+    let first_measure = (pagination.page - 1) * per_page + 1;
+    let last_measure = (first_measure + per_page - 1).min(pagination.total_measures);
+    let previous = if pagination.page > 1 {
+        format!(
+            "<button type='button' class='page-link' data-page='{}'>‹ Previous</button>",
+            pagination.page - 1
+        )
+    } else {
+        String::new()
+    };
+    let next = if pagination.has_more {
+        format!(
+            "<button type='button' class='page-link' data-page='{}'>Next ›</button>",
+            pagination.page + 1
+        )
+    } else {
+        String::new()
+    };
+
+    format!(
+        "<div class='pagination' data-total-measures='{}' data-page='{}' data-page-count='{}' data-has-more='{}'>{}<span class='page-info'>Measures {}–{} of {} (page {}/{})</span>{}</div>",
+        pagination.total_measures,
+        pagination.page,
+        pagination.page_count,
+        pagination.has_more,
+        previous,
+        first_measure,
+        last_measure,
+        pagination.total_measures,
+        pagination.page,
+        pagination.page_count,
+        next
+    )
+}
+
+/// Reserves a slot among the concurrent generate requests.
+///
+/// # Returns
+/// `true` when a slot was taken (release it with `release_generate_slot`), or `false` if the limit is reached.
+pub fn acquire_generate_slot() -> bool {
     let current_generates = GENERATE_COUNTER.fetch_add(1, Ordering::SeqCst);
 
     if current_generates >= MAX_GENERATES {
         GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-        return Ok(HttpResponse::TooManyRequests().body("Too many requests in progress"));
+        metrics::record_rejection(Endpoint::Generate, RejectionReason::TooManyRequests);
+        return false;
     }
+    metrics::record_request(Endpoint::Generate);
+    true
+}
+
+/// Releases a slot taken with `acquire_generate_slot`.
+pub fn release_generate_slot() {
+    GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
+}
 
+/// A slot taken with `acquire_generate_slot`, released when the guard is dropped.
+///
+/// A streamed page keeps rendering after its handler has returned, so its slot moves into the rendering task with the
+/// guard instead of being released by the handler.
+pub struct GenerateSlotGuard;
+
+impl Drop for GenerateSlotGuard {
+    fn drop(&mut self) {
+        release_generate_slot();
+    }
+}
+
+/// Reports how many generate slots are taken, for `/api/capacity`.
+///
+/// A rejected request bumps the counter for a moment before `acquire_generate_slot` takes it back, so the count is
+/// capped at the limit, like the guard sees it.
+///
+/// # Returns
+/// The number of slots in use and `MAX_GENERATES`.
+pub fn generate_slots() -> (usize, usize) {
+    (
+        GENERATE_COUNTER.load(Ordering::SeqCst).min(MAX_GENERATES),
+        MAX_GENERATES,
+    )
+}
+
+/// Reads the MSCX file and parses the selected part against the chosen scale.
+///
+/// This function performs the following tasks:
+///
+/// 1. **Form Processing**: Converts the optional form fields into concrete transposition, spelling and parsing options.
+/// 2. **File Handling**: Opens and reads the MSCX file specified in the form and, if `resolve_upload_path` finds it in
+///    the upload directory, marks it as in use with `touch_upload` so the upload cleanup keeps it for the grace period.
+/// 3. **Scale Selection**: Retrieves the handpan scale and formats its notes, re-spelling and renaming them if requested.
+/// 4. **MSCX Parsing**: Parses the MSCX content to extract musical measures, applying any necessary transpositions and scale constraints.
+///    In `ding` mode, the part is first parsed untransposed to find the shift putting its lowest note on the ding.
+/// 5. **Practice Loop**: Keeps only the requested measure range and repeats it `loop_count` times.
+///
+/// # Parameters
+/// - `form`: The generate parameters, already normalized to a `GenerateForm`.
+///
+/// # Returns
+/// - `Ok(Arrangement)` on success.
+/// - `Err(HttpResponse)` with the error response to send to the client.
+pub async fn build_arrangement(form: GenerateForm) -> Result<Arrangement, HttpResponse> {
     // Extract form data into individual variables
     let GenerateForm {
         mscx_path,
@@ -77,23 +726,170 @@ pub async fn handle_generate(
         auto_transpose,
         play_only_inscale,
         transpose,
-    } = form.into_inner();
+        scale_accidentals,
+        lenient,
+        show_rests,
+        layout,
+        show_intervals,
+        split_hands,
+        start_measure,
+        end_measure,
+        loop_count,
+        transpose_mode,
+        transpose_range,
+        measure_order,
+        rhythm,
+        note_naming,
+        label_style,
+        fill_glissandos,
+        per_measure_transpose,
+        out_of_scale_rounding,
+        merge_short_rests,
+        part_program,
+        ding_target,
+        mirror,
+        layout_set,
+        voice,
+        only_problem_measures,
+        match_weight,
+        penalty_weight,
+    } = form;
 
-    // Convert optional form fields into concrete values
-    let auto_transpose = auto_transpose.is_some();
+    // Convert optional form fields into concrete values; the experimental per-measure flag wins over a ding target,
+    // which wins over an explicit transpose mode, which wins over the checkbox
+    let ding_target = match ding_target.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(name) => match parse_note_name(name) {
+            Some(target) => Some((name.to_string(), target)),
+            None => {
+                return Err(
+                    HttpResponse::BadRequest().body(format!("Invalid ding target note '{}'", name))
+                )
+            }
+        },
+    };
+    // Weigh matched notes against the penalties of the auto-transpose score, as tuned by advanced users
+    let default_weights = TranspositionWeights::default();
+    let transposition_weights = match (
+        parse_weight_field(match_weight.as_deref()),
+        parse_weight_field(penalty_weight.as_deref()),
+    ) {
+        (Ok(matched), Ok(penalty)) => TranspositionWeights {
+            matched: matched.unwrap_or(default_weights.matched),
+            penalty: penalty.unwrap_or(default_weights.penalty),
+        },
+        (Err(()), _) => return Err(invalid_weight_response("match", match_weight)),
+        (_, Err(())) => return Err(invalid_weight_response("penalty", penalty_weight)),
+    };
+    let voice = match voice.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(value) => match value.parse::<usize>() {
+            Ok(voice) if (1..=MAX_VOICES).contains(&voice) => Some(voice),
+            _ => {
+                return Err(HttpResponse::BadRequest().body(format!(
+                    "Invalid voice '{}': expected a number from 1 to {}",
+                    value, MAX_VOICES
+                )))
+            }
+        },
+    };
+    let layout_set = match layout_set.as_deref().map(str::trim) {
+        None | Some("") | Some("default") => None,
+        Some(name) if crate::utils::svg::is_layout_set(name) => Some(name.to_string()),
+        Some(name) => {
+            return Err(HttpResponse::BadRequest().body(format!("Unknown layout set '{}'", name)))
+        }
+    };
+    let transpose_mode = if per_measure_transpose.is_some_and(|v| v == "1") {
+        TransposeMode::PerMeasure
+    } else if ding_target.is_some() {
+        TransposeMode::Ding
+    } else {
+        transpose_mode
+            .as_deref()
+            .and_then(TransposeMode::from_form_value)
+            .unwrap_or(if auto_transpose.is_some() {
+                TransposeMode::Auto
+            } else {
+                TransposeMode::Manual
+            })
+    };
     let play_only_inscale: bool = play_only_inscale.map(|v| v == "1").unwrap_or(false);
     let transpose_value: i32 = transpose
         .unwrap_or_else(|| "0".to_string())
         .parse()
         .unwrap_or(0);
+    let scale_accidentals = scale_accidentals
+        .as_deref()
+        .and_then(AccidentalPreference::from_form_value);
+    let note_naming = note_naming
+        .as_deref()
+        .and_then(NoteNaming::from_form_value)
+        .unwrap_or_default();
+    let label_style = label_style
+        .as_deref()
+        .and_then(LabelStyle::from_form_value)
+        .unwrap_or_default();
+    let lenient = lenient.map(|v| v != "0").unwrap_or(true);
+    let show_rests = show_rests.map(|v| v != "0").unwrap_or(true);
+    let layout = layout
+        .as_deref()
+        .and_then(MeasureLayout::from_form_value)
+        .unwrap_or_default();
+    let measure_order = measure_order
+        .as_deref()
+        .and_then(MeasureOrder::from_form_value)
+        .unwrap_or_default();
+    let show_rhythm = rhythm.map(|v| v != "off").unwrap_or(true);
+    let show_intervals = show_intervals.map(|v| v == "1").unwrap_or(false);
+    let split_hands = split_hands.map(|v| v == "1").unwrap_or(false);
+    let fill_glissandos = fill_glissandos.map(|v| v == "1").unwrap_or(false);
+    let out_of_scale_rounding = out_of_scale_rounding
+        .as_deref()
+        .and_then(OutOfScaleRounding::from_form_value)
+        .unwrap_or_default();
+    let merge_short_rests = merge_short_rests
+        .as_deref()
+        .and_then(|duration| duration_to_beats(duration, ""));
+    let use_part_program = part_program.map(|v| v == "1").unwrap_or(false);
+    let mirror = mirror.map(|v| v == "1").unwrap_or(false);
+    let only_problem_measures = only_problem_measures.map(|v| v == "1").unwrap_or(false);
+    let (start_measure, end_measure) = match (
+        parse_count_field(start_measure),
+        parse_count_field(end_measure),
+    ) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return Err(HttpResponse::BadRequest().body("Invalid measure range")),
+    };
+    let loop_count = match parse_count_field(loop_count) {
+        Ok(count) => count.unwrap_or(1),
+        Err(()) => return Err(HttpResponse::BadRequest().body("Invalid loop count")),
+    };
+    if loop_count == 0 || loop_count > MAX_LOOP_COUNT {
+        return Err(HttpResponse::BadRequest().body(format!(
+            "The loop count must be between 1 and {}",
+            MAX_LOOP_COUNT
+        )));
+    }
+
+    let transpose_range = match parse_count_field(transpose_range) {
+        Ok(None) => DEFAULT_TRANSPOSE_RANGE,
+        Ok(Some(range)) if range <= MAX_TRANSPOSE_RANGE as usize => range as i32,
+        Ok(Some(_)) => {
+            return Err(HttpResponse::BadRequest().body(format!(
+                "The transpose range must be between 0 and {}",
+                MAX_TRANSPOSE_RANGE
+            )))
+        }
+        Err(()) => return Err(HttpResponse::BadRequest().body("Invalid transpose range")),
+    };
 
     // Attempt to open the MSCX file and handle any errors
     let file = match File::open(&mscx_path) {
         Ok(file) => file,
         Err(e) => {
             log::error!("Failed to open MSCX file: {:?}", e);
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::InternalServerError().body("Failed to open MSCX file"));
+            return Err(HttpResponse::InternalServerError().body("Failed to open MSCX file"));
         }
     };
 
@@ -103,43 +899,529 @@ pub async fn handle_generate(
         Ok(content) => content,
         Err(e) => {
             log::error!("Failed to read MSCX content: {:?}", e);
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::InternalServerError().body("Failed to read MSCX content"));
+            return Err(HttpResponse::InternalServerError().body("Failed to read MSCX content"));
         }
     };
 
-    // Retrieve the handpan scale based on the provided index, or return an error if the scale is invalid
-    let (scale_name, scale_notes, scale_tpc) = match get_handpan_scale(scale) {
-        Some(scale_data) => scale_data,
-        None => {
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::BadRequest().body("Invalid scale index"));
+    // Refresh the file's age so it isn't cleaned up while it is being worked with; only files inside the upload
+    // directory are touched, so a client-supplied path can't be used to modify any other file
+    if let Some(upload_path) = resolve_upload_path(&mscx_path, upload_dir()) {
+        if let Err(e) = touch_upload(&upload_path) {
+            log::warn!("Failed to mark the MSCX file as in use: {:?}", e);
         }
+    }
+
+    // Retrieve the handpan scale based on the provided key, or return an error if the scale is invalid
+    let (scale_name, scale_notes, scale_tpc) = match get_handpan_scale(&scale) {
+        Some(scale_data) => scale_data,
+        None => return Err(HttpResponse::BadRequest().body("Unknown scale")),
     };
 
-    // Prepare the scale name and notes for inclusion in the response
+    // Prepare the scale name and notes for inclusion in the response, re-spelling them if requested
     let scale_name_with_count = format!("{} ({} Notes)", scale_name, scale_notes.len());
     let scale_notes_str = scale_notes
         .iter()
         .zip(scale_tpc.iter())
         .map(|(&midi_note, &tpc_note)| {
-            let (note, octave) =
-                crate::utils::scales::midi_to_note_and_octave_with_tpc(midi_note, tpc_note);
-            format!("{}{}", note, octave)
+            let tpc_note = match scale_accidentals {
+                Some(preference) => tpc_for_pitch_class(midi_note, preference),
+                None => tpc_note,
+            };
+            let (note, octave) = crate::utils::scales::spell_note(midi_note, tpc_note);
+            note_naming.display_name(&format!("{}{}", note, octave))
         })
         .collect::<Vec<String>>()
         .join(", ");
 
-    let scale_notes_slice: &[u8] = &scale_notes;
+    // A manual offset only applies in manual mode, so say so when it was sent with another one
+    let mut warnings = transpose_mode
+        .ignored_transpose_warning(transpose_value)
+        .map(|warning| warning.to_string())
+        .into_iter()
+        .collect::<Vec<String>>();
+    for warning in &warnings {
+        log::warn!("{}", warning);
+    }
+
+    // In ding and per-measure modes, the untransposed pitches decide the shift before the part is matched to the scale
+    let mut written_measures = None;
+    let mut parse_warnings = Vec::new();
+    let transpose_value = if transpose_mode == TransposeMode::PerMeasure {
+        let (written, _, written_warnings) = crate::templates::parser::parse_mscx_score(
+            &mscx_content,
+            part_id,
+            voice,
+            &scale_notes,
+            None,
+            transposition_weights,
+            0,
+            lenient,
+            None,
+        )
+        .map_err(parse_error_response)?;
+        written_measures = Some(written);
+        // Every per-measure pass reads the same part, so the untransposed pass reports the problems
+        parse_warnings = written_warnings;
+        0
+    } else if transpose_mode == TransposeMode::Ding {
+        let (written, _, _) = crate::templates::parser::parse_mscx_score(
+            &mscx_content,
+            part_id,
+            voice,
+            &scale_notes,
+            None,
+            transposition_weights,
+            0,
+            lenient,
+            None,
+        )
+        .map_err(parse_error_response)?;
+        let histogram = pitch_histogram(&written);
+        let shift = match (&ding_target, scale_notes.first()) {
+            (Some((name, target)), Some(&ding)) => {
+                let shift = ding_target_transposition(&histogram, ding, *target);
+                if shift.is_none() {
+                    let warning =
+                        format!("The part never plays {}, so it was not transposed", name);
+                    log::warn!("{}", warning);
+                    warnings.push(warning);
+                }
+                shift
+            }
+            (None, Some(&ding)) => ding_transposition(&histogram, ding),
+            (_, None) => None,
+        }
+        .unwrap_or(0);
+        written_measures = Some(written);
+        shift
+    } else {
+        transpose_value
+    };
+
+    // Parse the MSCX content to extract measures and apply transpositions and scale constraints
+    let (mut measures, final_transposed_value) = match &written_measures {
+        Some(written) if transpose_mode == TransposeMode::PerMeasure => transpose_per_measure(
+            &mscx_content,
+            part_id,
+            voice,
+            &scale_notes,
+            written,
+            transpose_range,
+            transposition_weights,
+            lenient,
+        )
+        .map_err(parse_error_response)?,
+        _ => {
+            let (measures, transposed_value, warnings) =
+                crate::templates::parser::parse_mscx_score(
+                    &mscx_content,
+                    part_id,
+                    voice,
+                    &scale_notes,
+                    (transpose_mode == TransposeMode::Auto).then_some(transpose_range),
+                    transposition_weights,
+                    transpose_value,
+                    lenient,
+                    None,
+                )
+                .map_err(parse_error_response)?;
+            parse_warnings = warnings;
+            (measures, transposed_value)
+        }
+    };
+
+    // Compare with the untransposed part to report what the transposition changed
+    let key_signature = crate::templates::parser::parse_key_signature(&mscx_content, part_id);
+    let (transpose_chromatic, _) =
+        crate::templates::parser::parse_part_transposition(&mscx_content, part_id);
+    let transpose_report =
+        if final_transposed_value == 0 && transpose_mode != TransposeMode::PerMeasure {
+            transpose_report(&measures, &measures, 0, key_signature, transpose_chromatic)
+        } else {
+            let written = match written_measures {
+                Some(written) => written,
+                None => {
+                    crate::templates::parser::parse_mscx_score(
+                        &mscx_content,
+                        part_id,
+                        voice,
+                        &scale_notes,
+                        None,
+                        transposition_weights,
+                        0,
+                        lenient,
+                        None,
+                    )
+                    .map_err(parse_error_response)?
+                    .0
+                }
+            };
+            transpose_report(
+                &written,
+                &measures,
+                final_transposed_value,
+                key_signature,
+                transpose_chromatic,
+            )
+        };
+
+    // Map out-of-scale notes to the field below or above them when asked to
+    crate::templates::parser::round_out_of_scale_notes(
+        &mut measures,
+        &scale_notes,
+        out_of_scale_rounding,
+    );
+
+    // Drop short rests and hold the chord before them instead, for a legato practice view
+    if let Some(threshold) = merge_short_rests {
+        crate::templates::parser::absorb_short_rests(&mut measures, threshold);
+    }
 
+    // Label melody intervals over the whole score, so they carry across page boundaries
+    if show_intervals {
+        crate::templates::parser::annotate_melody_intervals(&mut measures);
+    }
+
+    // Suggest a hand for every in-scale note from its field's side of the ding
+    if split_hands {
+        crate::templates::parser::annotate_hand_hints(&mut measures, scale_notes.len(), mirror);
+    }
+
+    // Number every note after the field it is played on, for field-number labels
+    if label_style == LabelStyle::FieldNumber {
+        crate::templates::parser::annotate_nearest_fields(&mut measures, &scale_notes);
+    }
+
+    // Keep the passage to practice, put it in the requested order and repeat it
+    let mut range = match select_measure_range(measures, start_measure, end_measure) {
+        Some(range) => range,
+        None => return Err(HttpResponse::BadRequest().body("Measure range out of bounds")),
+    };
+
+    // Narrow the passage down to its out-of-scale spots when only those are wanted
+    if only_problem_measures {
+        range = keep_problem_measures(range);
+        if range.is_empty() {
+            warnings.push(
+                "Every note is in the scale, so there are no problem measures to show".to_string(),
+            );
+        }
+    }
+    let measures = repeat_measures(order_measures(range, measure_order), loop_count);
+
+    // Describe the instrument transposition; pitches are already stored at sounding pitch
+    let instrument_transposition = if transpose_chromatic == 0 {
+        "None".to_string()
+    } else {
+        format!("{} (sounding pitch used)", transpose_chromatic)
+    };
+
+    // The part's own sound, used by the MIDI export when requested
+    let midi_program = crate::templates::parser::parse_part_program(&mscx_content, part_id)
+        .filter(|_| use_part_program)
+        .unwrap_or(HANDPAN_PROGRAM);
+
+    let (title, _, _) = crate::templates::parser::parse_mscx_metadata(&mscx_content);
+
+    // Fall back to the default layout when the chosen set doesn't cover this scale's note count
+    if let Some(set) = &layout_set {
+        if !crate::utils::svg::layout_set_has_scale(set, scale_notes.len()) {
+            warnings.push(format!(
+                "The {} layout set has no {}-note layout, so the default layout is shown",
+                set,
+                scale_notes.len()
+            ));
+        }
+    }
+
+    Ok(Arrangement {
+        title,
+        part_name,
+        scale_name: scale_name_with_count,
+        scale_notes: scale_notes_str,
+        scale_len: scale_notes.len(),
+        measures,
+        transposed_value: final_transposed_value,
+        instrument_transposition,
+        play_only_inscale,
+        show_rests,
+        layout,
+        show_rhythm,
+        note_naming,
+        label_style,
+        fill_glissandos,
+        midi_program,
+        mirror,
+        layout_set,
+        warnings,
+        parse_warnings,
+        transpose_report,
+    })
+}
+
+/// Transposes every measure of a part by its own best shift, for the experimental per-measure transpose mode.
+///
+/// This function:
+///
+/// 1. **Picks the Shifts**: Runs `per_measure_transpositions` over the untransposed measures.
+/// 2. **Parses Once**: Parses the part with `parse_mscx_score_per_measure`, which transposes each measure by its
+///    shift, so the notes are spelled exactly like in a whole-part transposition.
+/// 3. **Records the Shifts**: Records its shift on every measure.
+///
+/// # Parameters
+/// - `mscx_content`: The MSCX content of the score.
+/// - `part_id`: The ID of the selected part.
+/// - `voice`: The selected voice of the part, or `None` for all of them.
+/// - `scale_notes`: The MIDI notes of the handpan scale.
+/// - `written`: The measures of the part parsed without transposition.
+/// - `range`: How far each measure's search reaches either way, in semitones.
+/// - `weights`: The weights of each measure's auto-transpose score.
+/// - `lenient`: Whether unparseable measures are skipped, as for the untransposed pass.
+///
+/// # Returns
+/// - `Ok((measures, shift))` with the transposed measures and the most common shift, which stands for the part in the
+///   header and the transpose report.
+/// - The error of the transposed pass, or of a pass that doesn't line up with the untransposed measures.
+#[allow(clippy::too_many_arguments)]
+fn transpose_per_measure(
+    mscx_content: &str,
+    part_id: u32,
+    voice: Option<usize>,
+    scale_notes: &[u8],
+    written: &[ScoreMeasure],
+    range: i32,
+    weights: TranspositionWeights,
+    lenient: bool,
+) -> Result<(Vec<ScoreMeasure>, i32), Box<dyn std::error::Error + Send + Sync>> {
+    let shifts = per_measure_transpositions(written, scale_notes, range, weights);
+
+    let (mut measures, _, _) = crate::templates::parser::parse_mscx_score_per_measure(
+        mscx_content,
+        part_id,
+        voice,
+        scale_notes,
+        &shifts,
+        lenient,
+    )?;
+    if measures.len() != written.len() {
+        return Err(format!(
+            "Transposing per measure gave {} measures instead of {}",
+            measures.len(),
+            written.len()
+        )
+        .into());
+    }
+    for (measure, &shift) in measures.iter_mut().zip(&shifts) {
+        measure.transposition = Some(shift);
+    }
+
+    let mut counts: HashMap<i32, usize> = HashMap::new();
+    for &shift in &shifts {
+        *counts.entry(shift).or_default() += 1;
+    }
+    // On a tie, the shift reached first wins
+    let main_shift = shifts
+        .iter()
+        .rev()
+        .copied()
+        .max_by_key(|shift| counts[shift])
+        .unwrap_or(0);
+
+    Ok((measures, main_shift))
+}
+
+/// Maps an error from `parse_mscx_score` to the response sent to the client.
+///
+/// # Returns
+/// `400 Bad Request` for a score over the configured limits, or `500 Internal Server Error` otherwise.
+fn parse_error_response(e: Box<dyn std::error::Error + Send + Sync>) -> HttpResponse {
+    if e.is::<ScoreTooLarge>() {
+        log::error!("Rejected score: {}", e);
+        HttpResponse::BadRequest().body(e.to_string())
+    } else {
+        log::error!("Failed to parse MSCX: {:?}", e);
+        HttpResponse::InternalServerError().body("Failed to parse MSCX")
+    }
+}
+
+/// Handles the generation of musical content based on an uploaded MSCX file and user-provided parameters.
+///
+/// This function performs the following tasks:
+///
+/// 1. **Rate Limiting**: Checks the current number of active generate requests against a maximum limit. If the limit is exceeded, returns a "Too Many Requests" response.
+/// 2. **Arrangement**: Reads and parses the MSCX file against the selected scale with `build_arrangement`. If any step fails, its error response is returned.
+/// 3. **Template Loading**: Loads the HTML template used for generating the response. If the template cannot be opened or read, an error response is returned.
+/// 4. **SVG Handling**: Loads an SVG representation of the scale. If the SVG cannot be loaded, an error response is returned.
+/// 5. **Pagination**: Keeps only the requested page of measures when the part is longer than the configured cap.
+/// 6. **HTML Generation**: Generates HTML content representing the musical measures and integrates it with the loaded template.
+/// 7. **Response Construction**: Replaces placeholders in the template with the generated content and streams the HTML response to the client
+///    with `stream_arrangement_html`, one measure at a time, with the `X-Total-Measures`, `X-Page` and `X-Has-More` headers describing the page.
+///
+/// With `?format=sequence`, steps 3 to 7 are replaced by a plain-text response listing the played note names of the
+/// whole part, built with `note_sequence`. With `?format=staff`, they are replaced by the whole part drawn on Unicode
+/// staff lines, built with `export_unicode_staff`.
+///
+/// # Parameters
+/// - `_req`: The incoming `HttpRequest`.
+/// - `query`: The `?page=N` query parameter selecting the page of measures, and the optional `?format=sequence` or `?format=staff`.
+/// - `form`: The parameters submitted by the client, either as a JSON body (`Json<GenerateJson>`)
+///   or as form-encoded data (`Form<GenerateForm>`), depending on the request's `Content-Type`.
+///
+/// # Returns
+/// - `Result<HttpResponse, Error>`: The final HTML response or an error if any step fails.
+pub async fn handle_generate(
+    _req: HttpRequest,
+    query: Query<PageQuery>,
+    form: Either<Json<GenerateJson>, Form<GenerateForm>>,
+) -> Result<HttpResponse, Error> {
+    // Increment the generate counter and check if the maximum number of concurrent requests is exceeded
+    if !acquire_generate_slot() {
+        return Ok(HttpResponse::TooManyRequests().body("Too many requests in progress"));
+    }
+    // Released when the response is built, or once a streamed page has been rendered
+    let slot = GenerateSlotGuard;
+
+    let page = query.page.unwrap_or(1);
+    let response = match query.format.as_deref() {
+        Some("sequence") => match build_arrangement(form.into()).await {
+            Ok(arrangement) => HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .body(note_sequence(
+                    &arrangement.measures,
+                    arrangement.play_only_inscale,
+                )),
+            Err(response) => response,
+        },
+        Some("staff") => match build_arrangement(form.into()).await {
+            Ok(arrangement) => HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .body(export_unicode_staff(
+                    &arrangement.measures,
+                    arrangement.play_only_inscale,
+                )),
+            Err(response) => response,
+        },
+        _ => return Ok(render_generate_page(form.into(), page, slot).await),
+    };
+
+    drop(slot);
+    Ok(response)
+}
+
+/// Builds the arrangement and renders it into the generate page template.
+///
+/// # Parameters
+/// - `form`: The generate parameters, already normalized to a `GenerateForm`.
+/// - `page`: The 1-based page of measures to render.
+/// - `slot`: The generate slot of the request, held until the page has been streamed.
+///
+/// # Returns
+/// The HTML response, or the error response of the first step that failed.
+async fn render_generate_page(
+    form: GenerateForm,
+    page: usize,
+    slot: GenerateSlotGuard,
+) -> HttpResponse {
+    let mut arrangement = match build_arrangement(form).await {
+        Ok(arrangement) => arrangement,
+        Err(response) => return response,
+    };
+
+    // Keep only the requested page of measures
+    let per_page = crate::utils::config::config().max_measures;
+    let measures = std::mem::take(&mut arrangement.measures);
+    let (measures, pagination) = match paginate_measures(measures, page, per_page) {
+        Some(paginated) => paginated,
+        None => return HttpResponse::BadRequest().body("Page out of range"),
+    };
+    let pagination_html = generate_pagination_html(&pagination, per_page);
+
+    let body = match stream_arrangement_html(arrangement, measures, &pagination_html, slot) {
+        Ok(body) => body,
+        Err(message) => return HttpResponse::InternalServerError().body(message),
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("X-Total-Measures", pagination.total_measures))
+        .insert_header(("X-Page", pagination.page))
+        .insert_header(("X-Has-More", pagination.has_more.to_string()))
+        .streaming(body)
+}
+
+/// Renders the transpose report as `details-item` rows of the generate page.
+///
+/// # Parameters
+/// - `report`: The `TransposeReport` of the arrangement.
+///
+/// # Returns
+/// The key change (when the score has a key signature), the in-scale share before and after, and the notes the
+/// transposition brought onto or off the scale.
+fn transpose_report_html(report: &TransposeReport) -> String {
+    let item = |title: &str, detail: String| {
+        format!(
+            "<div class=\"details-item transpose-report\"><span class=\"info-title\">{}</span><span class=\"info-detail\">{}</span></div>\n",
+            title, detail
+        )
+    };
+
+    let mut html = String::new();
+    if let (Some(source), Some(target)) = (&report.source_key, &report.target_key) {
+        html.push_str(&item("Key:", format!("{} → {}", source, target)));
+    }
+    html.push_str(&item(
+        "In Scale:",
+        format!(
+            "{}% → {}% of {} notes",
+            report.in_scale_before, report.in_scale_after, report.note_count
+        ),
+    ));
+    html.push_str(&item(
+        "Notes Changed:",
+        format!(
+            "{} moved into the scale, {} moved out",
+            report.notes_fixed, report.notes_lost
+        ),
+    ));
+    html
+}
+
+/// The generate page of an arrangement, split around its measures so they can be rendered (and streamed) separately.
+///
+/// Fields:
+/// - `head`: The filled-in template up to the measures.
+/// - `tail`: The filled-in template after the measures.
+/// - `buffer_svg`: The handpan layout the measures are drawn on, mirrored when asked to.
+struct PageParts {
+    head: String,
+    tail: String,
+    buffer_svg: String,
+}
+
+/// Loads the generate page template and the handpan layout of an arrangement, and fills in everything but the measures.
+///
+/// This function:
+///
+/// 1. **Template Loading**: Loads the `generate_tmpl.html` template and splits it at `{{measures}}`.
+/// 2. **SVG Handling**: Loads the SVG representation of the scale.
+/// 3. **Placeholders**: Fills in the warnings, part, scale, transposition and pagination on both halves.
+///
+/// # Parameters
+/// - `arrangement`: The arrangement providing the part, scale and display options.
+/// - `pagination_html`: The page navigation to show around the measures, or an empty string.
+///
+/// # Returns
+/// - `Ok(PageParts)` with the head, tail and layout of the page.
+/// - `Err(String)` with the message of the `500 Internal Server Error` to send if the template or SVG cannot be
+///   loaded. What went wrong with the handpan layout (e.g. a missing file and the supported note counts) is only
+///   logged, as file paths and I/O errors don't belong in a response.
+fn page_parts(arrangement: &Arrangement, pagination_html: &str) -> Result<PageParts, String> {
     // Load the HTML template for generating the response
-    let template_path = "src/html/generate_tmpl.html";
-    let mut template_file = match File::open(template_path) {
+    let mut template_file = match File::open(template_path("generate_tmpl.html")) {
         Ok(file) => file,
         Err(e) => {
             log::error!("Failed to open template file: {:?}", e);
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::InternalServerError().body("Failed to open template file"));
+            return Err("Failed to open template file".to_string());
         }
     };
 
@@ -147,51 +1429,583 @@ pub async fn handle_generate(
     let mut template_content = String::new();
     if let Err(e) = template_file.read_to_string(&mut template_content) {
         log::error!("Failed to read template file: {:?}", e);
-        GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-        return Ok(HttpResponse::InternalServerError().body("Failed to read template file"));
+        return Err("Failed to read template file".to_string());
     }
 
-    // Parse the MSCX content to extract measures and apply transpositions and scale constraints
-    let (measures, final_transposed_value) = match crate::templates::parser::parse_mscx_score(
-        &mscx_content,
-        part_id,
-        scale_notes_slice,
-        auto_transpose,
-        transpose_value,
+    // Load the SVG representation of the scale
+    let buffer_svg = match crate::utils::svg::load_svg_for_scale(
+        arrangement.scale_len,
+        arrangement.layout_set.as_deref(),
     ) {
-        Ok(result) => result,
+        Ok(svg_content) if arrangement.mirror => crate::utils::svg::mirror_svg(&svg_content),
+        Ok(svg_content) => svg_content,
         Err(e) => {
-            log::error!("Failed to parse MSCX: {:?}", e);
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::InternalServerError().body("Failed to parse MSCX"));
+            log::error!("Failed to load SVG: {}", e);
+            return Err("Server error".to_string());
         }
     };
 
-    // Load the SVG representation of the scale
-    let buffer_svg = match crate::utils::svg::load_svg_for_scale(scale_notes.len()) {
-        Ok(svg_content) => svg_content,
-        Err(e) => {
-            log::error!("Failed to load SVG: {:?}", e);
-            GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return Ok(HttpResponse::InternalServerError().body("Failed to load SVG"));
-        }
+    let mut warning_html = arrangement
+        .warnings
+        .iter()
+        .map(|warning| {
+            format!(
+                "<div class='warning' role='alert'>⚠ {}</div>\n",
+                crate::templates::html::sanitize_html(warning)
+            )
+        })
+        .collect::<String>();
+    // Parse problems are kept out of the way unless the user wants to know why the output looks odd
+    if !arrangement.parse_warnings.is_empty() {
+        warning_html.push_str(&format!(
+            "<details class='warning parse-warnings'><summary>⚠ {} parse warning{}</summary><ul>{}</ul></details>\n",
+            arrangement.parse_warnings.len(),
+            if arrangement.parse_warnings.len() == 1 { "" } else { "s" },
+            arrangement
+                .parse_warnings
+                .iter()
+                .map(|warning| format!(
+                    "<li>{}</li>",
+                    crate::templates::html::sanitize_html(warning)
+                ))
+                .collect::<String>()
+        ));
+    }
+
+    // Replace placeholders in both halves of the template; the measures go in between
+    let transpose_report = transpose_report_html(&arrangement.transpose_report);
+    let transposed_value = arrangement.transposed_value.to_string();
+    let fill = |template: &str| {
+        template
+            .replace("{{warning}}", &warning_html)
+            .replace("{{part_name}}", &arrangement.part_name)
+            .replace("{{scale_name}}", &arrangement.scale_name)
+            .replace("{{scale_notes}}", &arrangement.scale_notes)
+            .replace("{{transpose_report}}", &transpose_report)
+            .replace("{{pagination}}", pagination_html)
+            .replace("{{transposed_value}}", &transposed_value)
+            .replace(
+                "{{instrument_transposition}}",
+                &arrangement.instrument_transposition,
+            )
     };
+    let (head, tail) = template_content
+        .split_once("{{measures}}")
+        .unwrap_or((&template_content, ""));
+
+    Ok(PageParts {
+        head: fill(head),
+        tail: fill(tail),
+        buffer_svg,
+    })
+}
+
+/// Renders measures of an arrangement into the generate page template.
+///
+/// The page is built in memory from `page_parts` and `generate_measures_html`; `stream_arrangement_html` sends the
+/// same bytes piece by piece instead.
+///
+/// # Parameters
+/// - `arrangement`: The arrangement providing the part, scale and display options.
+/// - `measures`: The measures to render (e.g. one page of the arrangement).
+/// - `pagination_html`: The page navigation to show around the measures, or an empty string.
+///
+/// # Returns
+/// - `Ok(String)` with the rendered HTML fragment.
+/// - `Err(String)` with the message of the `500 Internal Server Error` to send if the template or SVG cannot be
+///   loaded, as for `page_parts`.
+pub fn render_arrangement_html(
+    arrangement: &Arrangement,
+    measures: &[ScoreMeasure],
+    pagination_html: &str,
+) -> Result<String, String> {
+    let PageParts {
+        head,
+        tail,
+        buffer_svg,
+    } = page_parts(arrangement, pagination_html)?;
 
     // Generate HTML content for the measures
-    let measures_html =
-        crate::templates::parser::generate_measures_html(measures, &buffer_svg, play_only_inscale);
-
-    // Replace placeholders in the template with generated content and prepare the final response
-    let response = template_content
-        .replace("{{part_name}}", &part_name)
-        .replace("{{scale_name}}", &scale_name_with_count)
-        .replace("{{scale_notes}}", &scale_notes_str)
-        .replace("{{measures}}", &measures_html)
-        .replace("{{transposed_value}}", &final_transposed_value.to_string());
-
-    // Decrement the generate counter and return the final HTML response
-    GENERATE_COUNTER.fetch_sub(1, Ordering::SeqCst);
-    Ok(HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(response))
+    let measures_html = crate::templates::parser::generate_measures_html(
+        measures,
+        &buffer_svg,
+        arrangement.play_only_inscale,
+        arrangement.show_rests,
+        arrangement.layout,
+        arrangement.show_rhythm,
+        arrangement.note_naming,
+        arrangement.label_style,
+    );
+
+    Ok(head + &measures_html + &tail)
+}
+
+/// Streams measures of an arrangement in the generate page template, so a long page never sits whole in memory.
+///
+/// This function:
+///
+/// 1. **Page Parts**: Fills in the template around the measures with `page_parts`.
+/// 2. **Rendering**: Renders the measures with `write_measures_html` on a blocking thread, sending the head, each
+///    measure and the tail through a channel holding at most `STREAM_BUFFER_CHUNKS` pieces, so rendering waits for a
+///    slow client and stops once the client is gone. The thread holds the request's generate slot until it is done.
+/// 3. **Body**: Turns the channel into the stream of the response body.
+///
+/// The streamed bytes are exactly those of `render_arrangement_html`.
+///
+/// # Parameters
+/// - `arrangement`: The arrangement providing the part, scale and display options.
+/// - `measures`: The measures to render (e.g. one page of the arrangement).
+/// - `pagination_html`: The page navigation to show around the measures, or an empty string.
+/// - `slot`: The generate slot of the request, released when rendering ends.
+///
+/// # Returns
+/// - `Ok(stream)` with the body, to pass to `HttpResponseBuilder::streaming`.
+/// - `Err(String)` with the message of the `500 Internal Server Error` to send if the template or SVG cannot be
+///   loaded, as for `page_parts`.
+pub fn stream_arrangement_html(
+    arrangement: Arrangement,
+    measures: Vec<ScoreMeasure>,
+    pagination_html: &str,
+    slot: GenerateSlotGuard,
+) -> Result<impl Stream<Item = Result<Bytes, Error>>, String> {
+    let PageParts {
+        head,
+        tail,
+        buffer_svg,
+    } = page_parts(&arrangement, pagination_html)?;
+
+    let (sender, receiver) = tokio::sync::mpsc::channel::<String>(STREAM_BUFFER_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        if sender.blocking_send(head).is_err() {
+            return;
+        }
+        let mut connected = true;
+        crate::templates::parser::write_measures_html(
+            &measures,
+            &buffer_svg,
+            arrangement.play_only_inscale,
+            arrangement.show_rests,
+            arrangement.layout,
+            arrangement.show_rhythm,
+            arrangement.note_naming,
+            arrangement.label_style,
+            |measure_html| {
+                connected = sender.blocking_send(measure_html).is_ok();
+                connected
+            },
+        );
+        if connected {
+            let _ = sender.blocking_send(tail);
+        }
+    });
+
+    Ok(futures_util::stream::unfold(
+        receiver,
+        |mut receiver| async move {
+            let chunk = receiver.recv().await?;
+            Some((Ok(Bytes::from(chunk)), receiver))
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::parser::{generate_measures_html, ScoreNote};
+
+    /// A 4/4 measure holding a single whole note.
+    fn whole_note_measure(number: u32, pitch: u32, delta: i32) -> ScoreMeasure {
+        ScoreMeasure {
+            number,
+            time_signature: if number == 1 {
+                "4|4".to_string()
+            } else {
+                String::new()
+            },
+            chords: vec![vec![ScoreNote {
+                pitch,
+                note: "D4".to_string(),
+                duration: "whole".to_string(),
+                delta,
+                note_index: (delta == 0).then_some(4),
+                velocity: 80,
+                ..Default::default()
+            }]],
+            ..Default::default()
+        }
+    }
+
+    /// A single-part score of 4/4 measures of quarter notes, given as `(pitch, tpc)` pairs per measure.
+    fn score_xml(measures: &[&[(u8, i8)]]) -> String {
+        let measures: String = measures
+            .iter()
+            .map(|notes| {
+                let chords: String = notes
+                    .iter()
+                    .map(|(pitch, tpc)| {
+                        format!(
+                            "<Chord><durationType>quarter</durationType><Note><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+                            pitch, tpc
+                        )
+                    })
+                    .collect();
+                format!("<Measure><voice>{}</voice></Measure>", chords)
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Piano</trackName></Part>\
+             <Staff id=\"1\">{}</Staff></Score></museScore>",
+            measures
+        )
+    }
+
+    /// The generate parameters for the piano part of `mscx_path` on D Kurd 9, plus the given form fields.
+    fn generate_form(mscx_path: &std::path::Path, fields: &[(&str, &str)]) -> GenerateForm {
+        let mut form = serde_json::json!({
+            "mscx_path": mscx_path.to_str().unwrap(),
+            "part_name": "Piano",
+            "part_id": 1,
+            "scale": "d_kurd_9",
+        });
+        for (name, value) in fields {
+            form[*name] = serde_json::Value::from(*value);
+        }
+        serde_json::from_value(form).unwrap()
+    }
+
+    #[test]
+    fn per_measure_transpose_records_each_measure_shift() {
+        // The first measure fits D Kurd as written, the second a semitone higher
+        let xml = score_xml(&[
+            &[(62, 16), (64, 18), (65, 13), (67, 15)],
+            &[(63, 11), (65, 13), (66, 20), (68, 10)],
+        ]);
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let (written, _, _) = crate::templates::parser::parse_mscx_score(
+            &xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+
+        let (measures, main_shift) = transpose_per_measure(
+            &xml,
+            1,
+            None,
+            &scale_notes,
+            &written,
+            DEFAULT_TRANSPOSE_RANGE,
+            TranspositionWeights::default(),
+            true,
+        )
+        .unwrap();
+        let shifts: Vec<Option<i32>> = measures.iter().map(|m| m.transposition).collect();
+        assert_eq!(shifts, vec![Some(0), Some(-1)]);
+        assert_eq!(main_shift, 0);
+        let pitches: Vec<u32> = measures[1]
+            .chords
+            .iter()
+            .map(|chord| chord[0].pitch)
+            .collect();
+        assert_eq!(pitches, vec![62, 64, 65, 67]);
+    }
+
+    #[actix_web::test]
+    async fn streamed_page_matches_the_rendered_one() {
+        use futures_util::StreamExt;
+
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let arrangement = || Arrangement {
+            title: "Song".to_string(),
+            part_name: "Piano".to_string(),
+            scale_name: "D Kurd".to_string(),
+            scale_notes: "D3 A3 Bb3 C4 D4 E4 F4 G4 A4".to_string(),
+            scale_len: scale_notes.len(),
+            measures: (1..=3).map(|n| whole_note_measure(n, 62, 0)).collect(),
+            transposed_value: 0,
+            instrument_transposition: String::new(),
+            play_only_inscale: false,
+            show_rests: true,
+            layout: MeasureLayout::Stacked,
+            show_rhythm: true,
+            note_naming: NoteNaming::Letters,
+            label_style: LabelStyle::Name,
+            fill_glissandos: false,
+            midi_program: HANDPAN_PROGRAM,
+            mirror: false,
+            layout_set: None,
+            warnings: Vec::new(),
+            parse_warnings: Vec::new(),
+            transpose_report: TransposeReport::default(),
+        };
+        let rendered = {
+            let arrangement = arrangement();
+            render_arrangement_html(&arrangement, &arrangement.measures, "").unwrap()
+        };
+
+        assert!(acquire_generate_slot());
+        let mut arrangement = arrangement();
+        let measures = std::mem::take(&mut arrangement.measures);
+        let chunks: Vec<Result<Bytes, Error>> =
+            stream_arrangement_html(arrangement, measures, "", GenerateSlotGuard)
+                .unwrap()
+                .collect()
+                .await;
+        let streamed: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(String::from_utf8(streamed).unwrap(), rendered);
+    }
+
+    #[actix_web::test]
+    async fn generating_keeps_the_score_for_the_grace_period() {
+        use crate::utils::file::{clean_old_uploads, is_pinned_upload, is_used_upload};
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = dir.path().join("score.mscx");
+        let other = dir.path().join("other.mscx");
+        let outside_dir = tempfile::tempdir().unwrap();
+        let outside = outside_dir.path().join("score.mscx");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for path in [&score, &other, &outside] {
+            std::fs::write(path, score_xml(&[&[(62, 16)]])).unwrap();
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(an_hour_ago)
+                .unwrap();
+        }
+        let modified =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().modified().unwrap();
+
+        assert!(build_arrangement(generate_form(&score, &[])).await.is_ok());
+        assert!(modified(&score) > an_hour_ago + Duration::from_secs(1800));
+        // A score outside the upload directory is read but never modified
+        assert!(build_arrangement(generate_form(&outside, &[]))
+            .await
+            .is_ok());
+        assert_eq!(modified(&outside), an_hour_ago);
+
+        clean_old_uploads(
+            dir.path(),
+            Duration::from_secs(600),
+            Duration::from_secs(3600),
+            is_pinned_upload,
+            is_used_upload,
+        )
+        .await
+        .unwrap();
+        assert!(score.exists());
+        assert!(!other.exists());
+    }
+
+    #[actix_web::test]
+    async fn only_problem_measures_are_rendered() {
+        let dir = tempfile::tempdir().unwrap();
+        let score = dir.path().join("score.mscx");
+        // Measures 2 and 4 hold an E♭ and a B, neither of which is on D Kurd
+        std::fs::write(
+            &score,
+            score_xml(&[&[(62, 16)], &[(63, 11)], &[(65, 13)], &[(71, 19)]]),
+        )
+        .unwrap();
+
+        let arrangement =
+            build_arrangement(generate_form(&score, &[("only_problem_measures", "1")]))
+                .await
+                .ok()
+                .unwrap();
+        let numbers: Vec<u32> = arrangement.measures.iter().map(|m| m.number).collect();
+        assert_eq!(numbers, vec![2, 4]);
+        let html = render_arrangement_html(&arrangement, &arrangement.measures, "").unwrap();
+        assert!(html.contains("Measure: 2"));
+        assert!(html.contains("Measure: 4"));
+        assert!(!html.contains("Measure: 1"));
+        assert!(!html.contains("Measure: 3"));
+    }
+
+    #[actix_web::test]
+    async fn transposition_weights_change_the_chosen_shift() {
+        let dir = tempfile::tempdir().unwrap();
+        let score = dir.path().join("score.mscx");
+        std::fs::write(
+            &score,
+            score_xml(&[&[(54, 20), (55, 15), (59, 19), (66, 20)], &[(69, 17)]]),
+        )
+        .unwrap();
+        let auto = [("auto_transpose", "1"), ("transpose_range", "12")];
+        let unpenalized = [
+            ("auto_transpose", "1"),
+            ("transpose_range", "12"),
+            ("penalty_weight", "0"),
+        ];
+
+        // +3 lands one more note on a field but pushes the top one off the handpan, which only the penalty weighs
+        let weighted = build_arrangement(generate_form(&score, &auto))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(weighted.transposed_value, -4);
+        let matched_only = build_arrangement(generate_form(&score, &unpenalized))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(matched_only.transposed_value, 3);
+    }
+
+    #[actix_web::test]
+    async fn manual_transpose_sent_with_auto_transpose_is_warned_about() {
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = dir.path().join("score.mscx");
+        std::fs::write(&score, score_xml(&[&[(62, 16), (65, 13)]])).unwrap();
+
+        let ignored = build_arrangement(generate_form(
+            &score,
+            &[("auto_transpose", "on"), ("transpose", "3")],
+        ))
+        .await
+        .ok()
+        .unwrap();
+        assert_eq!(
+            ignored.warnings,
+            ["auto-transpose was used; manual transpose ignored"]
+        );
+        let html = render_arrangement_html(&ignored, &ignored.measures, "").unwrap();
+        assert!(html.contains(
+            "<div class='warning' role='alert'>⚠ auto-transpose was used; manual transpose ignored</div>"
+        ));
+
+        let applied = build_arrangement(generate_form(&score, &[("transpose", "3")]))
+            .await
+            .ok()
+            .unwrap();
+        assert!(applied.warnings.is_empty());
+        assert_eq!(applied.transposed_value, 3);
+    }
+
+    #[test]
+    fn reverse_order_renders_the_last_measure_first() {
+        let mut measures: Vec<ScoreMeasure> =
+            (1..=3).map(|n| whole_note_measure(n, 62, 0)).collect();
+        measures[2].time_signature = "3|4".to_string();
+
+        let reversed = order_measures(measures, MeasureOrder::Reverse);
+        let numbers: Vec<u32> = reversed.iter().map(|m| m.number).collect();
+        assert_eq!(numbers, [3, 2, 1]);
+        // Measure 2 follows a 3/4 measure, so it restates its own 4/4
+        let time_signatures: Vec<&str> =
+            reversed.iter().map(|m| m.time_signature.as_str()).collect();
+        assert_eq!(time_signatures, ["3|4", "4|4", ""]);
+
+        let html = generate_measures_html(
+            &reversed,
+            "<svg></svg>",
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        );
+        let position = |label: &str| html.find(label).unwrap();
+        assert!(position("Measure: 3") < position("Measure: 2"));
+        assert!(position("Measure: 2") < position("Measure: 1"));
+    }
+
+    #[test]
+    fn long_score_is_split_into_pages() {
+        let measures: Vec<ScoreMeasure> = (1..=300).map(|n| whole_note_measure(n, 62, 0)).collect();
+
+        let (first, pagination) = paginate_measures(measures.clone(), 1, 200).unwrap();
+        assert_eq!(first.len(), 200);
+        assert_eq!(
+            pagination,
+            Pagination {
+                total_measures: 300,
+                page: 1,
+                page_count: 2,
+                has_more: true,
+            }
+        );
+
+        let (second, pagination) = paginate_measures(measures.clone(), 2, 200).unwrap();
+        assert_eq!(second.len(), 100);
+        assert_eq!(second[0].number, 201);
+        // The page starts with the time signature in effect
+        assert_eq!(second[0].time_signature, "4|4");
+        assert!(!pagination.has_more);
+
+        assert!(paginate_measures(measures, 3, 200).is_none());
+    }
+
+    #[actix_web::test]
+    async fn json_and_form_bodies_generate_the_same_sequence() {
+        use actix_web::{test, web, App};
+
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let score = dir.path().join("score.mscx");
+        std::fs::write(&score, score_xml(&[&[(62, 16), (65, 13), (69, 17)]])).unwrap();
+        let app = test::init_service(
+            App::new().service(web::resource("/generate").route(web::post().to(handle_generate))),
+        )
+        .await;
+        let fields = [
+            ("mscx_path", score.to_str().unwrap()),
+            ("part_name", "Piano"),
+            ("part_id", "1"),
+            ("scale", "d_kurd_9"),
+        ];
+
+        let json = test::TestRequest::post()
+            .uri("/generate?format=sequence")
+            .set_json(serde_json::json!({
+                "mscx_path": score.to_str().unwrap(),
+                "part_name": "Piano",
+                "part_id": 1,
+                "scale": "d_kurd_9",
+            }))
+            .to_request();
+        let json_body = test::call_and_read_body(&app, json).await;
+        let form = test::TestRequest::post()
+            .uri("/generate?format=sequence")
+            .set_form(fields)
+            .to_request();
+        let form_body = test::call_and_read_body(&app, form).await;
+
+        assert_eq!(json_body, form_body);
+        assert_eq!(json_body, "D4, F4, A4");
+    }
+
+    #[test]
+    fn looped_range_renders_every_pass() {
+        let measures = (1..=3).map(|n| whole_note_measure(n, 62, 0)).collect();
+        let range = select_measure_range(measures, Some(1), Some(2)).unwrap();
+        let looped = repeat_measures(range, 3);
+
+        let html = generate_measures_html(
+            &looped,
+            "<svg></svg>",
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        );
+        assert_eq!(html.matches("<div class='measure-header'>").count(), 6);
+        for pass in 1..=3 {
+            assert!(html.contains(&format!("Repetition {} of 3", pass)));
+        }
+    }
 }
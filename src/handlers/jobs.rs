@@ -0,0 +1,202 @@
+use crate::handlers::api::api_ok;
+use crate::utils::file::{pin_upload, unpin_upload};
+use actix_web::{web::Path, HttpResponse};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a finished job's result is kept before it is pruned from the job table.
+const JOB_RETENTION: Duration = Duration::from_secs(600);
+
+/// The state of an asynchronous upload job.
+///
+/// - **`Pending`**: The file was accepted and is still being extracted and parsed.
+/// - **`Done`**: Processing succeeded; holds the generated part-selection page.
+/// - **`Error`**: Processing failed; holds a user-facing error message.
+#[derive(Clone)]
+pub enum JobState {
+    Pending,
+    Done(String),
+    Error(String),
+}
+
+/// A job's creation time, its state and the score its page links to, which stays pinned (see `pin_upload`) as long
+/// as the job is kept.
+type JobEntry = (Instant, JobState, Option<PathBuf>);
+
+/// The in-memory job table, keyed by job id.
+static JOBS: Lazy<Mutex<HashMap<Uuid, JobEntry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The JSON body returned by `/api/jobs/{id}`.
+///
+/// Fields:
+/// - `status`: One of `pending`, `done` or `error`.
+/// - `result`: The generated HTML page once the job is done.
+/// - `error`: The error message if the job failed.
+#[derive(Serialize)]
+pub struct JobStatus {
+    status: &'static str,
+    result: Option<String>,
+    error: Option<String>,
+}
+
+/// Registers a new pending job and returns its id.
+///
+/// Finished jobs older than `JOB_RETENTION` are pruned at the same time so the table stays bounded, releasing the
+/// pins on their scores.
+pub fn create_job() -> Uuid {
+    let job_id = Uuid::new_v4();
+    let mut jobs = JOBS.lock().unwrap();
+    jobs.retain(|_, (created, state, score_path)| {
+        let keep = matches!(state, JobState::Pending) || created.elapsed() < JOB_RETENTION;
+        if let (false, Some(path)) = (keep, score_path) {
+            unpin_upload(path);
+        }
+        keep
+    });
+    jobs.insert(job_id, (Instant::now(), JobState::Pending, None));
+    job_id
+}
+
+/// Records the outcome of a job once its background processing completes.
+///
+/// The extracted score of a successful job is pinned until the job is pruned, so a client fetching the page late
+/// doesn't get a part-selection page for a score `clean_old_uploads` already removed.
+///
+/// # Parameters
+/// - `job_id`: The id returned by `create_job`.
+/// - `result`: The generated page and the path of the score it links to, or the error message if processing failed.
+pub fn finish_job(job_id: Uuid, result: Result<(String, PathBuf), String>) {
+    if let Some(job) = JOBS.lock().unwrap().get_mut(&job_id) {
+        match result {
+            Ok((page, score_path)) => {
+                pin_upload(&score_path);
+                job.1 = JobState::Done(page);
+                job.2 = Some(score_path);
+            }
+            Err(message) => job.1 = JobState::Error(message),
+        }
+    }
+}
+
+/// Returns the current state of a job, if it exists.
+pub fn job_state(job_id: &Uuid) -> Option<JobState> {
+    JOBS.lock()
+        .unwrap()
+        .get(job_id)
+        .map(|(_, state, _)| state.clone())
+}
+
+/// Handles GET requests to `/api/jobs/{id}`, reporting the progress of an asynchronous upload.
+///
+/// # Parameters
+/// - `path`: The job id from the URL.
+///
+/// # Returns
+/// - `200 OK` with a `JobStatus` JSON body wrapped in an `ApiEnvelope` when the job exists.
+/// - `404 Not Found` when the id is unknown or has expired.
+pub async fn handle_job_status(path: Path<Uuid>) -> HttpResponse {
+    let status = match job_state(&path.into_inner()) {
+        Some(JobState::Pending) => JobStatus {
+            status: "pending",
+            result: None,
+            error: None,
+        },
+        Some(JobState::Done(page)) => JobStatus {
+            status: "done",
+            result: Some(page),
+            error: None,
+        },
+        Some(JobState::Error(message)) => JobStatus {
+            status: "error",
+            result: None,
+            error: Some(message),
+        },
+        None => return HttpResponse::NotFound().body("Unknown job id"),
+    };
+
+    api_ok(status, Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{export::build_zip, upload::handle_mscz_upload};
+    use crate::utils::config::upload_field;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn job_stays_pending_until_it_is_finished() {
+        let job_id = create_job();
+        assert!(matches!(job_state(&job_id), Some(JobState::Pending)));
+
+        finish_job(job_id, Err("Invalid or too large ZIP file".to_string()));
+        assert!(
+            matches!(job_state(&job_id), Some(JobState::Error(message)) if message == "Invalid or too large ZIP file")
+        );
+        assert!(job_state(&Uuid::new_v4()).is_none());
+    }
+
+    #[actix_web::test]
+    async fn upload_job_goes_from_pending_to_done() {
+        let app = test::init_service(
+            App::new()
+                .route("/upload", web::post().to(handle_mscz_upload))
+                .route("/api/jobs/{id}", web::get().to(handle_job_status)),
+        )
+        .await;
+        let mscz = build_zip(&[(
+            "score.mscx".to_string(),
+            b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+              <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Flute</trackName></Part>\
+              <Staff id=\"1\"><Measure><voice><Chord><durationType>whole</durationType>\
+              <Note><pitch>62</pitch><tpc>16</tpc></Note></Chord></voice></Measure></Staff></Score></museScore>"
+                .to_vec(),
+        )])
+        .unwrap();
+        let mut body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"score.mscz\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            upload_field()
+        )
+        .into_bytes();
+        body.extend_from_slice(&mscz);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+        let request = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header(("content-type", "multipart/form-data; boundary=boundary"))
+            .set_payload(body)
+            .to_request();
+
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::ACCEPTED);
+        let accepted: serde_json::Value = test::read_body_json(response).await;
+        let job_id = accepted["job_id"].as_str().unwrap().to_string();
+
+        let mut statuses = Vec::new();
+        let job = loop {
+            let request = test::TestRequest::get()
+                .uri(&format!("/api/jobs/{}", job_id))
+                .to_request();
+            let job: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+            statuses.push(job["data"]["status"].as_str().unwrap().to_string());
+            if statuses.last().unwrap() != "pending" || statuses.len() > 500 {
+                break job;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        // Every poll before the last one found the job still pending
+        let (last, earlier) = statuses.split_last().unwrap();
+        assert_eq!(last, "done");
+        assert!(earlier.iter().all(|status| status == "pending"));
+        assert!(job["data"]["result"]
+            .as_str()
+            .unwrap()
+            .contains("<option value=\"1\">Flute</option>"));
+    }
+}
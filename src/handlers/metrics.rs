@@ -0,0 +1,41 @@
+use crate::utils::metrics::render_metrics;
+use actix_web::HttpResponse;
+
+/// Handles GET requests to `/metrics`, exposing the request counters and parse timings.
+///
+/// # Returns
+/// - `200 OK` with the metrics in the Prometheus text format.
+pub async fn handle_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(render_metrics())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn metrics_endpoint_lists_every_metric() {
+        let app =
+            test::init_service(App::new().route("/metrics", web::get().to(handle_metrics))).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert!(response.status().is_success());
+
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        for name in [
+            "handflow_requests_total{endpoint=\"upload\"}",
+            "handflow_requests_total{endpoint=\"generate\"}",
+            "handflow_rejections_total",
+            "handflow_parse_errors_total",
+            "handflow_parse_duration_seconds_bucket",
+            "handflow_parse_duration_seconds_sum",
+            "handflow_parse_duration_seconds_count",
+        ] {
+            assert!(body.contains(name), "{} missing from {}", name, body);
+        }
+    }
+}
@@ -1,3 +1,7 @@
+pub mod api;
+pub mod export;
 pub mod generate;
 pub mod home;
+pub mod jobs;
+pub mod metrics;
 pub mod upload;
@@ -1,19 +1,34 @@
+use crate::handlers::api::{api_ok, ScoreMetadata};
+use crate::handlers::jobs::{create_job, finish_job};
+use crate::templates::midi_in::{is_midi, parse_midi_to_score, score_to_mscx};
+use crate::templates::parser::{
+    metadata_from_tags, parse_mscx_meta_tags, parse_mscx_parts, percussion_staff_ids,
+    ScoreTooLarge, EXTRA_META_TAGS,
+};
 use crate::templates::{
     html::generate_html_css_legend, html::load_header_content, html::sanitize_html,
 };
-use crate::templates::{parser::parse_mscx_metadata, parser::parse_mscx_parts};
-use crate::utils::{file::is_valid_zip, file::sanitize_file_name, scales::scales_list};
-use actix_multipart::Multipart;
-use actix_web::HttpResponse;
+use crate::utils::config::{template_path, upload_dir, upload_field};
+use crate::utils::metrics::{self, Endpoint, RejectionReason};
+use crate::utils::{
+    file::is_valid_zip, file::pin_upload, file::retry_io, file::sanitize_file_name,
+    file::save_extracted_mscx, file::select_primary_mscx, file::unpin_upload,
+    scales::scale_families, scales::scales_list,
+};
+use actix_multipart::{Field, Multipart};
+use actix_web::{http::StatusCode, web, HttpResponse};
 use futures_util::StreamExt;
 use rand::{distributions::Alphanumeric, Rng};
-use std::io::Read;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Read, SeekFrom};
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
 use zip::ZipArchive;
 
 /// The `UPLOAD_COUNTER` and `MAX_UPLOADS` constants are used to manage and limit the number of simultaneous file uploads
@@ -31,6 +46,126 @@ use zip::ZipArchive;
 static UPLOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
 const MAX_UPLOADS: usize = 100;
 
+/// Reports how many upload slots are taken, for `/api/capacity`.
+///
+/// A rejected upload bumps the counter for a moment before taking it back, so the count is capped at the limit,
+/// like the guard in `handle_mscz_upload` sees it.
+///
+/// # Returns
+/// The number of slots in use and `MAX_UPLOADS`.
+pub fn upload_slots() -> (usize, usize) {
+    (
+        UPLOAD_COUNTER.load(Ordering::SeqCst).min(MAX_UPLOADS),
+        MAX_UPLOADS,
+    )
+}
+
+/// The largest MIDI file accepted for import, in bytes.
+const MAX_MIDI_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The smallest upload that can hold a score, in bytes: both an empty ZIP archive (its end of central directory
+/// record) and a MIDI header followed by a single track header take 22 bytes.
+const MIN_UPLOAD_SIZE: u64 = 22;
+
+/// The largest MSCX document accepted by `/paste`, in bytes.
+pub const MAX_PASTE_SIZE: usize = 10 * 1024 * 1024;
+
+/// The most files accepted by one `/api/upload/batch` request; further files are reported as rejected.
+const MAX_BATCH_FILES: usize = 50;
+
+/// The file extensions accepted for an upload, matching the formats `process_mscz` can read (MSCZ archives and
+/// Standard MIDI Files).
+const ACCEPTED_EXTENSIONS: [&str; 3] = ["mscz", "mid", "midi"];
+
+/// Returns whether an uploaded file name has one of the `ACCEPTED_EXTENSIONS` (case-insensitive).
+fn has_accepted_extension(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ACCEPTED_EXTENSIONS
+                .iter()
+                .any(|accepted| extension.eq_ignore_ascii_case(accepted))
+        })
+}
+
+/// The message rejecting a file without one of the `ACCEPTED_EXTENSIONS`.
+fn unsupported_type_message() -> String {
+    format!(
+        "Unsupported file type, expected one of: {}",
+        ACCEPTED_EXTENSIONS
+            .iter()
+            .map(|extension| format!(".{}", extension))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+/// Query parameters accepted by the upload endpoint.
+///
+/// Fields:
+/// - `exclude_staves`: An optional comma-separated list of staff ids (e.g. `3,4`) left out of the part selection, such
+///   as cue staves; percussion staves are left out without being listed.
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    exclude_staves: Option<String>,
+}
+
+/// The form posted to `/paste`.
+///
+/// Fields:
+/// - `mscx`: The raw MSCX XML of the score.
+/// - `exclude_staves`: An optional comma-separated list of staff ids left out of the part selection, as for uploads.
+#[derive(Deserialize)]
+pub struct PasteForm {
+    mscx: String,
+    exclude_staves: Option<String>,
+}
+
+/// Parses the comma-separated staff ids of an `exclude_staves` parameter (e.g. `"3, 4"`).
+///
+/// # Parameters
+/// - `value`: The parameter, if it was sent.
+///
+/// # Returns
+/// - `Ok(HashSet<u32>)` with the staff ids, empty when the parameter is missing or blank.
+/// - `Err(String)` naming the first entry that isn't a staff id.
+fn parse_excluded_staves(value: Option<&str>) -> Result<HashSet<u32>, String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse::<u32>()
+                .map_err(|_| format!("Invalid staff id {:?} in exclude_staves", id))
+        })
+        .collect()
+}
+
+/// The JSON body returned when an upload is accepted.
+#[derive(Serialize)]
+pub struct JobAccepted {
+    job_id: Uuid,
+}
+
+/// The outcome of one file of a `/api/upload/batch` request.
+///
+/// Fields:
+/// - `filename`: The file name the file was uploaded with.
+/// - `status`: The HTTP status a single `/upload` of the file would have returned (`200` when it was stored).
+/// - `mscx_path`: The path of the extracted score, to pass to `/generate` and the other endpoints, when stored.
+/// - `error`: Why the file was rejected, when it was.
+/// - `metadata`: The title, composer, arranger and metaTags of the score, when stored.
+#[derive(Serialize)]
+pub struct BatchUploadResult {
+    filename: String,
+    status: u16,
+    mscx_path: Option<String>,
+    error: Option<String>,
+    metadata: Option<ScoreMetadata>,
+}
+
 /// Asynchronously handles the upload and processing of an MSCZ file (a compressed file format).
 ///
 /// This function performs the following steps:
@@ -38,168 +173,595 @@ const MAX_UPLOADS: usize = 100;
 /// 1. **Upload Limit Check**: Increments the upload counter to track the number of active uploads.
 ///    If the number of active uploads exceeds `MAX_UPLOADS`, the function returns a `429 Too Many Requests` response.
 ///
-/// 2. **File Handling**: Iterates through the uploaded file data:
-///    - If a file is detected, a unique file name is generated using a timestamp and random suffix.
-///    - The file is saved to a designated upload directory, ensuring the directory exists with appropriate permissions.
+/// 2. **File Handling**: Iterates through the uploaded file data and saves the field named by `HANDFLOW_UPLOAD_FIELD`
+///    (`file` by default) with `save_uploaded_file`. A field whose file name doesn't end in one of the
+///    `ACCEPTED_EXTENSIONS` is rejected with `415 Unsupported Media Type` before anything is written to disk, and a
+///    request without that field gets a `400 Bad Request` naming it. An empty or implausibly small file is removed
+///    again and rejected with `400 Bad Request`.
 ///
-/// 3. **File Writing**: The function writes the received chunks of data to the file asynchronously using `tokio::fs::File`.
+/// 3. **Job Creation**: Registers a job and returns `202 Accepted` with its `job_id` as soon as the file is saved.
 ///
-/// 4. **ZIP File Processing**:
-///    - Opens the saved MSCZ file as a ZIP archive.
-///    - Validates the ZIP file's integrity and size.
-///    - Searches for the `.mscx` file within the ZIP archive and reads its content.
-///    - Saves the extracted `.mscx` file to the upload directory.
+/// 4. **Processing**: Extracts and parses the archive with `process_mscz` in a spawned task, whose outcome (the
+///    part-selection page or an error message) is reported by `/api/jobs/{id}`. The staves listed in
+///    `?exclude_staves=` are left out of the part selection; an invalid list is rejected with `400 Bad Request` before
+///    the upload is read.
 ///
-/// 5. **Response Preparation**:
-///    - Parses the MSCX file for available parts and generates HTML options for those parts.
-///    - Loads a template file, injects the necessary content, and generates the final HTML response.
-///
-/// 6. **Clean-Up**: Decrements the upload counter after processing is complete or if an error occurs.
-///
-/// 7. **Error Handling**:
-///    - Logs errors encountered during the file processing.
-///    - Returns appropriate HTTP responses (e.g., `InternalServerError`, `BadRequest`) based on the error context.
-///
-/// 8. **Final Response**: Returns an HTTP response with the generated HTML content, including metadata about the uploaded and processed file.
-pub async fn handle_mscz_upload(mut payload: Multipart) -> HttpResponse {
+/// 5. **Clean-Up**: Decrements the upload counter once processing is complete or if an error occurs.
+pub async fn handle_mscz_upload(
+    mut payload: Multipart,
+    query: web::Query<UploadQuery>,
+) -> HttpResponse {
+    let excluded_staves = match parse_excluded_staves(query.exclude_staves.as_deref()) {
+        Ok(staves) => staves,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
     let current_uploads = UPLOAD_COUNTER.fetch_add(1, Ordering::SeqCst);
 
     if current_uploads >= MAX_UPLOADS {
         UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+        metrics::record_rejection(Endpoint::Upload, RejectionReason::TooManyRequests);
         return HttpResponse::TooManyRequests().body("Too many uploads in progress");
     }
+    metrics::record_request(Endpoint::Upload);
 
-    let mut mscx_content = String::new();
-    let mut mscx_path: Option<PathBuf> = None;
+    let mut saved_upload: Option<(PathBuf, String)> = None;
+    let mut upload_name = String::new();
 
+    let field_name = upload_field();
     while let Some(Ok(mut field)) = payload.next().await {
         let content_disposition = field.content_disposition();
         let name = content_disposition.get_name(); // This is already an Option<&str>
 
-        if let Some(name) = name {
-            if name == "file" {
-                let unique_suffix: String = rand::thread_rng()
-                    .sample_iter(&Alphanumeric)
-                    .take(8)
-                    .map(char::from)
-                    .collect();
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                let file_name = sanitize_file_name(&format!(
-                    "uploaded_file_{}_{}.mscz",
-                    timestamp, unique_suffix
-                ));
+        if name == Some(field_name) {
+            let file_name = content_disposition.get_filename().unwrap_or_default();
+            if !has_accepted_extension(file_name) {
+                log::error!(
+                    "Rejected upload with unsupported file name: {:?}",
+                    file_name
+                );
+                UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+                metrics::record_rejection(Endpoint::Upload, RejectionReason::UnsupportedType);
+                return HttpResponse::UnsupportedMediaType().body(unsupported_type_message());
+            }
+
+            upload_name = file_name.to_string();
 
-                let upload_dir = PathBuf::from("uploads");
-                if !upload_dir.exists() {
-                    if let Err(e) = fs::create_dir_all(&upload_dir).await {
-                        log::error!("Failed to create upload directory: {:?}", e);
-                        return HttpResponse::InternalServerError().body("Failed to save the file");
-                    }
-
-                    if let Err(e) =
-                        fs::set_permissions(&upload_dir, std::fs::Permissions::from_mode(0o700))
-                            .await
-                    {
-                        log::error!("Failed to set directory permissions: {:?}", e);
-                        return HttpResponse::InternalServerError().body("Failed to save the file");
-                    }
+            match save_uploaded_file(&mut field).await {
+                Ok(saved) => {
+                    saved_upload = Some(saved);
+                    break;
                 }
+                Err((status, message)) => {
+                    UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+                    return HttpResponse::build(status).body(message);
+                }
+            }
+        }
+    }
 
-                let mscz_path = upload_dir.join(file_name);
+    let (mscz_path, upload_id) = match saved_upload {
+        Some(saved) => saved,
+        None => {
+            log::error!("Upload without a file field named {:?}", field_name);
+            UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+            return HttpResponse::BadRequest()
+                .body(format!("No file field named \"{}\" in request", field_name));
+        }
+    };
 
-                let mut file = fs::File::create(mscz_path.clone()).await.unwrap();
+    let job_id = create_job();
+    // Keep the upload from being cleaned up while the job still has to read it
+    pin_upload(&mscz_path);
+    actix_web::rt::spawn(async move {
+        let result = process_mscz(&mscz_path, &upload_id, &upload_name, &excluded_staves).await;
+        // The job now pins the extracted score its page links to instead
+        unpin_upload(&mscz_path);
+        UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+        finish_job(job_id, result.map_err(|(_, message)| message.to_string()));
+    });
+    HttpResponse::Accepted().json(JobAccepted { job_id })
+}
 
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    file.write_all(&data).await.unwrap();
-                }
+/// Handles POST requests to `/api/upload/batch`, storing several scores in one request.
+///
+/// This function performs the following steps:
+///
+/// 1. **Reads Every File**: Goes through every multipart field named by `HANDFLOW_UPLOAD_FIELD` (`file` by default),
+///    in order, up to `MAX_BATCH_FILES`; further files are reported as rejected with `413 Payload Too Large`.
+/// 2. **Stores Each File**: Runs each file through `store_batch_file`, which takes its own upload slot and follows
+///    the checks and extraction of `handle_mscz_upload`, so one bad file doesn't fail the others.
+/// 3. **Reports the Outcomes**: Returns one `BatchUploadResult` per file, in upload order.
+///
+/// # Parameters
+/// - `payload`: The multipart request body.
+///
+/// # Returns
+/// - `200 OK` with the `BatchUploadResult` list wrapped in an `ApiEnvelope`, even when some files were rejected.
+/// - `400 Bad Request` if the request holds no file field.
+pub async fn handle_batch_upload(mut payload: Multipart) -> HttpResponse {
+    let field_name = upload_field();
+    let mut results = Vec::new();
 
-                drop(file);
-
-                let file = match fs::File::open(&mscz_path).await {
-                    Ok(file) => file.into_std().await,
-                    Err(e) => {
-                        log::error!("Failed to open uploaded file: {:?}", e);
-                        return HttpResponse::InternalServerError().body("Failed to process file");
-                    }
-                };
-
-                let mut zip = match ZipArchive::new(file) {
-                    Ok(zip) => zip,
-                    Err(e) => {
-                        log::error!("Failed to open ZIP archive: {:?}", e);
-                        return HttpResponse::InternalServerError().body("Failed to process file");
-                    }
-                };
-
-                if !is_valid_zip(&mut zip) {
-                    log::error!("ZIP archive is invalid or too large");
-                    return HttpResponse::BadRequest().body("Invalid or too large ZIP file");
-                }
+    while let Some(Ok(mut field)) = payload.next().await {
+        let content_disposition = field.content_disposition();
+        if content_disposition.get_name() != Some(field_name) {
+            continue;
+        }
+        let filename = String::from(content_disposition.get_filename().unwrap_or_default());
+
+        let outcome = if results.len() >= MAX_BATCH_FILES {
+            Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Only {} files are accepted per batch", MAX_BATCH_FILES),
+            ))
+        } else {
+            store_batch_file(&mut field, &filename).await
+        };
 
-                for i in 0..zip.len() {
-                    let mut file = match zip.by_index(i) {
-                        Ok(file) => file,
-                        Err(e) => {
-                            log::error!("Failed to read file from ZIP: {:?}", e);
-                            return HttpResponse::InternalServerError()
-                                .body("Failed to extract file");
-                        }
-                    };
-                    if file.name().ends_with(".mscx") {
-                        if let Err(e) = file.read_to_string(&mut mscx_content) {
-                            log::error!("Failed to read .mscx content: {:?}", e);
-                            return HttpResponse::InternalServerError()
-                                .body("Failed to extract file");
-                        }
-
-                        let mscx_file_name =
-                            format!("extracted_file_{}_{}.mscx", timestamp, unique_suffix);
-                        let mscx_file_path = upload_dir.join(mscx_file_name);
-                        if let Err(e) = tokio::fs::write(&mscx_file_path, &mscx_content).await {
-                            log::error!("Failed to save extracted .mscx file: {:?}", e);
-                            return HttpResponse::InternalServerError().body("Failed to save file");
-                        }
-
-                        mscx_path = Some(mscx_file_path);
-                        break;
-                    }
+        results.push(match outcome {
+            Ok((mscx_path, metadata)) => BatchUploadResult {
+                filename,
+                status: StatusCode::OK.as_u16(),
+                mscx_path: Some(mscx_path.display().to_string()),
+                error: None,
+                metadata: Some(metadata),
+            },
+            Err((status, message)) => {
+                log::error!("Rejected batch file {:?}: {}", filename, message);
+                BatchUploadResult {
+                    filename,
+                    status: status.as_u16(),
+                    mscx_path: None,
+                    error: Some(message),
+                    metadata: None,
                 }
             }
-        }
+        });
+    }
+
+    if results.is_empty() {
+        log::error!("Batch upload without a file field named {:?}", field_name);
+        return HttpResponse::BadRequest()
+            .body(format!("No file field named \"{}\" in request", field_name));
+    }
+
+    api_ok(results, Vec::new())
+}
+
+/// Stores one file of a batch upload and reads its metadata.
+///
+/// The file takes an upload slot for as long as it is processed, like a single upload, and is checked the same way:
+/// its extension, its size (`save_uploaded_file`), its format and extraction (`extract_score`) and its part count
+/// (`store_score`).
+///
+/// # Parameters
+/// - `field`: The multipart field carrying the file data.
+/// - `filename`: The file name the file was uploaded with.
+///
+/// # Returns
+/// - `Ok((PathBuf, ScoreMetadata))` with the path of the extracted score and its metadata.
+/// - `Err((StatusCode, String))` with the status and message a single upload of the file would have returned.
+async fn store_batch_file(
+    field: &mut Field,
+    filename: &str,
+) -> Result<(PathBuf, ScoreMetadata), (StatusCode, String)> {
+    if !has_accepted_extension(filename) {
+        metrics::record_rejection(Endpoint::Upload, RejectionReason::UnsupportedType);
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            unsupported_type_message(),
+        ));
+    }
+
+    let current_uploads = UPLOAD_COUNTER.fetch_add(1, Ordering::SeqCst);
+    if current_uploads >= MAX_UPLOADS {
+        UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+        metrics::record_rejection(Endpoint::Upload, RejectionReason::TooManyRequests);
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many uploads in progress".to_string(),
+        ));
+    }
+    metrics::record_request(Endpoint::Upload);
+
+    let result = async {
+        let (upload_path, upload_id) = save_uploaded_file(field).await?;
+        let upload_dir = upload_path.parent().unwrap_or_else(|| upload_dir());
+        let mscx_content = extract_score(&upload_path, filename).await?;
+        let (mscx_path, _) = store_score(upload_dir, &mscx_content, &upload_id).await?;
+        Ok((mscx_path, ScoreMetadata::from_mscx(&mscx_content)))
+    }
+    .await;
+    UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+
+    result.map_err(|(status, message): (StatusCode, &str)| (status, message.to_string()))
+}
+
+/// Handles POST requests to `/paste`, building the part-selection page from MSCX XML pasted into the home page.
+///
+/// This function performs the following steps:
+///
+/// 1. **Upload Limit Check**: Takes an upload slot like `handle_mscz_upload`, returning `429 Too Many Requests` when
+///    none is free.
+///
+/// 2. **Validation**: Rejects empty text and text that isn't a MuseScore document (no `<museScore` root) with
+///    `400 Bad Request`. Bodies larger than `MAX_PASTE_SIZE` are refused by the form extractor.
+///
+/// 3. **Processing**: Saves the XML with `save_extracted_mscx`, named after its content hash, and builds the same
+///    part-selection page as an upload with `build_parts_page`, leaving out the staves listed in `exclude_staves`.
+///
+/// # Parameters
+/// - `form`: The form-encoded `mscx` field.
+///
+/// # Returns
+/// - `200 OK` with the part-selection page.
+/// - `400 Bad Request`, `429 Too Many Requests` or the error of the failing step otherwise.
+pub async fn handle_mscx_paste(form: web::Form<PasteForm>) -> HttpResponse {
+    let PasteForm {
+        mscx: mscx_content,
+        exclude_staves,
+    } = form.into_inner();
+    let excluded_staves = match parse_excluded_staves(exclude_staves.as_deref()) {
+        Ok(staves) => staves,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    let current_uploads = UPLOAD_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    if current_uploads >= MAX_UPLOADS {
+        UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
+        metrics::record_rejection(Endpoint::Upload, RejectionReason::TooManyRequests);
+        return HttpResponse::TooManyRequests().body("Too many uploads in progress");
     }
+    metrics::record_request(Endpoint::Upload);
 
+    let result = if mscx_content.trim().is_empty() {
+        Err((StatusCode::BAD_REQUEST, "The pasted score is empty"))
+    } else if !mscx_content.contains("<museScore") {
+        log::error!("Rejected pasted text without a <museScore> root");
+        Err((
+            StatusCode::BAD_REQUEST,
+            "The pasted text is not a MuseScore (MSCX) score",
+        ))
+    } else {
+        build_parts_page(
+            upload_dir(),
+            &mscx_content,
+            &new_upload_id(),
+            &excluded_staves,
+        )
+        .await
+    };
     UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
 
+    match result {
+        Ok((response, _)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(response),
+        Err((status, message)) => HttpResponse::build(status).body(message),
+    }
+}
+
+/// Generates the `{timestamp}_{suffix}` id naming the files of a new upload.
+fn new_upload_id() -> String {
+    let unique_suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    format!("{}_{}", timestamp, unique_suffix)
+}
+
+/// Saves an uploaded multipart field to a uniquely named `.mscz` file in the upload directory.
+///
+/// A unique file name is generated using a timestamp and random suffix, and the upload directory is
+/// created with restrictive permissions if it doesn't exist yet. The received chunks are written
+/// asynchronously using `tokio::fs::File`.
+///
+/// Creating the file and writing each chunk are retried on transient errors with `retry_io`. A chunk is written at
+/// its fixed offset, so a retry after a partial write doesn't duplicate data.
+///
+/// A file shorter than `MIN_UPLOAD_SIZE` can't be a score, so it is deleted and reported as empty or too small
+/// instead of failing later as an unreadable archive.
+///
+/// # Parameters
+/// - `field`: The multipart field carrying the file data.
+///
+/// # Returns
+/// - `Ok((PathBuf, String))` with the saved file path and the `{timestamp}_{suffix}` upload id.
+/// - `Err((StatusCode, &str))` with the status and message to report if the file could not be saved.
+async fn save_uploaded_file(
+    field: &mut Field,
+) -> Result<(PathBuf, String), (StatusCode, &'static str)> {
+    let upload_id = new_upload_id();
+    let file_name = sanitize_file_name(&format!("uploaded_file_{}.mscz", upload_id));
+
+    let upload_dir = upload_dir().to_path_buf();
+    if !upload_dir.exists() {
+        if let Err(e) = fs::create_dir_all(&upload_dir).await {
+            log::error!("Failed to create upload directory: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to save the file"));
+        }
+
+        if let Err(e) =
+            fs::set_permissions(&upload_dir, std::fs::Permissions::from_mode(0o700)).await
+        {
+            log::error!("Failed to set directory permissions: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to save the file"));
+        }
+    }
+
+    let mscz_path = upload_dir.join(file_name);
+
+    let file = match retry_io("create the upload file", || fs::File::create(&mscz_path)).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to create the upload file: {:?}", e);
+            return Err((io_error_status(&e), io_error_message(&e)));
+        }
+    };
+
+    let mut offset = 0;
+    while let Some(chunk) = field.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to receive the uploaded file: {:?}", e);
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Failed to receive the uploaded file",
+                ));
+            }
+        };
+        let file = &file;
+        let data = &data[..];
+        let written = retry_io("write the upload file", || async move {
+            let mut handle = file.try_clone().await?;
+            handle.seek(SeekFrom::Start(offset)).await?;
+            handle.write_all(data).await?;
+            handle.flush().await
+        })
+        .await;
+        if let Err(e) = written {
+            log::error!("Failed to write the upload file: {:?}", e);
+            return Err((io_error_status(&e), io_error_message(&e)));
+        }
+        offset += data.len() as u64;
+    }
+
+    if offset < MIN_UPLOAD_SIZE {
+        log::error!("Rejected upload of {} bytes", offset);
+        drop(file);
+        if let Err(e) = fs::remove_file(&mscz_path).await {
+            log::error!("Failed to remove the rejected upload: {:?}", e);
+        }
+        return Err((
+            StatusCode::BAD_REQUEST,
+            if offset == 0 {
+                "The uploaded file is empty"
+            } else {
+                "The uploaded file is too small to be a score"
+            },
+        ));
+    }
+
+    Ok((mscz_path, upload_id))
+}
+
+/// Picks the status reported for a filesystem error that persisted through the retries.
+///
+/// # Parameters
+/// - `error`: The last error returned by the operation.
+///
+/// # Returns
+/// - `507 Insufficient Storage` when the disk is full, or `500 Internal Server Error` otherwise.
+fn io_error_status(error: &io::Error) -> StatusCode {
+    if error.kind() == io::ErrorKind::StorageFull {
+        StatusCode::INSUFFICIENT_STORAGE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Picks the message reported for a filesystem error that persisted through the retries.
+///
+/// # Parameters
+/// - `error`: The last error returned by the operation.
+///
+/// # Returns
+/// - A message telling the user whether the server ran out of disk space or failed for another reason.
+fn io_error_message(error: &io::Error) -> &'static str {
+    if error.kind() == io::ErrorKind::StorageFull {
+        "The server is out of disk space, please try again later"
+    } else {
+        "Failed to save the file"
+    }
+}
+
+/// Extracts the `.mscx` score from a saved upload and builds the part-selection page.
+///
+/// This function performs the following steps:
+///
+/// 1. **Score Extraction**: Reads the score out of the saved file with `extract_score`.
+///
+/// 2. **Response Preparation**: Saves the score and builds the part-selection page with `build_parts_page`.
+///
+/// # Parameters
+/// - `mscz_path`: The path of the saved MSCZ or MIDI file.
+/// - `upload_id`: The `{timestamp}_{suffix}` id used to name the extracted `.mscx` file if its hashed name collides.
+/// - `upload_name`: The file name the score was uploaded with, used to recognize the primary score of an archive.
+/// - `excluded_staves`: The staff ids left out of the part selection.
+///
+/// # Returns
+/// - `Ok((String, PathBuf))` containing the final HTML page and the path of the extracted score it links to.
+/// - `Err((StatusCode, &str))` with the status and message to report if processing fails.
+async fn process_mscz(
+    mscz_path: &Path,
+    upload_id: &str,
+    upload_name: &str,
+    excluded_staves: &HashSet<u32>,
+) -> Result<(String, PathBuf), (StatusCode, &'static str)> {
+    let upload_dir = mscz_path.parent().unwrap_or_else(|| upload_dir());
+    let mscx_content = extract_score(mscz_path, upload_name).await?;
+    build_parts_page(upload_dir, &mscx_content, upload_id, excluded_staves).await
+}
+
+/// Reads the MSCX score out of a saved upload.
+///
+/// The saved file is sniffed: a Standard MIDI File (`MThd` header) is converted with `convert_midi_upload`, anything
+/// else is treated as an MSCZ archive and read with `extract_mscx_from_zip`, which picks the primary score if the
+/// archive holds several `.mscx` files.
+///
+/// # Parameters
+/// - `mscz_path`: The path of the saved MSCZ or MIDI file.
+/// - `upload_name`: The file name the score was uploaded with, used to recognize the primary score of an archive.
+///
+/// # Returns
+/// - `Ok(String)` containing the MSCX content.
+/// - `Err((StatusCode, &str))` with the status and message to report if nothing could be extracted.
+async fn extract_score(
+    mscz_path: &Path,
+    upload_name: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let mscx_content = if is_midi_upload(mscz_path).await {
+        convert_midi_upload(mscz_path).await?
+    } else {
+        extract_mscx_from_zip(mscz_path, upload_name).await?
+    };
+
     if mscx_content.is_empty() {
-        return HttpResponse::BadRequest()
-            .body("Failed to extract .mscx content from uploaded file");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Failed to extract .mscx content from uploaded file",
+        ));
+    }
+
+    Ok(mscx_content)
+}
+
+/// Saves an MSCX score to the upload directory and lists its parts.
+///
+/// The `.mscx` file is written with `save_extracted_mscx`, named after its content hash so identical scores share
+/// one stable path. Scores with more parts than the configured `part_limit` are rejected.
+///
+/// # Parameters
+/// - `upload_dir`: The directory the score is saved to.
+/// - `mscx_content`: The MSCX content of the score.
+/// - `upload_id`: The `{timestamp}_{suffix}` id used to name the `.mscx` file if its hashed name collides.
+///
+/// # Returns
+/// - `Ok((PathBuf, Vec<(u32, String, Option<u8>)>))` with the path of the saved score and its parts, as returned by
+///   `parse_mscx_parts`.
+/// - `Err((StatusCode, &str))` with the status and message to report if a step fails.
+async fn store_score(
+    upload_dir: &Path,
+    mscx_content: &str,
+    upload_id: &str,
+) -> Result<(PathBuf, Vec<(u32, String, Option<u8>)>), (StatusCode, &'static str)> {
+    let mscx_path = match save_extracted_mscx(upload_dir, mscx_content, upload_id).await {
+        Ok(path) => path,
+        Err(e) => {
+            log::error!("Failed to save extracted .mscx file: {:?}", e);
+            return Err((io_error_status(&e), io_error_message(&e)));
+        }
+    };
+
+    match parse_mscx_parts(mscx_content) {
+        Ok(parts) => Ok((mscx_path, parts)),
+        Err(e) if e.is::<ScoreTooLarge>() => {
+            log::error!("Rejected score: {}", e);
+            Err((StatusCode::BAD_REQUEST, "The score has too many parts"))
+        }
+        Err(_) => {
+            metrics::record_parse_error();
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to parse MSCX parts",
+            ))
+        }
     }
+}
 
-    let available_parts = parse_mscx_parts(&mscx_content);
-    if available_parts.is_err() {
-        return HttpResponse::InternalServerError().body("Failed to parse MSCX parts");
+/// Saves an MSCX score and builds the part-selection page for it, shared by uploads and pasted scores.
+///
+/// This function:
+///
+/// 1. **Saves the Score**: Writes the `.mscx` file to the upload directory and lists its parts with `store_score`.
+/// 2. **Lists the Parts**: Generates HTML options for the parts, with the part's MIDI program as `data-program` when the score declares one. Percussion parts (see
+///    `percussion_staff_ids`) are left out so they are never picked by default, unless the score has nothing else.
+///    The staves in `excluded_staves` are always left out, and a score left without any part is rejected.
+/// 3. **Renders the Page**: Loads a template file, injects the metadata, parts and scales, and generates the final
+///    HTML page.
+///
+/// # Parameters
+/// - `upload_dir`: The directory the score is saved to.
+/// - `mscx_content`: The MSCX content of the score.
+/// - `upload_id`: The `{timestamp}_{suffix}` id used to name the `.mscx` file if its hashed name collides.
+/// - `excluded_staves`: The staff ids, as returned by `parse_mscx_parts`, left out of the part selection.
+///
+/// # Returns
+/// - `Ok((String, PathBuf))` containing the final HTML page and the path of the saved score it links to.
+/// - `Err((StatusCode, &str))` with the status and message to report if a step fails.
+async fn build_parts_page(
+    upload_dir: &Path,
+    mscx_content: &str,
+    upload_id: &str,
+    excluded_staves: &HashSet<u32>,
+) -> Result<(String, PathBuf), (StatusCode, &'static str)> {
+    let (mscx_path, available_parts) = store_score(upload_dir, mscx_content, upload_id).await?;
+
+    let available_parts: Vec<(u32, String, Option<u8>)> = available_parts
+        .into_iter()
+        .filter(|(id, _, _)| !excluded_staves.contains(id))
+        .collect();
+    if available_parts.is_empty() && !excluded_staves.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Every staff of the score is excluded",
+        ));
     }
-    let available_parts = available_parts.unwrap();
+
+    // Drum staves have no pitches to play on a handpan
+    let percussion = percussion_staff_ids(mscx_content);
+    let available_parts = if available_parts
+        .iter()
+        .all(|(id, _, _)| percussion.contains(id))
+    {
+        available_parts
+    } else {
+        available_parts
+            .into_iter()
+            .filter(|(id, _, _)| !percussion.contains(id))
+            .collect()
+    };
 
     let part_options = available_parts
         .into_iter()
-        .map(|(id, name)| {
-            format!(
+        .map(|(id, name, program)| match program {
+            Some(program) => format!(
+                "<option value=\"{}\" data-program=\"{}\" title=\"MIDI program {}\">{}</option>",
+                id,
+                program,
+                program,
+                &sanitize_html(&name)
+            ),
+            None => format!(
                 "<option value=\"{}\">{}</option>",
                 id,
                 &sanitize_html(&name)
-            )
+            ),
         })
         .collect::<String>();
 
     let mut grouped_options = String::new();
     let mut last_note_count = 0;
 
-    for (id, name, notes, _) in scales_list() {
+    for (_, key, name, family, notes, _) in scales_list() {
         let note_count = notes.len();
 
         if note_count != last_note_count {
@@ -211,8 +773,11 @@ pub async fn handle_mscz_upload(mut payload: Multipart) -> HttpResponse {
         }
 
         grouped_options.push_str(&format!(
-            "<option value=\"{}\">{} ({} notes)</option>",
-            id, name, note_count
+            "<option value=\"{}\" data-family=\"{}\">{} ({} notes)</option>",
+            key,
+            sanitize_html(&family),
+            name,
+            note_count
         ));
     }
 
@@ -220,15 +785,46 @@ pub async fn handle_mscz_upload(mut payload: Multipart) -> HttpResponse {
         grouped_options.push_str("</optgroup>");
     }
 
-    let (work_title, composer, arranger) = parse_mscx_metadata(&mscx_content);
+    let family_options = scale_families()
+        .iter()
+        .map(|family| {
+            let family = sanitize_html(family);
+            format!("<option value=\"{}\">{}</option>", family, family)
+        })
+        .collect::<String>();
+
+    let layout_set_options = crate::utils::config::layout_sets()
+        .iter()
+        .map(|set| {
+            let set = sanitize_html(set);
+            format!("<option value=\"{}\">{}</option>", set, set)
+        })
+        .collect::<String>();
 
-    let body_path = "src/html/upload_tmpl.html";
+    let meta_tags = parse_mscx_meta_tags(mscx_content);
+    let (work_title, composer, arranger) = metadata_from_tags(&meta_tags);
+    let metadata_extras = EXTRA_META_TAGS
+        .iter()
+        .filter_map(|(name, label)| {
+            meta_tags.get(*name).map(|value| {
+                format!(
+                    "<div class=\"metadata-extra\"><span class=\"metadata-label\">{}:</span> {}</div>",
+                    label,
+                    sanitize_html(value)
+                )
+            })
+        })
+        .collect::<String>();
+
+    let body_path = template_path("upload_tmpl.html");
     let mut body_file = match tokio::fs::File::open(body_path).await {
         Ok(file) => file,
         Err(e) => {
             log::error!("Failed to open template file: {:?}", e);
-            UPLOAD_COUNTER.fetch_sub(1, Ordering::SeqCst);
-            return HttpResponse::InternalServerError().body("Failed to open template file");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to open template file",
+            ));
         }
     };
 
@@ -236,7 +832,10 @@ pub async fn handle_mscz_upload(mut payload: Multipart) -> HttpResponse {
     if let Err(e) = tokio::io::AsyncReadExt::read_to_string(&mut body_file, &mut body_content).await
     {
         log::error!("Failed to read template file: {:?}", e);
-        return HttpResponse::InternalServerError().body("Failed to read template file");
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to read template file",
+        ));
     }
 
     let legend_html = generate_html_css_legend();
@@ -245,16 +844,413 @@ pub async fn handle_mscz_upload(mut payload: Multipart) -> HttpResponse {
         .replace("{{work_title}}", &sanitize_html(&work_title))
         .replace("{{composer}}", &sanitize_html(&composer))
         .replace("{{arranger}}", &sanitize_html(&arranger))
-        .replace("{{mscx_path}}", &mscx_path.unwrap().display().to_string())
+        .replace("{{metadata_extras}}", &metadata_extras)
+        .replace("{{mscx_path}}", &mscx_path.display().to_string())
         .replace("{{part_options}}", &part_options)
         .replace("{{legend_html}}", &legend_html)
+        .replace("{{scale_family_options}}", &family_options)
+        .replace("{{layout_set_options}}", &layout_set_options)
         .replace("{{scale_options}}", &grouped_options);
 
     // Load header content
     let header_content = load_header_content().await;
-    let response = header_content.replace("{{body}}", &body_content);
+    Ok((header_content.replace("{{body}}", &body_content), mscx_path))
+}
+
+/// Returns whether a saved upload is a Standard MIDI File rather than an MSCZ archive.
+///
+/// The format is sniffed from the `MThd` header, so the file name or extension chosen by the user does not matter.
+async fn is_midi_upload(path: &Path) -> bool {
+    let mut header = [0u8; 4];
+    match fs::File::open(path).await {
+        Ok(mut file) => {
+            tokio::io::AsyncReadExt::read_exact(&mut file, &mut header)
+                .await
+                .is_ok()
+                && is_midi(&header)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Reads the `.mscx` score out of a saved MSCZ archive.
+///
+/// When the archive holds more than one `.mscx` file, the primary score is chosen with `select_primary_mscx` and the
+/// choice is logged.
+///
+/// # Parameters
+/// - `mscz_path`: The path of the saved MSCZ file.
+/// - `upload_name`: The file name the archive was uploaded with.
+///
+/// # Returns
+/// - `Ok(String)` with the content of the primary `.mscx` file in the archive, or an empty string if there is none.
+/// - `Err((StatusCode, &str))` if the archive cannot be opened, is invalid or cannot be read.
+async fn extract_mscx_from_zip(
+    mscz_path: &Path,
+    upload_name: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let mut mscx_content = String::new();
 
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(response)
+    let file = match retry_io("open the uploaded file", || fs::File::open(mscz_path)).await {
+        Ok(file) => file.into_std().await,
+        Err(e) => {
+            log::error!("Failed to open uploaded file: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to process file"));
+        }
+    };
+
+    let mut zip = match ZipArchive::new(file) {
+        Ok(zip) => zip,
+        Err(e) => {
+            log::error!("Failed to open ZIP archive: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to process file"));
+        }
+    };
+
+    if !is_valid_zip(&mut zip) {
+        log::error!("ZIP archive is invalid or too large");
+        return Err((StatusCode::BAD_REQUEST, "Invalid or too large ZIP file"));
+    }
+
+    let mut indices: Vec<usize> = Vec::new();
+    let mut candidates: Vec<(String, u64)> = Vec::new();
+    for i in 0..zip.len() {
+        let file = match zip.by_index(i) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to read file from ZIP: {:?}", e);
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to extract file"));
+            }
+        };
+        if file.name().ends_with(".mscx") {
+            indices.push(i);
+            candidates.push((file.name().to_string(), file.size()));
+        }
+    }
+
+    let chosen = match select_primary_mscx(&candidates, Some(upload_name)) {
+        Some(chosen) => chosen,
+        None => return Ok(mscx_content),
+    };
+    if candidates.len() > 1 {
+        log::info!(
+            "Archive contains {} .mscx files, using {:?}",
+            candidates.len(),
+            candidates[chosen].0
+        );
+    }
+
+    let mut file = match zip.by_index(indices[chosen]) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to read file from ZIP: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to extract file"));
+        }
+    };
+    if let Err(e) = file.read_to_string(&mut mscx_content) {
+        log::error!("Failed to read .mscx content: {:?}", e);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to extract file"));
+    }
+
+    Ok(mscx_content)
+}
+
+/// Converts a saved MIDI upload into an equivalent `.mscx` score.
+///
+/// The file is parsed with `parse_midi_to_score` and written back out with `score_to_mscx`, so the rest of the
+/// pipeline (part selection, generation and exports) treats it exactly like a MuseScore upload.
+///
+/// # Parameters
+/// - `midi_path`: The path of the saved `.mid` file.
+///
+/// # Returns
+/// - `Ok(String)` with the generated MSCX content.
+/// - `Err((StatusCode, &str))` if the file is too large, cannot be read or is not a usable MIDI file.
+async fn convert_midi_upload(midi_path: &Path) -> Result<String, (StatusCode, &'static str)> {
+    match fs::metadata(midi_path).await {
+        Ok(metadata) if metadata.len() > MAX_MIDI_SIZE => {
+            log::error!("MIDI file is too large: {} bytes", metadata.len());
+            return Err((StatusCode::BAD_REQUEST, "Invalid or too large MIDI file"));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("Failed to read uploaded file metadata: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to process file"));
+        }
+    }
+
+    let bytes = match fs::read(midi_path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Failed to read uploaded MIDI file: {:?}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to process file"));
+        }
+    };
+
+    match parse_midi_to_score(&bytes) {
+        Ok((parts, part_measures)) => Ok(score_to_mscx(&parts, &part_measures)),
+        Err(e) if e.is::<ScoreTooLarge>() => {
+            log::error!("Rejected MIDI file: {}", e);
+            Err((
+                StatusCode::BAD_REQUEST,
+                "The score has too many parts or measures",
+            ))
+        }
+        Err(e) => {
+            log::error!("Failed to parse MIDI file: {:?}", e);
+            Err((StatusCode::BAD_REQUEST, "Invalid or too large MIDI file"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::metrics::render_metrics;
+    use actix_web::{test, App};
+
+    /// A `multipart/form-data` body with `boundary` as its boundary, holding one file per `(field, file name, content)`.
+    fn multipart_body(files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (field, file_name, content) in files {
+            body.extend_from_slice(
+                format!(
+                    "--boundary\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n",
+                    field, file_name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+        body
+    }
+
+    /// A `POST` of a multipart body to `uri`.
+    fn multipart_request(uri: &str, body: Vec<u8>) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("content-type", "multipart/form-data; boundary=boundary"))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn text_file_upload_is_rejected_early() {
+        let app =
+            test::init_service(App::new().route("/upload", web::post().to(handle_mscz_upload)))
+                .await;
+        let body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"notes.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nnot a score\r\n--boundary--\r\n",
+            upload_field()
+        );
+        let request = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header(("content-type", "multipart/form-data; boundary=boundary"))
+            .set_payload(body)
+            .to_request();
+
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let rejected = render_metrics()
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix(
+                    "handflow_rejections_total{endpoint=\"upload\",reason=\"unsupported_type\"} ",
+                )
+            })
+            .and_then(|count| count.parse::<u64>().ok());
+        assert!(rejected.is_some_and(|count| count >= 1));
+    }
+
+    #[actix_web::test]
+    async fn excluded_and_percussion_staves_are_not_listed() {
+        let part = |id: u32, name: &str, group: &str| {
+            format!(
+                "<Part id=\"{}\"><Staff id=\"{}\"><StaffType group=\"{}\"/></Staff><trackName>{}</trackName></Part>",
+                id, id, group, name
+            )
+        };
+        let mscx = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>{}{}{}</Score></museScore>",
+            part(1, "Flute", "pitched"),
+            part(2, "Drumset", "percussion"),
+            part(3, "Flute cue", "pitched")
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let excluded = parse_excluded_staves(Some("3")).unwrap();
+
+        let (page, _) = build_parts_page(dir.path(), &mscx, "1_test", &excluded)
+            .await
+            .unwrap();
+        assert!(page.contains("<option value=\"1\">Flute</option>"));
+        assert!(!page.contains("Drumset"));
+        assert!(!page.contains("Flute cue"));
+
+        let every_staff = parse_excluded_staves(Some("1, 2,3")).unwrap();
+        let error = build_parts_page(dir.path(), &mscx, "1_test", &every_staff)
+            .await
+            .unwrap_err();
+        assert_eq!(error.0, StatusCode::BAD_REQUEST);
+        assert!(parse_excluded_staves(Some("1,cue")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn primary_score_is_extracted_from_an_archive_with_two() {
+        let dir = tempfile::tempdir_in(upload_dir()).unwrap();
+        let mscz = dir.path().join("upload.mscz");
+        let full_score =
+            "<museScore version=\"4.20\"><Score><!-- every part --></Score></museScore>";
+        let linked_part = "<museScore version=\"4.20\"><Score/></museScore>";
+        let archive = crate::handlers::export::build_zip(&[
+            (
+                "Excerpts/Flute.mscx".to_string(),
+                linked_part.as_bytes().to_vec(),
+            ),
+            ("Song.mscx".to_string(), full_score.as_bytes().to_vec()),
+        ])
+        .unwrap();
+        std::fs::write(&mscz, archive).unwrap();
+
+        // The largest entry is the full score when the upload name matches none of them
+        assert_eq!(
+            extract_mscx_from_zip(&mscz, "renamed.mscz").await.unwrap(),
+            full_score
+        );
+        // An entry named after the upload is preferred even if it is smaller
+        assert_eq!(
+            extract_mscx_from_zip(&mscz, "flute.mscz").await.unwrap(),
+            linked_part
+        );
+    }
+
+    #[actix_web::test]
+    async fn upload_is_read_from_the_configured_field() {
+        let app =
+            test::init_service(App::new().route("/upload", web::post().to(handle_mscz_upload)))
+                .await;
+        let mscz = crate::handlers::export::build_zip(&[(
+            "score.mscx".to_string(),
+            b"<museScore version=\"4.20\"><Score/></museScore>".to_vec(),
+        )])
+        .unwrap();
+        assert_eq!(upload_field(), "score");
+
+        let request = multipart_request("/upload", multipart_body(&[("file", "song.mscz", &mscz)]))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(response).await,
+            "No file field named \"score\" in request"
+        );
+
+        let request =
+            multipart_request("/upload", multipart_body(&[("score", "song.mscz", &mscz)]))
+                .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[actix_web::test]
+    async fn empty_upload_is_rejected_with_a_clear_message() {
+        let app =
+            test::init_service(App::new().route("/upload", web::post().to(handle_mscz_upload)))
+                .await;
+
+        let request = multipart_request(
+            "/upload",
+            multipart_body(&[(upload_field(), "song.mscz", b"")]),
+        )
+        .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(response).await,
+            "The uploaded file is empty"
+        );
+
+        let request = multipart_request(
+            "/upload",
+            multipart_body(&[(upload_field(), "song.mscz", b"PK\x03\x04")]),
+        )
+        .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            test::read_body(response).await,
+            "The uploaded file is too small to be a score"
+        );
+    }
+
+    #[actix_web::test]
+    async fn pasted_score_returns_the_parts_page() {
+        let app =
+            test::init_service(App::new().route("/paste", web::post().to(handle_mscx_paste))).await;
+        let mscx = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <metaTag name=\"workTitle\">Pasted tune</metaTag>\
+             <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Flute</trackName></Part>\
+             <Staff id=\"1\"><Measure><voice><Chord><durationType>whole</durationType>\
+             <Note><pitch>62</pitch><tpc>16</tpc></Note></Chord></voice></Measure></Staff></Score></museScore>";
+
+        let request = test::TestRequest::post()
+            .uri("/paste")
+            .set_form([("mscx", mscx)])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(page.contains("<option value=\"1\">Flute</option>"));
+        assert!(page.contains("Pasted tune"));
+        let hashed_path = upload_dir().join(format!(
+            "extracted_{}.mscx",
+            crate::utils::file::content_hash(mscx.as_bytes())
+        ));
+        assert!(page.contains(&hashed_path.display().to_string()));
+
+        let request = test::TestRequest::post()
+            .uri("/paste")
+            .set_form([("mscx", "<html></html>")])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn batch_upload_reports_every_file() {
+        let app = test::init_service(
+            App::new().route("/api/upload/batch", web::post().to(handle_batch_upload)),
+        )
+        .await;
+        let mscz = crate::handlers::export::build_zip(&[(
+            "score.mscx".to_string(),
+            b"<museScore version=\"4.20\"><Score><metaTag name=\"workTitle\">Batch tune</metaTag>\
+              <Part id=\"1\"><Staff id=\"1\"/><trackName>Flute</trackName></Part></Score></museScore>"
+                .to_vec(),
+        )])
+        .unwrap();
+        let body = multipart_body(&[
+            (upload_field(), "tune.mscz", &mscz),
+            (upload_field(), "notes.txt", b"not a score"),
+        ]);
+
+        let request = multipart_request("/api/upload/batch", body).to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let results = response["data"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0]["filename"], "tune.mscz");
+        assert_eq!(results[0]["status"], 200);
+        assert_eq!(results[0]["metadata"]["title"], "Batch tune");
+        let mscx_path = results[0]["mscx_path"].as_str().unwrap();
+        assert!(std::path::Path::new(mscx_path).starts_with(upload_dir()));
+        assert!(results[0]["error"].is_null());
+
+        assert_eq!(results[1]["filename"], "notes.txt");
+        assert_eq!(results[1]["status"], 415);
+        assert!(results[1]["mscx_path"].is_null());
+        assert_eq!(results[1]["error"], unsupported_type_message());
+    }
 }
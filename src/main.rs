@@ -1,9 +1,22 @@
 extern crate actix_web;
-extern crate env_logger;
 
 use actix_files::Files;
 use actix_web::{web, App, HttpServer};
-use handlers::{generate::handle_generate, home::handler_home, upload::handle_mscz_upload};
+use handlers::{
+    api::{
+        handle_capacity, handle_compare, handle_measure_svg, handle_metadata, handle_preview,
+        handle_scale_svg, handle_scales, handle_transpose_report,
+    },
+    export::{
+        handle_export_bundle, handle_export_html, handle_export_midi, handle_export_musicxml,
+        handle_export_numbered_sheet, handle_export_text,
+    },
+    generate::handle_generate,
+    home::handler_home,
+    jobs::handle_job_status,
+    metrics::handle_metrics,
+    upload::{handle_batch_upload, handle_mscx_paste, handle_mscz_upload, MAX_PASTE_SIZE},
+};
 
 mod handlers;
 mod templates;
@@ -11,8 +24,19 @@ mod utils;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Initialize the logger for capturing and displaying log messages
-    env_logger::init();
+    // Initialize the logger with the level and format configured in the environment
+    utils::logging::init_logger();
+
+    // Read the upload and template directories from the environment and make sure they are usable
+    utils::config::init_config()?;
+
+    // Load the optional scales file supplementing the built-in scales
+    utils::scales::init_custom_scales()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    // Apply the optional duration color overrides
+    templates::html::init_duration_colors()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
     // Start an Actix web server on port 8080
     HttpServer::new(|| {
@@ -21,8 +45,56 @@ async fn main() -> std::io::Result<()> {
             .route("/", web::get().to(handler_home))
             // Route for handling MSCZ file uploads, mapped to `handle_mscz_upload`
             .service(web::resource("/upload").route(web::post().to(handle_mscz_upload)))
+            // Route building the part selection from pasted MSCX XML, mapped to `handle_mscx_paste`
+            .service(
+                web::resource("/paste")
+                    .app_data(web::FormConfig::default().limit(MAX_PASTE_SIZE))
+                    .route(web::post().to(handle_mscx_paste)),
+            )
             // Route for generating content from uploaded files, mapped to `handle_generate`
             .service(web::resource("/generate").route(web::post().to(handle_generate)))
+            // Route exporting the arrangement as a MIDI file, mapped to `handle_export_midi`
+            .service(web::resource("/export/midi").route(web::post().to(handle_export_midi)))
+            // Route exporting the arrangement as a MusicXML file, mapped to `handle_export_musicxml`
+            .service(
+                web::resource("/export/musicxml").route(web::post().to(handle_export_musicxml)),
+            )
+            // Route exporting the arrangement as a plain-text tab, mapped to `handle_export_text`
+            .service(web::resource("/export/text").route(web::post().to(handle_export_text)))
+            // Route exporting the arrangement as a self-contained HTML page, mapped to `handle_export_html`
+            .service(web::resource("/export/html").route(web::post().to(handle_export_html)))
+            // Route exporting a numbered "follow the numbers" sheet as SVG, mapped to `handle_export_numbered_sheet`
+            .service(
+                web::resource("/export/numbered-sheet")
+                    .route(web::post().to(handle_export_numbered_sheet)),
+            )
+            // Route exporting every format at once as a ZIP bundle, mapped to `handle_export_bundle`
+            .service(web::resource("/export/bundle").route(web::post().to(handle_export_bundle)))
+            // Route storing several uploaded scores at once, mapped to `handle_batch_upload`
+            .service(web::resource("/api/upload/batch").route(web::post().to(handle_batch_upload)))
+            // Route for polling the status of an asynchronous upload, mapped to `handle_job_status`
+            .service(web::resource("/api/jobs/{id}").route(web::get().to(handle_job_status)))
+            // Route returning the labeled handpan layout of a scale, mapped to `handle_scale_svg`
+            .service(web::resource("/api/scale/{id}/svg").route(web::get().to(handle_scale_svg)))
+            // Route listing the available scales, optionally within one family, mapped to `handle_scales`
+            .service(web::resource("/api/scales").route(web::get().to(handle_scales)))
+            // Route comparing how two scales fit the same part, mapped to `handle_compare`
+            .service(web::resource("/api/compare").route(web::get().to(handle_compare)))
+            // Route returning the first measures of a part as JSON, mapped to `handle_preview`
+            .service(web::resource("/api/preview").route(web::get().to(handle_preview)))
+            // Route returning the handpan colored with the notes of one measure, mapped to `handle_measure_svg`
+            .service(web::resource("/api/measure/{n}/svg").route(web::get().to(handle_measure_svg)))
+            // Route describing what a transposition does to a part as JSON, mapped to `handle_transpose_report`
+            .service(
+                web::resource("/api/transpose-report")
+                    .route(web::get().to(handle_transpose_report)),
+            )
+            // Route returning the title, composer and arranger of an uploaded score, mapped to `handle_metadata`
+            .service(web::resource("/api/metadata").route(web::get().to(handle_metadata)))
+            // Route reporting the upload and generate slots in use, mapped to `handle_capacity`
+            .service(web::resource("/api/capacity").route(web::get().to(handle_capacity)))
+            // Route exposing request counters and parse timings for Prometheus, mapped to `handle_metrics`
+            .service(web::resource("/metrics").route(web::get().to(handle_metrics)))
             // Serve static files from the "static" directory with directory listing enabled
             .service(Files::new("/static", "static").show_files_listing())
     })
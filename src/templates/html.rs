@@ -1,9 +1,15 @@
-use once_cell::sync::OnceCell;
-use std::path::PathBuf;
+use crate::utils::config::template_path;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashMap;
+use std::sync::RwLock;
 use tokio::fs;
 
 static HEADER_CONTENT: OnceCell<String> = OnceCell::new();
 
+/// The effective duration colors: the default palette with the `HANDFLOW_DURATION_COLORS` overrides applied.
+static DURATION_COLORS: Lazy<RwLock<HashMap<&'static str, &'static str>>> =
+    Lazy::new(|| RwLock::new(DEFAULT_DURATION_COLORS.into_iter().collect()));
+
 /// Loads and caches the header content for the HTML pages.
 ///
 /// This function:
@@ -20,7 +26,7 @@ pub async fn load_header_content() -> String {
     if let Some(content) = HEADER_CONTENT.get() {
         content.clone()
     } else {
-        let header_path = PathBuf::from("src/html/html_tmpl.html");
+        let header_path = template_path("html_tmpl.html");
         let content = match fs::read_to_string(header_path).await {
             Ok(content) => content,
             Err(_) => {
@@ -46,9 +52,112 @@ pub fn sanitize_html(input: &str) -> String {
     htmlescape::encode_minimal(input)
 }
 
+/// The default color of each note duration.
+const DEFAULT_DURATION_COLORS: [(&str, &str); 9] = [
+    ("64th", "#B13B8E"),
+    ("32nd", "#4B348B"),
+    ("16th", "#4563AC"),
+    ("eighth", "#32CD32"),
+    ("quarter", "#DAA520"),
+    ("half", "#FF4500"),
+    ("whole", "#8B0000"),
+    ("breve", "#5C3317"),
+    ("longa", "#2F2F4F"),
+];
+
+/// Checks that a color is a hex color code (`#RGB` or `#RRGGBB`).
+fn is_hex_color(color: &str) -> bool {
+    color.strip_prefix('#').is_some_and(|hex| {
+        (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Builds the duration palette from a JSON object of overrides (e.g. `{"quarter": "#1E90FF"}`).
+///
+/// This function:
+///
+/// 1. **Starts From the Defaults**: Every duration keeps its default color unless overridden.
+/// 2. **Parses the Overrides**: Reads the JSON object mapping duration names to colors.
+/// 3. **Validates Them**: Each duration must be one `get_color_for_duration` knows, and each color a hex code.
+///
+/// # Parameters
+/// - `json`: The JSON object of overrides, as set in `HANDFLOW_DURATION_COLORS`.
+///
+/// # Returns
+/// - `Ok(HashMap)` with the effective color of every duration.
+/// - `Err(String)` describing the invalid JSON, duration or color.
+pub fn parse_duration_colors(json: &str) -> Result<HashMap<String, String>, String> {
+    let overrides: HashMap<String, String> = serde_json::from_str(json).map_err(|e| {
+        format!(
+            "Invalid {}: {}",
+            crate::utils::config::DURATION_COLORS_ENV,
+            e
+        )
+    })?;
+
+    let mut colors = default_duration_colors();
+    for (duration, color) in overrides {
+        if !colors.contains_key(&duration) {
+            return Err(format!(
+                "Unknown duration {:?} in duration colors",
+                duration
+            ));
+        }
+        if !is_hex_color(&color) {
+            return Err(format!(
+                "Invalid color {:?} for {}, expected a hex color such as #DAA520",
+                color, duration
+            ));
+        }
+        colors.insert(duration, color);
+    }
+    Ok(colors)
+}
+
+/// Returns the default palette as a map from duration to color.
+fn default_duration_colors() -> HashMap<String, String> {
+    DEFAULT_DURATION_COLORS
+        .iter()
+        .map(|&(duration, color)| (duration.to_string(), color.to_string()))
+        .collect()
+}
+
+/// Applies the optional `HANDFLOW_DURATION_COLORS` overrides once at startup.
+///
+/// # Returns
+/// - `Ok(())` if there are no overrides or they were applied.
+/// - `Err(String)` if the overrides are invalid.
+pub fn init_duration_colors() -> Result<(), String> {
+    let json = match crate::utils::config::config().duration_colors.as_ref() {
+        Some(json) => json,
+        None => return Ok(()),
+    };
+    let colors = parse_duration_colors(json)?;
+    log::info!("Using custom duration colors");
+    set_duration_colors(colors);
+    Ok(())
+}
+
+/// Replaces the effective palette with `colors`, as returned by `parse_duration_colors`.
+///
+/// The palette is only set at startup, so its few strings are leaked to hand out `&'static str` colors.
+fn set_duration_colors(colors: HashMap<String, String>) {
+    let palette = colors
+        .into_iter()
+        .map(|(duration, color)| {
+            (
+                &*Box::leak(duration.into_boxed_str()),
+                &*Box::leak(color.into_boxed_str()),
+            )
+        })
+        .collect();
+    *DURATION_COLORS.write().unwrap() = palette;
+}
+
 /// Retrieves the color associated with a given musical note duration.
 ///
-/// This function maps the duration string (e.g., "quarter", "half") to a specific color hex code.
+/// This function maps the duration string (e.g., "quarter", "half") to its color hex code in the effective palette,
+/// i.e. the defaults with the `HANDFLOW_DURATION_COLORS` overrides applied.
 ///
 /// # Parameters
 /// - `duration`: The duration of the musical note (e.g., "quarter", "half").
@@ -56,15 +165,49 @@ pub fn sanitize_html(input: &str) -> String {
 /// # Returns
 /// An `Option<&'static str>` containing the color hex code if the duration is recognized, or `None` otherwise.
 pub fn get_color_for_duration(duration: &str) -> Option<&'static str> {
-    match duration {
-        "64th" => Some("#B13B8E"),
-        "32nd" => Some("#4B348B"),
-        "16th" => Some("#4563AC"),
-        "eighth" => Some("#32CD32"),
-        "quarter" => Some("#DAA520"),
-        "half" => Some("#FF4500"),
-        "whole" => Some("#8B0000"),
-        _ => None,
+    DURATION_COLORS.read().unwrap().get(duration).copied()
+}
+
+/// The note durations that have a color, ordered from the longest to the shortest.
+const KNOWN_DURATIONS: [&str; 9] = [
+    "longa", "breve", "whole", "half", "quarter", "eighth", "16th", "32nd", "64th",
+];
+
+/// The index of `"whole"` in `KNOWN_DURATIONS`, i.e. of a duration written as a fraction `1/1` of a whole note.
+const WHOLE_INDEX: f64 = 2.0;
+
+/// Snaps a duration string to the closest duration that `get_color_for_duration` recognizes.
+///
+/// This function:
+///
+/// 1. **Keeps Known Values**: Returns recognized durations (and MuseScore's `"measure"`) unchanged.
+/// 2. **Snaps Fractions**: Reads numeric forms such as `"128th"`, `"8th"` or `"4"` as a fraction of a whole note
+///    and picks the known duration closest to it on a logarithmic scale.
+/// 3. **Handles Long Values**: Keeps `"breve"` and `"longa"`, and maps any value longer than a longa to `"longa"`.
+/// 4. **Falls Back**: Maps anything else to `"quarter"`.
+///
+/// # Parameters
+/// - `duration`: The `durationType` value read from the score.
+///
+/// # Returns
+/// A `&'static str` naming the known duration to use.
+pub fn snap_duration(duration: &str) -> &'static str {
+    if duration == "measure" {
+        return "measure";
+    }
+    if let Some(known) = KNOWN_DURATIONS.iter().find(|&&known| known == duration) {
+        return known;
+    }
+
+    // Numeric forms give the note as a fraction of a whole note (e.g. "128th" is 1/128)
+    let digits = duration.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    match digits.parse::<f64>() {
+        Ok(denominator) if denominator > 0.0 => {
+            let index = (denominator.log2().round() + WHOLE_INDEX)
+                .clamp(0.0, (KNOWN_DURATIONS.len() - 1) as f64) as usize;
+            KNOWN_DURATIONS[index]
+        }
+        _ => "quarter",
     }
 }
 
@@ -79,7 +222,9 @@ pub fn get_color_for_duration(duration: &str) -> Option<&'static str> {
 /// # Returns
 /// A `String` containing the HTML structure for the note & rest duration legend.
 pub fn generate_html_css_legend() -> String {
-    let durations = vec!["64th", "32nd", "16th", "eighth", "quarter", "half", "whole"];
+    let durations = vec![
+        "64th", "32nd", "16th", "eighth", "quarter", "half", "whole", "breve", "longa",
+    ];
     let mut legend_html = String::from(
         r#"
     <div id="legends" class="information-container">
@@ -111,3 +256,49 @@ pub fn generate_html_css_legend() -> String {
     legend_html.push_str("</div></div>\n");
     legend_html
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::svg::modify_svg_note_color;
+
+    #[test]
+    fn overridden_color_is_used_in_legend_and_notes() {
+        set_duration_colors(parse_duration_colors(r##"{"quarter": "#1E90FF"}"##).unwrap());
+        let legend = generate_html_css_legend();
+        let note = modify_svg_note_color(r#"<svg><circle id="note_1"/></svg>"#, 1, "quarter");
+        let half = get_color_for_duration("half");
+        set_duration_colors(default_duration_colors());
+
+        assert!(legend.contains("background-color:#1E90FF;"));
+        assert!(!legend.contains("#DAA520"));
+        assert!(note.contains(r#"id="note_1" style="fill:#1E90FF;"#));
+        assert_eq!(half, Some("#FF4500"));
+    }
+
+    #[test]
+    fn unknown_durations_snap_to_the_nearest_colored_one() {
+        assert_eq!(snap_duration("quarter"), "quarter");
+        assert_eq!(snap_duration("measure"), "measure");
+        assert_eq!(snap_duration("8th"), "eighth");
+        assert_eq!(snap_duration("4"), "quarter");
+        // Shorter than the shortest colored duration
+        assert_eq!(snap_duration("128th"), "64th");
+        assert_eq!(snap_duration("dotted"), "quarter");
+        assert_eq!(snap_duration("0th"), "quarter");
+    }
+
+    #[test]
+    fn breve_and_longa_are_colored_and_listed() {
+        assert_eq!(get_color_for_duration("breve"), Some("#5C3317"));
+        assert_eq!(get_color_for_duration("longa"), Some("#2F2F4F"));
+        assert_eq!(snap_duration("breve"), "breve");
+        // Numeric forms longer than a whole note: 1/0.5 is a breve, anything longer than a longa is a longa
+        assert_eq!(snap_duration("0.5"), "breve");
+        assert_eq!(snap_duration("0.01"), "longa");
+
+        let legend = generate_html_css_legend();
+        assert!(legend.find("whole").unwrap() < legend.find("breve").unwrap());
+        assert!(legend.find("breve").unwrap() < legend.find("longa").unwrap());
+    }
+}
@@ -0,0 +1,520 @@
+use crate::templates::html::sanitize_html;
+use crate::templates::parser::{ScoreMeasure, ScoreNote, ScoreTooLarge, DEFAULT_VELOCITY};
+use crate::utils::config::config;
+use crate::utils::scales::{spell_note, tpc_for_pitch_class, AccidentalPreference};
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::HashMap;
+
+/// The note durations an imported note can take, with their length in 64th notes.
+const IMPORT_DURATIONS: [(&str, u32); 7] = [
+    ("whole", 64),
+    ("half", 32),
+    ("quarter", 16),
+    ("eighth", 8),
+    ("16th", 4),
+    ("32nd", 2),
+    ("64th", 1),
+];
+
+/// The MIDI channel reserved for percussion (channel 10), which has no pitches to map onto a handpan.
+const PERCUSSION_CHANNEL: u8 = 9;
+
+/// A note read from a MIDI track, with its start and end on the 64th-note grid.
+struct ImportedNote {
+    start: u32,
+    end: u32,
+    pitch: u8,
+    velocity: u8,
+}
+
+/// Returns whether the bytes start with a Standard MIDI File header (`MThd`).
+pub fn is_midi(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"MThd")
+}
+
+/// Splits a length in 64th notes into known durations, longest first.
+///
+/// # Parameters
+/// - `length`: The length to fill, in 64th notes.
+///
+/// # Returns
+/// The duration names whose lengths add up to `length`.
+fn split_into_durations(mut length: u32) -> Vec<&'static str> {
+    let mut durations = Vec::new();
+    while length > 0 {
+        let (name, units) = IMPORT_DURATIONS
+            .iter()
+            .find(|(_, units)| *units <= length)
+            .copied()
+            .unwrap_or(("64th", 1));
+        durations.push(name);
+        length -= units;
+    }
+    durations
+}
+
+/// Builds a rest of the given duration.
+fn rest(duration: &str) -> Vec<ScoreNote> {
+    vec![ScoreNote {
+        note: "Rest".to_string(),
+        duration: duration.to_string(),
+        ..Default::default()
+    }]
+}
+
+/// Parses a Standard MIDI File into parts and measures.
+///
+/// This function:
+///
+/// 1. **Reads the File**: Parses the MIDI data with `midly`. Only metrical (ticks per beat) timing is supported.
+/// 2. **Collects Time Signatures**: Gathers the time signature meta events of every track (4/4 when there is none).
+/// 3. **Maps Tracks to Parts**: Every track with pitched notes becomes a part, named after its track name and
+///    keeping its first program change. Percussion (channel 10) is ignored.
+/// 4. **Quantizes Notes**: Pairs note-on and note-off events and snaps them to a 64th-note grid.
+/// 5. **Builds Measures**: Cuts the timeline into measures following the time signatures, groups notes starting
+///    together into chords, and fills the gaps with rests. Notes crossing a barline are shortened to it.
+///
+/// Files with more tracks or measures than the configured `part_limit` and `measure_limit`, or with events too far
+/// apart for the 64th-note grid to hold, are rejected with a `ScoreTooLarge` error.
+///
+/// # Parameters
+/// - `bytes`: The content of the `.mid` file.
+///
+/// # Returns
+/// A `Result` containing the parts as `(part_id, name, program)` and, for each part in the same order, its measures.
+#[allow(clippy::type_complexity)]
+pub fn parse_midi_to_score(
+    bytes: &[u8],
+) -> Result<
+    (Vec<(u32, String, Option<u8>)>, Vec<Vec<ScoreMeasure>>),
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let smf = Smf::parse(bytes)?;
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) if ticks.as_int() > 0 => ticks.as_int() as u64,
+        _ => return Err("Only MIDI files with metrical timing are supported".into()),
+    };
+    // Convert absolute ticks to 64th notes (16 per beat), rejecting positions past the grid's range
+    let to_grid = |tick: u64| {
+        u32::try_from(tick.saturating_mul(16).saturating_add(ticks_per_beat / 2) / ticks_per_beat)
+            .map_err(|_| ScoreTooLarge {
+                what: "64th notes",
+                limit: u32::MAX as usize,
+            })
+    };
+
+    let mut time_signatures: Vec<(u32, u32, u32)> = Vec::new();
+    let mut tracks = Vec::new();
+
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut tick: u64 = 0;
+        let mut name: Option<String> = None;
+        let mut program: Option<u8> = None;
+        let mut open_notes: HashMap<(u8, u8), Vec<(u64, u8)>> = HashMap::new();
+        let mut notes = Vec::new();
+
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::TrackName(text)) if name.is_none() => {
+                    let text = String::from_utf8_lossy(text).trim().to_string();
+                    if !text.is_empty() {
+                        name = Some(text);
+                    }
+                }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, power, _, _)) => {
+                    let denominator = 1u32.checked_shl(power as u32).unwrap_or(4);
+                    if numerator > 0 && denominator <= 64 {
+                        time_signatures.push((to_grid(tick)?, numerator as u32, denominator));
+                    }
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = channel.as_int();
+                    if channel == PERCUSSION_CHANNEL {
+                        continue;
+                    }
+                    match message {
+                        MidiMessage::ProgramChange { program: p } if program.is_none() => {
+                            program = Some(p.as_int());
+                        }
+                        MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            open_notes
+                                .entry((channel, key.as_int()))
+                                .or_default()
+                                .push((tick, vel.as_int()));
+                        }
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            let pending = open_notes.entry((channel, key.as_int())).or_default();
+                            if !pending.is_empty() {
+                                let (start, velocity) = pending.remove(0);
+                                notes.push(ImportedNote {
+                                    start: to_grid(start)?,
+                                    end: to_grid(tick)?,
+                                    pitch: key.as_int(),
+                                    velocity,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Notes still sounding at the end of the track last until then
+        for ((_, pitch), pending) in open_notes {
+            for (start, velocity) in pending {
+                notes.push(ImportedNote {
+                    start: to_grid(start)?,
+                    end: to_grid(tick)?,
+                    pitch,
+                    velocity,
+                });
+            }
+        }
+
+        if !notes.is_empty() {
+            let name = name.unwrap_or_else(|| format!("Track {}", track_index + 1));
+            tracks.push((name, program, notes));
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err("The MIDI file contains no pitched notes".into());
+    }
+    let limits = config();
+    if tracks.len() > limits.part_limit {
+        return Err(Box::new(ScoreTooLarge {
+            what: "parts",
+            limit: limits.part_limit,
+        }));
+    }
+
+    // Cut the timeline into measures, applying each time signature from the next barline
+    time_signatures.sort_by_key(|&(position, _, _)| position);
+    let song_end = tracks
+        .iter()
+        .flat_map(|(_, _, notes)| notes.iter().map(|n| n.end.max(n.start.saturating_add(1))))
+        .max()
+        .unwrap_or(0);
+    let mut measure_bounds: Vec<(u32, u32, String)> = Vec::new();
+    let (mut numerator, mut denominator) = (4, 4);
+    let mut written_signature = String::new();
+    let mut signature_index = 0;
+    let mut measure_start = 0;
+    while measure_start < song_end {
+        while signature_index < time_signatures.len()
+            && time_signatures[signature_index].0 <= measure_start
+        {
+            (_, numerator, denominator) = time_signatures[signature_index];
+            signature_index += 1;
+        }
+        let signature = format!("{}|{}", numerator, denominator);
+        let time_signature = if signature != written_signature {
+            written_signature = signature.clone();
+            signature
+        } else {
+            String::new()
+        };
+        if measure_bounds.len() >= limits.measure_limit {
+            return Err(Box::new(ScoreTooLarge {
+                what: "measures",
+                limit: limits.measure_limit,
+            }));
+        }
+        let measure_length = (numerator * 64 / denominator).max(1);
+        measure_bounds.push((
+            measure_start,
+            measure_start.saturating_add(measure_length),
+            time_signature,
+        ));
+        measure_start = measure_start.saturating_add(measure_length);
+    }
+
+    let mut parts = Vec::new();
+    let mut part_measures = Vec::new();
+
+    for (part_index, (name, program, notes)) in tracks.into_iter().enumerate() {
+        let mut measures = Vec::new();
+
+        for (measure_index, (start, end, time_signature)) in measure_bounds.iter().enumerate() {
+            let mut onsets: Vec<u32> = notes
+                .iter()
+                .map(|n| n.start)
+                .filter(|onset| onset >= start && onset < end)
+                .collect();
+            onsets.sort_unstable();
+            onsets.dedup();
+
+            let mut chords = Vec::new();
+            if onsets.is_empty() {
+                chords.push(rest("measure"));
+            }
+
+            let mut cursor = *start;
+            for (i, &onset) in onsets.iter().enumerate() {
+                if onset < cursor {
+                    continue;
+                }
+                for duration in split_into_durations(onset - cursor) {
+                    chords.push(rest(duration));
+                }
+
+                let next_onset = onsets.get(i + 1).copied().unwrap_or(*end);
+                let mut chord_notes: Vec<&ImportedNote> =
+                    notes.iter().filter(|n| n.start == onset).collect();
+                chord_notes.sort_by_key(|n| n.pitch);
+                chord_notes.dedup_by_key(|n| n.pitch);
+                let chord_end = chord_notes
+                    .iter()
+                    .map(|n| n.end)
+                    .max()
+                    .unwrap_or(onset)
+                    .min(next_onset)
+                    .max(onset.saturating_add(1));
+
+                // The chord takes the longest known duration that fits, the remainder becomes rests
+                let duration = split_into_durations(chord_end - onset)[0];
+                let units = IMPORT_DURATIONS
+                    .iter()
+                    .find(|(name, _)| *name == duration)
+                    .map(|(_, units)| *units)
+                    .unwrap_or(1);
+                chords.push(
+                    chord_notes
+                        .iter()
+                        .map(|n| {
+                            let tpc = tpc_for_pitch_class(n.pitch, AccidentalPreference::Sharps);
+                            let (note, octave) = spell_note(n.pitch, tpc);
+                            ScoreNote {
+                                pitch: n.pitch as u32,
+                                note: format!("{}{}", note, octave),
+                                duration: duration.to_string(),
+                                velocity: n.velocity,
+                                ..Default::default()
+                            }
+                        })
+                        .collect(),
+                );
+                cursor = onset.saturating_add(units);
+            }
+            if !onsets.is_empty() && cursor < *end {
+                for duration in split_into_durations(end - cursor) {
+                    chords.push(rest(duration));
+                }
+            }
+
+            measures.push(ScoreMeasure {
+                number: measure_index as u32 + 1,
+                time_signature: time_signature.clone(),
+                chords,
+                ..Default::default()
+            });
+        }
+
+        parts.push((part_index as u32 + 1, name, program));
+        part_measures.push(measures);
+    }
+
+    Ok((parts, part_measures))
+}
+
+/// Writes imported parts as a minimal MuseScore (MSCX) document.
+///
+/// The document holds one `Part`/`Staff` per imported part, so an imported MIDI file follows exactly the same
+/// part selection, generation and export path as an uploaded MuseScore file. A part's program is written to its
+/// instrument channel the way MuseScore does.
+///
+/// # Parameters
+/// - `parts`: The parts as `(staff_id, name, program)`.
+/// - `part_measures`: The measures of each part, in the same order.
+///
+/// # Returns
+/// The MSCX content as a `String`.
+pub fn score_to_mscx(
+    parts: &[(u32, String, Option<u8>)],
+    part_measures: &[Vec<ScoreMeasure>],
+) -> String {
+    let mut mscx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"3.02\">\n  <Score>\n",
+    );
+
+    for (staff_id, name, program) in parts {
+        let instrument = program
+            .map(|program| {
+                format!(
+                    "      <Instrument>\n        <Channel>\n          <program value=\"{}\"/>\n          </Channel>\n        </Instrument>\n",
+                    program
+                )
+            })
+            .unwrap_or_default();
+        mscx.push_str(&format!(
+            "    <Part>\n      <Staff id=\"{}\">\n        </Staff>\n      <trackName>{}</trackName>\n{}      </Part>\n",
+            staff_id,
+            sanitize_html(name),
+            instrument
+        ));
+    }
+
+    for ((staff_id, _, _), measures) in parts.iter().zip(part_measures) {
+        mscx.push_str(&format!("    <Staff id=\"{}\">\n", staff_id));
+        for measure in measures {
+            mscx.push_str("      <Measure>\n        <voice>\n");
+            if let Some((numerator, denominator)) = measure.time_signature.split_once('|') {
+                mscx.push_str(&format!(
+                    "          <TimeSig>\n            <sigN>{}</sigN>\n            <sigD>{}</sigD>\n            </TimeSig>\n",
+                    numerator, denominator
+                ));
+            }
+            for chord in &measure.chords {
+                let Some(first) = chord.first() else {
+                    continue;
+                };
+                if first.note == "Rest" {
+                    mscx.push_str(&format!(
+                        "          <Rest>\n            <durationType>{}</durationType>\n            </Rest>\n",
+                        first.duration
+                    ));
+                    continue;
+                }
+                mscx.push_str(&format!(
+                    "          <Chord>\n            <durationType>{}</durationType>\n",
+                    first.duration
+                ));
+                for note in chord {
+                    let pitch = note.pitch.min(127) as u8;
+                    let velocity = if note.velocity == 0 {
+                        DEFAULT_VELOCITY
+                    } else {
+                        note.velocity
+                    };
+                    mscx.push_str(&format!(
+                        "            <Note>\n              <pitch>{}</pitch>\n              <tpc>{}</tpc>\n              <velocity>{}</velocity>\n              <veloType>user</veloType>\n              </Note>\n",
+                        pitch,
+                        tpc_for_pitch_class(pitch, AccidentalPreference::Sharps),
+                        velocity
+                    ));
+                }
+                mscx.push_str("            </Chord>\n");
+            }
+            mscx.push_str("          </voice>\n        </Measure>\n");
+        }
+        mscx.push_str("      </Staff>\n");
+    }
+
+    mscx.push_str("    </Score>\n  </museScore>\n");
+    mscx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a MIDI variable-length quantity.
+    fn vlq(mut value: u32) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7F) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    /// Builds a format 1 Standard MIDI File from tracks of `(delta, event bytes)`.
+    fn smf(ticks_per_beat: u16, tracks: &[Vec<(u32, Vec<u8>)>]) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&ticks_per_beat.to_be_bytes());
+        for track in tracks {
+            let mut data = Vec::new();
+            for (delta, event) in track {
+                data.extend(vlq(*delta));
+                data.extend_from_slice(event);
+            }
+            data.extend([0x00, 0xFF, 0x2F, 0x00]);
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    /// A track name meta event.
+    fn track_name(name: &str) -> Vec<u8> {
+        let mut event = vec![0xFF, 0x03, name.len() as u8];
+        event.extend_from_slice(name.as_bytes());
+        event
+    }
+
+    /// The duration and pitches of every chord of a measure.
+    fn chords(measure: &ScoreMeasure) -> Vec<(String, Vec<u32>)> {
+        measure
+            .chords
+            .iter()
+            .map(|chord| {
+                let pitches = chord
+                    .iter()
+                    .filter(|n| n.note != "Rest")
+                    .map(|n| n.pitch)
+                    .collect();
+                (chord[0].duration.clone(), pitches)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn multi_track_file_becomes_parts_and_measures() {
+        let melody = vec![
+            (0, track_name("Melody")),
+            (0, vec![0x90, 60, 80]),
+            (480, vec![0x80, 60, 0]),
+            (0, vec![0x90, 64, 80]),
+            (480, vec![0x80, 64, 0]),
+        ];
+        let bass = vec![
+            (0, track_name("Bass")),
+            (0, vec![0xC1, 32]),
+            (0, vec![0x91, 50, 90]),
+            (960, vec![0x81, 50, 0]),
+        ];
+        let (parts, measures) = parse_midi_to_score(&smf(480, &[melody, bass])).unwrap();
+
+        assert_eq!(
+            parts,
+            vec![
+                (1, "Melody".to_string(), None),
+                (2, "Bass".to_string(), Some(32))
+            ]
+        );
+        assert_eq!(measures.len(), 2);
+        assert_eq!(measures[0].len(), 1);
+        assert_eq!(
+            chords(&measures[0][0]),
+            vec![
+                ("quarter".to_string(), vec![60]),
+                ("quarter".to_string(), vec![64]),
+                ("half".to_string(), vec![])
+            ]
+        );
+        assert_eq!(
+            chords(&measures[1][0]),
+            vec![("half".to_string(), vec![50]), ("half".to_string(), vec![])]
+        );
+    }
+
+    #[test]
+    fn positions_past_the_grid_are_rejected() {
+        // Two of the longest deltas at one tick per beat land past u32::MAX 64th notes
+        let track = vec![
+            (0, vec![0x90, 60, 80]),
+            (0x0FFF_FFFF, track_name("Far")),
+            (0x0FFF_FFFF, vec![0x80, 60, 0]),
+        ];
+        let error = parse_midi_to_score(&smf(1, &[track])).unwrap_err();
+        assert!(error.is::<ScoreTooLarge>());
+    }
+}
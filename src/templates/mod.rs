@@ -1,2 +1,3 @@
 pub mod html;
+pub mod midi_in;
 pub mod parser;
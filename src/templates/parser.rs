@@ -1,11 +1,213 @@
+use crate::templates::html::{sanitize_html, snap_duration};
+use crate::utils::config::config;
 use crate::utils::logging::log_error;
+use crate::utils::metrics;
+use crate::utils::midi::{duration_to_beats, note_beats, TICKS_PER_QUARTER};
 use crate::utils::{
-    scales::find_best_transposition_with_harmonic_context,
-    scales::midi_to_note_and_octave_with_tpc, scales::transpose_pitch_and_tpc,
+    scales::closest_field, scales::find_best_transposition_with_harmonic_context,
+    scales::hand_for_field, scales::key_name, scales::midi_to_note_and_octave_with_tpc,
+    scales::transpose_key, scales::transpose_pitch_and_tpc, scales::Hand, scales::NoteNaming,
+    scales::OutOfScaleRounding, scales::TranspositionWeights,
 };
-use quick_xml::events::Event;
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::attributes::Attributes;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::name::QName;
 use quick_xml::Reader;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A single note (or rest) parsed from the selected staff of an MSCX score.
+///
+/// Fields:
+/// - `pitch`: The transposed sounding MIDI pitch, including any ottava shift (`0` for rests).
+/// - `note`: The note name with its octave (e.g. `"C♯4"`), or `"Rest"`.
+/// - `duration`: The MuseScore `durationType` value (e.g. `"quarter"`).
+/// - `delta`: The signed distance in semitones to the closest handpan field (`0` for enharmonic spellings of a field).
+/// - `note_index`: The index of the matching handpan field when the note is in scale.
+/// - `slur_group`: The id of the outermost slur covering the note, if any.
+/// - `velocity`: The MIDI velocity (1–127) derived from the active dynamic, accents and any per-note override (`0` for rests).
+/// - `fermata`: Whether the chord or rest carries a fermata and should be held longer.
+/// - `interval`: The interval from the previous melody note (e.g. `"M3"`), set by `annotate_melody_intervals`.
+/// - `fingering`: The text of the note's `<Fingering>` (e.g. a finger or hand number), if any.
+/// - `glissando_start`: Whether a glissando slides from this note to a note of the next chord.
+/// - `glissando_end`: Whether a glissando from a note of the previous chord slides into this note.
+/// - `hand_hint`: The hand suggested for the note's field, set by `annotate_hand_hints`.
+/// - `nearest_field`: The index of the field the note is played on or measured against (its `delta` away), set by
+///   `annotate_nearest_fields`.
+/// - `lyrics`: The lyric syllables sung on the chord, one per verse, attached to its first note only.
+/// - `tick`: The onset of the chord or rest from the start of the score, in `TICKS_PER_QUARTER` ticks per quarter note.
+/// - `duration_ticks`: How long the chord or rest lasts in ticks, with dots and tuplets applied (`0` for grace notes).
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScoreNote {
+    pub pitch: u32,
+    pub note: String,
+    pub duration: String,
+    pub delta: i32,
+    pub note_index: Option<usize>,
+    pub slur_group: Option<u32>,
+    pub velocity: u8,
+    pub fermata: bool,
+    pub interval: Option<String>,
+    pub fingering: Option<String>,
+    pub glissando_start: bool,
+    pub glissando_end: bool,
+    pub hand_hint: Option<Hand>,
+    pub nearest_field: Option<usize>,
+    pub lyrics: Vec<Lyric>,
+    pub tick: u32,
+    pub duration_ticks: u32,
+}
+
+impl ScoreNote {
+    /// Returns whether the note is sounded in playback and the exports: rests never are, and with
+    /// `play_only_inscale` neither are the notes that had to be moved to a field (`delta != 0`).
+    pub fn is_played(&self, play_only_inscale: bool) -> bool {
+        self.note != "Rest" && (!play_only_inscale || self.delta == 0)
+    }
+}
+
+/// A lyric syllable sung on a chord.
+///
+/// Fields:
+/// - `verse`: The 0-based verse number.
+/// - `text`: The syllable, or an empty string on the chords a melisma extends over.
+/// - `hyphen`: Whether the word goes on with the next syllable (a `begin` or `middle` syllable).
+/// - `melisma`: Whether the chord only holds the syllable of an earlier chord.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Lyric {
+    pub verse: usize,
+    pub text: String,
+    pub hyphen: bool,
+    pub melisma: bool,
+}
+
+/// The velocity used for notes before any dynamic marking is encountered (mezzo-forte).
+pub const DEFAULT_VELOCITY: u8 = 80;
+
+/// The velocity added to a note carrying an accent or marcato articulation.
+const ACCENT_VELOCITY_BOOST: u8 = 20;
+
+/// Maps a MuseScore dynamic subtype (e.g. `"mf"`) to the velocity MuseScore itself plays it with.
+///
+/// # Parameters
+/// - `subtype`: The content of the `<subtype>` element of a `<Dynamic>`.
+///
+/// # Returns
+/// `Some(velocity)` for a known dynamic, or `None` for subtypes that don't set a level (e.g. `"other-dynamics"`).
+fn velocity_for_dynamic(subtype: &str) -> Option<u8> {
+    let velocity = match subtype {
+        "pppppp" => 1,
+        "ppppp" => 5,
+        "pppp" => 10,
+        "ppp" => 16,
+        "pp" => 33,
+        "p" => 49,
+        "mp" => 64,
+        "mf" => 80,
+        "f" | "fp" | "pf" => 96,
+        "ff" => 112,
+        "fff" => 126,
+        "ffff" | "fffff" | "ffffff" => 127,
+        "sf" | "sfz" | "sff" | "sffz" | "sfp" | "sfpp" | "rfz" | "rf" | "fz" => 112,
+        _ => return None,
+    };
+    Some(velocity)
+}
+
+/// Maps a MuseScore ottava subtype (e.g. `"8vb"`) to the shift from written to sounding pitch.
+///
+/// MuseScore leaves the subtype out for a plain `8va`, and MuseScore 2 files write it as a number (`0` to `5`).
+///
+/// # Parameters
+/// - `subtype`: The content of the `<subtype>` element of an `<Ottava>`.
+///
+/// # Returns
+/// `Some(semitones)` for a known ottava, or `None` for any other subtype.
+fn ottava_shift(subtype: &str) -> Option<i32> {
+    let shift = match subtype {
+        "8va" | "8va alta" | "0" => 12,
+        "8vb" | "8va bassa" | "1" => -12,
+        "15ma" | "15ma alta" | "2" => 24,
+        "15mb" | "15ma bassa" | "3" => -24,
+        "22ma" | "22ma alta" | "4" => 36,
+        "22mb" | "22ma bassa" | "5" => -36,
+        _ => return None,
+    };
+    Some(shift)
+}
+
+/// The words (as lowercase prefixes) that mark a written tempo change, e.g. `"rit."` or `"poco accel."`.
+const TEMPO_CHANGE_WORDS: [&str; 9] = [
+    "rit.",
+    "ritard",
+    "riten",
+    "rall",
+    "accel",
+    "allarg",
+    "stringendo",
+    "string.",
+    "smorz",
+];
+
+/// Checks whether a staff or system text marks a change of tempo (e.g. `"rit."`, `"accel."` or `"a tempo"`).
+fn is_tempo_change_text(text: &str) -> bool {
+    let text = text.to_lowercase();
+    text.contains("a tempo")
+        || text.contains("tempo primo")
+        || text.split_whitespace().any(|word| {
+            TEMPO_CHANGE_WORDS
+                .iter()
+                .any(|prefix| word.starts_with(prefix))
+        })
+}
+
+/// Maps a metronome symbol of a tempo text (e.g. `"metNoteQuarterUp"`) to the character it is drawn with.
+fn metronome_symbol(name: &str) -> &'static str {
+    match name {
+        "metNoteWhole" => "𝅝",
+        "metNoteHalfUp" => "𝅗𝅥",
+        "metNoteQuarterUp" => "♩",
+        "metNote8thUp" => "♪",
+        "metNote16thUp" => "𝅘𝅥𝅯",
+        "metAugmentationDot" => ".",
+        "space" => " ",
+        _ => "",
+    }
+}
+
+/// A tempo marking anchored to a measure, kept so playback can follow the written tempo.
+///
+/// Fields:
+/// - `text`: The text of the marking (e.g. `"♩ = 80"`, `"rit."`), or the type of a gradual tempo change without text.
+/// - `bpm`: The tempo set by the marking in quarter notes per minute, when it carries a numeric one. Gradual
+///   changes such as `"rit."` have none; their target is the next marking that does.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TempoMark {
+    pub text: String,
+    pub bpm: Option<f64>,
+}
+
+/// A measure parsed from the selected staff of an MSCX score.
+///
+/// Fields:
+/// - `number`: The 1-based measure number.
+/// - `time_signature`: The time signature introduced in this measure as `"N|D"`, or empty if unchanged.
+/// - `chords`: The chords (and rests) of the measure, each being a list of `ScoreNote`.
+/// - `annotations`: The staff and system texts (e.g. `"rit."`) anchored to this measure.
+/// - `repetition`: Set on the first measure of each pass of a practice loop, as `(pass, loop_count)`.
+/// - `tempo_marks`: The tempo markings and written tempo changes (e.g. `"rit."`) starting in this measure.
+/// - `transposition`: The shift chosen for this measure alone, in semitones, when the part is transposed per measure.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScoreMeasure {
+    pub number: u32,
+    pub time_signature: String,
+    pub chords: Vec<Vec<ScoreNote>>,
+    pub annotations: Vec<String>,
+    pub repetition: Option<(usize, usize)>,
+    pub tempo_marks: Vec<TempoMark>,
+    pub transposition: Option<i32>,
+}
 
 /// Extracts text content from the current position in the XML reader.
 ///
@@ -35,51 +237,111 @@ pub fn extract_text<R: std::io::BufRead>(
     Ok(None)
 }
 
-/// Parses metadata from an MSCX file, extracting the work title, composer, and arranger.
+/// Collects all the text content nested inside an element until its closing tag.
+///
+/// Unlike `extract_text`, this function keeps reading through nested formatting elements
+/// (e.g. `<text><b>rit.</b></text>`) and joins every text fragment it encounters.
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the element's start tag.
+/// - `element`: The name of the element whose content should be collected.
+///
+/// # Returns
+/// A `Result<String, Box<dyn std::error::Error + Send + Sync>>` containing the trimmed text.
+fn collect_element_text<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+    element: &[u8],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut text = String::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Text(e) => text.push_str(&e.unescape()?),
+            Event::End(ref e) if e.name() == QName(element) => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(text.trim().to_string())
+}
+
+/// Reads the relative `<location>` of a spanner end (its `<next>` or `<prev>` element).
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the `<next>` or `<prev>` start tag.
+/// - `element`: The name of that element.
+///
+/// # Returns
+/// The distance to the other end as a number of measures and a number of beats (both `0` when left out).
+fn read_spanner_location<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+    element: &[u8],
+) -> Result<(i32, f64), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut measures = 0;
+    let mut beats = 0.0;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if e.name() == QName(b"measures") => {
+                measures = collect_element_text(reader, b"measures")?
+                    .parse()
+                    .unwrap_or(0);
+            }
+            Event::Start(ref e) if e.name() == QName(b"fractions") => {
+                beats =
+                    fraction_to_beats(&collect_element_text(reader, b"fractions")?).unwrap_or(0.0);
+            }
+            Event::End(ref e) if e.name() == QName(element) => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok((measures, beats))
+}
+
+/// The common metaTags shown on the upload page in addition to the title, composer and arranger, with their labels.
+pub const EXTRA_META_TAGS: [(&str, &str); 4] = [
+    ("subtitle", "Subtitle"),
+    ("lyricist", "Lyricist"),
+    ("copyright", "Copyright"),
+    ("creationDate", "Created"),
+];
+
+/// Parses every `metaTag` of an MSCX file.
 ///
-/// This function reads the XML content of an MSCX file, looking for `metaTag` elements that contain
-/// the metadata. It assigns the found values to the work title, composer, and arranger, defaulting to "Unknown" if not found.
+/// This function reads the XML content of an MSCX file and collects the text of each `metaTag` element under its
+/// `name` attribute. Tags without a name or with an empty value are left out.
 ///
 /// # Parameters
 /// - `xml_content`: The XML content of the MSCX file as a `&str`.
 ///
 /// # Returns
-/// A tuple `(String, String, String)` containing the work title, composer, and arranger.
-pub fn parse_mscx_metadata(xml_content: &str) -> (String, String, String) {
+/// A `BTreeMap<String, String>` of the tag names (e.g. `"lyricist"`) and their values, sorted by name.
+pub fn parse_mscx_meta_tags(xml_content: &str) -> BTreeMap<String, String> {
     let mut reader = Reader::from_str(xml_content);
     let mut buf = Vec::new();
-
-    let mut composer = String::from("Unknown");
-    let mut arranger = String::from("Unknown");
-    let mut work_title = String::from("Unknown");
+    let mut tags = BTreeMap::new();
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) if e.name() == QName(b"metaTag") => {
-                let mut name = None;
-                let mut value = None;
-                for attr in e.attributes().filter_map(Result::ok) {
-                    match attr.key {
-                        QName(b"name") => {
-                            name = Some(attr.unescape_value().unwrap_or_default().to_string());
+                let name = e
+                    .attributes()
+                    .filter_map(Result::ok)
+                    .find(|attr| attr.key == QName(b"name"))
+                    .map(|attr| attr.unescape_value().unwrap_or_default().to_string());
+                match collect_element_text(&mut reader, b"metaTag") {
+                    Ok(value) if !value.is_empty() => {
+                        if let Some(name) = name {
+                            tags.insert(name, value);
                         }
-                        _ => {}
-                    }
-                }
-                if let Some(name) = name {
-                    match name.as_str() {
-                        "composer" => value = Some(&mut composer),
-                        "arranger" => value = Some(&mut arranger),
-                        "workTitle" => value = Some(&mut work_title),
-                        _ => {}
                     }
-                }
-                if let Some(value) = value {
-                    if let Ok(Event::Text(e)) = reader.read_event_into(&mut buf) {
-                        *value = e
-                            .unescape()
-                            .unwrap_or_else(|_| "Unknown".into())
-                            .into_owned();
+                    Ok(_) => {}
+                    Err(e) => {
+                        log_error("Error while parsing XML: {}", e);
+                        break;
                     }
                 }
             }
@@ -93,28 +355,83 @@ pub fn parse_mscx_metadata(xml_content: &str) -> (String, String, String) {
         buf.clear();
     }
 
-    (work_title, composer, arranger)
+    tags
+}
+
+/// Parses metadata from an MSCX file, extracting the work title, composer, and arranger.
+///
+/// This is a convenience over `parse_mscx_meta_tags` for the three fields shown everywhere, each defaulting to
+/// "Unknown" if not found.
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+///
+/// # Returns
+/// A tuple `(String, String, String)` containing the work title, composer, and arranger.
+pub fn parse_mscx_metadata(xml_content: &str) -> (String, String, String) {
+    metadata_from_tags(&parse_mscx_meta_tags(xml_content))
+}
+
+/// Picks the work title, composer and arranger out of already parsed metaTags, defaulting to "Unknown".
+///
+/// # Parameters
+/// - `tags`: The tags returned by `parse_mscx_meta_tags`.
+///
+/// # Returns
+/// A tuple `(String, String, String)` containing the work title, composer, and arranger.
+pub fn metadata_from_tags(tags: &BTreeMap<String, String>) -> (String, String, String) {
+    let tag = |name: &str| {
+        tags.get(name)
+            .cloned()
+            .unwrap_or_else(|| "Unknown".to_string())
+    };
+
+    (tag("workTitle"), tag("composer"), tag("arranger"))
+}
+
+/// The error returned when a score exceeds the configured `measure_limit` or `part_limit`.
+///
+/// It is kept distinct from XML errors so lenient parsing never skips past it and handlers can report it as a
+/// client error.
+#[derive(Debug)]
+pub struct ScoreTooLarge {
+    pub what: &'static str,
+    pub limit: usize,
+}
+
+impl std::fmt::Display for ScoreTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The score has more than {} {}", self.limit, self.what)
+    }
 }
 
-/// Parses the part names and their corresponding staff IDs from an MSCX file.
+impl std::error::Error for ScoreTooLarge {}
+
+/// Parses the part names, their corresponding staff IDs and their MIDI programs from an MSCX file.
 ///
 /// This function reads the XML content of an MSCX file to identify `Part` elements and their associated
 /// `Staff` IDs, returning a list of staff IDs and part names. It also differentiates between Treble and Bass
-/// for parts with multiple staffs.
+/// for parts with multiple staffs. The General MIDI program of the part's first instrument `<Channel>` (written
+/// `<program value="N"/>` by MuseScore) identifies its sound. Parsing stops with a `ScoreTooLarge` error once more
+/// than the configured `part_limit` parts have been found.
 ///
 /// # Parameters
 /// - `xml_content`: The XML content of the MSCX file as a `&str`.
 ///
 /// # Returns
-/// A `Result<Vec<(u32, String)>, Box<dyn std::error::Error + Send + Sync>>` containing the parsed parts or an error.
+/// A `Result<Vec<(u32, String, Option<u8>)>, Box<dyn std::error::Error + Send + Sync>>` containing the parsed parts
+/// as `(staff_id, name, program)` or an error. The program is `None` when the part doesn't declare one.
+#[allow(clippy::type_complexity)]
 pub fn parse_mscx_parts(
     xml_content: &str,
-) -> Result<Vec<(u32, String)>, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Vec<(u32, String, Option<u8>)>, Box<dyn std::error::Error + Send + Sync>> {
     let mut reader = Reader::from_str(xml_content);
     let mut buf = Vec::new();
     let mut parts = Vec::new();
     let mut current_part_name: Option<String> = None;
     let mut current_staff_ids: Vec<u32> = Vec::new();
+    let mut current_program: Option<u8> = None;
+    let part_limit = config().part_limit;
 
     loop {
         match reader.read_event_into(&mut buf) {
@@ -123,6 +440,7 @@ pub fn parse_mscx_parts(
                 // Reset for the new part
                 current_part_name = None;
                 current_staff_ids.clear();
+                current_program = None;
             }
             // Detect the end of a <Part> block
             Ok(Event::End(ref e)) if e.name() == QName(b"Part") => {
@@ -130,17 +448,33 @@ pub fn parse_mscx_parts(
                 if let Some(ref part_name) = current_part_name {
                     if current_staff_ids.len() == 2 {
                         // If two staff IDs, label them as Treble and Bass
-                        parts.push((current_staff_ids[0], format!("{} (Treble)", part_name)));
-                        parts.push((current_staff_ids[1], format!("{} (Bass)", part_name)));
+                        parts.push((
+                            current_staff_ids[0],
+                            format!("{} (Treble)", part_name),
+                            current_program,
+                        ));
+                        parts.push((
+                            current_staff_ids[1],
+                            format!("{} (Bass)", part_name),
+                            current_program,
+                        ));
                     } else {
                         // Otherwise, just use the part name
                         for &staff_id in &current_staff_ids {
-                            parts.push((staff_id, part_name.clone()));
+                            parts.push((staff_id, part_name.clone(), current_program));
                         }
                     }
                 }
                 current_part_name = None;
                 current_staff_ids.clear();
+                current_program = None;
+
+                if parts.len() > part_limit {
+                    return Err(Box::new(ScoreTooLarge {
+                        what: "parts",
+                        limit: part_limit,
+                    }));
+                }
             }
             // Detect the <trackName> inside a <Part> block
             Ok(Event::Start(ref e)) if e.name() == QName(b"trackName") => {
@@ -149,6 +483,14 @@ pub fn parse_mscx_parts(
                     current_part_name = Some(name);
                 }
             }
+            // Keep the first <program> of the part, written as a `value` attribute or as text
+            Ok(Event::Empty(ref e)) if e.name() == QName(b"program") => {
+                current_program = current_program.or_else(|| program_value(e.attributes(), None));
+            }
+            Ok(Event::Start(ref e)) if e.name() == QName(b"program") => {
+                let text = extract_text(&mut reader).ok().flatten();
+                current_program = current_program.or_else(|| program_value(e.attributes(), text));
+            }
             // Detect the <Staff> element
             Ok(Event::Start(ref e)) if e.name() == QName(b"Staff") => {
                 let mut staff_id = None;
@@ -178,52 +520,65 @@ pub fn parse_mscx_parts(
     Ok(parts)
 }
 
-/// Parses the musical score from an MSCX file, handling transposition and scale matching.
+/// Reads a `<program>` element's General MIDI program from its `value` attribute, or else from its text.
+fn program_value(attributes: Attributes, text: Option<String>) -> Option<u8> {
+    attributes
+        .filter_map(Result::ok)
+        .find(|a| a.key == QName(b"value"))
+        .and_then(|a| a.unescape_value().ok().map(|value| value.into_owned()))
+        .or(text)
+        .and_then(|value| value.trim().parse::<u8>().ok())
+        .filter(|program| *program < 128)
+}
+
+/// Reads the General MIDI program of the part that owns the given staff.
 ///
-/// This function processes the XML content of an MSCX file, extracting musical measures and chords,
-/// and transposing notes according to the provided settings. It also matches the notes to a specified scale.
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_id`: The staff ID selected by the user.
+///
+/// # Returns
+/// The zero-based program of the part's first instrument channel, or `None` if the part doesn't declare one.
+pub fn parse_part_program(xml_content: &str, part_id: u32) -> Option<u8> {
+    parse_mscx_parts(xml_content)
+        .ok()?
+        .into_iter()
+        .find(|(staff_id, _, _)| *staff_id == part_id)
+        .and_then(|(_, _, program)| program)
+}
+
+/// Finds the staves of percussion parts, which have no pitches to map onto a handpan.
+///
+/// A part is percussion when its instrument uses a drumset (`<useDrumset>1</useDrumset>`, written by MuseScore along
+/// with the `<Drum>` definitions) or one of its staves has a `<StaffType group="percussion">`.
 ///
 /// # Parameters
 /// - `xml_content`: The XML content of the MSCX file as a `&str`.
-/// - `part_id`: The ID of the part to be parsed.
-/// - `scale_notes`: A slice of bytes representing the notes in the handpan scale.
-/// - `auto_transpose`: A boolean indicating whether to auto-transpose notes.
-/// - `transpose_value`: The value by which to transpose the notes.
 ///
 /// # Returns
-/// A `Result` containing a vector of measures and the final transposed value, or an error.
-pub fn parse_mscx_score(
-    xml_content: &str,
-    part_id: u32,
-    scale_notes: &[u8],
-    auto_transpose: bool,
-    transpose_value: i32,
-) -> Result<
-    (
-        Vec<(
-            u32,
-            String,
-            Vec<Vec<(u32, String, String, i32, Option<usize>)>>,
-        )>,
-        i32,
-    ),
-    Box<dyn std::error::Error + Send + Sync>,
-> {
+/// The staff IDs of every percussion part, as numbered by `parse_mscx_parts`.
+pub fn percussion_staff_ids(xml_content: &str) -> HashSet<u32> {
     let mut reader = Reader::from_str(xml_content);
     let mut buf = Vec::new();
-    let mut measures = Vec::new();
-    let mut in_correct_staff = false;
-    let mut current_duration: Option<String> = None;
-    let mut current_time_signature = String::new();
-    let mut all_notes = Vec::new();
-    let mut measure_chords = Vec::new();
-    let mut current_chord_notes = Vec::new();
-    let mut final_transposed_value = transpose_value;
-    let mut mesure_id = 0;
+    let mut staff_ids = HashSet::new();
+    let mut part_staff_ids: Vec<u32> = Vec::new();
+    let mut in_part = false;
+    let mut percussion = false;
 
     loop {
-        match reader.read_event_into(&mut buf)? {
-            Event::Start(ref e) if e.name() == QName(b"Staff") => {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Part") => {
+                in_part = true;
+                percussion = false;
+                part_staff_ids.clear();
+            }
+            Ok(Event::End(ref e)) if e.name() == QName(b"Part") => {
+                if percussion {
+                    staff_ids.extend(part_staff_ids.drain(..));
+                }
+                in_part = false;
+            }
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Staff") && in_part => {
                 if let Some(id) = e
                     .attributes()
                     .filter_map(|a| a.ok())
@@ -231,234 +586,2432 @@ pub fn parse_mscx_score(
                     .and_then(|a| a.unescape_value().ok())
                     .and_then(|id_str| id_str.parse::<u32>().ok())
                 {
-                    if id == part_id {
-                        in_correct_staff = true;
-                    }
+                    part_staff_ids.push(id);
                 }
             }
-            Event::End(ref e) if e.name() == QName(b"Staff") => {
-                if in_correct_staff {
-                    in_correct_staff = false;
-                }
-            }
-            Event::Start(ref e) if e.name() == QName(b"Measure") && in_correct_staff => {
-                mesure_id += 1;
-                measures.push((mesure_id, String::new(), Vec::new()));
-                current_time_signature.clear(); // Reset the time signature for the new measure
-                measure_chords.clear(); // Reset chords for the new measure
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if e.name() == QName(b"StaffType") && in_part =>
+            {
+                percussion |= e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .any(|a| a.key == QName(b"group") && a.value.as_ref() == b"percussion");
             }
-            Event::End(ref e) if e.name() == QName(b"Measure") && in_correct_staff => {
-                if let Some((_, time_sig, chords)) = measures.last_mut() {
-                    *time_sig = current_time_signature.clone();
-                    *chords = measure_chords.clone(); // Add the collected chords to the measure
+            Ok(Event::Start(ref e)) if e.name() == QName(b"useDrumset") && in_part => {
+                if let Ok(Some(value)) = extract_text(&mut reader) {
+                    percussion |= value.trim() == "1";
                 }
             }
-            Event::Start(ref e) if e.name() == QName(b"TimeSig") && in_correct_staff => {
-                let mut sig_n = String::new();
-                let mut sig_d = String::new();
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                log_error("Error while parsing XML", e);
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
 
-                // Extract time signature numbers
-                loop {
-                    match reader.read_event_into(&mut buf)? {
-                        Event::Start(ref e) if e.name() == QName(b"sigN") => {
-                            if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
-                                sig_n = text.unescape()?.trim().to_string();
-                            }
-                        }
-                        Event::Start(ref e) if e.name() == QName(b"sigD") => {
-                            if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
-                                sig_d = text.unescape()?.trim().to_string();
-                            }
-                        }
-                        Event::End(ref e) if e.name() == QName(b"TimeSig") => {
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
+    staff_ids
+}
 
-                // Format and store the time signature
-                current_time_signature = format!("{}|{}", sig_n, sig_d);
-            }
-            Event::Start(ref e) if e.name() == QName(b"Chord") && in_correct_staff => {
-                // Extract the duration when inside a Chord
-                current_duration = None; // Reset the duration at the start of each Chord
-                current_chord_notes.clear(); // Reset notes for the current chord
+/// Reads the instrument transposition of the part that owns the given staff.
+///
+/// MuseScore stores `<pitch>` values at concert (sounding) pitch, so the values returned here are
+/// informational: they describe how the instrument's written part relates to what is matched
+/// against the handpan (e.g. `-12` for a guitar, which sounds an octave below its notation).
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_id`: The staff ID selected by the user.
+///
+/// # Returns
+/// A tuple `(i32, i32)` containing the `<transposeChromatic>` and `<transposeDiatonic>` values, or `(0, 0)` if absent.
+pub fn parse_part_transposition(xml_content: &str, part_id: u32) -> (i32, i32) {
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+    let mut in_part = false;
+    let mut part_has_staff = false;
+    let mut chromatic = 0;
+    let mut diatonic = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Part") => {
+                in_part = true;
+                part_has_staff = false;
+                chromatic = 0;
+                diatonic = 0;
             }
-            Event::End(ref e) if e.name() == QName(b"Chord") && in_correct_staff => {
-                // Add the collected notes to the chord list
-                if !current_chord_notes.is_empty() {
-                    measure_chords.push(current_chord_notes.clone());
+            Ok(Event::End(ref e)) if e.name() == QName(b"Part") => {
+                if part_has_staff {
+                    return (chromatic, diatonic);
                 }
+                in_part = false;
             }
-            Event::Start(ref e) if e.name() == QName(b"Rest") && in_correct_staff => {
-                // Extract the duration when inside a Rest
-                current_duration = None; // Reset the duration at the start of each Rest
-                current_chord_notes.clear(); // Reset notes for the current Rest
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Staff") && in_part => {
+                part_has_staff |= e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .find(|a| a.key == QName(b"id"))
+                    .and_then(|a| a.unescape_value().ok())
+                    .and_then(|id_str| id_str.parse::<u32>().ok())
+                    == Some(part_id);
             }
-            Event::End(ref e) if e.name() == QName(b"Rest") && in_correct_staff => {
-                // Add the collected notes to the Rest list
-                if let Some(ref duration) = current_duration {
-                    let note_info = (0, "Rest".to_string(), duration.clone(), 0, None);
-                    current_chord_notes.push(note_info);
-                    measure_chords.push(current_chord_notes.clone());
+            Ok(Event::Start(ref e)) if e.name() == QName(b"transposeChromatic") && in_part => {
+                if let Ok(Some(value)) = extract_text(&mut reader) {
+                    chromatic = value.trim().parse().unwrap_or(0);
                 }
             }
-            Event::Start(ref e) if e.name() == QName(b"durationType") && in_correct_staff => {
-                // Read the durationType value inside a Chord
-                if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
-                    current_duration = Some(text.unescape()?.trim().to_string());
+            Ok(Event::Start(ref e)) if e.name() == QName(b"transposeDiatonic") && in_part => {
+                if let Ok(Some(value)) = extract_text(&mut reader) {
+                    diatonic = value.trim().parse().unwrap_or(0);
                 }
             }
-            Event::Start(ref e) if e.name() == QName(b"Note") && in_correct_staff => {
-                let mut pitch: Option<u8> = None;
-                let mut tpc: Option<i8> = None;
-
-                // Extract pitch inside the Note element
-                loop {
-                    match reader.read_event_into(&mut buf)? {
-                        Event::Start(ref e) if e.name() == QName(b"pitch") => {
-                            if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
-                                pitch = text.unescape()?.trim().parse::<u8>().ok();
-                                if let Some(p) = pitch {
-                                    all_notes.push(p);
-                                }
-                            }
-                        }
-                        Event::Start(ref e) if e.name() == QName(b"tpc") => {
-                            if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
-                                tpc = text.unescape()?.trim().parse::<i8>().ok();
-                            }
-                        }
-                        Event::End(ref e) if e.name() == QName(b"Note") => {
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-
-                if let Some(pitch) = pitch {
-                    let (transposed_pitch, transposed_tpc) = if auto_transpose {
-                        let best_transpose_value =
-                            find_best_transposition_with_harmonic_context(&all_notes, scale_notes);
-                        final_transposed_value = best_transpose_value;
-                        // Call the function to transpose the pitch and TPC
-                        transpose_pitch_and_tpc(pitch, tpc, best_transpose_value).unwrap()
-                    } else {
-                        final_transposed_value = transpose_value;
-                        transpose_pitch_and_tpc(pitch, tpc, transpose_value).unwrap()
-                    };
-
-                    let (note, octave) =
-                        midi_to_note_and_octave_with_tpc(transposed_pitch, transposed_tpc);
-                    let note_with_octave = format!("{}{}", note, octave);
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                log_error("Error while parsing XML", e);
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
 
-                    // Calculate the delta and find the index of the closest note in the handpan scale
-                    let mut closest_index = None;
-                    let mut min_delta = i32::MAX;
-                    for (i, &s_note) in scale_notes.iter().enumerate() {
-                        let current_delta = transposed_pitch as i32 - s_note as i32;
-                        if current_delta.abs() < min_delta.abs() {
-                            // Compare absolute values to find the smallest difference
-                            min_delta = current_delta; // Keep the actual signed delta
-                            closest_index = Some(i);
-                        }
-                    }
+    (0, 0)
+}
 
-                    let delta = min_delta;
+/// Reads the string tuning of a tablature staff, such as a guitar or bass part written as tab.
+///
+/// A staff is tablature when it has a `<StaffType group="tablature">` (MuseScore's spelling; `group="tab"` is accepted
+/// too). Its notes still carry their sounding `<pitch>` next to the `<string>` and `<fret>` they are played on, so
+/// they are matched like any other note. The tuning, read from the `<string>` entries of the part's `<StringData>`
+/// (lowest string first), only recovers the sounding pitch of a tab note that lacks a `<pitch>`.
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_id`: The staff ID selected by the user.
+///
+/// # Returns
+/// - `Some(tuning)` with the open string pitches, lowest first, when the staff is tablature. The tuning is empty if
+///   the part has no `<StringData>`.
+/// - `None` when the staff is a standard (or percussion) staff, or is not found.
+pub fn tablature_string_pitches(xml_content: &str, part_id: u32) -> Option<Vec<u8>> {
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+    let mut in_part = false;
+    let mut in_selected_staff = false;
+    let mut in_string_data = false;
+    let mut tablature = false;
+    let mut strings = Vec::new();
 
-                    if let Some(ref duration) = current_duration {
-                        let note_info = if delta == 0 {
-                            (
-                                transposed_pitch.clone() as u32,
-                                note_with_octave,
-                                duration.clone(),
-                                delta,
-                                Some(closest_index.unwrap()),
-                            )
-                        } else {
-                            (
-                                transposed_pitch.clone() as u32,
-                                note_with_octave,
-                                duration.clone(),
-                                delta,
-                                None,
-                            )
-                        };
-                        current_chord_notes.push(note_info);
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Part") => {
+                in_part = true;
+                tablature = false;
+                strings.clear();
+            }
+            Ok(Event::End(ref e)) if e.name() == QName(b"Part") => {
+                if tablature {
+                    return Some(strings);
+                }
+                in_part = false;
+            }
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Staff") && in_part => {
+                in_selected_staff = e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .find(|a| a.key == QName(b"id"))
+                    .and_then(|a| a.unescape_value().ok())
+                    .and_then(|id_str| id_str.parse::<u32>().ok())
+                    == Some(part_id);
+            }
+            Ok(Event::End(ref e)) if e.name() == QName(b"Staff") => in_selected_staff = false,
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if e.name() == QName(b"StaffType") && in_selected_staff =>
+            {
+                tablature |= e.attributes().filter_map(|a| a.ok()).any(|a| {
+                    a.key == QName(b"group") && matches!(a.value.as_ref(), b"tablature" | b"tab")
+                });
+            }
+            Ok(Event::Start(ref e)) if e.name() == QName(b"StringData") && in_part => {
+                in_string_data = true;
+                strings.clear();
+            }
+            Ok(Event::End(ref e)) if e.name() == QName(b"StringData") => in_string_data = false,
+            Ok(Event::Start(ref e)) if e.name() == QName(b"string") && in_string_data => {
+                if let Ok(Some(value)) = extract_text(&mut reader) {
+                    if let Ok(pitch) = value.trim().parse::<u8>() {
+                        strings.push(pitch);
                     }
                 }
             }
-            Event::Eof => break,
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                log_error("Error while parsing XML", e);
+                break;
+            }
             _ => {}
         }
-        buf.clear(); // Clear the buffer at the end of the loop iteration
+        buf.clear();
     }
-    Ok((measures, final_transposed_value))
+
+    None
 }
 
-/// Generates HTML for musical measures based on parsed score data and SVG templates.
+/// Returns the sounding pitch of a tab note from its `<string>` and `<fret>`.
 ///
-/// This function:
+/// MuseScore numbers the strings of a note from the highest one (`0`), while the tuning lists them from the lowest.
 ///
-/// 1. **Initializes HTML Structure**: Sets up the initial HTML structure for the measures.
-/// 2. **Processes Measures**: Iterates over each measure, handling time signatures and chords.
-/// 3. **Formats Notes**: Applies formatting to notes, including handling transpositions and assigning colors.
-/// 4. **Adjusts SVGs**: Modifies SVG images for notes and rests based on their pitch, duration, and other attributes.
-/// 5. **Compiles HTML Output**: Assembles the complete HTML structure for all measures, incorporating formatted notes and time signatures.
+/// # Returns
+/// The MIDI pitch, or `None` if the string is not part of the tuning or the pitch is out of range.
+fn tab_note_pitch(tuning: &[u8], string: usize, fret: u8) -> Option<u8> {
+    let open = *tuning.get(tuning.len().checked_sub(string + 1)?)?;
+    open.checked_add(fret).filter(|pitch| *pitch <= 127)
+}
+
+/// Returns whether the score was saved with MuseScore's "Concert Pitch" display on (`<concertPitch>1</concertPitch>`
+/// in its style).
+fn score_in_concert_pitch(xml_content: &str) -> bool {
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"concertPitch") => {
+                return matches!(extract_text(&mut reader), Ok(Some(value)) if value.trim() == "1");
+            }
+            Ok(Event::Eof) | Err(_) => return false,
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Reads the first key signature of the selected part, as a concert key.
+///
+/// MuseScore 4 writes the concert key as `<concertKey>`, which matches the concert pitches that are parsed, while
+/// older files only write `<accidental>`: the key as displayed, which for a transposing instrument (e.g. a B♭
+/// clarinet) is its written key unless the score was saved in concert pitch. Such a key is converted to concert
+/// pitch with the part's `<transposeChromatic>`. A `<mode>minor</mode>` marks a minor key.
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_id`: The ID of the part, mapped to the score body's numbering with `resolve_body_staff_id`.
+///
+/// # Returns
+/// `Some((fifths, minor))` with the number of sharps (positive) or flats (negative), or `None` if the part has no
+/// key signature.
+pub fn parse_key_signature(xml_content: &str, part_id: u32) -> Option<(i32, bool)> {
+    let staff_id = resolve_body_staff_id(xml_content, part_id);
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+    let mut in_correct_staff = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Staff") => {
+                in_correct_staff = e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .find(|a| a.key == QName(b"id"))
+                    .and_then(|a| a.unescape_value().ok())
+                    .and_then(|id_str| id_str.parse::<u32>().ok())
+                    == Some(staff_id);
+            }
+            Ok(Event::End(ref e)) if e.name() == QName(b"Staff") => in_correct_staff = false,
+            Ok(Event::Start(ref e)) if e.name() == QName(b"KeySig") && in_correct_staff => {
+                let mut concert_key = None;
+                let mut accidental = None;
+                let mut minor = false;
+                loop {
+                    match reader.read_event_into(&mut buf) {
+                        Ok(Event::Start(ref e)) if e.name() == QName(b"concertKey") => {
+                            let text = collect_element_text(&mut reader, b"concertKey").ok()?;
+                            concert_key = text.parse::<i32>().ok();
+                        }
+                        Ok(Event::Start(ref e)) if e.name() == QName(b"accidental") => {
+                            let text = collect_element_text(&mut reader, b"accidental").ok()?;
+                            accidental = text.parse::<i32>().ok();
+                        }
+                        Ok(Event::Start(ref e)) if e.name() == QName(b"mode") => {
+                            minor = collect_element_text(&mut reader, b"mode").ok()? == "minor";
+                        }
+                        Ok(Event::End(ref e)) if e.name() == QName(b"KeySig") => break,
+                        Ok(Event::Eof) | Err(_) => return None,
+                        _ => {}
+                    }
+                }
+                let fifths = match (concert_key, accidental) {
+                    (Some(fifths), _) => fifths,
+                    (None, Some(fifths)) if !score_in_concert_pitch(xml_content) => {
+                        transpose_key(fifths, parse_part_transposition(xml_content, part_id).0)
+                    }
+                    (None, fifths) => fifths.unwrap_or(0),
+                };
+                return Some((fifths, minor));
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                log_error("Error while parsing XML", e);
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    None
+}
+
+/// The MuseScore elements marking a chord as a grace note, which takes no time of its own.
+const GRACE_MARKERS: [&[u8]; 10] = [
+    b"acciaccatura",
+    b"appoggiatura",
+    b"grace4",
+    b"grace8",
+    b"grace16",
+    b"grace32",
+    b"grace8after",
+    b"grace16after",
+    b"grace32after",
+    b"graceNote",
+];
+
+/// Works out how long a chord or rest lasts, in quarter-note beats.
+///
+/// # Parameters
+/// - `duration`: The `durationType` of the chord or rest (e.g. `"eighth"` or `"measure"`).
+/// - `dots`: The number of augmentation dots.
+/// - `tuplet_ratio`: The combined `normalNotes / actualNotes` ratio of the enclosing tuplets (`1.0` outside tuplets).
+/// - `measure_beats`: The length of the current measure, used for whole-measure rests.
+///
+/// # Returns
+/// The length in beats, or `0.0` for a duration that isn't understood.
+fn chord_length_beats(duration: &str, dots: u32, tuplet_ratio: f64, measure_beats: f64) -> f64 {
+    if duration == "measure" {
+        return measure_beats;
+    }
+    let beats = duration_to_beats(duration, "").unwrap_or(0.0);
+    beats * (2.0 - 0.5f64.powi(dots as i32)) * tuplet_ratio
+}
+
+/// Converts a position or length in quarter-note beats into MIDI ticks.
+fn beats_to_ticks(beats: f64) -> u32 {
+    (beats.max(0.0) * TICKS_PER_QUARTER as f64).round() as u32
+}
+
+/// Parses a MuseScore fraction such as `"3/4"` or `"-1/8"` (of a whole note) into quarter-note beats.
+fn fraction_to_beats(fraction: &str) -> Option<f64> {
+    let (numerator, denominator) = fraction.trim().split_once('/')?;
+    let numerator: f64 = numerator.trim().parse().ok()?;
+    let denominator: f64 = denominator.trim().parse().ok()?;
+    if denominator <= 0.0 {
+        return None;
+    }
+    Some(numerator * 4.0 / denominator)
+}
+
+/// Maps a staff id from a `<Part>` definition to the id of the matching `<Staff>` in the score body.
+///
+/// Most files number the staves the same way in both places, but some (e.g. files converted by other tools)
+/// use a different numbering for the body. This function:
+///
+/// 1. **Collects the Ids**: Reads the staff ids of the `<Part>` definitions and those of the body staves, in order.
+/// 2. **Same Numbering**: Keeps `part_staff_id` as is when both lists hold the same ids.
+/// 3. **Different Numbering**: Otherwise matches the staves by position, the n-th part staff being the n-th body
+///    staff, as MuseScore writes both in score order.
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_staff_id`: The staff id as returned by `parse_mscx_parts`.
+///
+/// # Returns
+/// The id to look for in the score body, or `part_staff_id` itself if it can't be mapped.
+pub fn resolve_body_staff_id(xml_content: &str, part_staff_id: u32) -> u32 {
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+    let mut part_ids: Vec<u32> = Vec::new();
+    let mut body_ids: Vec<u32> = Vec::new();
+    let mut in_part = false;
+    let mut score_depth = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Score") => score_depth += 1,
+            Ok(Event::End(ref e)) if e.name() == QName(b"Score") => score_depth -= 1,
+            Ok(Event::Start(ref e)) if e.name() == QName(b"Part") => in_part = true,
+            Ok(Event::End(ref e)) if e.name() == QName(b"Part") => in_part = false,
+            // Excerpts are nested scores with staves of their own, so only the main score is read
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if e.name() == QName(b"Staff") && score_depth <= 1 =>
+            {
+                if let Some(id) = e
+                    .attributes()
+                    .filter_map(|a| a.ok())
+                    .find(|a| a.key == QName(b"id"))
+                    .and_then(|a| a.unescape_value().ok())
+                    .and_then(|id_str| id_str.parse::<u32>().ok())
+                {
+                    if in_part {
+                        part_ids.push(id);
+                    } else {
+                        body_ids.push(id);
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let mut sorted_part_ids = part_ids.clone();
+    let mut sorted_body_ids = body_ids.clone();
+    sorted_part_ids.sort_unstable();
+    sorted_body_ids.sort_unstable();
+    if sorted_part_ids == sorted_body_ids {
+        return part_staff_id;
+    }
+
+    part_ids
+        .iter()
+        .position(|&id| id == part_staff_id)
+        .and_then(|index| body_ids.get(index).copied())
+        .unwrap_or(part_staff_id)
+}
+
+/// Skips the remaining events of the current measure after a parse error.
+///
+/// # Parameters
+/// - `reader`: A mutable reference to the XML `Reader` positioned inside the broken measure.
+///
+/// # Returns
+/// `true` once the closing `</Measure>` has been consumed, or `false` if EOF is reached or the reader stops making progress.
+fn skip_to_measure_end<R: std::io::BufRead>(reader: &mut Reader<R>) -> bool {
+    let mut buf = Vec::new();
+    loop {
+        let position = reader.buffer_position();
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::End(ref e)) if e.name() == QName(b"Measure") => return true,
+            Ok(Event::Eof) => return false,
+            Err(quick_xml::Error::IllFormed(IllFormedError::MismatchedEndTag {
+                ref found,
+                ..
+            })) if found == "Measure" => return true,
+            Err(_) if reader.buffer_position() == position => return false,
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Turns the measure being read into an empty placeholder after an XML error inside it, in lenient mode.
+///
+/// This function:
+///
+/// 1. **Reports the Measure**: Logs the error and adds an "Unreadable measure" parse warning.
+/// 2. **Skips the Rest**: Reads past the measure's `</Measure>` with `skip_to_measure_end`, unless the error was a
+///    mismatched `</Measure>` the reader has already consumed. End tag names are no longer checked afterwards, as
+///    the reader's element stack is out of sync and would fail every following measure.
+/// 3. **Empties the Measure**: Clears its chords and annotates it, keeping its number and the time signature read
+///    before the error.
+///
+/// # Parameters
+/// - `reader`: A mutable reference to the XML `Reader` positioned inside the broken measure.
+/// - `error`: The error the measure failed with.
+/// - `measure`: The measure being read, if any.
+/// - `measure_number`: Its number, for the warning.
+/// - `time_signature`: The time signature introduced in the measure before the error (empty if none).
+/// - `warnings`: The parse warnings collected so far.
+///
+/// # Returns
+/// - `Ok(())` once the reader is past the measure.
+/// - The original error if the end of the measure can't be found.
+fn recover_unreadable_measure<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+    error: Box<dyn std::error::Error + Send + Sync>,
+    measure: Option<&mut ScoreMeasure>,
+    measure_number: u32,
+    time_signature: &str,
+    warnings: &mut Vec<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    log_error("Skipping unparseable measure", &error);
+    push_parse_warning(
+        warnings,
+        format!("Unreadable measure {} skipped", measure_number),
+    );
+    let measure_closed = matches!(
+        error.downcast_ref::<quick_xml::Error>(),
+        Some(quick_xml::Error::IllFormed(IllFormedError::MismatchedEndTag { found, .. }))
+            if found == "Measure"
+    );
+    if !measure_closed && !skip_to_measure_end(reader) {
+        return Err(error);
+    }
+    reader.config_mut().check_end_names = false;
+
+    if let Some(measure) = measure {
+        measure.time_signature = time_signature.to_string();
+        measure.chords.clear();
+        measure.annotations = vec!["Unreadable measure skipped".to_string()];
+    }
+    Ok(())
+}
+
+/// Tells whether a step of the parse loop ran into the end of a truncated file.
+///
+/// A file cut off inside a measure either ends early (the loop reaches EOF with a measure still open) or fails on its
+/// last, unfinished tag (an error with the whole input read). Exceeding the measure limit is never a truncation.
+///
+/// # Parameters
+/// - `step`: The outcome of the step: `Ok(true)` at the end of the parse, `Ok(false)` to go on, or its error.
+/// - `in_measure`: Whether a measure of the selected staff was still open.
+/// - `at_end`: Whether the reader has consumed the whole input.
+fn is_truncated(
+    step: &Result<bool, Box<dyn std::error::Error + Send + Sync>>,
+    in_measure: bool,
+    at_end: bool,
+) -> bool {
+    match step {
+        Ok(true) => in_measure,
+        Ok(false) => false,
+        Err(e) => !e.is::<ScoreTooLarge>() && at_end,
+    }
+}
+
+/// Keeps the measures read before the cut of a truncated file, dropping the unfinished one, with a warning.
+///
+/// # Parameters
+/// - `step`: The outcome of the step that hit the cut, whose error (if any) is logged.
+/// - `measures`: The measures read so far.
+/// - `in_measure`: Whether the last measure was still open, and is dropped.
+/// - `measure_number`: The number of the last measure started.
+/// - `warnings`: The parse warnings collected so far.
+fn recover_truncated_score(
+    step: &Result<bool, Box<dyn std::error::Error + Send + Sync>>,
+    measures: &mut Vec<ScoreMeasure>,
+    in_measure: bool,
+    measure_number: u32,
+    warnings: &mut Vec<String>,
+) {
+    if let Err(e) = step {
+        log_error("Recovering truncated score", e);
+    }
+    let message = if in_measure {
+        measures.pop();
+        format!(
+            "The score is cut off in measure {}; the {} measures before it were kept",
+            measure_number,
+            measures.len()
+        )
+    } else {
+        format!("The score is cut off after measure {}", measure_number)
+    };
+    push_parse_warning(warnings, message);
+}
+
+/// Returns the length of a measure in quarter-note beats, from its start tag.
+///
+/// An irregular (e.g. pickup) measure states its actual length in a `len` attribute (e.g. `len="1/4"`); any other
+/// measure lasts as long as the time signature in effect says.
+///
+/// # Parameters
+/// - `measure`: The `<Measure>` start tag.
+/// - `time_signature`: The time signature in effect, as `"N|D"`.
+///
+/// # Returns
+/// The length in beats (4 if nothing tells), and whether it came from a `len` attribute, which a time signature
+/// written inside the measure must not override.
+fn measure_length_beats(measure: &BytesStart, time_signature: &str) -> (f64, bool) {
+    let len = measure
+        .attributes()
+        .filter_map(|a| a.ok())
+        .find(|a| a.key == QName(b"len"))
+        .and_then(|a| a.unescape_value().ok())
+        .and_then(|len| fraction_to_beats(&len));
+    let beats = len
+        .or_else(|| duration_to_beats("measure", time_signature))
+        .unwrap_or(4.0);
+    (beats, len.is_some())
+}
+
+/// Reads a `<location>` between two segments of a voice: how far it moves the voice forward (or back).
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the `<location>` start tag.
+///
+/// # Returns
+/// The move in quarter-note beats (`0` when the location has no readable `<fractions>`).
+fn read_location_beats<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut beats = 0.0;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if e.name() == QName(b"fractions") => {
+                beats +=
+                    fraction_to_beats(&collect_element_text(reader, b"fractions")?).unwrap_or(0.0);
+            }
+            Event::End(ref e) if e.name() == QName(b"location") => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(beats)
+}
+
+/// Reads a `<Tuplet>` definition: the factor it scales the length of its notes by (e.g. `2/3` for a triplet).
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the `<Tuplet>` start tag.
+///
+/// # Returns
+/// `normalNotes / actualNotes`, or `1` when either is missing or not a positive number.
+fn read_tuplet_ratio<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut normal_notes: Option<f64> = None;
+    let mut actual_notes: Option<f64> = None;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if e.name() == QName(b"normalNotes") => {
+                normal_notes = collect_element_text(reader, b"normalNotes")?.parse().ok();
+            }
+            Event::Start(ref e) if e.name() == QName(b"actualNotes") => {
+                actual_notes = collect_element_text(reader, b"actualNotes")?.parse().ok();
+            }
+            Event::End(ref e) if e.name() == QName(b"Tuplet") => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(match (normal_notes, actual_notes) {
+        (Some(normal), Some(actual)) if normal > 0.0 && actual > 0.0 => normal / actual,
+        _ => 1.0,
+    })
+}
+
+/// Checks whether a `<Spanner>` start tag is of the given type (e.g. `b"Slur"` or `b"Ottava"`).
+fn is_spanner_of_type(element: &BytesStart, spanner_type: &[u8]) -> bool {
+    element.name() == QName(b"Spanner")
+        && element
+            .attributes()
+            .filter_map(|a| a.ok())
+            .any(|a| a.key == QName(b"type") && a.value.as_ref() == spanner_type)
+}
+
+/// Checks whether an element belongs to a single voice of a staff: its chords, rests, fermatas and slurs, which are
+/// skipped whole when another voice is selected. Key and time signatures, dynamics and ottavas apply to every voice.
+fn is_voice_element(element: &BytesStart) -> bool {
+    matches!(element.name().as_ref(), b"Chord" | b"Rest" | b"Fermata")
+        || is_spanner_of_type(element, b"Slur")
+}
+
+/// Returns the transposition of the notes of the measure being read.
+///
+/// # Parameters
+/// - `measure_shifts`: The shift of each measure in the per-measure transpose mode (empty otherwise).
+/// - `measures_read`: The number of measures started so far, the last one being the one read.
+/// - `transpose_value`: The transposition of the measures without an entry in `measure_shifts`.
+fn measure_shift(measure_shifts: &[i32], measures_read: usize, transpose_value: i32) -> i32 {
+    measures_read
+        .checked_sub(1)
+        .and_then(|index| measure_shifts.get(index))
+        .copied()
+        .unwrap_or(transpose_value)
+}
+
+/// The content of a `<Note>` element, before it is transposed and matched to the scale.
+///
+/// Fields:
+/// - `pitch`: The sounding MIDI pitch written in `<pitch>`, without any ottava shift.
+/// - `string` / `fret`: The position of a tablature note, used for its pitch when `<pitch>` is missing.
+/// - `tpc`: The spelling of the note (`<tpc>`), if any.
+/// - `velocity`: The `<velocity>` of the note: an offset from the dynamic, or the velocity itself when
+///   `user_velocity` is set (`<veloType>user</veloType>`).
+/// - `fingering`: The text of its `<Fingering>`, if not empty.
+/// - `glissando_start` / `glissando_end`: Whether a glissando starts or ends on the note.
+#[derive(Debug, Default)]
+struct WrittenNote {
+    pitch: Option<u8>,
+    string: Option<usize>,
+    fret: Option<u8>,
+    tpc: Option<i8>,
+    velocity: Option<i32>,
+    user_velocity: bool,
+    fingering: Option<String>,
+    glissando_start: bool,
+    glissando_end: bool,
+}
+
+impl WrittenNote {
+    /// Returns the pitch the note sounds: its `<pitch>`, or on a tablature staff the pitch of its string and fret
+    /// when it has none, shifted by the ottava in effect.
+    ///
+    /// # Parameters
+    /// - `tablature`: The open string pitches of a tablature staff (see `tablature_string_pitches`), if it is one.
+    /// - `ottava`: The shift of the ottava in effect, in semitones.
+    fn sounding_pitch(&self, tablature: Option<&[u8]>, ottava: i32) -> Option<u8> {
+        let pitch = match (self.pitch, tablature, self.string, self.fret) {
+            (None, Some(tuning), Some(string), Some(fret)) => tab_note_pitch(tuning, string, fret),
+            (pitch, ..) => pitch,
+        };
+        pitch.map(|p| (p as i32 + ottava).clamp(0, 127) as u8)
+    }
+}
+
+/// Reads the next text of a `<Note>` child element, trimmed and parsed, or `None` if it has no parseable text.
+fn read_note_value<R: std::io::BufRead, T: std::str::FromStr>(
+    reader: &mut Reader<R>,
+) -> Result<Option<T>, Box<dyn std::error::Error + Send + Sync>> {
+    match reader.read_event_into(&mut Vec::new()) {
+        Ok(Event::Text(text)) => Ok(text.unescape()?.trim().parse::<T>().ok()),
+        _ => Ok(None),
+    }
+}
+
+/// Reads a `<Note>` element: its pitch or tablature position, spelling, velocity, fingering and glissando ends.
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the `<Note>` start tag.
+///
+/// # Returns
+/// The `WrittenNote`, once its `</Note>` has been read.
+fn read_note<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<WrittenNote, Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut note = WrittenNote::default();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if e.name() == QName(b"pitch") => {
+                note.pitch = read_note_value(reader)?;
+            }
+            Event::Start(ref e) if e.name() == QName(b"string") => {
+                note.string = read_note_value(reader)?;
+            }
+            Event::Start(ref e) if e.name() == QName(b"fret") => {
+                note.fret = read_note_value(reader)?;
+            }
+            Event::Start(ref e) if e.name() == QName(b"tpc") => {
+                note.tpc = read_note_value(reader)?;
+            }
+            Event::Start(ref e) if e.name() == QName(b"velocity") => {
+                note.velocity = read_note_value(reader)?;
+            }
+            Event::Start(ref e) if e.name() == QName(b"veloType") => {
+                note.user_velocity =
+                    read_note_value::<R, String>(reader)?.as_deref() == Some("user");
+            }
+            Event::Start(ref e) if e.name() == QName(b"Fingering") => {
+                let text = collect_element_text(reader, b"Fingering")?;
+                if !text.is_empty() {
+                    note.fingering = Some(text);
+                }
+            }
+            Event::Start(ref e) if is_spanner_of_type(e, b"Glissando") => {
+                // Like a slur, the origin note carries a <next> location and the target a <prev> one
+                let mut spanner_buf = Vec::new();
+                loop {
+                    match reader.read_event_into(&mut spanner_buf)? {
+                        Event::Start(ref e) if e.name() == QName(b"next") => {
+                            note.glissando_start = true
+                        }
+                        Event::Start(ref e) if e.name() == QName(b"prev") => {
+                            note.glissando_end = true
+                        }
+                        Event::End(ref e) if e.name() == QName(b"Spanner") => break,
+                        Event::Eof => break,
+                        _ => {}
+                    }
+                    spanner_buf.clear();
+                }
+            }
+            Event::End(ref e) if e.name() == QName(b"Note") => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(note)
+}
+
+/// Reads the numerator and denominator of a `<TimeSig>`, written in either order.
+///
+/// # Parameters
+/// - `reader`: A mutable reference to an XML `Reader` positioned just after the `<TimeSig>` start tag.
+///
+/// # Returns
+/// The `<sigN>` and `<sigD>` values; self-closing, empty, zero or non-numeric ones are left unset.
+fn read_time_signature<R: std::io::BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<(Option<u32>, Option<u32>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    let mut sig_n: Option<u32> = None;
+    let mut sig_d: Option<u32> = None;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(ref e) if e.name() == QName(b"sigN") => {
+                sig_n = collect_element_text(reader, b"sigN")?
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0);
+            }
+            Event::Start(ref e) if e.name() == QName(b"sigD") => {
+                sig_d = collect_element_text(reader, b"sigD")?
+                    .parse()
+                    .ok()
+                    .filter(|&d| d > 0);
+            }
+            Event::End(ref e) if e.name() == QName(b"TimeSig") => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok((sig_n, sig_d))
+}
+
+/// The result of `parse_mscx_score`: the measures, the transposition that was applied and the parse warnings.
+pub type ParsedScore = (Vec<ScoreMeasure>, i32, Vec<String>);
+
+/// The most warnings `parse_mscx_score` collects; further problems are only logged.
+const MAX_PARSE_WARNINGS: usize = 50;
+
+/// Merges the notes of a chord that sound the same pitch, as left behind when voices are merged in MuseScore.
+///
+/// The first of the unison notes keeps its place and spelling, and takes over what the others add: a fingering,
+/// either end of a glissando and the louder velocity.
+///
+/// # Parameters
+/// - `notes`: The notes of one chord.
+///
+/// # Returns
+/// The number of duplicate notes removed.
+fn merge_unison_notes(notes: &mut Vec<ScoreNote>) -> usize {
+    let count = notes.len();
+    let mut merged: Vec<ScoreNote> = Vec::with_capacity(count);
+    for note in notes.drain(..) {
+        match merged.iter_mut().find(|kept| kept.pitch == note.pitch) {
+            Some(kept) => {
+                kept.velocity = kept.velocity.max(note.velocity);
+                kept.glissando_start |= note.glissando_start;
+                kept.glissando_end |= note.glissando_end;
+                if kept.fingering.is_none() {
+                    kept.fingering = note.fingering;
+                }
+            }
+            None => merged.push(note),
+        }
+    }
+    *notes = merged;
+    count - notes.len()
+}
+
+/// Logs a parse problem and adds it to the warnings returned to the user, up to `MAX_PARSE_WARNINGS`.
+///
+/// # Parameters
+/// - `warnings`: The warnings collected so far.
+/// - `message`: The problem, naming the measure it was found in.
+fn push_parse_warning(warnings: &mut Vec<String>, message: String) {
+    log::warn!("{}", message);
+    match warnings.len() {
+        len if len < MAX_PARSE_WARNINGS => warnings.push(message),
+        MAX_PARSE_WARNINGS => warnings.push("Further warnings omitted".to_string()),
+        _ => {}
+    }
+}
+
+/// Parses the musical score from an MSCX file, handling transposition and scale matching.
+///
+/// This function processes the XML content of an MSCX file, extracting musical measures and chords,
+/// and transposing notes according to the provided settings. It also matches the notes to a specified scale.
+///
+/// Notes are matched at concert pitch: MuseScore stores `<pitch>` at concert pitch even for transposing instruments
+/// (their written spelling lives in `<tpc2>`, which is ignored), so a B♭ clarinet part is matched as it sounds. The
+/// instrument's transposition (see `parse_part_transposition`) is never mixed into `transpose_value`. The same goes for
+/// tablature staves (see `tablature_string_pitches`): a tab note is matched by its sounding `<pitch>`, or by the pitch
+/// of its `<string>` and `<fret>` when it has none.
+///
+/// # Parameters
+/// - `xml_content`: The XML content of the MSCX file as a `&str`.
+/// - `part_id`: The ID of the part to be parsed, as returned by `parse_mscx_parts`. It is mapped to the score body's
+///   numbering with `resolve_body_staff_id`.
+/// - `voice`: When set, only the chords, rests, slurs and fermatas of this 1-based voice of the staff are read (e.g.
+///   `1` for the melody of a two-voice piano staff); key and time signatures, dynamics and ottavas still apply
+///   whichever voice they are written in. A warning is returned if the voice never occurs.
+/// - `scale_notes`: A slice of bytes representing the notes in the handpan scale.
+/// - `auto_transpose`: When set, notes are auto-transposed, searching shifts of up to this many semitones either way
+///   with `find_best_transposition_with_harmonic_context` (`transpose_value` is then ignored). The search runs once,
+///   over the untransposed notes of the part, before the part is parsed again with the shift it found.
+/// - `weights`: The weights of the auto-transpose score (`TranspositionWeights::default()` unless a request tunes
+///   them); unused without `auto_transpose`.
+/// - `transpose_value`: The value by which to transpose the notes.
+/// - `lenient`: When `true`, an XML error inside a measure is logged and that measure is replaced by an
+///   empty placeholder instead of aborting the whole parse. Exceeding the configured `measure_limit` always aborts
+///   with a `ScoreTooLarge` error. In either mode, a truncated file (one that ends or fails at the end of its input)
+///   keeps the measures read before the cut, dropping the unfinished one, with a warning.
+/// - `measure_count`: When set, parsing stops as soon as this many measures of the part have been read, leaving the
+///   rest of the document unread. Auto-transposition then only considers the notes of those measures.
+///
+/// Every chord and rest is also placed in time: each `<voice>` of a measure restarts at the measure's first beat,
+/// `<location>` elements between segments move the position forward (or back), and dots, tuplets and grace notes
+/// are taken into account. The chords of a measure are ordered by this onset, so notes from several voices
+/// interleave as they are played. A chord holding the same sounding pitch more than once keeps a single note for it
+/// (see `merge_unison_notes`).
+///
+/// Problems that don't stop the parse (notes without a pitch or chords without a duration that are dropped, unknown
+/// durations that are snapped, unreadable measures that are skipped, a truncated file, ...) are logged and returned
+/// as warnings, so users can see why the output looks odd.
+///
+/// Every call is timed and counted in the `/metrics` parse histogram and error counter.
+///
+/// # Returns
+/// A `Result` containing a vector of measures, the final transposed value and the parse warnings, or an error.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_mscx_score(
+    xml_content: &str,
+    part_id: u32,
+    voice: Option<usize>,
+    scale_notes: &[u8],
+    auto_transpose: Option<i32>,
+    weights: TranspositionWeights,
+    transpose_value: i32,
+    lenient: bool,
+    measure_count: Option<usize>,
+) -> Result<ParsedScore, Box<dyn std::error::Error + Send + Sync>> {
+    let started = std::time::Instant::now();
+    let result = parse_mscx_score_inner(
+        xml_content,
+        part_id,
+        voice,
+        scale_notes,
+        auto_transpose,
+        weights,
+        transpose_value,
+        &[],
+        lenient,
+        measure_count,
+        config().measure_limit,
+    );
+    metrics::record_parse_duration(started.elapsed());
+    if result.is_err() {
+        metrics::record_parse_error();
+    }
+    result
+}
+
+/// Parses a part with its own shift for every measure, for the experimental per-measure transpose mode.
+///
+/// The score is read once, like `parse_mscx_score` with a manual transposition, except that the notes of the `n`th
+/// measure are transposed (and respelled) by `measure_shifts[n]`. Measures past the end of `measure_shifts` are
+/// left as written. The shifts are not recorded on the measures.
+///
+/// # Parameters
+/// - `xml_content`: The MSCX content of the score.
+/// - `part_id`: The ID of the part to parse.
+/// - `voice`: The voice to keep, or `None` for all of them.
+/// - `scale_notes`: The MIDI notes of the handpan scale.
+/// - `measure_shifts`: The shift of each measure, in semitones, e.g. from `per_measure_transpositions`.
+/// - `lenient`: Whether unparseable measures are skipped instead of failing the parse.
+///
+/// # Returns
+/// A `Result` containing the measures, `0` as the transposed value and the parse warnings, or an error.
+pub fn parse_mscx_score_per_measure(
+    xml_content: &str,
+    part_id: u32,
+    voice: Option<usize>,
+    scale_notes: &[u8],
+    measure_shifts: &[i32],
+    lenient: bool,
+) -> Result<ParsedScore, Box<dyn std::error::Error + Send + Sync>> {
+    let started = std::time::Instant::now();
+    let result = parse_mscx_score_inner(
+        xml_content,
+        part_id,
+        voice,
+        scale_notes,
+        None,
+        TranspositionWeights::default(),
+        0,
+        measure_shifts,
+        lenient,
+        None,
+        config().measure_limit,
+    );
+    metrics::record_parse_duration(started.elapsed());
+    if result.is_err() {
+        metrics::record_parse_error();
+    }
+    result
+}
+
+/// Does the work of `parse_mscx_score` and `parse_mscx_score_per_measure`, which wrap it to record the parse metrics
+/// and pass the configured `measure_limit`. A measure with an entry in `measure_shifts` is transposed by it instead
+/// of `transpose_value`.
+#[allow(clippy::too_many_arguments)]
+fn parse_mscx_score_inner(
+    xml_content: &str,
+    part_id: u32,
+    voice: Option<usize>,
+    scale_notes: &[u8],
+    auto_transpose: Option<i32>,
+    weights: TranspositionWeights,
+    transpose_value: i32,
+    measure_shifts: &[i32],
+    lenient: bool,
+    measure_count: Option<usize>,
+    measure_limit: usize,
+) -> Result<ParsedScore, Box<dyn std::error::Error + Send + Sync>> {
+    // Search the auto-transposition once for the whole part, over its untransposed notes, then parse with that shift
+    if let Some(range) = auto_transpose {
+        let (untransposed, _, _) = parse_mscx_score_inner(
+            xml_content,
+            part_id,
+            voice,
+            scale_notes,
+            None,
+            weights,
+            0,
+            &[],
+            lenient,
+            measure_count,
+            measure_limit,
+        )?;
+        let notes: Vec<u8> = untransposed
+            .iter()
+            .flat_map(|measure| measure.chords.iter().flatten())
+            .filter(|note| note.note != "Rest")
+            .map(|note| note.pitch as u8)
+            .collect();
+        let best_transpose_value =
+            find_best_transposition_with_harmonic_context(&notes, scale_notes, range, weights);
+        return parse_mscx_score_inner(
+            xml_content,
+            part_id,
+            voice,
+            scale_notes,
+            None,
+            weights,
+            best_transpose_value,
+            &[],
+            lenient,
+            measure_count,
+            measure_limit,
+        );
+    }
+    let staff_id = resolve_body_staff_id(xml_content, part_id);
+    let tablature = tablature_string_pitches(xml_content, part_id);
+    if let Some(tuning) = &tablature {
+        log::debug!(
+            "Staff {} is tablature ({} strings); notes are matched at sounding pitch",
+            part_id,
+            tuning.len()
+        );
+    }
+    // Transposed notes are respelled for the key the part is transposed to
+    let key_fifths = parse_key_signature(xml_content, part_id).map_or(0, |(fifths, _)| fifths);
+    let mut reader = Reader::from_str(xml_content);
+    let mut buf = Vec::new();
+    let mut measures = Vec::new();
+    let mut in_correct_staff = false;
+    let mut current_duration: Option<String> = None;
+    let mut current_time_signature = String::new();
+    let mut measure_chords = Vec::new();
+    let mut current_chord_notes: Vec<ScoreNote> = Vec::new();
+    let mut mesure_id = 0;
+    let mut slur_counter = 0;
+    // Each open slur keeps the location of its stop, and each pending stop the location of its start
+    let mut open_slurs: Vec<(u32, (i32, f64))> = Vec::new();
+    let mut pending_slur_stops: Vec<(i32, f64)> = Vec::new();
+    let mut staff_measure_index = 0;
+    let mut system_texts: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut system_tempo_marks: HashMap<usize, Vec<TempoMark>> = HashMap::new();
+    let mut in_measure = false;
+    let mut dynamic_velocity = DEFAULT_VELOCITY;
+    let mut chord_accent = false;
+    let mut pending_fermata = false;
+    let mut chord_fermata = false;
+    let mut raw_duration = String::new();
+    let mut chord_dots = 0;
+    let mut chord_grace = false;
+    let mut tuplet_ratios: Vec<f64> = Vec::new();
+    let mut active_time_signature = "4|4".to_string();
+    let mut measure_start_beat = 0.0;
+    let mut next_measure_beat = 0.0;
+    let mut measure_beats = 4.0;
+    let mut measure_has_len = false;
+    let mut voice_cursor = 0.0;
+    let mut voice_index = 0;
+    let mut voice_found = false;
+    let mut chord_lyrics: Vec<(Lyric, u32)> = Vec::new();
+    let mut melismas: HashMap<(usize, usize), u32> = HashMap::new();
+    let mut ottava = 0;
+    let mut warnings = Vec::new();
+
+    loop {
+        // Each event is handled in its own scope so an error can be recovered per measure
+        let step = (|| -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(ref e) if e.name() == QName(b"Staff") => {
+                    if let Some(id) = e
+                        .attributes()
+                        .filter_map(|a| a.ok())
+                        .find(|a| a.key == QName(b"id"))
+                        .and_then(|a| a.unescape_value().ok())
+                        .and_then(|id_str| id_str.parse::<u32>().ok())
+                    {
+                        if id == staff_id {
+                            in_correct_staff = true;
+                        }
+                    }
+                    staff_measure_index = 0;
+                    ottava = 0;
+                }
+                Event::End(ref e) if e.name() == QName(b"Staff") && in_correct_staff => {
+                    in_correct_staff = false;
+                }
+                Event::Start(ref e) if e.name() == QName(b"Measure") => {
+                    staff_measure_index += 1;
+                    if in_correct_staff {
+                        if measures.len() >= measure_limit {
+                            return Err(Box::new(ScoreTooLarge {
+                                what: "measures",
+                                limit: measure_limit,
+                            }));
+                        }
+                        in_measure = true;
+                        mesure_id += 1;
+                        measures.push(ScoreMeasure {
+                            number: mesure_id,
+                            ..Default::default()
+                        });
+                        current_time_signature.clear(); // Reset the time signature for the new measure
+                        measure_chords.clear(); // Reset chords for the new measure
+
+                        measure_start_beat = next_measure_beat;
+                        (measure_beats, measure_has_len) =
+                            measure_length_beats(e, &active_time_signature);
+                        next_measure_beat = measure_start_beat + measure_beats;
+                        voice_cursor = measure_start_beat;
+                        tuplet_ratios.clear();
+                        voice_index = 0;
+                    }
+                }
+                Event::End(ref e) if e.name() == QName(b"Measure") && in_correct_staff => {
+                    in_measure = false;
+                    // Voices are written one after the other, so order the chords by when they start
+                    measure_chords.sort_by_key(|chord: &Vec<ScoreNote>| {
+                        chord.first().map_or(0, |note| note.tick)
+                    });
+                    if let Some(measure) = measures.last_mut() {
+                        measure.time_signature = current_time_signature.clone();
+                        measure.chords = std::mem::take(&mut measure_chords); // Move the collected chords to the measure
+                    }
+                    // Stop early once the requested number of measures is complete
+                    if measure_count.is_some_and(|count| measures.len() >= count) {
+                        return Ok(true);
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"StaffText") && in_correct_staff => {
+                    let text = collect_element_text(&mut reader, b"StaffText")?;
+                    if let Some(measure) = measures.last_mut() {
+                        if !text.is_empty() {
+                            measure.annotations.push(text);
+                        }
+                    }
+                }
+                Event::Start(ref e)
+                    if e.name() == QName(b"SystemText") || e.name() == QName(b"systemText") =>
+                {
+                    // System text is only written to the top staff but applies to every staff
+                    let element = e.name().as_ref().to_vec();
+                    let text = collect_element_text(&mut reader, &element)?;
+                    if !text.is_empty() {
+                        system_texts
+                            .entry(staff_measure_index)
+                            .or_default()
+                            .push(text);
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Tempo") => {
+                    // Tempo markings are only written to the top staff but apply to every staff
+                    let mut bpm = None;
+                    let mut text = String::new();
+                    let mut in_text = false;
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"tempo") => {
+                                // Stored in quarter notes per second
+                                bpm = collect_element_text(&mut reader, b"tempo")?
+                                    .parse::<f64>()
+                                    .ok()
+                                    .map(|tempo| (tempo * 60.0 * 100.0).round() / 100.0);
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"text") => in_text = true,
+                            Event::End(ref e) if e.name() == QName(b"text") => in_text = false,
+                            Event::Start(ref e) if e.name() == QName(b"sym") && in_text => {
+                                let name = collect_element_text(&mut reader, b"sym")?;
+                                text.push_str(metronome_symbol(&name));
+                            }
+                            Event::Text(e) if in_text => text.push_str(&e.unescape()?),
+                            Event::End(ref e) if e.name() == QName(b"Tempo") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                    system_tempo_marks
+                        .entry(staff_measure_index)
+                        .or_default()
+                        .push(TempoMark {
+                            text: text.trim().to_string(),
+                            bpm,
+                        });
+                }
+                Event::Start(ref e) if is_spanner_of_type(e, b"GradualTempoChange") => {
+                    // A gradual tempo change (MuseScore 4) is a system spanner; its start carries a <next> location
+                    let mut text = String::new();
+                    let mut change_type = String::new();
+                    let mut starts = false;
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"beginText") => {
+                                text = collect_element_text(&mut reader, b"beginText")?;
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"tempoChangeType") => {
+                                change_type =
+                                    collect_element_text(&mut reader, b"tempoChangeType")?;
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"next") => starts = true,
+                            Event::End(ref e) if e.name() == QName(b"Spanner") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                    if starts {
+                        system_tempo_marks
+                            .entry(staff_measure_index)
+                            .or_default()
+                            .push(TempoMark {
+                                text: if text.is_empty() { change_type } else { text },
+                                bpm: None,
+                            });
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"TimeSig") && in_correct_staff => {
+                    let (sig_n, sig_d) = read_time_signature(&mut reader)?;
+
+                    // A missing number keeps the one of the time signature in effect
+                    if sig_n.is_none() && sig_d.is_none() {
+                        push_parse_warning(
+                            &mut warnings,
+                            format!(
+                                "Time signature without numbers in measure {} ignored",
+                                mesure_id
+                            ),
+                        );
+                        return Ok(false);
+                    }
+                    let (active_n, active_d) =
+                        active_time_signature.split_once('|').unwrap_or(("4", "4"));
+                    let sig_n = sig_n.map_or(active_n.to_string(), |n| n.to_string());
+                    let sig_d = sig_d.map_or(active_d.to_string(), |d| d.to_string());
+
+                    // Format and store the time signature
+                    current_time_signature = format!("{}|{}", sig_n, sig_d);
+                    active_time_signature = current_time_signature.clone();
+                    if !measure_has_len {
+                        if let Some(beats) = duration_to_beats("measure", &active_time_signature) {
+                            measure_beats = beats;
+                            next_measure_beat = measure_start_beat + measure_beats;
+                        }
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"voice") && in_correct_staff => {
+                    // Every voice starts again at the beginning of the measure
+                    voice_cursor = measure_start_beat;
+                    voice_index += 1;
+                    tuplet_ratios.clear();
+                    voice_found |= voice == Some(voice_index);
+                }
+                Event::Start(ref e)
+                    if in_correct_staff
+                        // Files without <voice> elements hold a single, first voice
+                        && voice.is_some_and(|voice| voice != voice_index.max(1))
+                        && is_voice_element(e) =>
+                {
+                    // The chords, rests and slurs of the other voices are skipped whole
+                    reader.read_to_end_into(e.name(), &mut Vec::new())?;
+                }
+                Event::Start(ref e) if e.name() == QName(b"location") && in_correct_staff => {
+                    // A location moves the voice past a gap (or back) before the next segment
+                    voice_cursor += read_location_beats(&mut reader)?;
+                }
+                Event::Start(ref e) if e.name() == QName(b"Tuplet") && in_correct_staff => {
+                    // The tuplet lasts until the matching <endTuplet/>
+                    tuplet_ratios.push(read_tuplet_ratio(&mut reader)?);
+                }
+                Event::Empty(ref e) if e.name() == QName(b"endTuplet") && in_correct_staff => {
+                    tuplet_ratios.pop();
+                }
+                Event::Empty(ref e)
+                    if in_correct_staff && GRACE_MARKERS.contains(&e.name().as_ref()) =>
+                {
+                    chord_grace = true;
+                }
+                Event::Start(ref e) if e.name() == QName(b"dots") && in_correct_staff => {
+                    let text = collect_element_text(&mut reader, b"dots")?;
+                    chord_dots = text.parse().unwrap_or(0);
+                }
+                Event::Start(ref e) if e.name() == QName(b"Chord") && in_correct_staff => {
+                    // Extract the duration when inside a Chord
+                    current_duration = None; // Reset the duration at the start of each Chord
+                    current_chord_notes.clear(); // Reset notes for the current chord
+                    chord_accent = false;
+                    chord_fermata = std::mem::take(&mut pending_fermata);
+                    chord_dots = 0;
+                    chord_grace = false;
+                    chord_lyrics.clear();
+                }
+                Event::End(ref e) if e.name() == QName(b"Chord") && in_correct_staff => {
+                    // The durationType may follow the notes, so it is only resolved once the chord is complete
+                    match current_duration {
+                        Some(ref duration) => {
+                            for note in current_chord_notes.iter_mut() {
+                                note.duration = duration.clone();
+                            }
+                        }
+                        None if !current_chord_notes.is_empty() => {
+                            push_parse_warning(
+                                &mut warnings,
+                                format!(
+                                    "Chord without a durationType in measure {} skipped",
+                                    mesure_id
+                                ),
+                            );
+                            current_chord_notes.clear();
+                        }
+                        None => {}
+                    }
+                    // Place the chord at the voice's position, then move past it (grace notes take no time)
+                    let length = if chord_grace {
+                        0.0
+                    } else {
+                        chord_length_beats(
+                            &raw_duration,
+                            chord_dots,
+                            tuplet_ratios.iter().product(),
+                            measure_beats,
+                        )
+                    };
+                    let chord_tick = beats_to_ticks(voice_cursor);
+                    for note in current_chord_notes.iter_mut() {
+                        note.tick = chord_tick;
+                        note.duration_ticks = beats_to_ticks(length);
+                    }
+                    voice_cursor += length;
+                    // Keep a single note per sounding pitch, so a field isn't colored or labeled twice
+                    let duplicates = merge_unison_notes(&mut current_chord_notes);
+                    if duplicates > 0 {
+                        log::debug!(
+                            "Merged {} unison note(s) of a chord in measure {}",
+                            duplicates,
+                            mesure_id
+                        );
+                    }
+                    // Attach the lyrics to the first note, and hold the syllables of running melismas
+                    if let Some(first) = current_chord_notes.first_mut() {
+                        let mut lyrics = Vec::new();
+                        for (lyric, span) in chord_lyrics.drain(..) {
+                            if span > 0 {
+                                melismas.insert((voice_index, lyric.verse), chord_tick + span);
+                            } else {
+                                melismas.remove(&(voice_index, lyric.verse));
+                            }
+                            lyrics.push(lyric);
+                        }
+                        melismas.retain(|_, end| *end >= chord_tick);
+                        if !chord_grace {
+                            for (&(voice, verse), _) in melismas.iter() {
+                                if voice == voice_index && !lyrics.iter().any(|l| l.verse == verse)
+                                {
+                                    lyrics.push(Lyric {
+                                        verse,
+                                        melisma: true,
+                                        ..Default::default()
+                                    });
+                                }
+                            }
+                        }
+                        lyrics.sort_by_key(|lyric| lyric.verse);
+                        first.lyrics = lyrics;
+                    }
+                    // Accents and fermatas can be written after the notes, so apply them once the chord is complete
+                    for note in current_chord_notes.iter_mut() {
+                        if chord_accent {
+                            note.velocity =
+                                note.velocity.saturating_add(ACCENT_VELOCITY_BOOST).min(127);
+                        }
+                        note.fermata = chord_fermata;
+                    }
+                    // Add the collected notes to the chord list
+                    if !current_chord_notes.is_empty() {
+                        measure_chords.push(std::mem::take(&mut current_chord_notes));
+                    }
+                    // Close the slurs ending on this chord now that its notes are grouped: a stop points back to
+                    // its start by the opposite of the distance the start points forward, and without a match
+                    // the oldest open slur is closed
+                    for (measures, beats) in pending_slur_stops.drain(..) {
+                        let index = open_slurs
+                            .iter()
+                            .position(|(_, (next_measures, next_beats))| {
+                                *next_measures == -measures && (*next_beats + beats).abs() < 1e-9
+                            })
+                            .unwrap_or(0);
+                        if index < open_slurs.len() {
+                            open_slurs.remove(index);
+                        }
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Lyrics") && in_correct_staff => {
+                    // A syllable, its verse and the length of its melisma (from this chord to the last one it holds)
+                    let mut lyric = Lyric::default();
+                    let mut span = 0;
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"no") => {
+                                let text = collect_element_text(&mut reader, b"no")?;
+                                lyric.verse = text.parse().unwrap_or(0);
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"syllabic") => {
+                                let text = collect_element_text(&mut reader, b"syllabic")?;
+                                lyric.hyphen = text == "begin" || text == "middle";
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"ticks") => {
+                                let text = collect_element_text(&mut reader, b"ticks")?;
+                                span = text.parse().unwrap_or(0);
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"ticks_f") => {
+                                let text = collect_element_text(&mut reader, b"ticks_f")?;
+                                span = fraction_to_beats(&text).map_or(0, beats_to_ticks);
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"text") => {
+                                lyric.text = collect_element_text(&mut reader, b"text")?;
+                            }
+                            Event::End(ref e) if e.name() == QName(b"Lyrics") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                    if !lyric.text.is_empty() {
+                        chord_lyrics.push((lyric, span));
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Dynamic") && in_correct_staff => {
+                    // A dynamic stays in effect for the following notes until the next one
+                    let mut subtype = String::new();
+                    let mut velocity: Option<u8> = None;
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"subtype") => {
+                                if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
+                                    subtype = text.unescape()?.trim().to_string();
+                                }
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"velocity") => {
+                                if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
+                                    velocity = text.unescape()?.trim().parse::<u8>().ok();
+                                }
+                            }
+                            Event::End(ref e) if e.name() == QName(b"Dynamic") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                    if let Some(velocity) = velocity.or_else(|| velocity_for_dynamic(&subtype)) {
+                        dynamic_velocity = velocity.clamp(1, 127);
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Articulation") && in_correct_staff => {
+                    let subtype = collect_element_text(&mut reader, b"Articulation")?;
+                    if subtype.contains("Accent") || subtype.contains("Marcato") {
+                        chord_accent = true;
+                    }
+                    // Older files write the fermata as an articulation of the chord or rest
+                    if subtype.contains("fermata") {
+                        chord_fermata = true;
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Fermata") && in_correct_staff => {
+                    // A fermata is written in the segment just before the chord or rest it holds
+                    collect_element_text(&mut reader, b"Fermata")?;
+                    pending_fermata = true;
+                }
+                Event::Start(ref e) if in_correct_staff && is_spanner_of_type(e, b"Slur") => {
+                    // A slur start carries a <next> location, a slur stop carries a <prev> one
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"next") => {
+                                slur_counter += 1;
+                                let stop = read_spanner_location(&mut reader, b"next")?;
+                                open_slurs.push((slur_counter, stop));
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"prev") => {
+                                let start = read_spanner_location(&mut reader, b"prev")?;
+                                pending_slur_stops.push(start);
+                            }
+                            Event::End(ref e) if e.name() == QName(b"Spanner") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                }
+                Event::Start(ref e) if in_correct_staff && is_spanner_of_type(e, b"Ottava") => {
+                    // The notes under an ottava are written at their pitch without the shift, so it's
+                    // applied to every note until the ottava's stop (the one with a <prev> location)
+                    let mut subtype = String::new();
+                    let mut starts = false;
+                    loop {
+                        match reader.read_event_into(&mut buf)? {
+                            Event::Start(ref e) if e.name() == QName(b"subtype") => {
+                                subtype = collect_element_text(&mut reader, b"subtype")?;
+                            }
+                            Event::Start(ref e) if e.name() == QName(b"next") => {
+                                starts = true;
+                            }
+                            Event::End(ref e) if e.name() == QName(b"Spanner") => break,
+                            Event::Eof => break,
+                            _ => {}
+                        }
+                    }
+                    ottava = if starts {
+                        if subtype.is_empty() {
+                            12
+                        } else {
+                            ottava_shift(&subtype).unwrap_or(0)
+                        }
+                    } else {
+                        0
+                    };
+                }
+                Event::Start(ref e) if e.name() == QName(b"Spanner") && in_correct_staff => {
+                    // Other spanners (hairpins, pedals, ...) carry locations that don't move the voice
+                    collect_element_text(&mut reader, b"Spanner")?;
+                }
+                Event::Start(ref e) if e.name() == QName(b"Rest") && in_correct_staff => {
+                    // Extract the duration when inside a Rest
+                    current_duration = None; // Reset the duration at the start of each Rest
+                    current_chord_notes.clear(); // Reset notes for the current Rest
+                    chord_fermata = std::mem::take(&mut pending_fermata);
+                    chord_dots = 0;
+                }
+                Event::End(ref e) if e.name() == QName(b"Rest") && in_correct_staff => {
+                    // Add the collected notes to the Rest list
+                    if let Some(ref duration) = current_duration {
+                        let length = chord_length_beats(
+                            &raw_duration,
+                            chord_dots,
+                            tuplet_ratios.iter().product(),
+                            measure_beats,
+                        );
+                        let note_info = ScoreNote {
+                            note: "Rest".to_string(),
+                            duration: duration.clone(),
+                            fermata: chord_fermata,
+                            tick: beats_to_ticks(voice_cursor),
+                            duration_ticks: beats_to_ticks(length),
+                            ..Default::default()
+                        };
+                        voice_cursor += length;
+                        current_chord_notes.push(note_info);
+                        measure_chords.push(std::mem::take(&mut current_chord_notes));
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"durationType") && in_correct_staff => {
+                    // Read the durationType value inside a Chord
+                    if let Ok(Event::Text(text)) = reader.read_event_into(&mut buf) {
+                        let duration = text.unescape()?.trim().to_string();
+                        // Snap unexpected values so every note gets a color and a rest symbol
+                        let snapped = snap_duration(&duration);
+                        if snapped != duration {
+                            push_parse_warning(
+                                &mut warnings,
+                                format!(
+                                    "Unknown duration '{}' in measure {} snapped to '{}'",
+                                    duration, mesure_id, snapped
+                                ),
+                            );
+                        }
+                        current_duration = Some(snapped.to_string());
+                        // Timing keeps the written value, as e.g. 128th notes are snapped for display only
+                        raw_duration = if duration_to_beats(&duration, "").is_some() {
+                            duration
+                        } else {
+                            snapped.to_string()
+                        };
+                    }
+                }
+                Event::Start(ref e) if e.name() == QName(b"Note") && in_correct_staff => {
+                    let written = read_note(&mut reader)?;
+                    let pitch = written.sounding_pitch(tablature.as_deref(), ottava);
+
+                    if let Some(pitch) = pitch {
+                        let shift = measure_shift(measure_shifts, measures.len(), transpose_value);
+                        let (transposed_pitch, transposed_tpc) =
+                            transpose_pitch_and_tpc(pitch, written.tpc, shift, key_fifths);
+
+                        let (note, octave) =
+                            midi_to_note_and_octave_with_tpc(transposed_pitch, transposed_tpc)
+                                .ok_or("Invalid TPC for transposed note")?;
+                        let note_with_octave = format!("{}{}", note, octave);
+
+                        // Calculate the delta and find the index of the closest note in the handpan scale
+                        let (delta, closest_index) = closest_field(
+                            transposed_pitch as i32,
+                            scale_notes,
+                            OutOfScaleRounding::Nearest,
+                        );
+
+                        // A "user" velocity replaces the dynamic, otherwise it is an offset from it
+                        let velocity = match written.velocity {
+                            Some(v) if written.user_velocity => v,
+                            Some(offset) => dynamic_velocity as i32 + offset,
+                            None => dynamic_velocity as i32,
+                        }
+                        .clamp(1, 127) as u8;
+
+                        // The duration is filled in at the end of the chord, wherever its durationType appears
+                        let note_info = ScoreNote {
+                            pitch: transposed_pitch as u32,
+                            note: note_with_octave,
+                            duration: String::new(),
+                            delta,
+                            note_index: if delta == 0 { closest_index } else { None },
+                            // Nested slurs share the phrase of the outermost one
+                            slur_group: open_slurs.first().map(|(group, _)| *group),
+                            velocity,
+                            fermata: chord_fermata,
+                            interval: None,
+                            fingering: written.fingering,
+                            glissando_start: written.glissando_start,
+                            glissando_end: written.glissando_end,
+                            hand_hint: None,
+                            nearest_field: None,
+                            lyrics: Vec::new(),
+                            tick: 0,
+                            duration_ticks: 0,
+                        };
+                        current_chord_notes.push(note_info);
+                    } else {
+                        push_parse_warning(
+                            &mut warnings,
+                            format!("Note without a pitch in measure {} dropped", mesure_id),
+                        );
+                    }
+                }
+                Event::Eof => return Ok(true),
+                _ => {}
+            }
+            Ok(false)
+        })();
+        buf.clear(); // Clear the buffer at the end of the loop iteration
+
+        // The measures read before the cut are kept in strict mode too, so a truncated download still opens
+        let at_end = reader.buffer_position() >= xml_content.len() as u64;
+        if is_truncated(&step, in_measure, at_end) {
+            recover_truncated_score(&step, &mut measures, in_measure, mesure_id, &mut warnings);
+            break;
+        }
+
+        match step {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) if lenient && in_measure && !e.is::<ScoreTooLarge>() => {
+                recover_unreadable_measure(
+                    &mut reader,
+                    e,
+                    measures.last_mut(),
+                    mesure_id,
+                    &current_time_signature,
+                    &mut warnings,
+                )?;
+                measure_chords.clear();
+                current_chord_notes.clear();
+                pending_slur_stops.clear();
+                in_measure = false;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    // Anchor the system texts and tempo markings to the selected staff's measures
+    for (index, measure) in measures.iter_mut().enumerate() {
+        if let Some(texts) = system_texts.remove(&(index + 1)) {
+            for text in texts {
+                if !measure.annotations.contains(&text) {
+                    measure.annotations.push(text);
+                }
+            }
+        }
+        if let Some(tempo_marks) = system_tempo_marks.remove(&(index + 1)) {
+            measure.tempo_marks.extend(tempo_marks);
+        }
+        // Tempo changes written as plain text (e.g. "rit.") are kept as tempo markings too
+        for annotation in &measure.annotations {
+            if is_tempo_change_text(annotation)
+                && !measure
+                    .tempo_marks
+                    .iter()
+                    .any(|mark| &mark.text == annotation)
+            {
+                measure.tempo_marks.push(TempoMark {
+                    text: annotation.clone(),
+                    bpm: None,
+                });
+            }
+        }
+    }
+
+    if let Some(voice) = voice.filter(|&voice| voice > 1 && !voice_found) {
+        push_parse_warning(
+            &mut warnings,
+            format!("The part has no voice {}, so no notes were read", voice),
+        );
+    }
+
+    Ok((measures, transpose_value, warnings))
+}
+
+/// A summary of what a transposition did to the fit of a part on the handpan.
+///
+/// Fields:
+/// - `transposition`: The applied transposition, in semitones.
+/// - `instrument_transposition`: The part's written-to-concert transposition (e.g. `-2` for a B♭ clarinet), in
+///   semitones. Notes are matched at concert pitch, so it is never part of `transposition`.
+/// - `source_key` / `target_key`: The key of the part before and after transposing (e.g. `"D minor"`), when the
+///   score has a key signature.
+/// - `note_count`: The number of notes compared, rests excluded.
+/// - `in_scale_before` / `in_scale_after`: The share of notes landing on a field of the scale before and after
+///   transposing, in percent.
+/// - `notes_fixed`: How many notes moved from out of scale to in scale.
+/// - `notes_lost`: How many notes moved from in scale to out of scale.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TransposeReport {
+    pub transposition: i32,
+    pub instrument_transposition: i32,
+    pub source_key: Option<String>,
+    pub target_key: Option<String>,
+    pub note_count: usize,
+    pub in_scale_before: f64,
+    pub in_scale_after: f64,
+    pub notes_fixed: usize,
+    pub notes_lost: usize,
+}
+
+/// Compares a part parsed without transposition to the same part transposed.
+///
+/// Both passes come from the same staff, so their notes line up one to one; rests are skipped.
+///
+/// # Parameters
+/// - `before`: The measures parsed without transposition.
+/// - `after`: The measures parsed with `transposition` applied.
+/// - `transposition`: The applied transposition, in semitones.
+/// - `key_signature`: The part's key from `parse_key_signature`, if any.
+/// - `instrument_transposition`: The part's `<transposeChromatic>` from `parse_part_transposition`.
+///
+/// # Returns
+/// The `TransposeReport` of the transposition.
+pub fn transpose_report(
+    before: &[ScoreMeasure],
+    after: &[ScoreMeasure],
+    transposition: i32,
+    key_signature: Option<(i32, bool)>,
+    instrument_transposition: i32,
+) -> TransposeReport {
+    let notes = |measures: &[ScoreMeasure]| -> Vec<bool> {
+        measures
+            .iter()
+            .flat_map(|measure| measure.chords.iter().flatten())
+            .filter(|note| note.note != "Rest")
+            .map(|note| note.delta == 0)
+            .collect()
+    };
+    let (before, after) = (notes(before), notes(after));
+    let note_count = before.len().min(after.len());
+    let percent = |count: usize| {
+        if note_count == 0 {
+            0.0
+        } else {
+            (count as f64 * 1000.0 / note_count as f64).round() / 10.0
+        }
+    };
+    let pairs = || before.iter().zip(after.iter());
+
+    TransposeReport {
+        transposition,
+        instrument_transposition,
+        source_key: key_signature.and_then(|(fifths, minor)| key_name(fifths, minor)),
+        target_key: key_signature
+            .and_then(|(fifths, minor)| key_name(transpose_key(fifths, transposition), minor)),
+        note_count,
+        in_scale_before: percent(pairs().filter(|(&was, _)| was).count()),
+        in_scale_after: percent(pairs().filter(|(_, &is)| is).count()),
+        notes_fixed: pairs().filter(|(&was, &is)| !was && is).count(),
+        notes_lost: pairs().filter(|(&was, &is)| was && !is).count(),
+    }
+}
+
+/// Counts how many times each pitch is played in the given measures, leaving rests out.
+///
+/// # Parameters
+/// - `measures`: The parsed measures of a part.
+///
+/// # Returns
+/// A `BTreeMap` from MIDI pitch to its number of occurrences, sorted from the lowest pitch.
+pub fn pitch_histogram(measures: &[ScoreMeasure]) -> BTreeMap<u8, usize> {
+    let mut histogram = BTreeMap::new();
+    for note in measures
+        .iter()
+        .flat_map(|measure| measure.chords.iter().flatten())
+        .filter(|note| note.note != "Rest")
+    {
+        *histogram.entry(note.pitch as u8).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// Lists the fields of the handpan that no note of the arrangement is played on.
+///
+/// # Parameters
+/// - `measures`: The parsed measures of a part.
+/// - `scale_len`: The number of fields of the scale the measures were parsed against.
+///
+/// # Returns
+/// The indices of the unused fields, in ascending order.
+pub fn unused_fields(measures: &[ScoreMeasure], scale_len: usize) -> Vec<usize> {
+    let played: HashSet<usize> = measures
+        .iter()
+        .flat_map(|measure| measure.chords.iter().flatten())
+        .filter_map(|note| note.note_index)
+        .collect();
+    (0..scale_len)
+        .filter(|index| !played.contains(index))
+        .collect()
+}
+
+/// Picks the best transposition of every measure on its own, for the experimental per-measure transpose mode.
+///
+/// Each measure's notes go through `find_best_transposition_with_harmonic_context`, like the whole part does in
+/// auto-transpose. A measure without notes keeps the shift of the measure before it, so rests don't bounce the part
+/// back to its written key.
+///
+/// # Parameters
+/// - `measures`: The measures of the part, parsed without transposition.
+/// - `scale_notes`: The MIDI notes of the handpan scale.
+/// - `range`: How far the search reaches either way, in semitones.
+/// - `weights`: The weights of the auto-transpose score.
+///
+/// # Returns
+/// The shift of each measure, in semitones, in the order of `measures`.
+pub fn per_measure_transpositions(
+    measures: &[ScoreMeasure],
+    scale_notes: &[u8],
+    range: i32,
+    weights: TranspositionWeights,
+) -> Vec<i32> {
+    let mut shift = 0;
+    measures
+        .iter()
+        .map(|measure| {
+            let pitches: Vec<u8> = measure
+                .chords
+                .iter()
+                .flatten()
+                .filter(|note| note.note != "Rest")
+                .map(|note| note.pitch.min(127) as u8)
+                .collect();
+            if !pitches.is_empty() {
+                shift = find_best_transposition_with_harmonic_context(
+                    &pitches,
+                    scale_notes,
+                    range,
+                    weights,
+                );
+            }
+            shift
+        })
+        .collect()
+}
+
+/// Collects the chords of the first phrase of a part, for a "follow the numbers" sheet.
+///
+/// The phrase starts at the first chord with a note on a handpan field and ends at the next rest, or after
+/// `max_steps` chords. Notes that are not in the scale are left out, and so are chords without any field.
+///
+/// # Parameters
+/// - `measures`: The parsed measures of a part.
+/// - `max_steps`: The largest number of chords to collect.
+///
+/// # Returns
+/// One entry per chord in play order, holding the `(note_idx, duration)` of each of its fields.
+pub fn first_phrase(measures: &[ScoreMeasure], max_steps: usize) -> Vec<Vec<(usize, String)>> {
+    let mut steps = Vec::new();
+    for chord in measures.iter().flat_map(|measure| &measure.chords) {
+        if steps.len() >= max_steps {
+            break;
+        }
+        if chord.iter().any(|note| note.note == "Rest") {
+            if steps.is_empty() {
+                continue;
+            }
+            break;
+        }
+        let fields: Vec<(usize, String)> = chord
+            .iter()
+            .filter_map(|note| Some((note.note_index?, note.duration.clone())))
+            .collect();
+        if !fields.is_empty() {
+            steps.push(fields);
+        }
+    }
+    steps
+}
+
+/// Lists the played note names in performance order, as a minimal memorization aid.
+///
+/// Rests are skipped, and the notes of a chord are joined with `+`.
+///
+/// # Parameters
+/// - `measures`: The parsed measures of a part.
+/// - `play_only_inscale`: When `true`, notes that are not in the scale are left out, like in playback.
+///
+/// # Returns
+/// The note names separated by `", "` (e.g. `"D4, A4+C5, F4"`).
+pub fn note_sequence(measures: &[ScoreMeasure], play_only_inscale: bool) -> String {
+    measures
+        .iter()
+        .flat_map(|measure| measure.chords.iter())
+        .filter_map(|chord| {
+            let played: Vec<&str> = chord
+                .iter()
+                .filter(|note| note.is_played(play_only_inscale))
+                .map(|note| note.note.as_str())
+                .collect();
+            (!played.is_empty()).then(|| played.join("+"))
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// The short names of the simple intervals, indexed by their size in semitones.
+const INTERVAL_NAMES: [(&str, u32); 12] = [
+    ("P", 1),
+    ("m", 2),
+    ("M", 2),
+    ("m", 3),
+    ("M", 3),
+    ("P", 4),
+    ("TT", 0),
+    ("P", 5),
+    ("m", 6),
+    ("M", 6),
+    ("m", 7),
+    ("M", 7),
+];
+
+/// Names the interval between two pitches.
+///
+/// Simple intervals use their usual short names (`"m3"`, `"P5"`, `"TT"` for the tritone). Intervals of an octave or
+/// more keep their quality with a compound number (`"P8"`, `"M10"`), and descending intervals are prefixed with `↓`.
+///
+/// # Parameters
+/// - `semitones`: The signed distance from the previous pitch to the current one.
+///
+/// # Returns
+/// The interval name as a `String`.
+pub fn interval_name(semitones: i32) -> String {
+    let size = semitones.unsigned_abs();
+    let octaves = size / 12;
+    let (quality, number) = INTERVAL_NAMES[(size % 12) as usize];
+    let name = match (quality, size % 12) {
+        ("TT", _) => "TT".to_string(),
+        // A whole number of octaves is a perfect octave, fifteenth, ...
+        (_, 0) if octaves > 0 => format!("P{}", 1 + 7 * octaves),
+        _ => format!("{}{}", quality, number + 7 * octaves),
+    };
+    if semitones < 0 {
+        format!("↓{}", name)
+    } else {
+        name
+    }
+}
+
+/// Labels every melody note with the interval from the previous one.
+///
+/// This function:
+///
+/// 1. **Finds the Melody**: Takes the highest pitch of each chord as the top voice.
+/// 2. **Skips Rests**: Rests neither get a label nor break the line, so the note after a rest is compared with the
+///    last note before it.
+/// 3. **Names Intervals**: Stores the `interval_name` of the distance on the top note of the chord. The first note of
+///    the score has no previous note and is left unlabeled.
+///
+/// # Parameters
+/// - `measures`: The parsed measures, annotated in place.
+pub fn annotate_melody_intervals(measures: &mut [ScoreMeasure]) {
+    let mut previous_pitch: Option<u32> = None;
+
+    for chord in measures
+        .iter_mut()
+        .flat_map(|measure| measure.chords.iter_mut())
+    {
+        let Some(top_note) = chord
+            .iter_mut()
+            .filter(|note| note.note != "Rest")
+            .max_by_key(|note| note.pitch)
+        else {
+            continue;
+        };
+
+        if let Some(previous) = previous_pitch {
+            top_note.interval = Some(interval_name(top_note.pitch as i32 - previous as i32));
+        }
+        previous_pitch = Some(top_note.pitch);
+    }
+}
+
+/// The note values a chord extended by `absorb_short_rests` can be displayed with, from the longest to the shortest.
+const ABSORBED_DURATIONS: [&str; 9] = [
+    "longa", "breve", "whole", "half", "quarter", "eighth", "16th", "32nd", "64th",
+];
+
+/// Absorbs short rests into the chord before them, for a legato practice view.
+///
+/// This function:
+///
+/// 1. **Finds Short Rests**: Looks for rests lasting less than `threshold_beats`.
+/// 2. **Extends the Chord Before**: Drops each of them and adds its length to the notes of the chord right before it
+///    in the same measure. The displayed duration becomes the longest note value fitting the merged length.
+/// 3. **Keeps the Rest**: A rest at the start of a measure, or following a rest that was kept, has no note to extend
+///    and stays in place.
+///
+/// # Parameters
+/// - `measures`: The parsed measures, updated in place.
+/// - `threshold_beats`: The length in quarter-note beats below which a rest is absorbed.
+pub fn absorb_short_rests(measures: &mut [ScoreMeasure], threshold_beats: f64) {
+    let threshold_ticks = beats_to_ticks(threshold_beats);
+
+    for measure in measures.iter_mut() {
+        let mut kept: Vec<Vec<ScoreNote>> = Vec::with_capacity(measure.chords.len());
+        let mut previous_chord: Option<usize> = None;
+
+        for chord in measure.chords.drain(..) {
+            let rest_length = chord
+                .first()
+                .filter(|note| note.note == "Rest")
+                .map(|note| note.duration_ticks);
+            if let Some(length) = rest_length {
+                if let Some(index) = previous_chord.filter(|_| length < threshold_ticks) {
+                    for note in kept[index].iter_mut() {
+                        note.duration_ticks += length;
+                        note.duration = ABSORBED_DURATIONS
+                            .iter()
+                            .find(|name| {
+                                duration_to_beats(name, "").map(beats_to_ticks)
+                                    <= Some(note.duration_ticks)
+                            })
+                            .unwrap_or(&"64th")
+                            .to_string();
+                    }
+                    continue;
+                }
+                previous_chord = None;
+            } else if chord.iter().any(|note| note.duration_ticks > 0) {
+                // Grace notes take no time, so they can't be held over a rest
+                previous_chord = Some(kept.len());
+            }
+            kept.push(chord);
+        }
+
+        measure.chords = kept;
+    }
+}
+
+/// Maps every out-of-scale note to the field below or above it, as chosen by `rounding`.
+///
+/// `parse_mscx_score` always maps a note to its nearest field; this pass recomputes the delta of the notes that are
+/// not on the handpan with `closest_field`. In-scale notes and rests are left untouched.
+///
+/// # Parameters
+/// - `measures`: The parsed measures, updated in place.
+/// - `scale_notes`: The MIDI notes of the scale the measures were parsed against.
+/// - `rounding`: Which field an out-of-scale note goes to; `Nearest` leaves the measures unchanged.
+pub fn round_out_of_scale_notes(
+    measures: &mut [ScoreMeasure],
+    scale_notes: &[u8],
+    rounding: OutOfScaleRounding,
+) {
+    if rounding == OutOfScaleRounding::Nearest {
+        return;
+    }
+    for note in measures
+        .iter_mut()
+        .flat_map(|measure| measure.chords.iter_mut())
+        .flatten()
+        .filter(|note| note.note != "Rest" && note.delta != 0)
+    {
+        note.delta = closest_field(note.pitch as i32, scale_notes, rounding).0;
+    }
+}
+
+/// Tags every in-scale note with the hand suggested for its field.
+///
+/// The hand comes from `hand_for_field`, based on where the field sits on the layout relative to the ding.
+/// Out-of-scale notes and rests are left untagged.
+///
+/// # Parameters
+/// - `measures`: The parsed measures, annotated in place.
+/// - `note_count`: The number of notes of the scale the measures were parsed against.
+/// - `mirror`: Whether the layout is mirrored (see `mirror_svg`), which swaps the left and right hands.
+pub fn annotate_hand_hints(measures: &mut [ScoreMeasure], note_count: usize, mirror: bool) {
+    for note in measures
+        .iter_mut()
+        .flat_map(|measure| measure.chords.iter_mut())
+        .flatten()
+    {
+        note.hand_hint = note.note_index.map(|index| {
+            let hand = hand_for_field(note_count, index);
+            if mirror {
+                hand.mirrored()
+            } else {
+                hand
+            }
+        });
+    }
+}
+
+/// Tags every note with the index of the field it is played on or measured against, for field-number labels.
+///
+/// The field is the scale note `delta` semitones away from the note's pitch, so it follows the rounding applied by
+/// `round_out_of_scale_notes`. Rests are left untagged.
+///
+/// # Parameters
+/// - `measures`: The parsed measures, annotated in place.
+/// - `scale_notes`: The MIDI notes of the scale the measures were parsed against.
+pub fn annotate_nearest_fields(measures: &mut [ScoreMeasure], scale_notes: &[u8]) {
+    for note in measures
+        .iter_mut()
+        .flat_map(|measure| measure.chords.iter_mut())
+        .flatten()
+        .filter(|note| note.note != "Rest")
+    {
+        let field_pitch = note.pitch as i32 - note.delta;
+        note.nearest_field = note.note_index.or_else(|| {
+            scale_notes
+                .iter()
+                .position(|&field| field as i32 == field_pitch)
+        });
+    }
+}
+
+/// Formats a position or length in beats for a `data-` attribute, rounded to a thousandth of a beat.
+fn format_beats(beats: f64) -> String {
+    ((beats * 1000.0).round() / 1000.0).to_string()
+}
+
+/// The duration every note is shown with in the pitch map of `generate_measures_html`.
+const PITCH_MAP_DURATION: &str = "quarter";
+
+/// How `generate_measures_html` lays out each measure.
+///
+/// - **`Stacked`**: The default layout; the header, annotations and notes of a measure are stacked in a column,
+///   and time signature changes get a cell of their own.
+/// - **`Compact`**: Each measure is a single row holding its number, time signature, annotations and notes inline,
+///   for dense display on wide screens.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MeasureLayout {
+    #[default]
+    Stacked,
+    Compact,
+}
+
+impl MeasureLayout {
+    /// Parses a form value (`"stacked"` or `"compact"`).
+    ///
+    /// # Returns
+    /// The matching layout, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "stacked" => Some(MeasureLayout::Stacked),
+            "compact" => Some(MeasureLayout::Compact),
+            _ => None,
+        }
+    }
+}
+
+/// What the label under each note of `generate_measures_html` shows.
+///
+/// - **`Name`**: The default; the note name, in the requested `NoteNaming`.
+/// - **`FieldNumber`**: The number of the handpan field the note is played on (the ding is `0`), as many tutorials
+///   refer to fields; an out-of-scale note shows its nearest field followed by its delta.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LabelStyle {
+    #[default]
+    Name,
+    FieldNumber,
+}
+
+impl LabelStyle {
+    /// Parses a form value (`"name"` or `"field_number"`).
+    ///
+    /// # Returns
+    /// The matching label style, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(LabelStyle::Name),
+            "field_number" => Some(LabelStyle::FieldNumber),
+            _ => None,
+        }
+    }
+}
+
+/// Generates HTML for musical measures based on parsed score data and SVG templates.
+///
+/// This function:
+///
+/// 1. **Initializes HTML Structure**: Sets up the initial HTML structure for the measures.
+/// 2. **Processes Measures**: Iterates over each measure, handling time signatures and chords.
+/// 3. **Formats Notes**: Applies formatting to notes, including handling transpositions and assigning colors.
+/// 4. **Adjusts SVGs**: Modifies SVG images for notes and rests based on their pitch, duration, and other attributes.
+/// 5. **Compiles HTML Output**: Assembles the complete HTML structure for all measures, incorporating formatted notes and time signatures.
+///
+/// With `MeasureLayout::Compact`, every measure is rendered as one `measure-row` instead, with the time signature
+/// and annotations placed inline before the notes. A measure starting a pass of a practice loop is preceded by a
+/// `repetition-header`. Lyrics are written under the note label, one line per verse.
+///
+/// Without `show_rhythm`, a pitch map is rendered: every note gets the duration `PITCH_MAP_DURATION` (and so the same
+/// color), and the time signatures and rests are left out.
+///
+/// Every note cell carries its place on the timeline as `data-start` and `data-duration`, in quarter-note beats from
+/// the first rendered measure (see `note_beats`), so a script can move a play cursor along the notes. Hidden rests
+/// still take their time, and the pitch map keeps the real rhythm there.
 ///
 /// # Parameters
-/// - `measures`: A vector of tuples containing measure data `(measure_num, time_signature, chords)`.
+/// - `measures`: The `ScoreMeasure`s to render, with their measure number, time signature, chords and annotations.
 /// - `buffer_svg`: A reference to the SVG template to be used for notes.
 /// - `play_only_inscale`: A boolean flag indicating whether to display only in-scale notes.
+/// - `show_rests`: When `false`, rest cells are left out while the measures themselves are kept.
+/// - `layout`: Whether measures are stacked (default) or rendered as compact rows.
+/// - `show_rhythm`: When `false`, renders the rhythm-free pitch map described above.
+/// - `note_naming`: The names the note labels are displayed with (letters, solfège or fixed-do).
+/// - `label_style`: Whether the note labels show the note names or the field numbers. Field numbers come from
+///   `annotate_nearest_fields`; a note that wasn't annotated keeps its name.
 ///
 /// # Returns
 /// A `String` containing the generated HTML for the measures.
+#[allow(clippy::too_many_arguments)]
 pub fn generate_measures_html(
-    measures: Vec<(
-        u32,
-        String,
-        Vec<Vec<(u32, String, String, i32, Option<usize>)>>,
-    )>,
+    measures: &[ScoreMeasure],
     buffer_svg: &str,
     play_only_inscale: bool,
+    show_rests: bool,
+    layout: MeasureLayout,
+    show_rhythm: bool,
+    note_naming: NoteNaming,
+    label_style: LabelStyle,
 ) -> String {
+    let mut measures_html = String::new();
+    write_measures_html(
+        measures,
+        buffer_svg,
+        play_only_inscale,
+        show_rests,
+        layout,
+        show_rhythm,
+        note_naming,
+        label_style,
+        |measure_html| {
+            measures_html.push_str(&measure_html);
+            true
+        },
+    );
+    measures_html
+}
+
+/// Renders measures like `generate_measures_html`, handing the HTML of each measure to `emit` as soon as it is
+/// generated instead of building the whole string, so a long arrangement can be streamed with little memory.
+///
+/// A measure's HTML includes the repetition header and time signature cell placed before it, so concatenating every
+/// emitted piece gives exactly the output of `generate_measures_html`.
+///
+/// # Parameters
+/// - `measures`, `buffer_svg`, `play_only_inscale`, `show_rests`, `layout`, `show_rhythm`, `note_naming`,
+///   `label_style`: As for `generate_measures_html`.
+/// - `emit`: Receives the HTML of each measure in order, and returns `false` to stop rendering (e.g. once the client
+///   is gone).
+#[allow(clippy::too_many_arguments)]
+pub fn write_measures_html(
+    measures: &[ScoreMeasure],
+    buffer_svg: &str,
+    play_only_inscale: bool,
+    show_rests: bool,
+    layout: MeasureLayout,
+    show_rhythm: bool,
+    note_naming: NoteNaming,
+    label_style: LabelStyle,
+    mut emit: impl FnMut(String) -> bool,
+) {
+    // A pitch map only keeps the notes
+    let show_rests = show_rests && show_rhythm;
     let mut measures_html = String::new();
     let mut current_sign = String::new();
     let mut current_sigb = String::new();
 
-    for (measure_num, time_signature, chords) in measures {
+    // Flatten the slur group of every rendered chord so arcs know where they start and end
+    let slur_groups: Vec<Option<u32>> = measures
+        .iter()
+        .flat_map(|measure| {
+            measure
+                .chords
+                .iter()
+                .filter(|notes| !notes.is_empty())
+                .map(|notes| notes.iter().find_map(|n| n.slur_group))
+        })
+        .collect();
+    let mut chord_position = 0;
+    let mut timeline_time_signature = String::new();
+    let mut beat_position = 0.0;
+    let mut svg_cache = crate::utils::svg::SvgColorCache::new(buffer_svg);
+
+    for ScoreMeasure {
+        number: measure_num,
+        time_signature,
+        chords,
+        annotations,
+        repetition,
+        transposition,
+        ..
+    } in measures
+    {
+        let compact = layout == MeasureLayout::Compact;
+        if !time_signature.is_empty() {
+            timeline_time_signature = time_signature.clone();
+        }
+
+        // Each pass of a practice loop starts with a header naming it
+        if let Some((pass, loop_count)) = repetition {
+            measures_html.push_str(&format!(
+                "<div class='repetition-header'>Repetition {} of {}</div>\n",
+                pass, loop_count
+            ));
+        }
+
         if !time_signature.is_empty() {
             let sig: Vec<&str> = time_signature.split('|').collect();
-            current_sign = sig.get(0).unwrap_or(&"default").to_string();
+            current_sign = sig.first().unwrap_or(&"default").to_string();
             current_sigb = sig.get(1).unwrap_or(&"default").to_string();
 
+            if !compact && show_rhythm {
+                measures_html.push_str("<div class='measure'>\n");
+                measures_html.push_str("<div class='signature'>\n");
+                measures_html.push_str(&format!("<div class='sigN'>{}</div>\n", current_sign));
+                measures_html.push_str(&format!("<div class='sigD'>{}</div>\n", current_sigb));
+                measures_html.push_str("</div>\n");
+                measures_html.push_str("</div>\n");
+            }
+        }
+
+        if compact {
+            // A single row: number, inline time signature change and annotations, then the notes
+            measures_html.push_str("<div class='measure measure-row'>\n");
+            measures_html.push_str(&format!(
+                "<div class='measure-header'>{}{}</div>\n",
+                measure_num,
+                transposition
+                    .map(|shift| format!(" ({:+})", shift))
+                    .unwrap_or_default()
+            ));
+            if !time_signature.is_empty() && show_rhythm {
+                measures_html.push_str(&format!(
+                    "<div class='signature signature-inline'><div class='sigN'>{}</div><div class='sigD'>{}</div></div>\n",
+                    current_sign, current_sigb
+                ));
+            }
+        } else {
             measures_html.push_str("<div class='measure'>\n");
-            measures_html.push_str("<div class='signature'>\n");
-            measures_html.push_str(&format!("<div class='sigN'>{}</div>\n", current_sign));
-            measures_html.push_str(&format!("<div class='sigD'>{}</div>\n", current_sigb));
-            measures_html.push_str("</div>\n");
-            measures_html.push_str("</div>\n");
+            measures_html.push_str(&format!(
+                "<div class='measure-header'>Measure: {}{}</div>\n",
+                measure_num,
+                transposition
+                    .map(|shift| format!(" (transposed {:+})", shift))
+                    .unwrap_or_default()
+            ));
         }
 
-        measures_html.push_str("<div class='measure'>\n");
-        measures_html.push_str(&format!(
-            "<div class='measure-header'>Measure: {}</div>\n",
-            measure_num
-        ));
+        for annotation in annotations {
+            measures_html.push_str(&format!(
+                "<div class='measure-annotation'>{}</div>\n",
+                sanitize_html(annotation)
+            ));
+        }
 
         if !chords.is_empty() {
-            measures_html.push_str("<div class='notes'>\n");
+            measures_html.push_str(if compact {
+                "<div class='notes notes-row'>\n"
+            } else {
+                "<div class='notes'>\n"
+            });
 
             for notes in chords.iter() {
+                // Place the chord on the timeline before anything is skipped
+                let (start_beats, duration_beats) = match notes.first() {
+                    Some(first) => {
+                        let start = beat_position;
+                        let beats = note_beats(first, &timeline_time_signature);
+                        beat_position += beats;
+                        (start, beats)
+                    }
+                    None => (beat_position, 0.0),
+                };
+
+                // Hidden rests still take their place in the slur sequence
+                if !show_rests && !notes.is_empty() && notes.iter().all(|n| n.note == "Rest") {
+                    chord_position += 1;
+                    continue;
+                }
                 if !notes.is_empty() {
                     let mut svg_image = buffer_svg.to_string();
                     let mut note_formated = String::new();
                     let mut class_type = String::new();
                     let mut current_duration = String::new();
                     let mut pitches: Vec<&u32> = Vec::new();
+                    let contains_zero_delta = notes.iter().any(|n| n.delta == 0);
+                    let mut unreachable_notes: Vec<String> = Vec::new();
+                    let mut colorings: Vec<(usize, String)> = Vec::new();
+
+                    for score_note in notes {
+                        let ScoreNote {
+                            pitch,
+                            note,
+                            duration,
+                            delta,
+                            note_index,
+                            fingering,
+                            hand_hint,
+                            nearest_field,
+                            ..
+                        } = score_note;
+                        let duration = if show_rhythm {
+                            duration.as_str()
+                        } else {
+                            PITCH_MAP_DURATION
+                        };
 
-                    for (pitch, note, duration, delta, note_index) in notes {
                         if duration == "measure" {
                             current_duration = "whole".to_string();
                         } else {
@@ -483,6 +3036,7 @@ pub fn generate_measures_html(
                             }
                         } else {
                             class_type = "handpansvg".to_string();
+                            let note = note_naming.display_name(note);
                             let (note_style, delta_display) = if *delta == 0 {
                                 ("inscale", "".to_string()) // String
                             } else if *delta > 0 {
@@ -492,46 +3046,1293 @@ pub fn generate_measures_html(
                                 ("outscale", format!("<span class='delta'>(<span class='delta_red'>{}</span>)</span>", delta))
                                 // String
                             };
+                            // Spell the delta out so it doesn't depend on the colors alone
+                            let accessible_label = match *delta {
+                                0 => format!("{}, in scale", note),
+                                d => format!(
+                                    "{}, not in scale: {} by {} semitone{} from the closest field",
+                                    note,
+                                    if d > 0 { "sharp" } else { "flat" },
+                                    d.abs(),
+                                    if d.abs() == 1 { "" } else { "s" }
+                                ),
+                            };
+                            // Show the score's fingering as a small superscript next to the note name
+                            let fingering_html = match fingering {
+                                Some(text) => format!(
+                                    "<sup class='fingering' title='Fingering'>{}</sup>",
+                                    sanitize_html(text)
+                                ),
+                                None => String::new(),
+                            };
+                            // Split the notes into left and right hand streams when hints were computed
+                            let hand_class = match hand_hint {
+                                Some(hand) => format!(" hand-{}", hand.as_str()),
+                                None => String::new(),
+                            };
+                            // Field-number labels show the field instead, while the accessible label keeps the note
+                            let label = match (label_style, nearest_field) {
+                                (LabelStyle::FieldNumber, Some(field)) => field.to_string(),
+                                _ => note.clone(),
+                            };
                             note_formated.push_str(&format!(
-                                "<span class='noteformated {}'>{}{}</span>",
-                                note_style, note, delta_display
+                                "<span class='noteformated {}{}' role='img' aria-label='{}' title='{}'>{}{}{}</span>",
+                                note_style, hand_class, accessible_label, accessible_label, label, fingering_html, delta_display
                             ));
 
-                            let should_push_pitch =
-                                (!play_only_inscale && *delta != 0) || *delta == 0;
+                            let should_push_pitch = !play_only_inscale || *delta == 0;
                             if should_push_pitch {
                                 pitches.push(pitch);
                             }
 
-                            let contains_zero_delta =
-                                notes.iter().any(|(_, _, _, delta, _)| *delta == 0);
                             if let Some(index) = note_index {
-                                svg_image = crate::utils::svg::modify_svg_note_color(
-                                    &svg_image, *index, &duration,
-                                );
+                                colorings.push((*index, duration.to_string()));
                             } else if !contains_zero_delta {
-                                svg_image = crate::utils::svg::modify_svg_note_color(
-                                    &svg_image, 999, &duration,
-                                );
+                                colorings.push((999, duration.to_string()));
+                            } else {
+                                unreachable_notes.push(note);
                             }
                         }
                     }
 
+                    // Chords sharing fields and durations reuse the same colored SVG
+                    if !colorings.is_empty() {
+                        svg_image = svg_cache.colored_svg(&colorings);
+                    }
+
                     let pitches_data = pitches
                         .iter()
                         .map(|p| p.to_string())
                         .collect::<Vec<String>>()
                         .join(";");
+
+                    // Draw the slur arc segment covering this chord, if any
+                    let slur_html = match slur_groups[chord_position] {
+                        Some(group) => {
+                            let starts = chord_position == 0
+                                || slur_groups[chord_position - 1] != Some(group);
+                            let ends = slur_groups.get(chord_position + 1).copied().flatten()
+                                != Some(group);
+                            let slur_class = match (starts, ends) {
+                                (true, true) => "slur-single",
+                                (true, false) => "slur-start",
+                                (false, true) => "slur-end",
+                                (false, false) => "slur-middle",
+                            };
+                            format!(
+                                "<div class='slur {}' slur-group='{}'></div>",
+                                slur_class, group
+                            )
+                        }
+                        None => String::new(),
+                    };
+                    chord_position += 1;
+
+                    // Draw the half of the glissando line leaving or reaching this chord
+                    let glissando_html = match (
+                        notes.iter().any(|n| n.glissando_start),
+                        notes.iter().any(|n| n.glissando_end),
+                    ) {
+                        (true, true) => {
+                            "<div class='glissando glissando-through' title='Glissando'></div>"
+                        }
+                        (true, false) => {
+                            "<div class='glissando glissando-start' title='Glissando'></div>"
+                        }
+                        (false, true) => {
+                            "<div class='glissando glissando-end' title='Glissando'></div>"
+                        }
+                        (false, false) => "",
+                    };
+
+                    // Draw the hold symbol above a chord or rest carrying a fermata
+                    let fermata_html = if notes.iter().any(|n| n.fermata) {
+                        "<div class='fermata' title='Fermata'>𝄐</div>"
+                    } else {
+                        ""
+                    };
+
+                    // Label the melody note with the interval from the previous one
+                    let interval_html = match notes.iter().find_map(|n| n.interval.as_deref()) {
+                        Some(interval) => format!(
+                            "<div class='interval' title='Interval from the previous note'>{}</div>",
+                            sanitize_html(interval)
+                        ),
+                        None => String::new(),
+                    };
+
+                    // Write the lyrics under the note, one line per verse; a melisma holds a line instead
+                    let lyrics_html = {
+                        let lines = notes
+                            .iter()
+                            .flat_map(|n| n.lyrics.iter())
+                            .map(|lyric| {
+                                if lyric.melisma {
+                                    "<div class='lyric melisma'></div>".to_string()
+                                } else {
+                                    format!(
+                                        "<div class='lyric'>{}{}</div>",
+                                        sanitize_html(&lyric.text),
+                                        if lyric.hyphen { "-" } else { "" }
+                                    )
+                                }
+                            })
+                            .collect::<String>();
+                        if lines.is_empty() {
+                            lines
+                        } else {
+                            format!("<div class='lyrics'>{}</div>", lines)
+                        }
+                    };
+
+                    // In a partly playable chord, flag the tones that have no field on the layout
+                    let unreachable_html = if unreachable_notes.is_empty() {
+                        String::new()
+                    } else {
+                        class_type.push_str(" partial-chord");
+                        format!(
+                            "<div class='unreachable-badge' title='Not on this handpan'>{}</div>",
+                            sanitize_html(&unreachable_notes.join(", "))
+                        )
+                    };
+
                     measures_html.push_str(&format!(
-                        "<div class='note' sigN='{}' sigD='{}' pitches='{}' duration='{}'><div class='svg_container {}'>{}</div><div class='note-label'>{}</div></div>\n",
-                        current_sign, current_sigb, pitches_data, current_duration, class_type, svg_image, note_formated
+                        "<div class='note' sigN='{}' sigD='{}' pitches='{}' duration='{}' data-start='{}' data-duration='{}'>{}{}{}<div class='svg_container {}'>{}{}</div><div class='note-label'>{}</div>{}{}</div>\n",
+                        current_sign, current_sigb, pitches_data, current_duration, format_beats(start_beats), format_beats(duration_beats), slur_html, glissando_html, fermata_html, class_type, svg_image, unreachable_html, note_formated, interval_html, lyrics_html
                     ));
                 }
             }
             measures_html.push_str("</div>\n");
         }
         measures_html.push_str("</div>\n");
+
+        if !emit(std::mem::take(&mut measures_html)) {
+            break;
+        }
     }
 
-    measures_html
+    log::debug!("Reused {} colored SVGs", svg_cache.hits());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::scales::{get_handpan_scale, tpc_in_key};
+
+    /// Wraps the content of some measures into a score with a single part on staff 1.
+    fn score_with_measures(measures: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"pitched\"/></Staff><trackName>Piano</trackName></Part>\
+             <Staff id=\"1\">{}</Staff></Score></museScore>",
+            measures
+        )
+    }
+
+    /// Wraps the content of some measures into a score whose part on staff 1 has the given staff type group and
+    /// `<Instrument>` content.
+    fn score_with_instrument(staff_type: &str, instrument: &str, measures: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <Part id=\"1\"><Staff id=\"1\"><StaffType group=\"{}\"/></Staff><trackName>Part</trackName>\
+             <Instrument>{}</Instrument></Part>\
+             <Staff id=\"1\">{}</Staff></Score></museScore>",
+            staff_type, instrument, measures
+        )
+    }
+
+    /// Parses part 1 of a score against the 9-note D Kurd, without transposition.
+    fn parse_score(
+        xml: &str,
+        lenient: bool,
+    ) -> Result<ParsedScore, Box<dyn std::error::Error + Send + Sync>> {
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        parse_mscx_score(
+            xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            lenient,
+            None,
+        )
+    }
+
+    /// The measures of `parse_score` in lenient mode.
+    fn parse_measures(xml: &str) -> Vec<ScoreMeasure> {
+        parse_score(xml, true).unwrap().0
+    }
+
+    /// Renders measures as stacked, rhythmic note cells labeled with letter names, on an empty layout.
+    fn render_measures(measures: &[ScoreMeasure]) -> String {
+        generate_measures_html(
+            measures,
+            "<svg></svg>",
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        )
+    }
+
+    /// A quarter note chord with a single note.
+    fn quarter(pitch: u8, tpc: i8) -> String {
+        format!(
+            "<Chord><durationType>quarter</durationType><Note><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+            pitch, tpc
+        )
+    }
+
+    /// A slur start pointing a number of quarter notes forward.
+    fn slur_start(quarters: u32) -> String {
+        format!(
+            "<Spanner type=\"Slur\"><Slur/><next><location><fractions>{}/4</fractions></location></next></Spanner>",
+            quarters
+        )
+    }
+
+    /// A slur stop pointing a number of quarter notes back.
+    fn slur_stop(quarters: u32) -> String {
+        format!(
+            "<Spanner type=\"Slur\"><prev><location><fractions>-{}/4</fractions></location></prev></Spanner>",
+            quarters
+        )
+    }
+
+    /// The slur group of every chord, in order.
+    fn slur_groups(measures: &[ScoreMeasure]) -> Vec<Option<u32>> {
+        measures
+            .iter()
+            .flat_map(|m| m.chords.iter())
+            .map(|chord| chord[0].slur_group)
+            .collect()
+    }
+
+    #[test]
+    fn four_note_slur_shares_one_group() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}{}{}{}{}</voice></Measure>",
+            slur_start(3),
+            quarter(62, 16),
+            quarter(64, 18),
+            quarter(65, 13),
+            slur_stop(3),
+            quarter(67, 15)
+        ));
+        let groups = slur_groups(&parse_measures(&xml));
+        assert_eq!(groups.len(), 4);
+        assert!(groups[0].is_some());
+        assert!(groups.iter().all(|&group| group == groups[0]));
+    }
+
+    #[test]
+    fn overlapping_slurs_close_by_location() {
+        // The first slur covers chords 1 to 3 and the second chords 2 to 4
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}{}{}{}{}{}{}</voice></Measure>",
+            slur_start(2),
+            quarter(62, 16),
+            slur_start(2),
+            quarter(64, 18),
+            slur_stop(2),
+            quarter(65, 13),
+            slur_stop(2),
+            quarter(67, 15)
+        ));
+        let groups = slur_groups(&parse_measures(&xml));
+        assert_eq!(groups, vec![Some(1), Some(1), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn glissando_flags_both_notes() {
+        // The origin note points one quarter forward to its target, which points back to it
+        let glissando_note = |pitch: u8, tpc: i8, end: &str, fraction: &str| {
+            format!(
+                "<Chord><durationType>quarter</durationType><Note><Spanner type=\"Glissando\"><{end}><location>\
+                 <fractions>{}</fractions></location></{end}></Spanner><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+                fraction,
+                pitch,
+                tpc,
+                end = end
+            )
+        };
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}{}</voice></Measure>",
+            glissando_note(62, 16, "next", "1/4"),
+            glissando_note(67, 15, "prev", "-1/4"),
+            quarter(69, 17)
+        ));
+        let flags: Vec<(bool, bool)> = parse_measures(&xml)[0]
+            .chords
+            .iter()
+            .map(|chord| (chord[0].glissando_start, chord[0].glissando_end))
+            .collect();
+        assert_eq!(flags, vec![(true, false), (false, true), (false, false)]);
+    }
+
+    #[test]
+    fn short_rests_are_absorbed_and_longer_ones_kept() {
+        let chord = |note: &str, duration: &str, beats: f64| {
+            vec![ScoreNote {
+                pitch: 62,
+                note: note.to_string(),
+                duration: duration.to_string(),
+                duration_ticks: beats_to_ticks(beats),
+                ..Default::default()
+            }]
+        };
+        let mut measures = vec![ScoreMeasure {
+            chords: vec![
+                chord("D4", "quarter", 1.0),
+                chord("Rest", "16th", 0.25),
+                chord("D4", "quarter", 1.0),
+                chord("Rest", "quarter", 1.0),
+                chord("D4", "quarter", 1.0),
+            ],
+            ..Default::default()
+        }];
+
+        absorb_short_rests(&mut measures, 0.5);
+        let chords: Vec<(&str, &str, u32)> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| {
+                (
+                    chord[0].note.as_str(),
+                    chord[0].duration.as_str(),
+                    chord[0].duration_ticks,
+                )
+            })
+            .collect();
+        assert_eq!(
+            chords,
+            vec![
+                ("D4", "quarter", beats_to_ticks(1.25)),
+                ("D4", "quarter", beats_to_ticks(1.0)),
+                ("Rest", "quarter", beats_to_ticks(1.0)),
+                ("D4", "quarter", beats_to_ticks(1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_measure_is_skipped_in_lenient_mode() {
+        let good = format!("<Measure><voice>{}</voice></Measure>", quarter(62, 16));
+        let malformed = "<Measure><voice><Chord><durationType>quarter</durationType><Note><pitch>64</pitch></Chord></voice></Measure>";
+        let xml = score_with_measures(&format!("{}{}{}", good, malformed, good));
+
+        let (measures, _, warnings) = parse_score(&xml, true).unwrap();
+        assert_eq!(measures.len(), 3);
+        assert_eq!(measures[0].chords.len(), 1);
+        assert!(measures[1].chords.is_empty());
+        assert_eq!(measures[1].annotations, vec!["Unreadable measure skipped"]);
+        assert_eq!(measures[2].chords.len(), 1);
+        assert!(warnings.iter().any(|w| w.contains("measure 2")));
+
+        assert!(parse_score(&xml, false).is_err());
+    }
+
+    #[test]
+    fn corrupt_measure_among_good_ones_becomes_a_placeholder() {
+        let measure = |pitch: u8, tpc: i8| {
+            format!("<Measure><voice>{}</voice></Measure>", quarter(pitch, tpc))
+        };
+        // Well-formed XML, but the pitch holds an undefined entity, so the reader has to skip to the measure's end
+        let corrupt = "<Measure><voice><Chord><durationType>quarter</durationType>\
+                       <Note><pitch>6&bogus;4</pitch><tpc>18</tpc></Note></Chord></voice></Measure>";
+        let xml = score_with_measures(&format!(
+            "{}{}{}{}{}",
+            measure(62, 16),
+            measure(64, 18),
+            corrupt,
+            measure(65, 13),
+            measure(67, 15)
+        ));
+
+        let (measures, _, warnings) = parse_score(&xml, true).unwrap();
+        let numbers: Vec<u32> = measures.iter().map(|m| m.number).collect();
+        assert_eq!(numbers, vec![1, 2, 3, 4, 5]);
+        let pitches: Vec<Vec<u32>> = measures
+            .iter()
+            .map(|m| m.chords.iter().map(|chord| chord[0].pitch).collect())
+            .collect();
+        assert_eq!(
+            pitches,
+            vec![vec![62], vec![64], vec![], vec![65], vec![67]]
+        );
+        assert_eq!(measures[2].annotations, vec!["Unreadable measure skipped"]);
+        assert_eq!(warnings, vec!["Unreadable measure 3 skipped"]);
+    }
+
+    #[test]
+    fn time_signature_numbers_are_read_in_either_order() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice><TimeSig><sigD>8</sigD><sigN>6</sigN></TimeSig>{q}</voice></Measure>\
+             <Measure><voice>{q}</voice></Measure>",
+            q = quarter(62, 16)
+        ));
+        let measures = parse_measures(&xml);
+        assert_eq!(measures[0].time_signature, "6|8");
+        // Six eighths last three quarters, so the second measure starts three quarters in
+        assert_eq!(measures[1].chords[0][0].tick, 3 * TICKS_PER_QUARTER);
+    }
+
+    #[test]
+    fn time_signature_without_a_denominator_keeps_the_one_in_effect() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice><TimeSig><sigN>3</sigN><sigD>8</sigD></TimeSig>{q}</voice></Measure>\
+             <Measure><voice><TimeSig><sigN>5</sigN><sigD/></TimeSig>{q}</voice></Measure>\
+             <Measure><voice><TimeSig><sigN></sigN></TimeSig>{q}</voice></Measure>",
+            q = quarter(62, 16)
+        ));
+        let (measures, _, warnings) = parse_score(&xml, true).unwrap();
+        let signatures: Vec<&str> = measures.iter().map(|m| m.time_signature.as_str()).collect();
+        assert_eq!(signatures, vec!["3|8", "5|8", ""]);
+        assert_eq!(
+            warnings,
+            vec!["Time signature without numbers in measure 3 ignored"]
+        );
+    }
+
+    #[test]
+    fn truncated_score_keeps_the_measures_before_the_cut() {
+        let measure = format!("<Measure><voice>{}</voice></Measure>", quarter(62, 16));
+        let full = score_with_measures(&format!("{}{}{}", measure, measure, measure));
+        // Cut the file in the middle of the third measure's note
+        let cut = full.rfind("<pitch>").unwrap();
+        let xml = &full[..cut];
+
+        for lenient in [true, false] {
+            let (measures, _, warnings) = parse_score(xml, lenient).unwrap();
+            assert_eq!(measures.len(), 2);
+            assert!(measures.iter().all(|m| m.chords.len() == 1));
+            assert!(warnings.iter().any(|w| w.contains("cut off in measure 3")));
+        }
+    }
+
+    #[test]
+    fn note_cells_carry_numeric_timeline_attributes() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}<Chord><durationType>half</durationType><Note><pitch>64</pitch><tpc>18</tpc></Note></Chord>\
+             {}</voice></Measure><Measure><voice>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(65, 13),
+            quarter(67, 15)
+        ));
+        let html = generate_measures_html(
+            &parse_measures(&xml),
+            "<svg></svg>",
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        );
+        let attribute = |cell: &str, name: &str| -> f64 {
+            let start = cell.find(&format!("{}='", name)).unwrap() + name.len() + 2;
+            let end = start + cell[start..].find('\'').unwrap();
+            cell[start..end].parse().unwrap()
+        };
+
+        let timeline: Vec<(f64, f64)> = html
+            .split("<div class='note' ")
+            .skip(1)
+            .map(|cell| {
+                (
+                    attribute(cell, "data-start"),
+                    attribute(cell, "data-duration"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            timeline,
+            vec![(0.0, 1.0), (1.0, 2.0), (3.0, 1.0), (4.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn duplicated_pitch_in_a_chord_keeps_one_note() {
+        let xml = score_with_measures(
+            "<Measure><voice><Chord><durationType>quarter</durationType>\
+             <Note><pitch>62</pitch><tpc>16</tpc></Note>\
+             <Note><Fingering><text>2</text></Fingering><pitch>62</pitch><tpc>16</tpc></Note>\
+             <Note><pitch>65</pitch><tpc>13</tpc></Note>\
+             </Chord></voice></Measure>",
+        );
+        let measures = parse_measures(&xml);
+        let chord = &measures[0].chords[0];
+        let pitches: Vec<u32> = chord.iter().map(|note| note.pitch).collect();
+        assert_eq!(pitches, vec![62, 65]);
+        assert_eq!(chord[0].fingering.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn melody_intervals_skip_rests() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}<Rest><durationType>quarter</durationType></Rest>{}</voice></Measure>\
+             <Measure><voice>{}</voice></Measure>",
+            quarter(60, 14),
+            quarter(64, 18),
+            quarter(67, 15),
+            quarter(79, 15)
+        ));
+        let mut measures = parse_measures(&xml);
+        annotate_melody_intervals(&mut measures);
+
+        let intervals: Vec<Option<String>> = measures
+            .iter()
+            .flat_map(|m| m.chords.iter())
+            .filter(|chord| chord[0].note != "Rest")
+            .map(|chord| chord[0].interval.clone())
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![
+                None,
+                Some("M3".to_string()),
+                Some("m3".to_string()),
+                Some("P8".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn duration_type_after_the_notes_applies_to_them() {
+        let xml = score_with_measures(
+            "<Measure><voice>\
+             <Chord><Note><pitch>62</pitch><tpc>16</tpc></Note><Note><pitch>65</pitch><tpc>13</tpc></Note>\
+             <durationType>half</durationType></Chord>\
+             <Chord><Note><pitch>64</pitch><tpc>18</tpc></Note><durationType>quarter</durationType></Chord>\
+             </voice></Measure>",
+        );
+        let measures = parse_measures(&xml);
+        let chords = &measures[0].chords;
+
+        assert!(chords[0]
+            .iter()
+            .all(|n| n.duration == "half" && n.duration_ticks == 960));
+        assert_eq!(chords[1][0].duration, "quarter");
+        assert_eq!(chords[1][0].tick, 960);
+    }
+
+    #[test]
+    fn measures_past_the_limit_abort_parsing() {
+        let measure = format!("<Measure><voice>{}</voice></Measure>", quarter(62, 16));
+        let xml = score_with_measures(&measure.repeat(4));
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let parse = |measure_limit| {
+            parse_mscx_score_inner(
+                &xml,
+                1,
+                None,
+                &scale_notes,
+                None,
+                TranspositionWeights::default(),
+                0,
+                &[],
+                true,
+                None,
+                measure_limit,
+            )
+        };
+
+        let error = parse(3).unwrap_err();
+        assert!(error.is::<ScoreTooLarge>());
+        assert_eq!(error.to_string(), "The score has more than 3 measures");
+        assert_eq!(parse(4).unwrap().0.len(), 4);
+    }
+
+    #[test]
+    fn voices_share_tick_positions() {
+        let half = |pitch: u8, tpc: i8| {
+            format!(
+                "<Chord><durationType>half</durationType><Note><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+                pitch, tpc
+            )
+        };
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}</voice><voice>{}{}{}{}</voice></Measure>\
+             <Measure><voice>{}</voice></Measure>",
+            half(69, 17),
+            half(67, 15),
+            quarter(50, 16),
+            quarter(57, 17),
+            quarter(58, 12),
+            quarter(60, 14),
+            quarter(62, 16)
+        ));
+        let measures = parse_measures(&xml);
+
+        let onsets: Vec<(u32, u32)> = measures
+            .iter()
+            .flat_map(|m| m.chords.iter().flatten())
+            .map(|n| (n.pitch, n.tick))
+            .collect();
+        for expected in [
+            (69, 0),
+            (50, 0),
+            (57, 480),
+            (67, 960),
+            (58, 960),
+            (60, 1440),
+            (62, 1920),
+        ] {
+            assert!(onsets.contains(&expected), "{:?} in {:?}", expected, onsets);
+        }
+    }
+
+    #[test]
+    fn enharmonic_spelling_matches_the_field() {
+        // A G♯4 in the piece against a scale whose field is spelled A♭4
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}</voice></Measure>",
+            quarter(68, 22)
+        ));
+        let scale_notes = [56, 60, 61, 63, 65, 67, 68, 70, 72];
+        let (measures, _, _) = parse_mscx_score(
+            &xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+
+        let note = &measures[0].chords[0][0];
+        assert_eq!(note.note, "G♯4");
+        assert_eq!(note.delta, 0);
+        assert_eq!(note.note_index, Some(6));
+    }
+
+    #[test]
+    fn auto_transpose_shifts_the_whole_part_alike() {
+        // The 9 notes of D Kurd, 18 semitones up
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let chords: String = scale_notes
+            .iter()
+            .map(|&note| quarter(note + 18, tpc_in_key(note + 18, 0)))
+            .collect();
+        let xml = score_with_measures(&format!("<Measure><voice>{}</voice></Measure>", chords));
+
+        let (measures, transposed_value, _) = parse_mscx_score(
+            &xml,
+            1,
+            None,
+            &scale_notes,
+            Some(24),
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(transposed_value, -18);
+        let pitches: Vec<u8> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| chord[0].pitch as u8)
+            .collect();
+        assert_eq!(pitches, scale_notes);
+        assert!(measures[0].chords.iter().all(|chord| chord[0].delta == 0));
+    }
+
+    #[test]
+    fn guitar_part_is_matched_at_sounding_pitch() {
+        // A guitar is written an octave above where it sounds: this D3 is notated as D4, but stored as it sounds
+        let xml = score_with_instrument(
+            "pitched",
+            "<transposeDiatonic>-7</transposeDiatonic><transposeChromatic>-12</transposeChromatic>",
+            &format!("<Measure><voice>{}</voice></Measure>", quarter(50, 16)),
+        );
+        assert_eq!(parse_part_transposition(&xml, 1), (-12, -7));
+
+        let note = &parse_measures(&xml)[0].chords[0][0];
+        assert_eq!(note.pitch, 50);
+        assert_eq!(note.note, "D3");
+        // The ding, not the D4 field the written pitch would land on
+        assert_eq!(note.note_index, Some(0));
+        assert_eq!(note.delta, 0);
+    }
+
+    #[test]
+    fn clarinet_in_b_flat_is_matched_at_concert_pitch() {
+        // Written E4 and G4 (tpc2) sound a tone lower, as D4 and F4
+        let xml = score_with_instrument(
+            "pitched",
+            "<transposeDiatonic>-1</transposeDiatonic><transposeChromatic>-2</transposeChromatic>",
+            "<Measure><voice>\
+             <Chord><durationType>half</durationType><Note><pitch>62</pitch><tpc>16</tpc><tpc2>18</tpc2></Note></Chord>\
+             <Chord><durationType>half</durationType><Note><pitch>65</pitch><tpc>13</tpc><tpc2>15</tpc2></Note></Chord>\
+             </voice></Measure>",
+        );
+        assert_eq!(parse_part_transposition(&xml, 1), (-2, -1));
+
+        let (measures, transposed_value, _) = parse_score(&xml, true).unwrap();
+        assert_eq!(transposed_value, 0);
+        let notes: Vec<(&str, Option<usize>, i32)> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| (chord[0].note.as_str(), chord[0].note_index, chord[0].delta))
+            .collect();
+        assert_eq!(notes, [("D4", Some(4), 0), ("F4", Some(6), 0)]);
+    }
+
+    #[test]
+    fn selected_voice_keeps_only_its_notes() {
+        let half = |pitch: u8, tpc: i8| {
+            format!(
+                "<Chord><durationType>half</durationType><Note><pitch>{}</pitch><tpc>{}</tpc></Note></Chord>",
+                pitch, tpc
+            )
+        };
+        // A melody in voice 1 over a quarter note accompaniment in voice 2
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}</voice><voice>{}{}{}{}</voice></Measure>",
+            half(69, 17),
+            half(67, 15),
+            quarter(50, 16),
+            quarter(57, 17),
+            quarter(58, 12),
+            quarter(60, 14)
+        ));
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let voice_pitches = |voice: usize| -> Vec<u32> {
+            let (measures, _, _) = parse_mscx_score(
+                &xml,
+                1,
+                Some(voice),
+                &scale_notes,
+                None,
+                TranspositionWeights::default(),
+                0,
+                true,
+                None,
+            )
+            .unwrap();
+            measures[0]
+                .chords
+                .iter()
+                .map(|chord| chord[0].pitch)
+                .collect()
+        };
+
+        assert_eq!(voice_pitches(1), [69, 67]);
+        assert_eq!(voice_pitches(2), [50, 57, 58, 60]);
+    }
+
+    #[test]
+    fn tab_staff_notes_are_matched_at_sounding_pitch() {
+        // Standard guitar tuning, lowest string first; tab strings are numbered from the highest one
+        let xml = score_with_instrument(
+            "tablature",
+            "<StringData><frets>19</frets><string>40</string><string>45</string><string>50</string>\
+             <string>55</string><string>59</string><string>64</string></StringData>",
+            "<Measure><voice>\
+             <Chord><durationType>half</durationType>\
+             <Note><pitch>57</pitch><tpc>17</tpc><fret>2</fret><string>2</string></Note></Chord>\
+             <Chord><durationType>half</durationType><Note><fret>0</fret><string>3</string></Note></Chord>\
+             </voice></Measure>",
+        );
+        assert_eq!(
+            tablature_string_pitches(&xml, 1),
+            Some(vec![40, 45, 50, 55, 59, 64])
+        );
+
+        let (measures, _, warnings) = parse_score(&xml, true).unwrap();
+        let notes: Vec<(u32, Option<usize>)> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| (chord[0].pitch, chord[0].note_index))
+            .collect();
+        // The A3 keeps its <pitch>, the open D string without one sounds D3, the ding
+        assert_eq!(notes, [(57, Some(1)), (50, Some(0))]);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn staff_text_is_rendered_above_its_measure() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}</voice></Measure>\
+             <Measure><voice><StaffText><text><b>rit.</b> &lt;slowly&gt;</text></StaffText>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18)
+        ));
+        let measures = parse_measures(&xml);
+        assert!(measures[0].annotations.is_empty());
+        assert_eq!(measures[1].annotations, ["rit. <slowly>"]);
+
+        let html = render_measures(&measures);
+        assert!(html.contains(
+            "<div class='measure-header'>Measure: 2</div>\n\
+             <div class='measure-annotation'>rit. &lt;slowly&gt;</div>"
+        ));
+    }
+
+    #[test]
+    fn partly_playable_chord_flags_the_unreachable_tone() {
+        // D4 is a field of D Kurd, C♯4 is not
+        let xml = score_with_measures(
+            "<Measure><voice><Chord><durationType>whole</durationType>\
+             <Note><pitch>62</pitch><tpc>16</tpc></Note><Note><pitch>61</pitch><tpc>21</tpc></Note>\
+             </Chord></voice></Measure>",
+        );
+        let html = generate_measures_html(
+            &parse_measures(&xml),
+            r#"<svg><ellipse id="note_4"/></svg>"#,
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        );
+
+        // The D4 is colored on its field, the C♯4 is named in a badge next to the layout
+        assert!(html.contains("<div class='svg_container handpansvg partial-chord'><svg><ellipse id=\"note_4\" style=\"fill:"));
+        assert!(
+            html.contains("<div class='unreachable-badge' title='Not on this handpan'>C♯4</div>")
+        );
+    }
+
+    #[test]
+    fn out_of_scale_label_spells_out_the_delta() {
+        // C♯4 is a semitone above the C4 field, B♭3 is in the scale
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}</voice></Measure>",
+            quarter(61, 21),
+            quarter(58, 12)
+        ));
+        let html = render_measures(&parse_measures(&xml));
+
+        assert!(html.contains(
+            "<span class='noteformated outscale' role='img' \
+             aria-label='C♯4, not in scale: sharp by 1 semitone from the closest field'"
+        ));
+        assert!(html.contains("aria-label='B♭3, in scale'"));
+    }
+
+    #[test]
+    fn fermata_on_the_final_note_holds_it() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}<Fermata><subtype>fermataAbove</subtype></Fermata>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18),
+            quarter(65, 13)
+        ));
+        let measures = parse_measures(&xml);
+        let fermatas: Vec<bool> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| chord[0].fermata)
+            .collect();
+        assert_eq!(fermatas, [false, false, true]);
+
+        let html = render_measures(&measures);
+        let cells: Vec<&str> = html.split("<div class='note' ").skip(1).collect();
+        assert_eq!(cells.len(), 3);
+        assert!(cells[2].contains("<div class='fermata' title='Fermata'>𝄐</div>"));
+        assert!(!cells[..2].iter().any(|cell| cell.contains("fermata")));
+    }
+
+    #[test]
+    fn hidden_rests_leave_the_measures_in_place() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}</voice></Measure>\
+             <Measure><voice><Rest><durationType>whole</durationType></Rest></voice></Measure>\
+             <Measure><voice>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18)
+        ));
+        let measures = parse_measures(&xml);
+        let render = |show_rests: bool| {
+            generate_measures_html(
+                &measures,
+                "<svg></svg>",
+                false,
+                show_rests,
+                MeasureLayout::Stacked,
+                true,
+                NoteNaming::Letters,
+                LabelStyle::Name,
+            )
+        };
+
+        assert!(render(true).contains("restsvg"));
+        let html = render(false);
+        assert!(!html.contains("restsvg"));
+        assert!(html.contains("<div class='measure-header'>Measure: 2</div>"));
+        assert_eq!(html.matches("<div class='note' ").count(), 2);
+    }
+
+    #[test]
+    fn compact_layout_renders_one_row_per_measure() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice><TimeSig><sigN>2</sigN><sigD>4</sigD></TimeSig>{}{}</voice></Measure>\
+             <Measure><voice>{}{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18),
+            quarter(65, 13),
+            quarter(67, 15)
+        ));
+        let measures = parse_measures(&xml);
+        let render = |layout: MeasureLayout| {
+            generate_measures_html(
+                &measures,
+                "<svg></svg>",
+                false,
+                true,
+                layout,
+                true,
+                NoteNaming::Letters,
+                LabelStyle::Name,
+            )
+        };
+
+        let compact = render(MeasureLayout::Compact);
+        assert_eq!(
+            compact.matches("<div class='measure measure-row'>").count(),
+            2
+        );
+        assert_eq!(compact.matches("<div class='notes notes-row'>").count(), 2);
+        assert!(compact.contains("<div class='signature signature-inline'>"));
+        let stacked = render(MeasureLayout::Stacked);
+        assert!(!stacked.contains("measure-row") && !stacked.contains("notes-row"));
+    }
+
+    #[test]
+    fn fingering_is_shown_next_to_the_note_name() {
+        let xml = score_with_measures(
+            "<Measure><voice><Chord><durationType>whole</durationType>\
+             <Note><Fingering><text>2</text></Fingering><pitch>62</pitch><tpc>16</tpc></Note>\
+             </Chord></voice></Measure>",
+        );
+        let measures = parse_measures(&xml);
+        assert_eq!(measures[0].chords[0][0].fingering.as_deref(), Some("2"));
+
+        let html = render_measures(&measures);
+        assert!(html.contains(">D4<sup class='fingering' title='Fingering'>2</sup></span>"));
+    }
+
+    #[test]
+    fn field_number_labels_show_the_field_index() {
+        // C4 is field 3 of D Kurd 9, C♯4 is a semitone above it
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}</voice></Measure>",
+            quarter(60, 14),
+            quarter(61, 21)
+        ));
+        let mut measures = parse_measures(&xml);
+        annotate_nearest_fields(&mut measures, &get_handpan_scale("d_kurd_9").unwrap().1);
+        let html = generate_measures_html(
+            &measures,
+            "<svg></svg>",
+            false,
+            true,
+            MeasureLayout::Stacked,
+            true,
+            NoteNaming::Letters,
+            LabelStyle::FieldNumber,
+        );
+
+        assert!(html.contains("aria-label='C4, in scale' title='C4, in scale'>3</span>"));
+        assert!(html.contains(
+            "from the closest field'>3<span class='delta'>(<span class='delta_green'>1</span>)</span></span>"
+        ));
+    }
+
+    #[test]
+    fn lyricist_and_copyright_meta_tags_are_read() {
+        let xml = "<museScore version=\"4.20\"><Score>\
+            <metaTag name=\"workTitle\">Greensleeves</metaTag>\
+            <metaTag name=\"lyricist\">Anonymous</metaTag>\
+            <metaTag name=\"copyright\">Public domain</metaTag>\
+            <metaTag name=\"subtitle\"></metaTag>\
+            </Score></museScore>";
+        let tags = parse_mscx_meta_tags(xml);
+
+        assert_eq!(tags.get("lyricist").map(String::as_str), Some("Anonymous"));
+        assert_eq!(
+            tags.get("copyright").map(String::as_str),
+            Some("Public domain")
+        );
+        assert_eq!(
+            tags.get("workTitle").map(String::as_str),
+            Some("Greensleeves")
+        );
+        assert!(!tags.contains_key("subtitle"));
+    }
+
+    #[test]
+    fn part_staff_ids_are_mapped_to_the_body_numbering() {
+        // The parts number their staves 3 and 4, the body numbers the same staves 1 and 2
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<museScore version=\"4.20\"><Score>\
+             <Part id=\"1\"><Staff id=\"3\"><StaffType group=\"pitched\"/></Staff><trackName>Flute</trackName></Part>\
+             <Part id=\"2\"><Staff id=\"4\"><StaffType group=\"pitched\"/></Staff><trackName>Oboe</trackName></Part>\
+             <Staff id=\"1\"><Measure><voice>{}</voice></Measure></Staff>\
+             <Staff id=\"2\"><Measure><voice>{}</voice></Measure></Staff></Score></museScore>",
+            quarter(62, 16),
+            quarter(69, 17)
+        );
+        assert_eq!(resolve_body_staff_id(&xml, 3), 1);
+        assert_eq!(resolve_body_staff_id(&xml, 4), 2);
+
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let (measures, _, _) = parse_mscx_score(
+            &xml,
+            4,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(measures[0].chords[0][0].pitch, 69);
+        // Scores numbering both alike are left as they are
+        assert_eq!(resolve_body_staff_id(&score_with_measures(""), 1), 1);
+    }
+
+    #[test]
+    fn melisma_syllable_stays_on_its_first_note() {
+        // "Ah" is held from the D4 over the E4, then "men" is sung on the F4
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>\
+             <Chord><durationType>quarter</durationType><Lyrics><ticks_f>1/4</ticks_f><text>Ah</text></Lyrics>\
+             <Note><pitch>62</pitch><tpc>16</tpc></Note></Chord>\
+             {}\
+             <Chord><durationType>half</durationType><Lyrics><text>men</text></Lyrics>\
+             <Note><pitch>65</pitch><tpc>13</tpc></Note></Chord>\
+             </voice></Measure>",
+            quarter(64, 18)
+        ));
+        let measures = parse_measures(&xml);
+        let lyrics: Vec<Vec<(&str, bool)>> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| {
+                chord[0]
+                    .lyrics
+                    .iter()
+                    .map(|lyric| (lyric.text.as_str(), lyric.melisma))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            lyrics,
+            [vec![("Ah", false)], vec![("", true)], vec![("men", false)]]
+        );
+
+        let html = render_measures(&measures);
+        assert!(html.contains("<div class='lyrics'><div class='lyric'>Ah</div></div>"));
+        assert!(html.contains("<div class='lyrics'><div class='lyric melisma'></div></div>"));
+    }
+
+    #[test]
+    fn transpose_report_compares_the_fit_before_and_after() {
+        // A tone down, E4, G4, A4 and B4 become D4, F4, G4 and A4, all fields of D Kurd; as written, B4 has no field
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}{}{}</voice></Measure>",
+            quarter(64, 18),
+            quarter(67, 15),
+            quarter(69, 17),
+            quarter(71, 19)
+        ));
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let parse = |transpose_value: i32| {
+            parse_mscx_score(
+                &xml,
+                1,
+                None,
+                &scale_notes,
+                None,
+                TranspositionWeights::default(),
+                transpose_value,
+                true,
+                None,
+            )
+            .unwrap()
+            .0
+        };
+
+        let report = transpose_report(&parse(0), &parse(-2), -2, Some((1, false)), 0);
+        assert_eq!(report.note_count, 4);
+        assert_eq!(report.in_scale_before, 75.0);
+        assert_eq!(report.in_scale_after, 100.0);
+        assert_eq!((report.notes_fixed, report.notes_lost), (1, 0));
+        assert_eq!(report.source_key.as_deref(), Some("G major"));
+        assert_eq!(report.target_key.as_deref(), Some("F major"));
+    }
+
+    #[test]
+    fn notes_under_an_8va_sound_an_octave_higher() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}\
+             <Spanner type=\"Ottava\"><Ottava><subtype>8va</subtype></Ottava>\
+             <next><location><fractions>1/2</fractions></location></next></Spanner>\
+             {}{}\
+             <Spanner type=\"Ottava\"><prev><location><fractions>-1/2</fractions></location></prev></Spanner>\
+             {}</voice></Measure>",
+            quarter(50, 16),
+            quarter(50, 16),
+            quarter(57, 17),
+            quarter(50, 16)
+        ));
+        let measures = parse_measures(&xml);
+        let notes: Vec<(u32, Option<usize>)> = measures[0]
+            .chords
+            .iter()
+            .map(|chord| (chord[0].pitch, chord[0].note_index))
+            .collect();
+
+        // The written D3 and A3 under the 8va are matched as D4 and A4
+        assert_eq!(
+            notes,
+            [(50, Some(0)), (62, Some(4)), (69, Some(8)), (50, Some(0))]
+        );
+    }
+
+    #[test]
+    fn pitch_map_colors_every_note_alike() {
+        let xml = score_with_measures(
+            "<Measure><voice><TimeSig><sigN>3</sigN><sigD>4</sigD></TimeSig>\
+             <Chord><durationType>half</durationType><Note><pitch>62</pitch><tpc>16</tpc></Note></Chord>\
+             <Rest><durationType>eighth</durationType></Rest>\
+             <Chord><durationType>eighth</durationType><Note><pitch>65</pitch><tpc>13</tpc></Note></Chord>\
+             </voice></Measure>",
+        );
+        let html = generate_measures_html(
+            &parse_measures(&xml),
+            r#"<svg><ellipse id="note_4"/><ellipse id="note_6"/></svg>"#,
+            false,
+            true,
+            MeasureLayout::Stacked,
+            false,
+            NoteNaming::Letters,
+            LabelStyle::Name,
+        );
+
+        assert!(!html.contains("class='signature'") && !html.contains("restsvg"));
+        assert_eq!(html.matches("duration='quarter'").count(), 2);
+        let fills: Vec<&str> = html
+            .split("style=\"fill:")
+            .skip(1)
+            .map(|style| &style[..style.find(';').unwrap()])
+            .collect();
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0], fills[1]);
+    }
+
+    #[test]
+    fn rit_is_captured_as_a_tempo_mark_of_its_measure() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice><Tempo><tempo>1.333333</tempo><text><sym>metNoteQuarterUp</sym> = 80</text></Tempo>{}</voice></Measure>\
+             <Measure><voice>{}</voice></Measure>\
+             <Measure><voice><StaffText><text>rit.</text></StaffText>{}</voice></Measure>\
+             <Measure><voice>\
+             <Spanner type=\"GradualTempoChange\"><GradualTempoChange><tempoChangeType>accelerando</tempoChangeType>\
+             </GradualTempoChange><next><location><measures>1</measures></location></next></Spanner>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18),
+            quarter(65, 13),
+            quarter(67, 15)
+        ));
+        let measures = parse_measures(&xml);
+        let tempo_marks: Vec<Vec<TempoMark>> = measures
+            .iter()
+            .map(|measure| measure.tempo_marks.clone())
+            .collect();
+
+        let mark = |text: &str, bpm: Option<f64>| TempoMark {
+            text: text.to_string(),
+            bpm,
+        };
+        assert_eq!(
+            tempo_marks,
+            [
+                vec![mark("♩ = 80", Some(80.0))],
+                vec![],
+                vec![mark("rit.", None)],
+                vec![mark("accelerando", None)],
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_the_piece_never_plays_are_listed() {
+        // The ding, A3, B♭3, C4 and D4 are played; the upper four fields are not
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}{}{}{}</voice></Measure><Measure><voice>{}{}</voice></Measure>",
+            quarter(50, 16),
+            quarter(57, 17),
+            quarter(58, 12),
+            quarter(60, 14),
+            quarter(62, 16),
+            quarter(57, 17)
+        ));
+        let measures = parse_measures(&xml);
+
+        assert_eq!(unused_fields(&measures, 9), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn dropped_note_is_reported_as_a_warning() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}</voice></Measure>\
+             <Measure><voice><Chord><durationType>quarter</durationType><Note><tpc>16</tpc></Note></Chord>\
+             {}</voice></Measure>",
+            quarter(62, 16),
+            quarter(64, 18)
+        ));
+        let (measures, _, warnings) = parse_score(&xml, true).unwrap();
+
+        assert_eq!(warnings, ["Note without a pitch in measure 2 dropped"]);
+        assert_eq!(measures[1].chords.len(), 1);
+        assert_eq!(measures[1].chords[0][0].pitch, 64);
+    }
+
+    #[test]
+    fn note_sequence_joins_chords_and_skips_rests() {
+        let xml = score_with_measures(&format!(
+            "<Measure><voice>{}<Rest><durationType>quarter</durationType></Rest>\
+             <Chord><durationType>quarter</durationType><Note><pitch>65</pitch><tpc>13</tpc></Note>\
+             <Note><pitch>69</pitch><tpc>17</tpc></Note></Chord>{}</voice></Measure>",
+            quarter(62, 16),
+            quarter(63, 11)
+        ));
+        let measures = parse_measures(&xml);
+
+        assert_eq!(note_sequence(&measures, true), "D4, F4+A4");
+        assert_eq!(note_sequence(&measures, false), "D4, F4+A4, E♭4");
+    }
+
+    #[test]
+    fn part_program_is_read_from_its_first_channel() {
+        let flute = score_with_instrument(
+            "pitched",
+            "<Channel><program value=\"73\"/></Channel><Channel name=\"mute\"><program value=\"59\"/></Channel>",
+            "",
+        );
+        let violin =
+            score_with_instrument("pitched", "<Channel><program>40</program></Channel>", "");
+        let out_of_range =
+            score_with_instrument("pitched", "<Channel><program value=\"200\"/></Channel>", "");
+
+        assert_eq!(parse_part_program(&flute, 1), Some(73));
+        assert_eq!(parse_part_program(&violin, 1), Some(40));
+        assert_eq!(parse_part_program(&out_of_range, 1), None);
+        assert_eq!(parse_part_program(&score_with_measures(""), 1), None);
+        assert_eq!(parse_part_program(&flute, 2), None);
+    }
 }
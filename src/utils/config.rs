@@ -0,0 +1,294 @@
+use once_cell::sync::OnceCell;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The environment variable overriding the directory where uploads are stored.
+pub const UPLOAD_DIR_ENV: &str = "HANDFLOW_UPLOAD_DIR";
+
+/// The environment variable overriding the directory containing the HTML templates.
+pub const TEMPLATE_DIR_ENV: &str = "HANDFLOW_TEMPLATE_DIR";
+
+/// The environment variable naming an optional TOML or JSON file of additional scales.
+pub const SCALES_FILE_ENV: &str = "HANDFLOW_SCALES_FILE";
+
+/// The environment variable holding a JSON object of duration colors overriding the default palette.
+pub const DURATION_COLORS_ENV: &str = "HANDFLOW_DURATION_COLORS";
+
+/// The environment variable overriding how many measures a generated page contains.
+pub const MAX_MEASURES_ENV: &str = "HANDFLOW_MAX_MEASURES";
+
+/// The environment variable overriding how many measures a score may contain before parsing is aborted.
+pub const MEASURE_LIMIT_ENV: &str = "HANDFLOW_MEASURE_LIMIT";
+
+/// The environment variable overriding how many parts a score may declare before parsing is aborted.
+pub const PART_LIMIT_ENV: &str = "HANDFLOW_PART_LIMIT";
+
+/// The environment variable enabling gzip compression of the in-memory SVG cache (`1`, `true`, `yes` or `on`).
+pub const CACHE_COMPRESS_ENV: &str = "HANDFLOW_CACHE_COMPRESS";
+
+/// The environment variable selecting the log format: `plain` (the default) or `json`, one object per line.
+pub const LOG_FORMAT_ENV: &str = "HANDFLOW_LOG_FORMAT";
+
+/// The environment variable holding the log filter (e.g. `info` or `handflow=debug`) used when `RUST_LOG` is unset.
+pub const LOG_LEVEL_ENV: &str = "HANDFLOW_LOG_LEVEL";
+
+/// The environment variable overriding the multipart field name an upload is read from.
+pub const UPLOAD_FIELD_ENV: &str = "HANDFLOW_UPLOAD_FIELD";
+
+/// The environment variable holding a comma-separated allowlist of alternate handpan layout sets (e.g. `brandX`), each
+/// a subdirectory of `static/img` with its own `hand-{count}.svg` files.
+pub const LAYOUT_SETS_ENV: &str = "HANDFLOW_LAYOUT_SETS";
+
+/// The environment variable overriding how many seconds an upload that was generated from is kept after its last use.
+pub const UPLOAD_GRACE_ENV: &str = "HANDFLOW_UPLOAD_GRACE_SECS";
+
+/// The multipart field name of an upload when `HANDFLOW_UPLOAD_FIELD` is not set.
+const DEFAULT_UPLOAD_FIELD: &str = "file";
+
+/// The number of measures per generated page when `HANDFLOW_MAX_MEASURES` is not set.
+const DEFAULT_MAX_MEASURES: usize = 200;
+
+/// The maximum number of measures in a parsed score when `HANDFLOW_MEASURE_LIMIT` is not set.
+const DEFAULT_MEASURE_LIMIT: usize = 10_000;
+
+/// The maximum number of parts in a parsed score when `HANDFLOW_PART_LIMIT` is not set.
+const DEFAULT_PART_LIMIT: usize = 500;
+
+/// How many seconds an upload that was generated from is kept when `HANDFLOW_UPLOAD_GRACE_SECS` is not set.
+const DEFAULT_UPLOAD_GRACE_SECS: usize = 3600;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// The filesystem locations used by the application.
+///
+/// Fields:
+/// - `upload_dir`: Where uploaded MSCZ files and extracted MSCX files are written (default `uploads`).
+/// - `template_dir`: Where the HTML templates are read from (default `src/html`).
+/// - `scales_file`: An optional scales catalog supplementing the built-in scales.
+/// - `duration_colors`: An optional JSON object mapping durations to colors, merged over the default palette.
+/// - `max_measures`: The maximum number of measures rendered per generated page (default 200).
+/// - `measure_limit`: The maximum number of measures a parsed score may contain (default 10,000).
+/// - `part_limit`: The maximum number of parts a parsed score may declare (default 500).
+/// - `cache_compress`: Whether cached SVGs are kept gzip-compressed, trading CPU for memory (default off).
+/// - `upload_field`: The multipart field name the uploaded file is read from (default `file`).
+/// - `layout_sets`: The alternate handpan layout sets a request may select (default none).
+/// - `upload_grace`: How long an upload that was generated from survives the upload cleanup after its last use
+///   (default one hour).
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub upload_dir: PathBuf,
+    pub template_dir: PathBuf,
+    pub scales_file: Option<PathBuf>,
+    pub duration_colors: Option<String>,
+    pub max_measures: usize,
+    pub measure_limit: usize,
+    pub part_limit: usize,
+    pub cache_compress: bool,
+    pub upload_field: String,
+    pub layout_sets: Vec<String>,
+    pub upload_grace: Duration,
+}
+
+impl Config {
+    /// Builds the configuration from `HANDFLOW_UPLOAD_DIR`, `HANDFLOW_TEMPLATE_DIR`, `HANDFLOW_SCALES_FILE`,
+    /// `HANDFLOW_DURATION_COLORS`, `HANDFLOW_MAX_MEASURES`, `HANDFLOW_MEASURE_LIMIT`, `HANDFLOW_PART_LIMIT`,
+    /// `HANDFLOW_CACHE_COMPRESS`, `HANDFLOW_UPLOAD_FIELD`, `HANDFLOW_LAYOUT_SETS` and `HANDFLOW_UPLOAD_GRACE_SECS`, falling back to the paths relative to the working directory and the default sizes when they are unset,
+    /// empty or invalid. Layout set names other than ASCII letters, digits, `-` and `_` are dropped, so a set always
+    /// names a single directory inside `static/img`.
+    pub fn from_env() -> Self {
+        let path_from_env = |name: &str| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        let count_from_env = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .filter(|&value| value > 0)
+                .unwrap_or(default)
+        };
+
+        Config {
+            upload_dir: path_from_env(UPLOAD_DIR_ENV).unwrap_or_else(|| PathBuf::from("uploads")),
+            template_dir: path_from_env(TEMPLATE_DIR_ENV)
+                .unwrap_or_else(|| PathBuf::from("src/html")),
+            scales_file: path_from_env(SCALES_FILE_ENV),
+            duration_colors: std::env::var(DURATION_COLORS_ENV)
+                .ok()
+                .filter(|value| !value.trim().is_empty()),
+            max_measures: count_from_env(MAX_MEASURES_ENV, DEFAULT_MAX_MEASURES),
+            measure_limit: count_from_env(MEASURE_LIMIT_ENV, DEFAULT_MEASURE_LIMIT),
+            part_limit: count_from_env(PART_LIMIT_ENV, DEFAULT_PART_LIMIT),
+            cache_compress: std::env::var(CACHE_COMPRESS_ENV).is_ok_and(|value| {
+                matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "1" | "true" | "yes" | "on"
+                )
+            }),
+            upload_field: std::env::var(UPLOAD_FIELD_ENV)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| DEFAULT_UPLOAD_FIELD.to_string()),
+            layout_sets: std::env::var(LAYOUT_SETS_ENV)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|name| {
+                    !name.is_empty()
+                        && name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                })
+                .map(str::to_string)
+                .collect(),
+            upload_grace: Duration::from_secs(count_from_env(
+                UPLOAD_GRACE_ENV,
+                DEFAULT_UPLOAD_GRACE_SECS,
+            ) as u64),
+        }
+    }
+
+    /// Checks that the configured directories are usable.
+    ///
+    /// This function:
+    ///
+    /// 1. **Creates the Upload Directory**: Creates it with `0700` permissions if it does not exist yet.
+    /// 2. **Checks Writability**: Writes and removes a probe file in the upload directory.
+    /// 3. **Checks the Templates**: Ensures the template directory can be listed.
+    ///
+    /// # Returns
+    /// - `Ok(())` if both directories are usable.
+    /// - An `io::Error` naming the directory that is not.
+    pub fn validate(&self) -> io::Result<()> {
+        let with_path = |path: &Path, e: io::Error| {
+            io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+        };
+
+        if !self.upload_dir.exists() {
+            std::fs::create_dir_all(&self.upload_dir)
+                .map_err(|e| with_path(&self.upload_dir, e))?;
+            std::fs::set_permissions(&self.upload_dir, std::fs::Permissions::from_mode(0o700))
+                .map_err(|e| with_path(&self.upload_dir, e))?;
+        }
+
+        let probe = self.upload_dir.join(".handflow_write_test");
+        std::fs::write(&probe, b"").map_err(|e| with_path(&self.upload_dir, e))?;
+        std::fs::remove_file(&probe).map_err(|e| with_path(&self.upload_dir, e))?;
+
+        std::fs::read_dir(&self.template_dir).map_err(|e| with_path(&self.template_dir, e))?;
+        Ok(())
+    }
+}
+
+/// Reads and validates the configuration once at startup.
+///
+/// # Returns
+/// - `Ok(())` once the configuration is stored for the rest of the process.
+/// - An `io::Error` if a configured directory is not usable.
+pub fn init_config() -> io::Result<()> {
+    let config = Config::from_env();
+    config.validate()?;
+    log::info!(
+        "Using upload directory {} and template directory {}",
+        config.upload_dir.display(),
+        config.template_dir.display()
+    );
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// Returns the process-wide configuration, reading it from the environment if `init_config` was not called.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(default_config)
+}
+
+#[cfg(not(test))]
+fn default_config() -> Config {
+    Config::from_env()
+}
+
+/// Tests store their uploads in a temporary directory shared by the test process instead of `./uploads`, and read
+/// them from a `score` field so that a hardcoded `file` field name fails them.
+#[cfg(test)]
+fn default_config() -> Config {
+    static UPLOAD_DIR: OnceCell<tempfile::TempDir> = OnceCell::new();
+    let upload_dir = UPLOAD_DIR
+        .get_or_init(|| tempfile::tempdir().expect("failed to create the test upload directory"))
+        .path()
+        .to_path_buf();
+    Config {
+        upload_dir,
+        upload_field: "score".to_string(),
+        ..Config::from_env()
+    }
+}
+
+/// Returns the configured upload directory.
+pub fn upload_dir() -> &'static Path {
+    &config().upload_dir
+}
+
+/// Returns the configured multipart field name of an upload.
+pub fn upload_field() -> &'static str {
+    &config().upload_field
+}
+
+/// Returns the configured allowlist of alternate handpan layout sets.
+pub fn layout_sets() -> &'static [String] {
+    &config().layout_sets
+}
+
+/// Returns how long an upload that was generated from is kept after its last use.
+pub fn upload_grace() -> Duration {
+    config().upload_grace
+}
+
+/// Returns the path of a template file inside the configured template directory.
+///
+/// # Parameters
+/// - `name`: The template file name (e.g. `"generate_tmpl.html"`).
+pub fn template_path(name: &str) -> PathBuf {
+    config().template_dir.join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests_upload_into_a_temporary_directory() {
+        let dir = upload_dir();
+        assert!(dir.starts_with(std::env::temp_dir()));
+        assert!(dir.is_dir());
+        assert_ne!(dir, Path::new("uploads"));
+    }
+
+    #[test]
+    fn validation_creates_an_overridden_upload_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            upload_dir: dir.path().join("uploads"),
+            template_dir: dir.path().to_path_buf(),
+            ..Config::from_env()
+        };
+
+        config.validate().unwrap();
+        let permissions = std::fs::metadata(&config.upload_dir).unwrap().permissions();
+        assert_eq!(permissions.mode() & 0o777, 0o700);
+        // The probe file is removed again
+        assert_eq!(std::fs::read_dir(&config.upload_dir).unwrap().count(), 0);
+
+        let missing_templates = Config {
+            template_dir: dir.path().join("html"),
+            ..config
+        };
+        let error = missing_templates.validate().unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&dir.path().join("html").display().to_string()));
+    }
+}
@@ -1,56 +1,347 @@
+use once_cell::sync::Lazy;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::io::{self, BufReader, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 use tokio::fs::{self};
 
+/// How many times a filesystem write is attempted before its error is reported.
+pub const IO_ATTEMPTS: u32 = 3;
+
+/// The delay before the first retry of a failed filesystem write, doubled for each further retry.
+const IO_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Returns whether an I/O error may go away on its own, so the operation is worth retrying.
+///
+/// Busy or interrupted calls (`EAGAIN`, `EINTR`, `EBUSY`), timeouts and a full disk (`ENOSPC`, which often clears as
+/// `clean_old_uploads` frees space) are transient. Anything else, such as a missing directory or a permission
+/// error, will fail the same way again.
+///
+/// # Parameters
+/// - `error`: The error returned by the filesystem operation.
+///
+/// # Returns
+/// - `true` if the operation should be retried.
+pub fn is_transient_io_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StorageFull
+    )
+}
+
+/// Asynchronously runs a filesystem operation, retrying it with backoff on transient errors.
+///
+/// This function:
+///
+/// 1. **First Attempt**: Runs the operation and returns its result if it succeeds or fails permanently.
+/// 2. **Backoff**: On a transient error (see `is_transient_io_error`), waits `IO_RETRY_DELAY`, doubling the delay on
+///    each further retry, and runs the operation again.
+/// 3. **Give Up**: Returns the last error once `IO_ATTEMPTS` attempts have failed.
+///
+/// The operation is run from scratch on every attempt, so it must be safe to repeat (e.g. rewrite a whole file or
+/// write at a fixed offset).
+///
+/// # Parameters
+/// - `operation`: A short description of the operation, used in the log messages.
+/// - `op`: A closure producing the future that performs the operation.
+///
+/// # Returns
+/// - The result of the first successful attempt, or the error of the last one.
+pub async fn retry_io<T, F, Fut>(operation: &str, mut op: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut delay = IO_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if attempt < IO_ATTEMPTS && is_transient_io_error(&e) => {
+                log::warn!(
+                    "Failed to {} (attempt {}/{}), retrying: {:?}",
+                    operation,
+                    attempt,
+                    IO_ATTEMPTS,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The upload files that must survive `clean_old_uploads` whatever their age, e.g. while a job still reads them, by
+/// canonical path, along with how many holders pinned each of them.
+static PINNED_UPLOADS: Lazy<Mutex<HashMap<PathBuf, usize>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Keeps a file from being removed by `clean_old_uploads` until `unpin_upload` is called for it as many times as it
+/// was pinned, so holders sharing a file (e.g. two jobs extracting the same score) don't release it for each other.
+pub fn pin_upload(path: &Path) {
+    *PINNED_UPLOADS
+        .lock()
+        .unwrap()
+        .entry(canonical_upload_path(path))
+        .or_insert(0) += 1;
+}
+
+/// Releases one pin taken with `pin_upload`; `clean_old_uploads` may remove the file again once none are left.
+pub fn unpin_upload(path: &Path) {
+    let mut pinned = PINNED_UPLOADS.lock().unwrap();
+    let path = canonical_upload_path(path);
+    if let Some(count) = pinned.get_mut(&path) {
+        *count -= 1;
+        if *count == 0 {
+            pinned.remove(&path);
+        }
+    }
+}
+
+/// Returns whether a file is currently pinned with `pin_upload`.
+pub fn is_pinned_upload(path: &Path) -> bool {
+    PINNED_UPLOADS
+        .lock()
+        .unwrap()
+        .contains_key(&canonical_upload_path(path))
+}
+
+/// The upload files that were generated from, by canonical path, which `clean_old_uploads` keeps for the longer grace
+/// period.
+static USED_UPLOADS: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Returns the canonical form of an upload path, so a file is recognised however its path was spelled.
+fn canonical_upload_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Marks a file as in use: refreshes its modification time and remembers it, so `clean_old_uploads` measures its age
+/// from this use and keeps it for the grace period instead of the usual maximum age.
+///
+/// # Parameters
+/// - `path`: The upload that was just read (e.g. the MSCX file of a `/generate` request).
+///
+/// # Returns
+/// - `Ok(())` once the modification time is updated.
+/// - An `io::Error` if the file cannot be opened or its time set.
+pub fn touch_upload(path: &Path) -> io::Result<()> {
+    let file = std::fs::File::options().write(true).open(path)?;
+    file.set_modified(SystemTime::now())?;
+    USED_UPLOADS
+        .lock()
+        .unwrap()
+        .insert(canonical_upload_path(path));
+    Ok(())
+}
+
+/// Returns whether a file was marked as in use with `touch_upload`.
+pub fn is_used_upload(path: &Path) -> bool {
+    USED_UPLOADS
+        .lock()
+        .unwrap()
+        .contains(&canonical_upload_path(path))
+}
+
 /// Asynchronously cleans up old uploaded files from a specified directory.
 ///
 /// This function:
 ///
-/// 1. **Directory Check**: Converts the provided directory path to a `PathBuf` and checks if it exists.
+/// 1. **Directory Check**: Checks if the provided directory exists.
 /// 2. **File Iteration**: Asynchronously iterates over files in the directory.
-/// 3. **Age Calculation**: Determines the age of each file by comparing the current time with the last modified time.
-/// 4. **File Deletion**: Deletes files that exceed the specified maximum age (`max_age`).
+/// 3. **Pinned Files**: Skips the files for which `is_pinned` returns `true`, whatever their age.
+/// 4. **Age Calculation**: Determines the age of each file by comparing the current time with the last modified time.
+/// 5. **File Deletion**: Deletes files that exceed the specified maximum age (`max_age`), or the grace period
+///    (`used_max_age`) for the files for which `is_used` returns `true`.
+/// 6. **Used Files**: Forgets the files marked with `touch_upload` that no longer exist.
 ///
 /// # Parameters
-/// - `dir`: The directory path as a `&str`.
+/// - `dir`: The directory path.
 /// - `max_age`: The maximum age for files as a `Duration`.
+/// - `used_max_age`: The maximum age of the files that were generated from, usually longer than `max_age`.
+/// - `is_pinned`: Tells whether a file is still referenced and must be kept (e.g. `is_pinned_upload`).
+/// - `is_used`: Tells whether a file was generated from (e.g. `is_used_upload`).
 ///
 /// # Returns
 /// - `Ok(())` if the cleanup is successful.
 /// - An `std::io::Result` error if any I/O operations fail.
-pub async fn clean_old_uploads(dir: &str, max_age: Duration) -> std::io::Result<()> {
-    let upload_dir = PathBuf::from(dir);
+pub async fn clean_old_uploads<F, G>(
+    dir: &Path,
+    max_age: Duration,
+    used_max_age: Duration,
+    is_pinned: F,
+    is_used: G,
+) -> std::io::Result<()>
+where
+    F: Fn(&Path) -> bool,
+    G: Fn(&Path) -> bool,
+{
+    let upload_dir = dir.to_path_buf();
     if upload_dir.exists() {
         let mut entries = fs::read_dir(upload_dir).await?;
         while let Some(entry) = entries.next_entry().await? {
+            if is_pinned(&entry.path()) {
+                continue;
+            }
             let metadata = entry.metadata().await?;
             let modified = metadata.modified()?;
             let age = SystemTime::now()
                 .duration_since(modified)
                 .unwrap_or(Duration::from_secs(0));
-            if age > max_age {
+            let limit = if is_used(&entry.path()) {
+                used_max_age
+            } else {
+                max_age
+            };
+            if age > limit {
+                let used_path = canonical_upload_path(&entry.path());
                 fs::remove_file(entry.path()).await?;
+                USED_UPLOADS.lock().unwrap().remove(&used_path);
             }
         }
     }
+    // Forget the used files that were removed some other way, so the set only ever holds existing uploads
+    USED_UPLOADS.lock().unwrap().retain(|path| path.exists());
     Ok(())
 }
 
+/// The longest file name, in bytes, kept by `sanitize_file_name` (the usual filesystem limit).
+pub const MAX_FILE_NAME_LEN: usize = 255;
+
 /// Sanitizes a given file name by removing potentially dangerous or invalid characters.
 ///
-/// This function removes instances of "..", "/", and "\\" from the file name to prevent directory traversal attacks.
+/// This function:
+///
+/// 1. **Path Components**: Removes instances of "..", "/", and "\\" to prevent directory traversal attacks.
+/// 2. **Control Characters**: Removes null bytes and other control characters.
+/// 3. **Whitespace**: Collapses runs of whitespace into a single space and trims both ends.
+/// 4. **Hidden Files**: Strips leading dots, so the name can't hide the file.
+/// 5. **Length**: Truncates the name to `MAX_FILE_NAME_LEN` bytes, keeping a short extension.
+/// 6. **Fallback**: Replaces a name with nothing left by a random `file_<suffix>` name.
 ///
 /// # Parameters
 /// - `file_name`: The file name to sanitize as a `&str`.
 ///
 /// # Returns
-/// - A `String` containing the sanitized file name.
+/// - A non-empty `String` containing the sanitized file name.
 pub fn sanitize_file_name(file_name: &str) -> String {
-    file_name
+    let stripped: String = file_name
         .replace("..", "")
         .replace("/", "")
         .replace("\\", "")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let collapsed = stripped.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let name = collapsed.trim_start_matches(['.', ' ']);
+
+    let name = if name.len() > MAX_FILE_NAME_LEN {
+        // Keep a short extension so the truncated file still opens with the right program
+        let extension = name
+            .rfind('.')
+            .map(|dot| &name[dot..])
+            .filter(|extension| extension.len() <= 16)
+            .unwrap_or("");
+        let mut end = MAX_FILE_NAME_LEN - extension.len();
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}", name[..end].trim_end(), extension)
+    } else {
+        name.to_string()
+    };
+
+    if name.is_empty() {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        return format!("file_{}", suffix);
+    }
+    name
+}
+
+/// Computes the hex-encoded SHA-256 hash of some content.
+///
+/// # Parameters
+/// - `content`: The bytes to hash.
+///
+/// # Returns
+/// - A 64-character lowercase hexadecimal `String`.
+pub fn content_hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Asynchronously saves an extracted MSCX score under a path derived from its content.
+///
+/// This function:
+///
+/// 1. **Stable Naming**: Names the file `extracted_<hash>.mscx` after the SHA-256 of the content, so the same score
+///    always ends up at the same path, whoever uploads it and however many times.
+/// 2. **Deduplication**: If that file already holds the same content, it is kept and only its modification time is
+///    refreshed so `clean_old_uploads` treats it as a fresh upload.
+/// 3. **Collision Fallback**: If the file exists with different content, the score is saved as
+///    `extracted_file_<upload_id>.mscx` instead.
+///
+/// Writes are retried on transient errors with `retry_io`.
+///
+/// # Parameters
+/// - `dir`: The upload directory.
+/// - `content`: The MSCX content to save.
+/// - `upload_id`: The `{timestamp}_{suffix}` id of the upload, used by the fallback name.
+///
+/// # Returns
+/// - `Ok(PathBuf)` with the path the score was saved to.
+/// - An `std::io::Result` error if the file cannot be read or written.
+pub async fn save_extracted_mscx(
+    dir: &Path,
+    content: &str,
+    upload_id: &str,
+) -> io::Result<PathBuf> {
+    let hashed_path = dir.join(format!(
+        "extracted_{}.mscx",
+        content_hash(content.as_bytes())
+    ));
+
+    match fs::read(&hashed_path).await {
+        Ok(existing) if existing == content.as_bytes() => {
+            let file = std::fs::File::options().write(true).open(&hashed_path)?;
+            file.set_modified(SystemTime::now())?;
+            Ok(hashed_path)
+        }
+        Ok(_) => {
+            let fallback_path = dir.join(sanitize_file_name(&format!(
+                "extracted_file_{}.mscx",
+                upload_id
+            )));
+            retry_io("write the extracted score", || {
+                fs::write(&fallback_path, content)
+            })
+            .await?;
+            Ok(fallback_path)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            retry_io("write the extracted score", || {
+                fs::write(&hashed_path, content)
+            })
+            .await?;
+            Ok(hashed_path)
+        }
+        Err(e) => Err(e),
+    }
 }
 
 /// Asynchronously reads the content of an MSCX file into a string.
@@ -109,3 +400,178 @@ pub fn is_valid_zip(zip: &mut zip::ZipArchive<std::fs::File>) -> bool {
 
     true
 }
+
+/// Picks the primary score among the `.mscx` entries of an MSCZ archive.
+///
+/// Some archives (e.g. with linked parts) hold more than one `.mscx` file, and the first one isn't necessarily the
+/// full score. This function:
+///
+/// 1. **Base Name Match**: Prefers the entry whose file stem equals the stem of the uploaded file name
+///    (case-insensitive), as MuseScore names the main score after the file.
+/// 2. **Largest Entry**: Otherwise falls back to the largest entry, since the full score contains every part.
+///
+/// # Parameters
+/// - `entries`: The name and uncompressed size of each `.mscx` entry, in archive order.
+/// - `upload_name`: The file name the archive was uploaded with, if known.
+///
+/// # Returns
+/// - `Some(index)` of the chosen entry in `entries`, or `None` if there are no entries.
+pub fn select_primary_mscx(entries: &[(String, u64)], upload_name: Option<&str>) -> Option<usize> {
+    let stem = |name: &str| {
+        Path::new(name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+    };
+
+    let upload_stem = upload_name.and_then(stem);
+    if let Some(upload_stem) = upload_stem {
+        if let Some(index) = entries
+            .iter()
+            .position(|(name, _)| stem(name).as_deref() == Some(upload_stem.as_str()))
+        {
+            return Some(index);
+        }
+    }
+
+    // `max_by_key` keeps the last maximum, so ties are reversed first to prefer the earliest entry
+    entries
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, (_, size))| *size)
+        .map(|(index, _)| index)
+}
+
+/// Resolves a client-supplied path to a file inside the upload directory.
+///
+/// The path is canonicalized, so `..` components and symbolic links cannot be used to reach a file elsewhere.
+///
+/// # Parameters
+/// - `path`: The path as sent by the client (e.g. the `mscx_path` of the part-selection form).
+/// - `upload_dir`: The configured upload directory.
+///
+/// # Returns
+/// - `Some(PathBuf)` with the canonical path if it names an existing file inside `upload_dir`.
+/// - `None` if the file does not exist or lies outside the upload directory.
+pub fn resolve_upload_path(path: &str, upload_dir: &Path) -> Option<PathBuf> {
+    let upload_dir = std::fs::canonicalize(upload_dir).ok()?;
+    let path = std::fs::canonicalize(path).ok()?;
+    (path.starts_with(&upload_dir) && path.is_file()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_removes_null_bytes() {
+        assert_eq!(sanitize_file_name("song\0.mscz"), "song.mscz");
+    }
+
+    #[test]
+    fn sanitize_truncates_long_names_keeping_the_extension() {
+        let name = format!("{}.mscz", "a".repeat(500));
+        let sanitized = sanitize_file_name(&name);
+        assert_eq!(sanitized.len(), MAX_FILE_NAME_LEN);
+        assert!(sanitized.ends_with(".mscz"));
+    }
+
+    #[actix_web::test]
+    async fn transient_error_is_retried_once_more() {
+        let mut calls = 0;
+        let result = retry_io("write the upload", || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt == 1 {
+                    Err(io::Error::from(io::ErrorKind::Interrupted))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(calls, 2);
+    }
+
+    #[actix_web::test]
+    async fn permanent_error_is_not_retried() {
+        let mut calls = 0;
+        let result: io::Result<()> = retry_io("write the upload", || {
+            calls += 1;
+            async { Err(io::Error::from(io::ErrorKind::PermissionDenied)) }
+        })
+        .await;
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+    }
+
+    #[actix_web::test]
+    async fn same_content_is_extracted_to_the_same_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "<museScore version=\"4.20\"/>";
+
+        let first = save_extracted_mscx(dir.path(), content, "1_first")
+            .await
+            .unwrap();
+        let second = save_extracted_mscx(dir.path(), content, "2_second")
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(
+            first.file_name().unwrap().to_str().unwrap(),
+            format!("extracted_{}.mscx", content_hash(content.as_bytes()))
+        );
+
+        // A different file already at the hashed path is kept, and the score falls back to the upload id
+        std::fs::write(&first, "<museScore/>").unwrap();
+        let fallback = save_extracted_mscx(dir.path(), content, "3_third")
+            .await
+            .unwrap();
+        assert_eq!(fallback, dir.path().join("extracted_file_3_third.mscx"));
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "<museScore/>");
+    }
+
+    #[actix_web::test]
+    async fn cleanup_keeps_pinned_uploads() {
+        let dir = tempfile::tempdir().unwrap();
+        let pinned = dir.path().join("pinned.mscx");
+        let unpinned = dir.path().join("unpinned.mscx");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for path in [&pinned, &unpinned] {
+            std::fs::write(path, "<museScore/>").unwrap();
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(an_hour_ago).unwrap();
+        }
+
+        // Pinned twice through different spellings of its path, and released once
+        pin_upload(&pinned);
+        pin_upload(&dir.path().join(".").join("pinned.mscx"));
+        unpin_upload(&pinned);
+
+        clean_old_uploads(
+            dir.path(),
+            Duration::from_secs(600),
+            Duration::from_secs(600),
+            is_pinned_upload,
+            is_used_upload,
+        )
+        .await
+        .unwrap();
+        assert!(pinned.exists());
+        assert!(!unpinned.exists());
+
+        unpin_upload(&pinned);
+        assert!(!is_pinned_upload(&pinned));
+    }
+
+    #[test]
+    fn sanitize_replaces_a_name_of_dots() {
+        let sanitized = sanitize_file_name("....");
+        assert!(sanitized.starts_with("file_"));
+        assert_eq!(sanitized.len(), "file_".len() + 8);
+    }
+}
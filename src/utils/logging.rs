@@ -1,3 +1,87 @@
+use crate::utils::config::{LOG_FORMAT_ENV, LOG_LEVEL_ENV};
+use std::io::Write;
+
+/// The format of the log lines written to stderr.
+///
+/// - **`Plain`**: The default `env_logger` format, for reading in a terminal.
+/// - **`Json`**: One JSON object per line with `timestamp`, `level`, `target` and `message`, for log ingestion.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    Json,
+}
+
+impl LogFormat {
+    /// Parses the value of `HANDFLOW_LOG_FORMAT` (case-insensitive).
+    ///
+    /// # Returns
+    /// `Some(LogFormat)` for `plain` or `json`, or `None` for anything else.
+    pub fn from_env_value(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "plain" => Some(LogFormat::Plain),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Formats a log record as a single JSON line (without the trailing newline).
+///
+/// # Parameters
+/// - `timestamp`: The time the record was logged, as written by the logger.
+/// - `record`: The log record.
+///
+/// # Returns
+/// A JSON object with the `timestamp`, `level`, `target` and `message` of the record.
+pub fn json_log_line(timestamp: &str, record: &log::Record) -> String {
+    serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+/// Initializes the logger from the environment.
+///
+/// This function:
+///
+/// 1. **Reads the Filter**: Uses `RUST_LOG` like `env_logger` always did, falling back to `HANDFLOW_LOG_LEVEL` when it
+///    is unset, so the verbosity can be changed without recompiling.
+/// 2. **Picks the Format**: Writes plain lines, or JSON lines built by `json_log_line` when `HANDFLOW_LOG_FORMAT` is
+///    `json`. An unknown format falls back to plain lines with a warning.
+pub fn init_logger() {
+    let mut builder = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Ok(filter) = std::env::var(LOG_LEVEL_ENV) {
+            builder.parse_filters(&filter);
+        }
+    }
+
+    let format_value = std::env::var(LOG_FORMAT_ENV).ok();
+    let format = format_value
+        .as_deref()
+        .and_then(LogFormat::from_env_value)
+        .unwrap_or_default();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let timestamp = buf.timestamp().to_string();
+            writeln!(buf, "{}", json_log_line(&timestamp, record))
+        });
+    }
+    builder.init();
+
+    if let Some(value) = format_value.filter(|value| LogFormat::from_env_value(value).is_none()) {
+        log::warn!(
+            "Unknown {} value {:?}, using plain logs",
+            LOG_FORMAT_ENV,
+            value
+        );
+    }
+}
+
 /// Logs an error message along with a formatted error value using the `log` crate.
 ///
 /// This function:
@@ -14,3 +98,38 @@
 pub fn log_error<T: std::fmt::Debug>(message: &str, err: T) {
     log::error!("{}: {:?}", message, err);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_log_line_is_one_parseable_object() {
+        let args = format_args!("Rejected score: \"big\"\nover the limit");
+        let record = log::Record::builder()
+            .args(args)
+            .level(log::Level::Warn)
+            .target("handflow::upload")
+            .build();
+
+        let line = json_log_line("2026-10-16T09:00:00Z", &record);
+        assert!(!line.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({
+                "timestamp": "2026-10-16T09:00:00Z",
+                "level": "WARN",
+                "target": "handflow::upload",
+                "message": "Rejected score: \"big\"\nover the limit",
+            })
+        );
+    }
+
+    #[test]
+    fn log_format_is_read_case_insensitively() {
+        assert_eq!(LogFormat::from_env_value(" JSON "), Some(LogFormat::Json));
+        assert_eq!(LogFormat::from_env_value("plain"), Some(LogFormat::Plain));
+        assert_eq!(LogFormat::from_env_value("xml"), None);
+    }
+}
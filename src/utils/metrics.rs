@@ -0,0 +1,209 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of uploads accepted (i.e. not rejected by the upload limit).
+static UPLOADS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of generate and export requests accepted (i.e. not rejected by the generate limit).
+static GENERATES_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of uploads rejected with `429 Too Many Requests`.
+static UPLOAD_REJECTIONS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of uploads rejected with `415 Unsupported Media Type`.
+static UPLOAD_UNSUPPORTED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of generate and export requests rejected with `429 Too Many Requests`.
+static GENERATE_REJECTIONS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of generate and export requests rejected with `415 Unsupported Media Type` (none so far).
+static GENERATE_UNSUPPORTED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of scores that failed to parse.
+static PARSE_ERRORS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The upper bounds, in seconds, of the parse duration histogram buckets (`+Inf` is implied).
+const PARSE_DURATION_BUCKETS: [f64; 8] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+/// The number of parses that took at most each bucket's bound, followed by the `+Inf` bucket.
+static PARSE_DURATION_COUNTS: [AtomicU64; 9] = [const { AtomicU64::new(0) }; 9];
+
+/// The total time spent parsing, in microseconds.
+static PARSE_DURATION_SUM_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// The request kinds that are counted separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+    Upload,
+    Generate,
+}
+
+/// Counts a request accepted by the upload or generate limit.
+pub fn record_request(endpoint: Endpoint) {
+    match endpoint {
+        Endpoint::Upload => UPLOADS_TOTAL.fetch_add(1, Ordering::Relaxed),
+        Endpoint::Generate => GENERATES_TOTAL.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+/// Why a request was rejected, the `reason` label of the rejection counter.
+///
+/// - **`TooManyRequests`**: The concurrency limit was reached (`429 Too Many Requests`).
+/// - **`UnsupportedType`**: The uploaded file isn't an accepted format (`415 Unsupported Media Type`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectionReason {
+    TooManyRequests,
+    UnsupportedType,
+}
+
+/// Counts a rejected request.
+pub fn record_rejection(endpoint: Endpoint, reason: RejectionReason) {
+    let counter = match (endpoint, reason) {
+        (Endpoint::Upload, RejectionReason::TooManyRequests) => &UPLOAD_REJECTIONS_TOTAL,
+        (Endpoint::Upload, RejectionReason::UnsupportedType) => &UPLOAD_UNSUPPORTED_TOTAL,
+        (Endpoint::Generate, RejectionReason::TooManyRequests) => &GENERATE_REJECTIONS_TOTAL,
+        (Endpoint::Generate, RejectionReason::UnsupportedType) => &GENERATE_UNSUPPORTED_TOTAL,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a score that failed to parse.
+pub fn record_parse_error() {
+    PARSE_ERRORS_TOTAL.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Adds the duration of a score parse to the histogram.
+///
+/// # Parameters
+/// - `duration`: How long the parse took, whether it succeeded or not.
+pub fn record_parse_duration(duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    let bucket = PARSE_DURATION_BUCKETS
+        .iter()
+        .position(|&bound| seconds <= bound)
+        .unwrap_or(PARSE_DURATION_BUCKETS.len());
+    PARSE_DURATION_COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+    PARSE_DURATION_SUM_MICROS.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+///
+/// # Returns
+/// The metrics page as a `String`.
+pub fn render_metrics() -> String {
+    let mut out = String::new();
+    let requests = [("upload", &UPLOADS_TOTAL), ("generate", &GENERATES_TOTAL)];
+    let rejections = [
+        ("upload", "too_many_requests", &UPLOAD_REJECTIONS_TOTAL),
+        ("upload", "unsupported_type", &UPLOAD_UNSUPPORTED_TOTAL),
+        ("generate", "too_many_requests", &GENERATE_REJECTIONS_TOTAL),
+        ("generate", "unsupported_type", &GENERATE_UNSUPPORTED_TOTAL),
+    ];
+
+    out.push_str("# HELP handflow_requests_total Requests accepted, by endpoint.\n");
+    out.push_str("# TYPE handflow_requests_total counter\n");
+    for (endpoint, accepted) in &requests {
+        let _ = writeln!(
+            out,
+            "handflow_requests_total{{endpoint=\"{}\"}} {}",
+            endpoint,
+            accepted.load(Ordering::Relaxed)
+        );
+    }
+
+    out.push_str(
+        "# HELP handflow_rejections_total Requests rejected, by endpoint and reason (too_many_requests for 429, unsupported_type for 415).\n",
+    );
+    out.push_str("# TYPE handflow_rejections_total counter\n");
+    for (endpoint, reason, rejected) in &rejections {
+        let _ = writeln!(
+            out,
+            "handflow_rejections_total{{endpoint=\"{}\",reason=\"{}\"}} {}",
+            endpoint,
+            reason,
+            rejected.load(Ordering::Relaxed)
+        );
+    }
+
+    out.push_str("# HELP handflow_parse_errors_total Scores that failed to parse.\n");
+    out.push_str("# TYPE handflow_parse_errors_total counter\n");
+    let _ = writeln!(
+        out,
+        "handflow_parse_errors_total {}",
+        PARSE_ERRORS_TOTAL.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# HELP handflow_parse_duration_seconds Time spent parsing a score.\n");
+    out.push_str("# TYPE handflow_parse_duration_seconds histogram\n");
+    let mut cumulative = 0;
+    for (index, count) in PARSE_DURATION_COUNTS.iter().enumerate() {
+        cumulative += count.load(Ordering::Relaxed);
+        let bound = PARSE_DURATION_BUCKETS
+            .get(index)
+            .map_or("+Inf".to_string(), |bound| bound.to_string());
+        let _ = writeln!(
+            out,
+            "handflow_parse_duration_seconds_bucket{{le=\"{}\"}} {}",
+            bound, cumulative
+        );
+    }
+    let _ = writeln!(
+        out,
+        "handflow_parse_duration_seconds_sum {}",
+        PARSE_DURATION_SUM_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    );
+    let _ = writeln!(out, "handflow_parse_duration_seconds_count {}", cumulative);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the value of a sample from a rendered metrics page.
+    fn sample(page: &str, name: &str) -> u64 {
+        page.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_durations_fill_the_cumulative_histogram() {
+        let before = render_metrics();
+        record_parse_duration(Duration::from_secs(3));
+        record_parse_duration(Duration::from_secs(60));
+        let after = render_metrics();
+
+        // Other tests parse scores concurrently, so the counts grow by at least the recorded parses
+        let grown = |name: &str| sample(&after, name) - sample(&before, name);
+        assert!(grown("handflow_parse_duration_seconds_bucket{le=\"5\"}") >= 1);
+        assert!(grown("handflow_parse_duration_seconds_bucket{le=\"+Inf\"}") >= 2);
+        assert_eq!(
+            sample(
+                &after,
+                "handflow_parse_duration_seconds_bucket{le=\"+Inf\"}"
+            ),
+            sample(&after, "handflow_parse_duration_seconds_count")
+        );
+
+        let buckets: Vec<u64> = after
+            .lines()
+            .filter(|line| line.starts_with("handflow_parse_duration_seconds_bucket"))
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(buckets.len(), PARSE_DURATION_BUCKETS.len() + 1);
+        assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn rejections_are_counted_by_endpoint_and_reason() {
+        let name = "handflow_rejections_total{endpoint=\"generate\",reason=\"unsupported_type\"}";
+        let before = sample(&render_metrics(), name);
+        record_rejection(Endpoint::Generate, RejectionReason::UnsupportedType);
+
+        assert!(sample(&render_metrics(), name) > before);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::templates::parser::{ScoreMeasure, ScoreNote};
+
+/// The resolution of the exported MIDI file, in ticks per quarter note.
+pub const TICKS_PER_QUARTER: u32 = 480;
+
+/// The tempo written to the exported file, in microseconds per quarter note (120 BPM).
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// How much longer a chord or rest under a fermata is held.
+const FERMATA_STRETCH: f64 = 2.0;
+
+/// The General MIDI program used for the handpan track ("Steel Drums", zero-based).
+pub const HANDPAN_PROGRAM: u8 = 114;
+
+/// Converts a MuseScore `durationType` into its length in quarter-note beats.
+///
+/// # Parameters
+/// - `duration`: The duration name (e.g. `"eighth"`).
+/// - `time_signature`: The measure's time signature as `"N|D"`, used to size `"measure"` durations. An empty value
+///   stands for 4/4, MuseScore's default when a score has no time signature.
+///
+/// # Returns
+/// `Some(beats)` for a known duration, or `None` if the duration (or the time signature needed by `"measure"`) is not understood.
+pub fn duration_to_beats(duration: &str, time_signature: &str) -> Option<f64> {
+    let beats = match duration {
+        "longa" => 16.0,
+        "breve" => 8.0,
+        "whole" => 4.0,
+        "half" => 2.0,
+        "quarter" => 1.0,
+        "eighth" => 0.5,
+        "16th" => 0.25,
+        "32nd" => 0.125,
+        "64th" => 0.0625,
+        "128th" => 0.03125,
+        "measure" => {
+            let time_signature = if time_signature.is_empty() {
+                "4|4"
+            } else {
+                time_signature
+            };
+            let (numerator, denominator) = time_signature.split_once('|')?;
+            let numerator: f64 = numerator.trim().parse().ok()?;
+            let denominator: f64 = denominator.trim().parse().ok()?;
+            if denominator <= 0.0 {
+                return None;
+            }
+            numerator * 4.0 / denominator
+        }
+        _ => return None,
+    };
+    Some(beats)
+}
+
+/// Works out how long an exported chord or rest lasts, in quarter-note beats.
+///
+/// The length found by the parser (`duration_ticks`) is used whenever there is one, as it includes dots and tuplets
+/// and makes a `"measure"` duration follow irregular (e.g. pickup) measures. Otherwise (for grace notes and notes not
+/// read from a score), a `"measure"` duration takes the length of the active time signature, other durations follow
+/// `duration_to_beats`, and unknown ones count as a quarter note.
+///
+/// # Parameters
+/// - `note`: The first note of the chord or rest.
+/// - `time_signature`: The active time signature as `"N|D"`, or empty for 4/4.
+///
+/// # Returns
+/// The length in beats.
+pub fn note_beats(note: &ScoreNote, time_signature: &str) -> f64 {
+    if note.duration_ticks > 0 {
+        return note.duration_ticks as f64 / TICKS_PER_QUARTER as f64;
+    }
+    duration_to_beats(&note.duration, time_signature).unwrap_or(1.0)
+}
+
+/// Appends a MIDI variable-length quantity to `out`.
+fn write_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+/// Builds a single-track (format 0) Standard MIDI File from parsed measures.
+///
+/// This function:
+///
+/// 1. **Writes the Header**: Emits the `MThd` chunk with `TICKS_PER_QUARTER` resolution.
+/// 2. **Sets Up the Track**: Writes the tempo, the program change and every time signature change.
+/// 3. **Emits Notes**: Plays each chord's notes together with their parsed velocity, and advances time for rests.
+///
+/// Whole-measure chords and rests last as long as their measure (see `note_beats`), unknown durations are played as
+/// quarter notes so the remaining timeline stays aligned, and chords or rests under a fermata are held
+/// `FERMATA_STRETCH` times longer.
+///
+/// # Parameters
+/// - `measures`: The measures returned by `parse_mscx_score`.
+/// - `play_only_inscale`: When `true`, notes that are not on the handpan are left out, so the track only holds
+///   playable notes; a chord left without notes is kept as silence.
+/// - `fill_glissandos`: When `true`, a note starting a glissando is played as a chromatic run up to (but not
+///   including) its target note, splitting the note's length evenly; otherwise it is held like any other note.
+/// - `program`: The zero-based General MIDI program of the track, usually `HANDPAN_PROGRAM`.
+///
+/// # Returns
+/// The bytes of the `.mid` file.
+pub fn export_midi(
+    measures: &[ScoreMeasure],
+    play_only_inscale: bool,
+    fill_glissandos: bool,
+    program: u8,
+) -> Vec<u8> {
+    let mut track = Vec::new();
+    let mut pending_delta: u32 = 0;
+
+    // Tempo and program change at the start of the track
+    write_vlq(&mut track, 0);
+    track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+    track.extend_from_slice(&DEFAULT_TEMPO.to_be_bytes()[1..]);
+    write_vlq(&mut track, 0);
+    track.extend_from_slice(&[0xC0, program.min(127)]);
+
+    // The pitch each chord's glissando slides to, read from the first glissando target of the next chord
+    let chords: Vec<_> = measures
+        .iter()
+        .flat_map(|measure| measure.chords.iter().filter(|chord| !chord.is_empty()))
+        .collect();
+    let glissando_targets: Vec<Option<u8>> = chords
+        .iter()
+        .enumerate()
+        .map(|(index, chord)| {
+            if !fill_glissandos || !chord.iter().any(|note| note.glissando_start) {
+                return None;
+            }
+            chords
+                .get(index + 1)?
+                .iter()
+                .find(|note| note.glissando_end && note.is_played(play_only_inscale))
+                .map(|note| note.pitch.min(127) as u8)
+        })
+        .collect();
+    let mut chord_index = 0;
+
+    let mut time_signature = String::new();
+    for measure in measures {
+        if !measure.time_signature.is_empty() && measure.time_signature != time_signature {
+            time_signature = measure.time_signature.clone();
+            if let Some((numerator, denominator)) = time_signature.split_once('|') {
+                if let (Ok(numerator), Ok(denominator)) =
+                    (numerator.parse::<u8>(), denominator.parse::<u32>())
+                {
+                    if denominator.is_power_of_two() {
+                        write_vlq(&mut track, pending_delta);
+                        pending_delta = 0;
+                        track.extend_from_slice(&[
+                            0xFF,
+                            0x58,
+                            0x04,
+                            numerator,
+                            denominator.trailing_zeros() as u8,
+                            24,
+                            8,
+                        ]);
+                    }
+                }
+            }
+        }
+
+        for chord in &measure.chords {
+            let Some(first) = chord.first() else {
+                continue;
+            };
+            let mut beats = note_beats(first, &time_signature);
+            if first.fermata {
+                beats *= FERMATA_STRETCH;
+            }
+            let ticks = (beats * TICKS_PER_QUARTER as f64).round() as u32;
+
+            let glissando_target = glissando_targets[chord_index];
+            chord_index += 1;
+
+            let mut notes: Vec<(u8, u8)> = chord
+                .iter()
+                .filter(|note| note.is_played(play_only_inscale))
+                .map(|note| (note.pitch.min(127) as u8, note.velocity.clamp(1, 127)))
+                .collect();
+
+            if notes.is_empty() {
+                pending_delta += ticks;
+                continue;
+            }
+
+            // The sliding note is taken out of the held notes and played as a run of semitones instead
+            let mut run: Vec<u8> = Vec::new();
+            let mut run_velocity = 0;
+            if let Some(target) = glissando_target {
+                if let Some(position) = chord
+                    .iter()
+                    .filter(|note| note.is_played(play_only_inscale))
+                    .position(|note| note.glissando_start)
+                {
+                    let (start, velocity) = notes[position];
+                    if start.abs_diff(target) > 1 {
+                        run = if start < target {
+                            (start..target).collect()
+                        } else {
+                            ((target + 1)..=start).rev().collect()
+                        };
+                        run_velocity = velocity;
+                        notes.remove(position);
+                    }
+                }
+            }
+
+            for &(pitch, velocity) in &notes {
+                write_vlq(&mut track, pending_delta);
+                pending_delta = 0;
+                track.extend_from_slice(&[0x90, pitch, velocity]);
+            }
+            let step = ticks / run.len().max(1) as u32;
+            for (position, &pitch) in run.iter().enumerate() {
+                write_vlq(&mut track, pending_delta);
+                pending_delta = 0;
+                track.extend_from_slice(&[0x90, pitch, run_velocity]);
+                // The last step absorbs the rounding so the chord keeps its length
+                let length = if position + 1 == run.len() {
+                    ticks - step * position as u32
+                } else {
+                    step
+                };
+                write_vlq(&mut track, length);
+                track.extend_from_slice(&[0x80, pitch, 0]);
+            }
+            if run.is_empty() {
+                pending_delta = ticks;
+            }
+            for &(pitch, _) in &notes {
+                write_vlq(&mut track, pending_delta);
+                pending_delta = 0;
+                track.extend_from_slice(&[0x80, pitch, 0]);
+            }
+        }
+    }
+
+    // End of track
+    write_vlq(&mut track, pending_delta);
+    track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut midi = Vec::with_capacity(22 + track.len());
+    midi.extend_from_slice(b"MThd");
+    midi.extend_from_slice(&6u32.to_be_bytes());
+    midi.extend_from_slice(&0u16.to_be_bytes());
+    midi.extend_from_slice(&1u16.to_be_bytes());
+    midi.extend_from_slice(&(TICKS_PER_QUARTER as u16).to_be_bytes());
+    midi.extend_from_slice(b"MTrk");
+    midi.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    midi.extend_from_slice(&track);
+    midi
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::parser::parse_mscx_score;
+    use crate::utils::scales::{get_handpan_scale, TranspositionWeights};
+
+    /// Finds the velocity of the first note-on event of a pitch in an exported file.
+    fn note_on_velocity(midi: &[u8], pitch: u8) -> Option<u8> {
+        midi.windows(3)
+            .find(|event| event[0] == 0x90 && event[1] == pitch && event[2] > 0)
+            .map(|event| event[2])
+    }
+
+    #[test]
+    fn forte_and_accented_notes_are_louder_than_piano() {
+        let xml = "<museScore version=\"4.20\"><Score>\
+            <Part id=\"1\"><Staff id=\"1\"/><trackName>Piano</trackName></Part>\
+            <Staff id=\"1\"><Measure><voice>\
+            <Dynamic><subtype>p</subtype></Dynamic>\
+            <Chord><durationType>quarter</durationType><Note><pitch>62</pitch><tpc>16</tpc></Note></Chord>\
+            <Chord><durationType>quarter</durationType><Articulation><subtype>articAccentAbove</subtype></Articulation>\
+            <Note><pitch>64</pitch><tpc>18</tpc></Note></Chord>\
+            <Dynamic><subtype>f</subtype></Dynamic>\
+            <Chord><durationType>half</durationType><Note><pitch>65</pitch><tpc>13</tpc></Note></Chord>\
+            </voice></Measure></Staff></Score></museScore>";
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let (measures, _, _) = parse_mscx_score(
+            xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+
+        let midi = export_midi(&measures, false, false, HANDPAN_PROGRAM);
+        let piano = note_on_velocity(&midi, 62).unwrap();
+        let accented = note_on_velocity(&midi, 64).unwrap();
+        let forte = note_on_velocity(&midi, 65).unwrap();
+        assert!(accented > piano);
+        assert!(forte > piano);
+    }
+
+    #[test]
+    fn per_note_velocities_offset_or_replace_the_dynamic() {
+        let xml = "<museScore version=\"4.20\"><Score>\
+            <Part id=\"1\"><Staff id=\"1\"/><trackName>Piano</trackName></Part>\
+            <Staff id=\"1\"><Measure><voice>\
+            <Dynamic><subtype>p</subtype></Dynamic>\
+            <Chord><durationType>quarter</durationType><Note><pitch>62</pitch><tpc>16</tpc></Note></Chord>\
+            <Chord><durationType>quarter</durationType>\
+            <Note><pitch>64</pitch><tpc>18</tpc><velocity>20</velocity></Note></Chord>\
+            <Chord><durationType>half</durationType>\
+            <Note><pitch>65</pitch><tpc>13</tpc><veloType>user</veloType><velocity>110</velocity></Note></Chord>\
+            </voice></Measure></Staff></Score></museScore>";
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let (measures, _, _) = parse_mscx_score(
+            xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+
+        let midi = export_midi(&measures, false, false, HANDPAN_PROGRAM);
+        // An offset is added to the piano dynamic, a user velocity is played as written
+        assert_eq!(note_on_velocity(&midi, 62), Some(49));
+        assert_eq!(note_on_velocity(&midi, 64), Some(69));
+        assert_eq!(note_on_velocity(&midi, 65), Some(110));
+    }
+
+    #[test]
+    fn full_measure_note_in_three_four_lasts_three_beats() {
+        let xml = "<museScore version=\"4.20\"><Score>\
+            <Part id=\"1\"><Staff id=\"1\"/><trackName>Piano</trackName></Part>\
+            <Staff id=\"1\"><Measure><voice>\
+            <TimeSig><sigN>3</sigN><sigD>4</sigD></TimeSig>\
+            <Chord><durationType>measure</durationType><Note><pitch>62</pitch><tpc>16</tpc></Note></Chord>\
+            </voice></Measure></Staff></Score></museScore>";
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let (measures, _, _) = parse_mscx_score(
+            xml,
+            1,
+            None,
+            &scale_notes,
+            None,
+            TranspositionWeights::default(),
+            0,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(note_beats(&measures[0].chords[0][0], "3|4"), 3.0);
+
+        // Without a parsed length, the time signature gives it
+        let unparsed = ScoreNote {
+            duration: "measure".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(note_beats(&unparsed, "3|4"), 3.0);
+    }
+
+    #[test]
+    fn note_beats_follow_dots_and_tuplets() {
+        let dotted_quarter = ScoreNote {
+            duration: "quarter".to_string(),
+            duration_ticks: 720,
+            ..Default::default()
+        };
+        let triplet_eighth = ScoreNote {
+            duration: "eighth".to_string(),
+            duration_ticks: 160,
+            ..Default::default()
+        };
+        let grace = ScoreNote {
+            duration: "eighth".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(note_beats(&dotted_quarter, "4|4"), 1.5);
+        assert!((note_beats(&triplet_eighth, "4|4") - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(note_beats(&grace, "4|4"), 0.5);
+    }
+}
@@ -1,4 +1,10 @@
+pub mod config;
 pub mod file;
 pub mod logging;
+pub mod metrics;
+pub mod midi;
+pub mod musicxml;
 pub mod scales;
+pub mod staff;
 pub mod svg;
+pub mod tab;
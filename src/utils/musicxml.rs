@@ -0,0 +1,201 @@
+use crate::templates::html::sanitize_html;
+use crate::templates::parser::{ScoreMeasure, ScoreNote};
+use crate::utils::midi::{note_beats, TICKS_PER_QUARTER};
+
+/// The MuseScore durations that have a MusicXML `<type>` of their own (`"measure"` rests are written as whole rests).
+const NOTE_TYPES: [&str; 11] = [
+    "longa", "breve", "whole", "half", "quarter", "eighth", "16th", "32nd", "64th", "128th",
+    "measure",
+];
+
+/// Splits a spelled note (e.g. `"C♯4"`) into its MusicXML step, alteration and octave.
+///
+/// The octave is derived from the MIDI pitch rather than the name, so enharmonic spellings crossing the octave
+/// boundary (e.g. `B♯` sounding as C) land in the right one.
+///
+/// # Parameters
+/// - `note`: The `ScoreNote` to spell.
+///
+/// # Returns
+/// `Some((step, alter, octave))`, or `None` if the note name doesn't start with a letter from A to G.
+fn note_pitch(note: &ScoreNote) -> Option<(char, i32, i32)> {
+    let step = note
+        .note
+        .chars()
+        .next()
+        .filter(|c| ('A'..='G').contains(c))?;
+    let alter = note.note.matches('♯').count() as i32 - note.note.matches('♭').count() as i32;
+    let step_semitone = match step {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        _ => 11,
+    };
+    let octave = (note.pitch as i32 - alter - step_semitone).div_euclid(12) - 1;
+    Some((step, alter, octave))
+}
+
+/// Builds a single-part MusicXML (partwise) document from parsed measures.
+///
+/// This function:
+///
+/// 1. **Writes the Header**: Emits the work title and the part list with the part name.
+/// 2. **Writes the Attributes**: Uses `TICKS_PER_QUARTER` divisions, and writes every time signature change.
+/// 3. **Writes the Notes**: Writes each chord's notes (with `<chord/>` after the first one) and each rest with its
+///    pitch, duration and type. With `play_only_inscale`, the notes left out of playback (see `ScoreNote::is_played`)
+///    are left out here too, and a chord losing all of its notes is written as a rest so the measure keeps its length.
+///
+/// Notes keep their transposed pitch and spelling, so the file opens in notation software exactly as shown on the
+/// generated page. Durations come from `note_beats`, like in `export_midi`: dotted and tuplet notes keep their exact
+/// length, whole-measure notes and rests fill their measure and unknown durations are written as quarter notes.
+///
+/// # Parameters
+/// - `title`: The work title.
+/// - `part_name`: The name of the exported part.
+/// - `measures`: The measures returned by `parse_mscx_score`.
+/// - `play_only_inscale`: When `true`, notes that are not in the scale are left out, like in playback.
+///
+/// # Returns
+/// The MusicXML document as a `String`.
+pub fn export_musicxml(
+    title: &str,
+    part_name: &str,
+    measures: &[ScoreMeasure],
+    play_only_inscale: bool,
+) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+    xml.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+    xml.push_str("<score-partwise version=\"4.0\">\n");
+    xml.push_str(&format!(
+        "  <work><work-title>{}</work-title></work>\n",
+        sanitize_html(title)
+    ));
+    xml.push_str(&format!(
+        "  <part-list><score-part id=\"P1\"><part-name>{}</part-name></score-part></part-list>\n",
+        sanitize_html(part_name)
+    ));
+    xml.push_str("  <part id=\"P1\">\n");
+
+    let mut time_signature = String::new();
+    for (index, measure) in measures.iter().enumerate() {
+        xml.push_str(&format!("    <measure number=\"{}\">\n", index + 1));
+
+        let time_change =
+            !measure.time_signature.is_empty() && measure.time_signature != time_signature;
+        if index == 0 || time_change {
+            xml.push_str("      <attributes>\n");
+            if index == 0 {
+                xml.push_str(&format!(
+                    "        <divisions>{}</divisions>\n",
+                    TICKS_PER_QUARTER
+                ));
+            }
+            if time_change {
+                time_signature = measure.time_signature.clone();
+                if let Some((beats, beat_type)) = time_signature.split_once('|') {
+                    xml.push_str(&format!(
+                        "        <time><beats>{}</beats><beat-type>{}</beat-type></time>\n",
+                        sanitize_html(beats),
+                        sanitize_html(beat_type)
+                    ));
+                }
+            }
+            xml.push_str("      </attributes>\n");
+        }
+
+        for chord in &measure.chords {
+            // The notes left out of playback are left out here too; a chord left empty keeps its time as a rest
+            let mut written: Vec<(&ScoreNote, bool)> = chord
+                .iter()
+                .filter(|note| note.note == "Rest" || note.is_played(play_only_inscale))
+                .map(|note| (note, note.note == "Rest"))
+                .collect();
+            if written.is_empty() {
+                if let Some(first) = chord.first() {
+                    written.push((first, true));
+                }
+            }
+
+            for (position, (note, as_rest)) in written.into_iter().enumerate() {
+                let beats = note_beats(note, &time_signature);
+                let ticks = (beats * TICKS_PER_QUARTER as f64).round() as u32;
+                let note_type = match NOTE_TYPES.iter().find(|&&known| known == note.duration) {
+                    Some(&"longa") => "long",
+                    Some(&"measure") => "whole",
+                    Some(&known) => known,
+                    None => "quarter",
+                };
+
+                xml.push_str("      <note>\n");
+                if position > 0 {
+                    xml.push_str("        <chord/>\n");
+                }
+                match note_pitch(note) {
+                    Some((step, alter, octave)) if !as_rest => {
+                        xml.push_str(&format!("        <pitch><step>{}</step>", step));
+                        if alter != 0 {
+                            xml.push_str(&format!("<alter>{}</alter>", alter));
+                        }
+                        xml.push_str(&format!("<octave>{}</octave></pitch>\n", octave));
+                    }
+                    _ if note.duration == "measure" => {
+                        xml.push_str("        <rest measure=\"yes\"/>\n")
+                    }
+                    _ => xml.push_str("        <rest/>\n"),
+                }
+                xml.push_str(&format!("        <duration>{}</duration>\n", ticks));
+                xml.push_str(&format!("        <type>{}</type>\n", note_type));
+                xml.push_str("      </note>\n");
+            }
+        }
+
+        xml.push_str("    </measure>\n");
+    }
+
+    xml.push_str("  </part>\n");
+    xml.push_str("</score-partwise>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quarter note spelled `name`, `delta` semitones away from the field it was moved to.
+    fn quarter(pitch: u32, name: &str, delta: i32) -> ScoreNote {
+        ScoreNote {
+            pitch,
+            note: name.to_string(),
+            duration: "quarter".to_string(),
+            delta,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn play_only_inscale_leaves_out_moved_notes() {
+        let measures = vec![ScoreMeasure {
+            time_signature: "2|4".to_string(),
+            chords: vec![
+                vec![quarter(62, "D4", 0), quarter(66, "F♯4", 1)],
+                vec![quarter(61, "C♯4", -1)],
+            ],
+            ..Default::default()
+        }];
+
+        let all = export_musicxml("Song", "Piano", &measures, false);
+        assert!(all.contains("<step>F</step><alter>1</alter>"));
+        assert!(all.contains("<step>C</step><alter>1</alter>"));
+
+        let in_scale = export_musicxml("Song", "Piano", &measures, true);
+        assert!(in_scale.contains("<step>D</step>"));
+        assert!(!in_scale.contains("<step>F</step>"));
+        assert!(!in_scale.contains("<chord/>"));
+        assert!(!in_scale.contains("<step>C</step>"));
+        assert_eq!(in_scale.matches("<rest/>").count(), 1);
+    }
+}
@@ -1,183 +1,698 @@
-/// Generates a list of handpan scales with varying note counts.
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A scale definition read from the `HANDFLOW_SCALES_FILE` catalog.
+///
+/// Fields:
+/// - `name`: The scale name. A name matching a built-in scale overrides it.
+/// - `family`: The family the scale is listed under (e.g. `"Kurd"`); the scale's name when left out.
+/// - `midi`: The full scale as ascending MIDI notes, ding first (9 to 13 notes).
+/// - `tpc`: The TPC value spelling each MIDI note.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScaleDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub family: Option<String>,
+    pub midi: Vec<u8>,
+    pub tpc: Vec<i8>,
+}
+
+/// The layout of a scales file: a list of `[[scales]]` tables in TOML, or `{"scales": [...]}` in JSON.
+#[derive(Deserialize)]
+struct ScalesFile {
+    scales: Vec<ScaleDefinition>,
+}
+
+/// The scales loaded from `HANDFLOW_SCALES_FILE` at startup, if any.
+static CUSTOM_SCALES: OnceCell<Vec<ScaleDefinition>> = OnceCell::new();
+
+/// Parses and validates a scales file.
 ///
 /// This function:
 ///
-/// 1. **Defines Full Scales**: Initializes a set of predefined handpan scales, each with a name, a list of MIDI notes, and corresponding TPC (Tonnetz Pitch Class) values.
-/// 2. **Generates Variants**: For each scale, it generates variants with note counts ranging from 9 to 13 notes by clipping the full scale.
-/// 3. **Assigns IDs**: Each scale variant is assigned a unique ID to differentiate it.
-/// 4. **Returns**: A vector of tuples where each tuple contains:
-///     - A unique ID (`usize`)
-///     - The scale name (`&'static str`)
-///     - A vector of MIDI notes (`Vec<u8>`)
-///     - A vector of TPC values (`Vec<i8>`)
+/// 1. **Reads the File**: Loads the file content from disk.
+/// 2. **Parses It**: Uses TOML for `.toml` files and JSON otherwise.
+/// 3. **Validates Each Scale**: Checks that the name is not empty, that there are between 9 and 13 notes,
+///    that `midi` and `tpc` have the same length, that the notes are sorted in ascending order,
+///    and that every value is a valid MIDI note or TPC.
+///
+/// # Parameters
+/// - `path`: The path of the scales file.
+///
+/// # Returns
+/// - `Ok(Vec<ScaleDefinition>)` with the validated scales.
+/// - `Err(String)` describing the first problem found.
+pub fn load_scales_file(path: &Path) -> Result<Vec<ScaleDefinition>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let file: ScalesFile = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&content)
+            .map_err(|e| format!("Invalid TOML in {}: {}", path.display(), e))?
+    } else {
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))?
+    };
+
+    for scale in &file.scales {
+        if scale.name.trim().is_empty() {
+            return Err("A scale has an empty name".to_string());
+        }
+        if !(9..=13).contains(&scale.midi.len()) {
+            return Err(format!(
+                "Scale '{}' has {} notes, expected 9 to 13",
+                scale.name,
+                scale.midi.len()
+            ));
+        }
+        if scale.midi.len() != scale.tpc.len() {
+            return Err(format!(
+                "Scale '{}' has {} MIDI notes but {} TPC values",
+                scale.name,
+                scale.midi.len(),
+                scale.tpc.len()
+            ));
+        }
+        if scale.midi.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(format!(
+                "Scale '{}' notes are not sorted in ascending order",
+                scale.name
+            ));
+        }
+        if scale.midi.iter().any(|&note| note > 127) {
+            return Err(format!("Scale '{}' has a MIDI note above 127", scale.name));
+        }
+        if scale.tpc.iter().any(|&tpc| !is_valid_tpc(tpc)) {
+            return Err(format!(
+                "Scale '{}' has a TPC outside {}..={}",
+                scale.name, TPC_MIN, TPC_MAX
+            ));
+        }
+    }
+
+    Ok(file.scales)
+}
+
+/// Loads the optional `HANDFLOW_SCALES_FILE` catalog once at startup.
+///
+/// Without the variable (or with an empty value) the built-in catalog is used unchanged.
 ///
 /// # Returns
-/// A `Vec<(usize, &'static str, Vec<u8>, Vec<i8>)>` containing the generated scale variants with their respective IDs, names, MIDI notes, and TPC values.
-pub fn scales_list() -> Vec<(usize, &'static str, Vec<u8>, Vec<i8>)> {
-    let full_scales = vec![
+/// - `Ok(())` if there is no file or it was loaded.
+/// - `Err(String)` if the file can't be read or contains an invalid scale.
+pub fn init_custom_scales() -> Result<(), String> {
+    let path = match crate::utils::config::config().scales_file.as_ref() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let scales = load_scales_file(path)?;
+    log::info!("Loaded {} scales from {}", scales.len(), path.display());
+    let _ = CUSTOM_SCALES.set(scales);
+    Ok(())
+}
+
+/// Returns the built-in handpan scales, each with its family and full note range.
+fn builtin_scales() -> Vec<(&'static str, &'static str, Vec<u8>, Vec<i8>)> {
+    vec![
         (
             "D Kurd",
+            "Kurd",
             vec![50, 57, 58, 60, 62, 64, 65, 67, 69, 70, 72, 74, 77],
             vec![16, 17, 12, 14, 16, 18, 13, 15, 17, 12, 14, 16, 13],
         ),
         (
+            "Celtic",
             "Celtic",
             vec![50, 57, 60, 62, 64, 65, 67, 69, 72, 74, 77, 79, 81],
             vec![16, 10, 14, 16, 18, 19, 21, 23, 26, 28, 31, 33, 35],
         ),
         (
+            "Integral",
             "Integral",
             vec![45, 48, 50, 52, 55, 57, 60, 62, 64, 67, 69, 72, 74],
             vec![10, 13, 16, 18, 21, 23, 26, 28, 30, 33, 35, 38, 40],
         ),
         (
+            "Equinox",
             "Equinox",
             vec![50, 57, 60, 62, 65, 67, 69, 72, 74, 77, 79, 81],
             vec![16, 10, 14, 16, 19, 21, 23, 26, 28, 31, 33, 35],
         ), // 12-note base
         (
+            "Pygmy",
             "Pygmy",
             vec![43, 46, 48, 51, 55, 58, 60, 63, 67],
             vec![10, 13, 14, 16, 19, 20, 22, 23, 26],
         ), // Typically 9-note base
         (
+            "Hijaz",
             "Hijaz",
             vec![50, 51, 55, 57, 61, 62, 65, 66, 69],
             vec![16, 17, 21, 23, 25, 26, 28, 29, 32],
         ), // Typically 9-note base
         (
             "C# Annaziska",
+            "Annaziska",
             vec![49, 56, 58, 60, 62, 63, 66, 68, 71],
             vec![15, 22, 24, 26, 28, 29, 32, 34, 37],
         ), // Typically 9-note base
         (
             "Melog Selisir",
+            "Melog",
             vec![50, 53, 55, 58, 60, 62, 65, 67, 70, 72],
             vec![16, 19, 21, 24, 26, 28, 31, 33, 36, 38],
         ), // Typically 10-note base
         (
+            "Asha",
             "Asha",
             vec![45, 48, 50, 52, 55, 57, 60, 62, 64, 67, 69],
             vec![10, 13, 16, 18, 21, 23, 26, 28, 30, 33, 35],
         ), // Typically 11-note base
-    ];
+    ]
+}
 
-    let mut scales = Vec::new();
-    let mut id_counter = 0; // Initialize the ID counter
+/// Builds the stable key of a scale variant from its name and note count (e.g. `"d_kurd_10"`).
+///
+/// The name is lowercased and every run of characters other than ASCII letters and digits becomes a single `_`,
+/// so the key only depends on the scale itself and not on where it sits in the catalog. A name without any ASCII
+/// letter or digit gets the `scale_` prefix instead (e.g. `"scale_9"`), so its key never reads as a numeric ID.
+///
+/// # Parameters
+/// - `name`: The scale name.
+/// - `note_count`: The number of notes of the variant.
+///
+/// # Returns
+/// The key as a `String`.
+pub fn scale_key(name: &str, note_count: usize) -> String {
+    let mut key = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            key.push(c.to_ascii_lowercase());
+        } else if !key.is_empty() && !key.ends_with('_') {
+            key.push('_');
+        }
+    }
+    if key.is_empty() {
+        key.push_str("scale");
+    }
+    if !key.ends_with('_') {
+        key.push('_');
+    }
+    key.push_str(&note_count.to_string());
+    key
+}
 
-    // Iterate over each desired note count
-    for note_count in 9..=13 {
-        // Iterate over each full scale
-        for (name, full_midi, full_tpc) in &full_scales {
-            // Clip the full scale to the desired number of notes
-            let clipped_midi = full_midi
+/// A scale variant from `scales_list`: its numeric ID, stable key, name, family, MIDI notes and TPC values.
+pub type ScaleVariant = (usize, String, String, String, Vec<u8>, Vec<i8>);
+
+/// Generates a list of handpan scales with varying note counts.
+///
+/// This function:
+///
+/// 1. **Defines Full Scales**: Starts from the built-in handpan scales, each with a name, a family, a list of MIDI notes, and corresponding TPC (Tonnetz Pitch Class) values.
+/// 2. **Applies the Scales File**: Scales loaded from `HANDFLOW_SCALES_FILE` replace the built-in scale with the same name, or are added after the built-in ones.
+/// 3. **Generates Variants**: For each scale, it generates variants with note counts ranging from 9 to 13 notes by clipping the full scale.
+/// 4. **Assigns IDs and Keys**: Each scale variant is assigned a numeric ID and a stable key from `scale_key`. The numeric IDs
+///    only remain as a deprecated alias; the keys don't change when scales are added or removed.
+/// 5. **Returns**: A vector, ordered by note count, of tuples where each tuple contains:
+///     - A numeric ID (`usize`)
+///     - The stable key (`String`)
+///     - The scale name (`String`)
+///     - The scale family (`String`)
+///     - A vector of MIDI notes (`Vec<u8>`)
+///     - A vector of TPC values (`Vec<i8>`)
+///
+/// # Returns
+/// A `Vec<ScaleVariant>` containing the generated scale variants with their respective IDs, keys, names, families, MIDI notes, and TPC values.
+pub fn scales_list() -> Vec<ScaleVariant> {
+    build_scales_list(CUSTOM_SCALES.get().map_or(&[], |scales| scales.as_slice()))
+}
+
+/// Builds the variants of `scales_list` from the built-in scales and a list of custom ones.
+///
+/// The numeric IDs of the built-in variants follow the built-in catalog order and keep their slots whatever the
+/// custom scales hold: an override with fewer notes leaves its missing variants' IDs unused rather than shifting the
+/// following ones. The variants only an override or an added scale has get the IDs after the built-in ones.
+///
+/// # Parameters
+/// - `custom_scales`: The scales loaded from `HANDFLOW_SCALES_FILE`.
+///
+/// # Returns
+/// The variants, ordered by note count.
+fn build_scales_list(custom_scales: &[ScaleDefinition]) -> Vec<ScaleVariant> {
+    let builtin = builtin_scales();
+    let mut full_scales: Vec<(String, String, Vec<u8>, Vec<i8>)> = builtin
+        .iter()
+        .map(|(name, family, midi, tpc)| {
+            (
+                name.to_string(),
+                family.to_string(),
+                midi.clone(),
+                tpc.clone(),
+            )
+        })
+        .collect();
+    let mut added_scales = Vec::new();
+
+    for custom in custom_scales {
+        let scale = (
+            custom.name.clone(),
+            custom.family.clone().unwrap_or_else(|| custom.name.clone()),
+            custom.midi.clone(),
+            custom.tpc.clone(),
+        );
+        match full_scales
+            .iter_mut()
+            .find(|(name, _, _, _)| *name == custom.name)
+        {
+            Some(builtin) => *builtin = scale,
+            None => added_scales.push(scale),
+        }
+    }
+
+    // Clip a full scale to the desired number of notes
+    let variant = |id: usize,
+                   (name, family, full_midi, full_tpc): &(String, String, Vec<u8>, Vec<i8>),
+                   note_count: usize| {
+        (
+            id,
+            scale_key(name, note_count),
+            name.clone(),
+            family.clone(),
+            full_midi
                 .iter()
                 .take(note_count)
                 .cloned()
-                .collect::<Vec<_>>();
-            let clipped_tpc = full_tpc
+                .collect::<Vec<_>>(),
+            full_tpc
                 .iter()
                 .take(note_count)
                 .cloned()
-                .collect::<Vec<_>>();
+                .collect::<Vec<_>>(),
+        )
+    };
 
-            if full_midi.len() >= note_count {
-                // Append to the scales list with an auto-incrementing ID
-                scales.push((id_counter, *name, clipped_midi, clipped_tpc));
+    let mut scales = Vec::new();
+    let mut id_counter = 0; // Initialize the ID counter
+    let mut extra_variants = Vec::new();
+
+    // Reserve an ID for every built-in variant, whether or not its override still has it
+    for note_count in 9..=13 {
+        for (scale, (_, _, builtin_midi, _)) in full_scales.iter().zip(&builtin) {
+            if builtin_midi.len() >= note_count {
+                if scale.2.len() >= note_count {
+                    scales.push(variant(id_counter, scale, note_count));
+                }
                 id_counter += 1; // Increment the ID counter
+            } else if scale.2.len() >= note_count {
+                extra_variants.push((scale, note_count));
             }
         }
     }
 
+    // Number the variants only the custom scales have after the built-in ones
+    for note_count in 9..=13 {
+        let extras = extra_variants
+            .iter()
+            .filter(|(_, count)| *count == note_count)
+            .map(|(scale, _)| *scale);
+        let added = added_scales
+            .iter()
+            .filter(|(_, _, full_midi, _)| full_midi.len() >= note_count);
+        for scale in extras.chain(added) {
+            scales.push(variant(id_counter, scale, note_count));
+            id_counter += 1;
+        }
+    }
+
+    // Keep the variants grouped by note count for the scale selector
+    scales.sort_by_key(|(_, _, _, _, midi, _)| midi.len());
     scales
 }
 
-/// Retrieves a handpan scale by its ID.
+/// Lists the scale families of the catalog, in catalog order.
+///
+/// # Returns
+/// Every distinct family of `scales_list`, each once.
+pub fn scale_families() -> Vec<String> {
+    let mut scales = scales_list();
+    scales.sort_by_key(|(id, _, _, _, _, _)| *id);
+    let mut families: Vec<String> = Vec::new();
+    for (_, _, _, family, _, _) in scales {
+        if !families.contains(&family) {
+            families.push(family);
+        }
+    }
+    families
+}
+
+/// Lists the scale variants of one family, across every note count.
+///
+/// # Parameters
+/// - `family`: The family to keep (e.g. `"Kurd"`), compared case-insensitively.
+///
+/// # Returns
+/// The matching variants in `scales_list` order, or an empty list for an unknown family.
+pub fn scales_in_family(family: &str) -> Vec<ScaleVariant> {
+    let family = family.trim();
+    scales_list()
+        .into_iter()
+        .filter(|(_, _, _, scale_family, _, _)| scale_family.eq_ignore_ascii_case(family))
+        .collect()
+}
+
+/// Retrieves a handpan scale by its key.
 ///
 /// This function:
 ///
 /// 1. **Fetches the Scale List**: Calls `scales_list` to get the list of all available scales.
-/// 2. **Finds the Scale**: Searches the list for the scale with the given ID.
+/// 2. **Finds the Scale**: Searches the list for the scale with the given key. A plain number is still accepted as the
+///    deprecated numeric ID, which logs a warning since those IDs shift when the catalog changes.
 /// 3. **Returns**: If found, returns a tuple containing the scale's name, MIDI notes, and TPC values; otherwise, returns `None`.
 ///
 /// # Parameters
-/// - `scale_index`: The ID of the scale to retrieve.
+/// - `scale`: The key of the scale to retrieve (e.g. `"d_kurd_10"`), or its deprecated numeric ID.
 ///
 /// # Returns
 /// An `Option<(String, Vec<u8>, Vec<i8>)>` containing the scale's name, MIDI notes, and TPC values if found, or `None` if not.
-pub fn get_handpan_scale(scale_index: usize) -> Option<(String, Vec<u8>, Vec<i8>)> {
-    scales_list()
+pub fn get_handpan_scale(scale: &str) -> Option<(String, Vec<u8>, Vec<i8>)> {
+    let scale = scale.trim();
+    let mut scales = scales_list();
+
+    if let Some(index) = scales.iter().position(|(_, key, _, _, _, _)| key == scale) {
+        let (_, _, name, _, notes, tpc) = scales.swap_remove(index);
+        return Some((name, notes, tpc));
+    }
+
+    let scale_id = scale.parse::<usize>().ok()?;
+    let (_, key, name, _, notes, tpc) = scales
         .into_iter()
-        .find(|(id, _, _, _)| *id == scale_index)
-        .map(|(_, name, notes, tpc)| (name.to_string(), notes, tpc))
+        .find(|(id, _, _, _, _, _)| *id == scale_id)?;
+    log::warn!(
+        "Scale requested by the deprecated numeric id {}, use the key '{}' instead",
+        scale_id,
+        key
+    );
+    Some((name, notes, tpc))
+}
+
+/// The angular position of each tone field (the ding excluded) on the handpan layouts, indexed by note count from 9
+/// to 13.
+///
+/// Angles are in degrees clockwise from the top of the drum as the player sees it, and match the field centers of the
+/// `static/img/hand-{count}.svg` layouts: field 1 sits at the bottom (180°) and the following fields zigzag left and
+/// right up to the top.
+const FIELD_ANGLES: [&[u16]; 5] = [
+    &[180, 227, 134, 272, 88, 317, 45, 0],
+    &[180, 227, 134, 272, 88, 306, 53, 342, 18],
+    &[180, 227, 134, 272, 88, 317, 45, 0, 307, 52],
+    &[180, 227, 134, 272, 88, 306, 53, 342, 18, 330, 29],
+    &[180, 227, 134, 272, 88, 317, 45, 0, 307, 52, 342, 17],
+];
+
+/// Returns the angular position of a field on the layout of a scale.
+///
+/// # Parameters
+/// - `note_count`: The number of notes of the scale (9 to 13).
+/// - `note_index`: The index of the field, `0` being the ding.
+///
+/// # Returns
+/// The angle in degrees clockwise from the top of the drum, or `None` for the ding (which sits in the center) and
+/// for fields outside the known layouts.
+pub fn field_angle(note_count: usize, note_index: usize) -> Option<u16> {
+    let angles = FIELD_ANGLES.get(note_count.checked_sub(9)?)?;
+    angles.get(note_index.checked_sub(1)?).copied()
+}
+
+/// The hand suggested for playing a field.
+///
+/// - **`Left`** / **`Right`**: The field lies on that side of the ding.
+/// - **`Either`**: The ding itself, or a field right above or below it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hand {
+    Left,
+    Right,
+    Either,
+}
+
+impl Hand {
+    /// The CSS class suffix of the hand (`"left"`, `"right"` or `"either"`).
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Hand::Left => "left",
+            Hand::Right => "right",
+            Hand::Either => "either",
+        }
+    }
+
+    /// The hand playing the field at the mirrored position, for mirrored layouts.
+    pub fn mirrored(self) -> Hand {
+        match self {
+            Hand::Left => Hand::Right,
+            Hand::Right => Hand::Left,
+            Hand::Either => Hand::Either,
+        }
+    }
+}
+
+/// Suggests which hand plays a field, from its position on the layout.
+///
+/// Fields on the right half of the drum (between 0° and 180°) go to the right hand and those on the left half to the
+/// left hand. The ding, the fields on the vertical axis and fields without a known position can be played by either.
+///
+/// # Parameters
+/// - `note_count`: The number of notes of the scale.
+/// - `note_index`: The index of the field, `0` being the ding.
+///
+/// # Returns
+/// The suggested `Hand`.
+pub fn hand_for_field(note_count: usize, note_index: usize) -> Hand {
+    match field_angle(note_count, note_index) {
+        Some(angle) if angle > 0 && angle < 180 => Hand::Right,
+        Some(angle) if angle > 180 => Hand::Left,
+        _ => Hand::Either,
+    }
+}
+
+/// The note names of every Tonal Pitch Class, from `TPC_MIN` (F♭♭) to `TPC_MAX` (B♯♯).
+///
+/// The name of a TPC is at index `tpc - TPC_MIN`, so the valid range is derived from the table length rather
+/// than written out separately.
+const TPC_NOTE_NAMES: [&str; 35] = [
+    "F♭♭", "C♭♭", "G♭♭", "D♭♭", "A♭♭", "E♭♭", "B♭♭", "F♭", "C♭", "G♭", "D♭", "A♭", "E♭", "B♭", "F",
+    "C", "G", "D", "A", "E", "B", "F♯", "C♯", "G♯", "D♯", "A♯", "E♯", "B♯", "F♯♯", "C♯♯", "G♯♯",
+    "D♯♯", "A♯♯", "E♯♯", "B♯♯",
+];
+
+/// The lowest TPC MuseScore writes (F♭♭).
+pub const TPC_MIN: i8 = -1;
+
+/// The highest TPC MuseScore writes (B♯♯).
+pub const TPC_MAX: i8 = TPC_MIN + TPC_NOTE_NAMES.len() as i8 - 1;
+
+/// Returns whether a TPC value has a note name, i.e. lies within `TPC_MIN..=TPC_MAX`.
+pub fn is_valid_tpc(tpc: i8) -> bool {
+    (TPC_MIN..=TPC_MAX).contains(&tpc)
 }
 
 /// Converts a MIDI note number and TPC value into a human-readable note name and octave.
 ///
 /// This function:
 ///
-/// 1. **Defines Mapping**: Maps TPC values to corresponding note names.
-/// 2. **Calculates Octave**: Determines the octave number based on the MIDI note value.
-/// 3. **Adjusts Note Name**: Adjusts the note name based on the TPC value.
+/// 1. **Looks Up the Name**: Reads the note name for the TPC from `TPC_NOTE_NAMES`.
+/// 2. **Calculates Octave**: Determines the octave number of the written letter, without its accidentals, so a B♯
+///    sounding as C5 is B♯4 and a C♭ sounding as B3 is C♭4 (the octaves `parse_note_name` reads back).
+///
+/// An out-of-range TPC has no name; it is reported as `None` so callers decide how to spell the note instead of
+/// a placeholder string ending up in the output.
 ///
 /// # Parameters
 /// - `midi`: The MIDI note number.
 /// - `tpc`: The Tonnetz Pitch Class (TPC) value.
 ///
 /// # Returns
+/// `Some((String, i8))` with the note name and octave, or `None` if `tpc` is outside `TPC_MIN..=TPC_MAX`.
+pub fn midi_to_note_and_octave_with_tpc(midi: u8, tpc: i8) -> Option<(String, i8)> {
+    // Index the table relative to the lowest TPC; `get` rejects anything past either end
+    let index = usize::try_from(tpc as i16 - TPC_MIN as i16).ok()?;
+    let note_name = TPC_NOTE_NAMES.get(index)?.to_string();
+
+    // Calculate the octave of the letter: each 7 fifths from F♭♭ add one sharp to the double flats
+    let alteration = (tpc as i32 - TPC_MIN as i32).div_euclid(7) - 2;
+    let octave = ((midi as i32 - alteration).div_euclid(12) - 1) as i8;
+
+    Some((note_name, octave))
+}
+
+/// Spells a MIDI note, falling back to its pitch class when the stored TPC is out of range.
+///
+/// Used where a note name must always be shown (e.g. the fields of a scale): an invalid TPC is logged and the
+/// note is spelled with sharps from its pitch instead.
+///
+/// # Parameters
+/// - `midi`: The MIDI note number.
+/// - `tpc`: The stored Tonal Pitch Class (TPC) value.
+///
+/// # Returns
 /// A tuple `(String, i8)` containing the note name and octave.
-pub fn midi_to_note_and_octave_with_tpc(midi: u8, tpc: i8) -> (String, i8) {
-    // Mapping TPC to note names based on the provided array
-    let tpc_to_note = [
-        "F♭♭", "C♭♭", "G♭♭", "D♭♭", "A♭♭", "E♭♭", "B♭♭", "F♭", "C♭", "G♭", "D♭", "A♭", "E♭", "B♭",
-        "F", "C", "G", "D", "A", "E", "B", "F♯", "C♯", "G♯", "D♯", "A♯", "E♯", "B♯", "F♯♯", "C♯♯",
-        "G♯♯", "D♯♯", "A♯♯", "E♯♯", "B♯♯",
-    ];
-
-    // Calculate the octave
-    let octave = (midi as i8 / 12) - 1;
-
-    // Adjust for TPC indexing
-    let note_name = if tpc >= -1 && tpc <= 33 {
-        tpc_to_note[(tpc + 1) as usize].to_string()
+pub fn spell_note(midi: u8, tpc: i8) -> (String, i8) {
+    midi_to_note_and_octave_with_tpc(midi, tpc).unwrap_or_else(|| {
+        log::warn!(
+            "Invalid TPC {} for MIDI note {}, spelling it from its pitch class",
+            tpc,
+            midi
+        );
+        let tpc = tpc_for_pitch_class(midi, AccidentalPreference::Sharps);
+        let note_name = TPC_NOTE_NAMES[(tpc - TPC_MIN) as usize].to_string();
+        (note_name, (midi as i8 / 12) - 1)
+    })
+}
+
+/// Names the key of a key signature (e.g. `"D minor"`).
+///
+/// # Parameters
+/// - `fifths`: The key signature as a number of sharps (positive) or flats (negative), from -7 to 7.
+/// - `minor`: Whether the key is minor rather than major.
+///
+/// # Returns
+/// The tonic followed by `major` or `minor`, or `None` if `fifths` is out of range.
+pub fn key_name(fifths: i32, minor: bool) -> Option<String> {
+    if !(-7..=7).contains(&fifths) {
+        return None;
+    }
+    // C major has the TPC of C (14), each sharp moves the tonic a fifth up, and the relative minor is 3 fifths up
+    let tonic_tpc = 14 + fifths + if minor { 3 } else { 0 };
+    let tonic = TPC_NOTE_NAMES[(tonic_tpc - TPC_MIN as i32) as usize];
+    Some(format!(
+        "{} {}",
+        tonic,
+        if minor { "minor" } else { "major" }
+    ))
+}
+
+/// Transposes a key signature by a number of semitones.
+///
+/// Every semitone moves the key 7 fifths around the circle; of the enharmonic key signatures, the one with the fewest
+/// accidentals is kept (F♯ rather than G♭ for 6). Shifts by whole octaves keep the key signature as written.
+///
+/// # Parameters
+/// - `fifths`: The key signature as a number of sharps (positive) or flats (negative).
+/// - `semitones`: The transposition.
+///
+/// # Returns
+/// The transposed key signature, from -5 to 6 unless the shift is a whole number of octaves.
+pub fn transpose_key(fifths: i32, semitones: i32) -> i32 {
+    if semitones.rem_euclid(12) == 0 {
+        return fifths;
+    }
+    let fifths = (fifths + 7 * semitones).rem_euclid(12);
+    if fifths > 6 {
+        fifths - 12
     } else {
-        "Invalid TPC".to_string() // Handle invalid TPC values
-    };
+        fifths
+    }
+}
+
+/// The default reach of the auto-transpose search, in semitones either way (one octave).
+pub const DEFAULT_TRANSPOSE_RANGE: i32 = 12;
+
+/// The widest auto-transpose search a request may ask for, in semitones either way (two octaves).
+pub const MAX_TRANSPOSE_RANGE: i32 = 24;
+
+/// How much each note transposed outside the handpan's span costs in the auto-transpose score.
+///
+/// A matched note scores `1`, so a transposition keeping one more note playable wins over one matching a single
+/// extra note.
+const OUT_OF_RANGE_PENALTY: f64 = 1.5;
+
+/// The weights of the auto-transpose score, for users who value "most notes in the scale" and "keep the melody
+/// playable as written" differently.
+///
+/// Fields:
+/// - `matched`: What each note landing on a field is worth (default `1`).
+/// - `penalty`: How much the penalties count (default `1`): the changed intervals and the notes pushed out of the
+///   handpan's span (`OUT_OF_RANGE_PENALTY` each). A single shift moves every note alike, so for a whole part or
+///   measure the penalty comes from the notes out of the span.
+///
+/// The defaults give the unweighted score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TranspositionWeights {
+    pub matched: f64,
+    pub penalty: f64,
+}
 
-    (note_name, octave)
+impl Default for TranspositionWeights {
+    fn default() -> Self {
+        TranspositionWeights {
+            matched: 1.0,
+            penalty: 1.0,
+        }
+    }
 }
 
+/// The largest weight a request may give to the matched notes or the penalties of the auto-transpose score.
+pub const MAX_TRANSPOSITION_WEIGHT: f64 = 100.0;
+
 /// Finds the best transposition for a set of notes to match a given scale.
 ///
 /// This function:
 ///
-/// 1. **Iterates Transpositions**: Tests transpositions from -12 to +12 semitones.
+/// 1. **Iterates Transpositions**: Tests transpositions from `-range` to `+range` semitones.
 /// 2. **Matches Notes**: Counts the number of notes that match the target scale for each transposition.
 /// 3. **Evaluates Intervals**: Considers harmonic interval preservation, applying penalties for mismatches.
-/// 4. **Returns**: The transposition value that yields the highest score.
+/// 4. **Checks the Span**: Penalizes every note landing below the ding or above the highest field of the scale
+///    (by `OUT_OF_RANGE_PENALTY`), as no field comes close to it.
+/// 5. **Weighs**: Scores each transposition as `weights.matched` times the matched notes minus `weights.penalty` times
+///    the penalties.
+/// 6. **Returns**: The transposition value that yields the highest score.
 ///
 /// # Parameters
 /// - `notes`: A slice of MIDI notes to be transposed.
 /// - `scale_notes`: A slice of MIDI notes representing the target scale.
+/// - `range`: How far the search goes either way, in semitones (`DEFAULT_TRANSPOSE_RANGE` unless a request asks
+///   for more). Every transposition is scored against every note, so the cost is O(range × notes): doubling the
+///   range doubles the work. `parse_mscx_score` runs the search once per part, over all of its notes.
+/// - `weights`: The weights of the matched notes and the penalties (`TranspositionWeights::default()` for the
+///   unweighted score).
+///
+/// Among equally good transpositions, the lowest one is kept.
 ///
 /// # Returns
 /// The best transposition value (`i32`) that maximizes note matching and harmonic preservation.
-pub fn find_best_transposition_with_harmonic_context(notes: &[u8], scale_notes: &[u8]) -> i32 {
+pub fn find_best_transposition_with_harmonic_context(
+    notes: &[u8],
+    scale_notes: &[u8],
+    range: i32,
+    weights: TranspositionWeights,
+) -> i32 {
+    let (lowest, highest) = match (scale_notes.iter().min(), scale_notes.iter().max()) {
+        (Some(&lowest), Some(&highest)) if !notes.is_empty() => (lowest as i32, highest as i32),
+        _ => return 0,
+    };
     let mut best_transpose = 0;
-    let mut max_score = 0.0;
+    let mut max_score = f64::NEG_INFINITY;
 
     // Iterate over possible transpositions
-    for transpose in -12..=12 {
+    for transpose in -range..=range {
         let mut matched_notes = 0;
         let mut interval_penalty = 0.0;
+        let mut out_of_range = 0;
 
         // Transpose and score each note
         for i in 0..notes.len() {
-            let transposed_note = (notes[i] as i32 + transpose) as u8;
+            let transposed_note = notes[i] as i32 + transpose;
 
-            // Check if the transposed note is in the scale
-            if scale_notes.contains(&transposed_note) {
+            // Check if the transposed note is in the scale, or out of the handpan's span
+            if scale_notes
+                .iter()
+                .any(|&note| note as i32 == transposed_note)
+            {
                 matched_notes += 1;
+            } else if transposed_note < lowest || transposed_note > highest {
+                out_of_range += 1;
             }
 
             // Evaluate harmonic intervals if not the last note
@@ -193,8 +708,9 @@ pub fn find_best_transposition_with_harmonic_context(notes: &[u8], scale_notes:
             }
         }
 
-        // Calculate a score considering both note matching and interval preservation
-        let score = (matched_notes as f64) - interval_penalty;
+        // Calculate a score considering note matching, interval preservation and the notes out of reach
+        let score = weights.matched * matched_notes as f64
+            - weights.penalty * (interval_penalty + out_of_range as f64 * OUT_OF_RANGE_PENALTY);
 
         // Update the best transposition if this one scores higher
         if score > max_score {
@@ -206,70 +722,640 @@ pub fn find_best_transposition_with_harmonic_context(notes: &[u8], scale_notes:
     best_transpose
 }
 
+/// Finds the transposition putting the lowest note of a piece on the ding.
+///
+/// The ding is the central and lowest field of the handpan (`scale_notes[0]`), where players usually want the
+/// bottom of the melody to sit. Unlike `find_best_transposition_with_harmonic_context`, the shift isn't limited to
+/// an octave either way.
+///
+/// # Parameters
+/// - `histogram`: How many times each MIDI pitch occurs in the piece.
+/// - `ding`: The MIDI note of the ding.
+///
+/// # Returns
+/// The shift in semitones, or `None` if the piece has no notes.
+pub fn ding_transposition(histogram: &BTreeMap<u8, usize>, ding: u8) -> Option<i32> {
+    histogram
+        .iter()
+        .find(|(_, &count)| count > 0)
+        .map(|(&lowest, _)| ding as i32 - lowest as i32)
+}
+
+/// Parses a note name such as `"C4"`, `"F#3"`, `"B♭2"` or `"Eb"`.
+///
+/// The letter may be followed by any number of sharps (`#`, `♯`) or flats (`b`, `♭`) and by an octave from `-1` to
+/// `9` (the MIDI range), where `C4` is middle C (MIDI 60).
+///
+/// # Parameters
+/// - `name`: The note name, letter case and surrounding spaces ignored.
+///
+/// # Returns
+/// The pitch class (`0` for C to `11` for B) and, when an octave is given, the MIDI note; `None` if the name can't
+/// be read or the note is outside the MIDI range.
+pub fn parse_note_name(name: &str) -> Option<(u8, Option<u8>)> {
+    let name = name.trim();
+    let mut chars = name.chars();
+    let step: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let octave_start = rest
+        .find(|c: char| c.is_ascii_digit() || c == '-')
+        .unwrap_or(rest.len());
+    let alter = rest[..octave_start]
+        .chars()
+        .try_fold(0, |alter, c| match c {
+            '#' | '♯' => Some(alter + 1),
+            'b' | '♭' => Some(alter - 1),
+            _ => None,
+        })?;
+    let pitch = step + alter;
+    let pitch_class = pitch.rem_euclid(12) as u8;
+
+    let octave = &rest[octave_start..];
+    if octave.is_empty() {
+        return Some((pitch_class, None));
+    }
+    let octave = octave
+        .parse::<i32>()
+        .ok()
+        .filter(|octave| (-1..=9).contains(octave))?;
+    let midi = (octave + 1) * 12 + pitch;
+    Some((
+        pitch_class,
+        Some(u8::try_from(midi).ok().filter(|&m| m <= 127)?),
+    ))
+}
+
+/// Finds the transposition putting a chosen note of a piece on the ding.
+///
+/// The note is the one named by the user (see `parse_note_name`). When the piece plays exactly that note, it is
+/// moved onto the ding. Otherwise, or when no octave was given, the most played note of the same pitch class is:
+/// the piece's most prominent occurrence of it, ties going to the one closest to the named octave (or the lowest).
+///
+/// # Parameters
+/// - `histogram`: How many times each MIDI pitch occurs in the piece.
+/// - `ding`: The MIDI note of the ding.
+/// - `target`: The pitch class and optional MIDI note returned by `parse_note_name`.
+///
+/// # Returns
+/// The shift in semitones, or `None` if the piece never plays the pitch class.
+pub fn ding_target_transposition(
+    histogram: &BTreeMap<u8, usize>,
+    ding: u8,
+    target: (u8, Option<u8>),
+) -> Option<i32> {
+    let (pitch_class, midi) = target;
+    let played = |pitch: &u8| histogram.get(pitch).is_some_and(|&count| count > 0);
+
+    let pitch = match midi.filter(played) {
+        Some(pitch) => pitch,
+        None => histogram
+            .iter()
+            .filter(|(&pitch, &count)| pitch % 12 == pitch_class && count > 0)
+            .max_by_key(|(&pitch, &count)| {
+                let distance = midi.map_or(pitch as i32, |midi| (pitch as i32 - midi as i32).abs());
+                (count, std::cmp::Reverse(distance))
+            })
+            .map(|(&pitch, _)| pitch)?,
+    };
+    Some(ding as i32 - pitch as i32)
+}
+
+/// Which field an out-of-scale note is mapped to.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutOfScaleRounding {
+    /// The closest field either way; a note exactly between two fields goes to the lower one.
+    #[default]
+    Nearest,
+    /// The closest field at or below the note, for a consistent fingering. Notes under the lowest field use the
+    /// nearest one.
+    Below,
+    /// The closest field at or above the note. Notes over the highest field use the nearest one.
+    Above,
+}
+
+impl OutOfScaleRounding {
+    /// Parses a form value (`"nearest"`, `"below"` or `"above"`).
+    ///
+    /// # Returns
+    /// The matching rounding, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "nearest" => Some(OutOfScaleRounding::Nearest),
+            "below" => Some(OutOfScaleRounding::Below),
+            "above" => Some(OutOfScaleRounding::Above),
+            _ => None,
+        }
+    }
+}
+
+/// Finds the handpan field a pitch is mapped to.
+///
+/// Fields are compared by sounding pitch, not spelling, so an enharmonic equivalent (e.g. G♯ against an A♭ field) is
+/// an exact match on that field.
+///
+/// # Parameters
+/// - `pitch`: The MIDI pitch of the note.
+/// - `scale_notes`: The MIDI notes of the handpan scale, in ascending order.
+/// - `rounding`: Which field an out-of-scale note goes to.
+///
+/// # Returns
+/// The signed distance in semitones from the field to the note (positive when the note is above it) and the field's
+/// index, or `(i32::MAX, None)` for an empty scale.
+pub fn closest_field(
+    pitch: i32,
+    scale_notes: &[u8],
+    rounding: OutOfScaleRounding,
+) -> (i32, Option<usize>) {
+    let nearest = |allowed: &dyn Fn(i32) -> bool| {
+        let mut closest_index = None;
+        let mut min_delta = i32::MAX;
+        for (i, &s_note) in scale_notes.iter().enumerate() {
+            let current_delta = pitch - s_note as i32;
+            // Compare absolute values to find the smallest difference, keeping the actual signed delta
+            if allowed(current_delta) && current_delta.abs() < min_delta.abs() {
+                min_delta = current_delta;
+                closest_index = Some(i);
+            }
+        }
+        (min_delta, closest_index)
+    };
+
+    let (delta, index) = match rounding {
+        OutOfScaleRounding::Nearest => (i32::MAX, None),
+        OutOfScaleRounding::Below => nearest(&|delta| delta >= 0),
+        OutOfScaleRounding::Above => nearest(&|delta| delta <= 0),
+    };
+    if index.is_some() {
+        (delta, index)
+    } else {
+        nearest(&|_| true)
+    }
+}
+
+/// How the transposition of an arrangement is chosen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TransposeMode {
+    /// The transposition given by the user.
+    #[default]
+    Manual,
+    /// The shift fitting the most notes into the scale, from `find_best_transposition_with_harmonic_context`.
+    Auto,
+    /// The shift putting the piece's lowest note on the ding, from `ding_transposition`, or a note chosen by the user,
+    /// from `ding_target_transposition`.
+    Ding,
+    /// Experimental: every measure gets its own best shift, from `per_measure_transpositions`. The key changes from
+    /// measure to measure, which only makes sense for drilling sections of very chromatic pieces.
+    PerMeasure,
+}
+
+impl TransposeMode {
+    /// Parses a form value (`"manual"`, `"auto"`, `"ding"` or `"per_measure"`).
+    ///
+    /// # Returns
+    /// The matching mode, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "manual" => Some(TransposeMode::Manual),
+            "auto" => Some(TransposeMode::Auto),
+            "ding" => Some(TransposeMode::Ding),
+            "per_measure" => Some(TransposeMode::PerMeasure),
+            _ => None,
+        }
+    }
+
+    /// Tells the user that a manual transposition was sent but not applied.
+    ///
+    /// Only `Manual` uses the transposition given by the user, so a nonzero one sent along with another mode is
+    /// dropped.
+    ///
+    /// # Parameters
+    /// - `transpose_value`: The manual transposition sent with the request, in semitones.
+    ///
+    /// # Returns
+    /// The warning to show, or `None` if the manual transposition was applied or is `0`.
+    pub fn ignored_transpose_warning(self, transpose_value: i32) -> Option<&'static str> {
+        match self {
+            _ if transpose_value == 0 => None,
+            TransposeMode::Manual => None,
+            TransposeMode::Auto => Some("auto-transpose was used; manual transpose ignored"),
+            TransposeMode::Ding => {
+                Some("lowest note on ding transpose was used; manual transpose ignored")
+            }
+            TransposeMode::PerMeasure => {
+                Some("per-measure transpose was used; manual transpose ignored")
+            }
+        }
+    }
+}
+
 /// Transposes a MIDI pitch and TPC value by a given number of semitones.
 ///
 /// This function:
 ///
 /// 1. **Applies Transposition**: Adjusts the MIDI pitch by the specified transposition value.
-/// 2. **Determines New TPC**: Calculates the new TPC value based on the transposition direction (positive for sharps, negative for flats).
+/// 2. **Transposes the Key**: Finds the target key with `transpose_key`; the number of fifths it moves is the
+///    number of fifths every spelled note moves, so each note keeps its place in the key (the G♯ leading tone of A
+///    minor becomes the B♯ of C♯ minor, not a C natural). A spelling past the double accidentals is moved by 12
+///    fifths back into `TPC_MIN..=TPC_MAX`.
+/// 3. **Handles a Missing TPC**: When the note has no (or an out-of-range) TPC, it gets the spelling of its
+///    transposed pitch class closest to the target key on the line of fifths (see `tpc_in_key`).
 ///
 /// # Parameters
 /// - `pitch`: The original MIDI pitch.
-/// - `tpc`: The original TPC value.
+/// - `tpc`: The original TPC value, if the score provides one.
 /// - `transpose`: The number of semitones to transpose.
+/// - `key_fifths`: The key signature of the part, as a number of sharps (positive) or flats (negative).
 ///
 /// # Returns
-/// An `Option<(u8, i8)>` containing the transposed MIDI pitch and TPC value. Returns `None` if the TPC is invalid.
-pub fn transpose_pitch_and_tpc(pitch: u8, tpc: Option<i8>, transpose: i32) -> Option<(u8, i8)> {
-    let pitch = pitch;
-    if let Some(tpc) = tpc {
-        // Apply the transposition to the pitch
-        let new_pitch = (pitch as i32 + transpose).clamp(0, 127) as u8;
-
-        // Calculate the note class (modulo 12)
-        let note_class = (new_pitch % 12) as i8;
-
-        // Determine the new TPC based on the transposition direction
-        let new_tpc = if transpose > 0 {
-            // Use sharps for positive transpositions
-            match note_class {
-                0 => 14,  // C
-                1 => 21,  // C#
-                2 => 16,  // D
-                3 => 23,  // D#
-                4 => 18,  // E
-                5 => 13,  // F
-                6 => 20,  // F#
-                7 => 15,  // G
-                8 => 22,  // G#
-                9 => 17,  // A
-                10 => 24, // A#
-                11 => 19, // B
-                _ => tpc,
+/// A tuple `(u8, i8)` containing the transposed MIDI pitch and TPC value.
+pub fn transpose_pitch_and_tpc(
+    pitch: u8,
+    tpc: Option<i8>,
+    transpose: i32,
+    key_fifths: i32,
+) -> (u8, i8) {
+    // Apply the transposition to the pitch
+    let new_pitch = (pitch as i32 + transpose).clamp(0, 127) as u8;
+    let target_fifths = transpose_key(key_fifths, transpose);
+
+    // Move the spelling as many fifths as the key moves
+    let new_tpc = match tpc.filter(|&tpc| is_valid_tpc(tpc)) {
+        // If no transposition, keep the original TPC
+        Some(tpc) if transpose == 0 => tpc,
+        Some(tpc) => {
+            let mut new_tpc = tpc as i32 + target_fifths - key_fifths;
+            // Triple accidentals have no name: respell them enharmonically, 12 fifths away
+            while new_tpc > TPC_MAX as i32 {
+                new_tpc -= 12;
             }
-        } else if transpose < 0 {
-            // Use flats for negative transpositions
-            match note_class {
-                0 => 14,  // C
-                1 => 9,   // Db
-                2 => 16,  // D
-                3 => 11,  // Eb
-                4 => 18,  // E
-                5 => 13,  // F
-                6 => 8,   // Gb
-                7 => 15,  // G
-                8 => 10,  // Ab
-                9 => 17,  // A
-                10 => 12, // Bb
-                11 => 19, // B
-                _ => tpc,
+            while new_tpc < TPC_MIN as i32 {
+                new_tpc += 12;
             }
-        } else {
-            // If no transposition, return the original TPC
-            tpc
+            new_tpc as i8
+        }
+        // Spell notes without a spelling for the target key
+        None => tpc_in_key(new_pitch, target_fifths),
+    };
+
+    (new_pitch, new_tpc)
+}
+
+/// Spells a pitch for a key: of the TPCs of its pitch class, the one closest on the line of fifths to the middle of
+/// the key's scale (its second degree).
+///
+/// The diatonic notes keep their letter, and the others are spelled as the raised tonic, flat third, raised fourth,
+/// flat sixth and flat seventh (C♯, E♭, F♯, A♭ and B♭ in C major). In keys with many sharps or flats this gives
+/// double accidentals (F♯♯ as the raised tonic of F♯ major).
+///
+/// # Parameters
+/// - `pitch`: The MIDI pitch to spell.
+/// - `key_fifths`: The key signature, as a number of sharps (positive) or flats (negative).
+///
+/// # Returns
+/// The TPC of the pitch, within `TPC_MIN..=TPC_MAX`.
+pub fn tpc_in_key(pitch: u8, key_fifths: i32) -> i8 {
+    // The second degree of C major is D (16), and each sharp moves it a fifth up
+    let center = (16 + key_fifths).clamp(TPC_MIN as i32 + 6, TPC_MAX as i32 - 5);
+    let sharp_tpc = tpc_for_pitch_class(pitch, AccidentalPreference::Sharps) as i32;
+
+    // Pick the TPC of the pitch class within 6 fifths below and 5 above the center
+    (center + (sharp_tpc - center + 6).rem_euclid(12) - 6) as i8
+}
+
+/// The accidental convention used when spelling a pitch that falls on a black key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccidentalPreference {
+    Sharps,
+    Flats,
+}
+
+impl AccidentalPreference {
+    /// Parses a form value (`"sharps"` or `"flats"`).
+    ///
+    /// # Returns
+    /// The matching preference, or `None` for any other value (keeping the stored spelling).
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "sharps" => Some(AccidentalPreference::Sharps),
+            "flats" => Some(AccidentalPreference::Flats),
+            _ => None,
+        }
+    }
+}
+
+/// The names notes are displayed with.
+///
+/// - **`Letters`**: The default letter names (C, D, E, ...).
+/// - **`Solfege`**: Solfège syllables with Ti as the seventh (Do, Re, Mi, Fa, Sol, La, Ti), C being Do.
+/// - **`FixedDo`**: The fixed-do syllables of the Romance languages (Do, Ré, Mi, Fa, Sol, La, Si).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NoteNaming {
+    #[default]
+    Letters,
+    Solfege,
+    FixedDo,
+}
+
+impl NoteNaming {
+    /// Parses a form value (`"letters"`, `"solfege"` or `"fixed_do"`).
+    ///
+    /// # Returns
+    /// The matching naming, or `None` for any other value.
+    pub fn from_form_value(value: &str) -> Option<Self> {
+        match value {
+            "letters" => Some(NoteNaming::Letters),
+            "solfege" => Some(NoteNaming::Solfege),
+            "fixed_do" | "fixed-do" => Some(NoteNaming::FixedDo),
+            _ => None,
+        }
+    }
+
+    /// Renames a note spelled with a letter (e.g. `"C♯4"`), keeping its accidentals and octave.
+    ///
+    /// # Parameters
+    /// - `note`: The note name as spelled from `TPC_NOTE_NAMES`, optionally followed by its octave.
+    ///
+    /// # Returns
+    /// The note name in this naming (e.g. `"Do♯4"`), or `note` unchanged if it doesn't start with a letter.
+    pub fn display_name(self, note: &str) -> String {
+        let mut chars = note.chars();
+        let letter = match chars.next() {
+            Some(letter) => letter,
+            None => return String::new(),
+        };
+        let syllable = match (self, letter) {
+            (NoteNaming::Letters, _) => return note.to_string(),
+            (_, 'C') => "Do",
+            (NoteNaming::Solfege, 'D') => "Re",
+            (NoteNaming::FixedDo, 'D') => "Ré",
+            (_, 'E') => "Mi",
+            (_, 'F') => "Fa",
+            (_, 'G') => "Sol",
+            (_, 'A') => "La",
+            (NoteNaming::Solfege, 'B') => "Ti",
+            (NoteNaming::FixedDo, 'B') => "Si",
+            _ => return note.to_string(),
         };
+        format!("{}{}", syllable, chars.as_str())
+    }
+}
+
+/// Computes the TPC spelling of a MIDI pitch using the given accidental preference.
+///
+/// Natural notes always keep their natural spelling; black keys are spelled with a single sharp
+/// or a single flat depending on `preference`.
+///
+/// # Parameters
+/// - `pitch`: The MIDI pitch to spell.
+/// - `preference`: Whether black keys should be spelled with sharps or flats.
+///
+/// # Returns
+/// The TPC value (`i8`) for the pitch.
+pub fn tpc_for_pitch_class(pitch: u8, preference: AccidentalPreference) -> i8 {
+    let sharps = [14, 21, 16, 23, 18, 13, 20, 15, 22, 17, 24, 19]; // C, C#, D, D#, E, F, F#, G, G#, A, A#, B
+    let flats = [14, 9, 16, 11, 18, 13, 8, 15, 10, 17, 12, 19]; // C, Db, D, Eb, E, F, Gb, G, Ab, A, Bb, B
+
+    let note_class = (pitch % 12) as usize;
+    match preference {
+        AccidentalPreference::Sharps => sharps[note_class],
+        AccidentalPreference::Flats => flats[note_class],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The numeric ID of a variant, by key.
+    fn id_of(scales: &[ScaleVariant], key: &str) -> Option<usize> {
+        scales
+            .iter()
+            .find(|(_, variant_key, _, _, _, _)| variant_key == key)
+            .map(|(id, _, _, _, _, _)| *id)
+    }
+
+    #[test]
+    fn note_names_outside_the_midi_octaves_are_rejected() {
+        assert_eq!(parse_note_name("C4"), Some((0, Some(60))));
+        assert_eq!(parse_note_name("C-1"), Some((0, Some(0))));
+        assert_eq!(parse_note_name("G9"), Some((7, Some(127))));
+        assert_eq!(parse_note_name("A9"), None);
+        assert_eq!(parse_note_name("C10"), None);
+        assert_eq!(parse_note_name("C999999999"), None);
+        assert_eq!(parse_note_name("C-999999999"), None);
+    }
+
+    #[test]
+    fn note_between_two_fields_follows_the_rounding() {
+        let kurd = [50, 57, 58, 60, 62, 64, 65, 67, 69];
+
+        // C♯4 sits a semitone from both the C4 (index 3) and D4 (index 4) fields
+        assert_eq!(
+            closest_field(61, &kurd, OutOfScaleRounding::Nearest),
+            (1, Some(3))
+        );
+        assert_eq!(
+            closest_field(61, &kurd, OutOfScaleRounding::Below),
+            (1, Some(3))
+        );
+        assert_eq!(
+            closest_field(61, &kurd, OutOfScaleRounding::Above),
+            (-1, Some(4))
+        );
+    }
+
+    #[test]
+    fn black_keys_are_spelled_with_the_chosen_accidental() {
+        let spell = |pitch: u8, preference: &str| {
+            let preference = AccidentalPreference::from_form_value(preference).unwrap();
+            midi_to_note_and_octave_with_tpc(pitch, tpc_for_pitch_class(pitch, preference)).unwrap()
+        };
+
+        assert_eq!(spell(63, "sharps"), ("D♯".to_string(), 4));
+        assert_eq!(spell(63, "flats"), ("E♭".to_string(), 4));
+        // Natural notes keep their spelling either way
+        assert_eq!(spell(62, "sharps"), spell(62, "flats"));
+        assert_eq!(AccidentalPreference::from_form_value("as_written"), None);
+    }
+
+    #[test]
+    fn lowest_played_note_is_shifted_onto_the_ding() {
+        let histogram = BTreeMap::from([(48, 0), (55, 2), (62, 1)]);
+
+        // G3 is the lowest note actually played, a fifth above the D3 ding
+        assert_eq!(ding_transposition(&histogram, 50), Some(-5));
+        assert_eq!(ding_transposition(&BTreeMap::new(), 50), None);
+    }
+
+    #[test]
+    fn fields_on_either_side_of_the_ding_get_a_hand() {
+        // On the 9-note layout, field 2 sits at 227° and field 3 at 134°
+        assert_eq!(hand_for_field(9, 2), Hand::Left);
+        assert_eq!(hand_for_field(9, 3), Hand::Right);
+        // The ding, the bottom field and fields off the layout can be played by either hand
+        assert_eq!(hand_for_field(9, 0), Hand::Either);
+        assert_eq!(hand_for_field(9, 1), Hand::Either);
+        assert_eq!(hand_for_field(9, 9), Hand::Either);
+    }
+
+    #[test]
+    fn note_names_are_renamed_to_the_chosen_syllables() {
+        assert_eq!(NoteNaming::Solfege.display_name("C4"), "Do4");
+        assert_eq!(NoteNaming::Solfege.display_name("C♯4"), "Do♯4");
+        assert_eq!(NoteNaming::Solfege.display_name("B♭3"), "Ti♭3");
+        assert_eq!(NoteNaming::FixedDo.display_name("D5"), "Ré5");
+        assert_eq!(NoteNaming::FixedDo.display_name("B3"), "Si3");
+        assert_eq!(NoteNaming::Letters.display_name("C♯4"), "C♯4");
+    }
+
+    #[test]
+    fn scales_are_filtered_by_family() {
+        let kurd = scales_in_family(" kurd ");
+        let note_counts: Vec<usize> = kurd
+            .iter()
+            .map(|(_, _, _, _, midi, _)| midi.len())
+            .collect();
+        assert_eq!(note_counts, [9, 10, 11, 12, 13]);
+        assert!(kurd
+            .iter()
+            .all(|(_, _, name, family, _, _)| name == "D Kurd" && family == "Kurd"));
+        assert!(scales_in_family("Unknown").is_empty());
+
+        let families = scale_families();
+        assert_eq!(families[0], "Kurd");
+        assert_eq!(
+            families.iter().filter(|family| *family == "Kurd").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn transposing_into_a_sharp_key_keeps_each_note_letter_in_the_key() {
+        let spell = |pitch: u8, tpc: Option<i8>, transpose: i32, key_fifths: i32| {
+            let (pitch, tpc) = transpose_pitch_and_tpc(pitch, tpc, transpose, key_fifths);
+            midi_to_note_and_octave_with_tpc(pitch, tpc).unwrap()
+        };
+
+        // The G♯ leading tone of A minor is the B♯ of C♯ minor, not a C
+        assert_eq!(spell(68, Some(22), 4, 0), ("B♯".to_string(), 4));
+        // The D♯ leading tone of E major is the E♯ of F♯ major, not an F
+        assert_eq!(spell(75, Some(23), 2, 4), ("E♯".to_string(), 5));
+        // The raised fifth of E major (B♯) needs a double sharp in F♯ major
+        assert_eq!(spell(72, Some(26), 2, 4), ("C♯♯".to_string(), 5));
+        // Tablature notes have no spelling of their own: G is the raised tonic of F♯ major
+        assert_eq!(spell(65, None, 2, 4), ("F♯♯".to_string(), 4));
+    }
+
+    #[test]
+    fn out_of_range_notes_outweigh_one_more_match() {
+        let kurd = [50, 57, 58, 60, 62, 64, 65, 67, 69];
+        let notes = [54, 55, 59, 66, 69];
+        let unpenalized = TranspositionWeights {
+            matched: 1.0,
+            penalty: 0.0,
+        };
+
+        // +3 matches four notes but pushes the top one above the highest field; -4 matches three and keeps all
+        assert_eq!(
+            find_best_transposition_with_harmonic_context(&notes, &kurd, 12, unpenalized),
+            3
+        );
+        assert_eq!(
+            find_best_transposition_with_harmonic_context(
+                &notes,
+                &kurd,
+                12,
+                TranspositionWeights::default()
+            ),
+            -4
+        );
+    }
+
+    #[test]
+    fn shorter_override_keeps_builtin_ids() {
+        let builtin = build_scales_list(&[]);
+        let kurd = ScaleDefinition {
+            name: "D Kurd".to_string(),
+            family: None,
+            midi: vec![50, 57, 58, 60, 62, 64, 65, 67, 69],
+            tpc: vec![16, 17, 12, 14, 16, 18, 13, 15, 17],
+        };
+        let overridden = build_scales_list(&[kurd]);
+
+        assert_eq!(id_of(&overridden, "d_kurd_10"), None);
+        for (id, key, _, _, _, _) in &overridden {
+            assert_eq!(id_of(&builtin, key), Some(*id));
+        }
+    }
+
+    #[test]
+    fn scales_file_rejects_out_of_range_tpc() {
+        let mut file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+        std::io::Write::write_all(
+            &mut file,
+            br#"{"scales": [{"name": "Odd", "midi": [50, 52, 53, 55, 57, 58, 60, 62, 64], "tpc": [16, 18, 13, 15, 17, 12, 14, 16, 34]}]}"#,
+        )
+        .unwrap();
+
+        let error = load_scales_file(file.path()).unwrap_err();
+        assert!(error.contains("TPC outside"));
+    }
+
+    #[test]
+    fn transposing_a_note_without_tpc_derives_one() {
+        // An A (57) without a spelling, a whole tone up in D minor, is a B of E minor
+        let (pitch, tpc) = transpose_pitch_and_tpc(57, None, 2, -1);
+        assert_eq!(pitch, 59);
+        assert_eq!(
+            midi_to_note_and_octave_with_tpc(pitch, tpc),
+            Some(("B".to_string(), 3))
+        );
+    }
+
+    #[test]
+    fn tpc_table_boundaries() {
+        assert_eq!(
+            midi_to_note_and_octave_with_tpc(51, -1),
+            Some(("F♭♭".to_string(), 3))
+        );
+        assert_eq!(
+            midi_to_note_and_octave_with_tpc(73, 33),
+            Some(("B♯♯".to_string(), 4))
+        );
+        assert_eq!(midi_to_note_and_octave_with_tpc(60, 34), None);
+        assert_eq!(midi_to_note_and_octave_with_tpc(60, -2), None);
+    }
+
+    #[test]
+    fn scale_key_never_looks_like_a_numeric_id() {
+        assert_eq!(scale_key("D Kurd", 10), "d_kurd_10");
+        assert_eq!(scale_key("C# Annaziska", 9), "c_annaziska_9");
+        assert_eq!(scale_key("♯♯", 9), "scale_9");
+        assert_eq!(scale_key("", 12), "scale_12");
+    }
+
+    #[test]
+    fn widened_range_finds_a_shift_past_an_octave() {
+        let scale_notes = get_handpan_scale("d_kurd_9").unwrap().1;
+        let notes: Vec<u8> = scale_notes.iter().map(|note| note + 18).collect();
+        let weights = TranspositionWeights::default();
 
-        return Some((new_pitch as u8, new_tpc as i8));
+        assert_ne!(
+            find_best_transposition_with_harmonic_context(&notes, &scale_notes, 12, weights),
+            -18
+        );
+        assert_eq!(
+            find_best_transposition_with_harmonic_context(&notes, &scale_notes, 24, weights),
+            -18
+        );
     }
-    None
 }
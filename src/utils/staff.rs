@@ -0,0 +1,255 @@
+use crate::templates::parser::{ScoreMeasure, ScoreNote};
+
+/// The number of measures drawn side by side on one staff.
+const MEASURES_PER_SYSTEM: usize = 4;
+
+/// The diatonic positions of the five treble staff lines, from E4 (bottom) to F5 (top).
+const STAFF_BOTTOM: i32 = 30;
+const STAFF_TOP: i32 = 38;
+
+/// The diatonic positions where the numerator and denominator of a time signature are written (D5 and G4).
+const SIGNATURE_ROWS: (i32, i32) = (36, 32);
+
+/// The glyph drawn for each duration; anything shorter than a 16th uses the 16th glyph.
+const DURATION_GLYPHS: [(&str, char); 6] = [
+    ("longa", '○'),
+    ("breve", '○'),
+    ("whole", '○'),
+    ("measure", '○'),
+    ("half", '◐'),
+    ("quarter", '●'),
+];
+
+/// The glyph of a rest, drawn on the middle line.
+const REST_GLYPH: char = '▬';
+
+/// Returns the glyph drawn for a note of the given duration.
+fn note_glyph(duration: &str) -> char {
+    match duration {
+        "eighth" => '♪',
+        "16th" | "32nd" | "64th" | "128th" => '♬',
+        _ => DURATION_GLYPHS
+            .iter()
+            .find(|(name, _)| *name == duration)
+            .map_or('●', |(_, glyph)| *glyph),
+    }
+}
+
+/// Finds where a note sits on the staff from its spelled name (e.g. `"C♯4"`).
+///
+/// # Returns
+/// The diatonic position of the note (`octave * 7` plus the letter's step from C) and its accidental, or `None` if the
+/// name can't be read.
+fn staff_position(note: &str) -> Option<(i32, Option<char>)> {
+    let mut chars = note.chars();
+    let step = match chars.next()? {
+        'C' => 0,
+        'D' => 1,
+        'E' => 2,
+        'F' => 3,
+        'G' => 4,
+        'A' => 5,
+        'B' => 6,
+        _ => return None,
+    };
+    let rest: String = chars.collect();
+    let octave_start = rest.find(|c: char| c.is_ascii_digit() || c == '-')?;
+    let octave: i32 = rest[octave_start..].parse().ok()?;
+    let accidental = rest[..octave_start].chars().next();
+    Some((octave * 7 + step, accidental))
+}
+
+/// One column of a system: the cells it writes, as `(position, text)`, and whether it is a barline.
+struct Column {
+    cells: Vec<(i32, String)>,
+    barline: bool,
+}
+
+/// Builds a rough Unicode staff rendering of parsed measures, as a pure-text alternative to the SVG view.
+///
+/// This function:
+///
+/// 1. **Groups the Measures**: Draws `MEASURES_PER_SYSTEM` measures per staff, each system introduced by the numbers
+///    of its measures.
+/// 2. **Places the Notes**: Places every chord in its own column on a treble staff, one row per diatonic step, with
+///    its accidental in front (`♯`, `♭`, ...). Notes above or below the staff get short ledger lines.
+/// 3. **Writes the Rhythm**: Picks each note's glyph from its duration (`○` whole, `◐` half, `●` quarter, `♪` eighth,
+///    `♬` shorter), draws rests as `▬` on the middle line and writes time signature changes inside the staff.
+///
+/// # Parameters
+/// - `measures`: The measures returned by `parse_mscx_score`.
+/// - `play_only_inscale`: When `true`, notes that are not on the handpan are left out; a chord left without notes is
+///   drawn as a rest.
+///
+/// # Returns
+/// The systems as lines of text, separated by blank lines.
+pub fn export_unicode_staff(measures: &[ScoreMeasure], play_only_inscale: bool) -> String {
+    let mut systems = Vec::new();
+
+    for system in measures.chunks(MEASURES_PER_SYSTEM) {
+        let mut columns = vec![Column {
+            cells: Vec::new(),
+            barline: true,
+        }];
+        for measure in system {
+            if let Some((numerator, denominator)) = measure.time_signature.split_once('|') {
+                columns.push(Column {
+                    cells: vec![
+                        (SIGNATURE_ROWS.0, numerator.to_string()),
+                        (SIGNATURE_ROWS.1, denominator.to_string()),
+                    ],
+                    barline: false,
+                });
+            }
+            for chord in &measure.chords {
+                if chord.is_empty() {
+                    continue;
+                }
+                let played: Vec<&ScoreNote> = chord
+                    .iter()
+                    .filter(|note| note.is_played(play_only_inscale))
+                    .collect();
+                let cells = if played.is_empty() {
+                    vec![((STAFF_BOTTOM + STAFF_TOP) / 2, REST_GLYPH.to_string())]
+                } else {
+                    played
+                        .iter()
+                        .filter_map(|note| {
+                            let (position, accidental) = staff_position(&note.note)?;
+                            let glyph = note_glyph(&note.duration);
+                            Some((
+                                position,
+                                accidental.map_or(glyph.to_string(), |a| format!("{}{}", a, glyph)),
+                            ))
+                        })
+                        .collect()
+                };
+                columns.push(Column {
+                    cells,
+                    barline: false,
+                });
+            }
+            columns.push(Column {
+                cells: Vec::new(),
+                barline: true,
+            });
+        }
+
+        // The staff grows to fit the highest and lowest notes of the system
+        let positions = columns
+            .iter()
+            .flat_map(|column| column.cells.iter().map(|(position, _)| *position));
+        let top = positions.clone().max().unwrap_or(STAFF_TOP).max(STAFF_TOP);
+        let bottom = positions.min().unwrap_or(STAFF_BOTTOM).min(STAFF_BOTTOM);
+
+        let first_number = system.first().map_or(0, |measure| measure.number);
+        let last_number = system.last().map_or(0, |measure| measure.number);
+        let mut lines = vec![if first_number == last_number {
+            format!("M{}", first_number)
+        } else {
+            format!("M{}-{}", first_number, last_number)
+        }];
+
+        for row in (bottom..=top).rev() {
+            let on_staff = (STAFF_BOTTOM..=STAFF_TOP).contains(&row);
+            let staff_line = on_staff && (row - STAFF_BOTTOM) % 2 == 0;
+            let mut line = String::new();
+            for column in &columns {
+                if column.barline {
+                    line.push(if on_staff { '│' } else { ' ' });
+                    continue;
+                }
+                // A ledger line runs through the column when one of its notes is at or beyond this row
+                let ledger = !on_staff
+                    && (row - STAFF_BOTTOM) % 2 == 0
+                    && column.cells.iter().any(|(position, _)| {
+                        (row > STAFF_TOP && *position >= row)
+                            || (row < STAFF_BOTTOM && *position <= row)
+                    });
+                let fill = if staff_line || ledger { '─' } else { ' ' };
+                let cell = column
+                    .cells
+                    .iter()
+                    .find(|(position, _)| *position == row)
+                    .map(|(_, text)| text.as_str());
+                match cell {
+                    Some(text) if text.chars().count() >= 2 => {
+                        line.push_str(text);
+                        if text.chars().count() == 2 {
+                            line.push(fill);
+                        }
+                    }
+                    Some(text) => {
+                        line.push(fill);
+                        line.push_str(text);
+                        line.push(fill);
+                    }
+                    None => line.extend([fill; 3]),
+                }
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        systems.push(lines.join("\n"));
+    }
+
+    let mut staff = systems.join("\n\n");
+    staff.push('\n');
+    staff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A measure of one chord per note, given as `(name, duration)`.
+    fn measure(number: u32, time_signature: &str, notes: &[(&str, &str)]) -> ScoreMeasure {
+        ScoreMeasure {
+            number,
+            time_signature: time_signature.to_string(),
+            chords: notes
+                .iter()
+                .map(|(note, duration)| {
+                    vec![ScoreNote {
+                        note: note.to_string(),
+                        duration: duration.to_string(),
+                        note_index: Some(0),
+                        ..Default::default()
+                    }]
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn systems_hold_four_measures_on_a_treble_staff() {
+        let mut measures = vec![measure(1, "4|4", &[("F♯4", "half"), ("C4", "quarter")])];
+        measures.extend((2..=5).map(|number| measure(number, "", &[("G5", "whole")])));
+
+        let staff = export_unicode_staff(&measures, false);
+        let lines: Vec<&str> = staff.lines().collect();
+
+        assert_eq!(
+            lines[..14],
+            [
+                "M1-4",
+                "            ○   ○   ○",
+                "│─────────│───│───│───│",
+                "│         │   │   │   │",
+                "│─4───────│───│───│───│",
+                "│         │   │   │   │",
+                "│─────────│───│───│───│",
+                "│         │   │   │   │",
+                "│─4───────│───│───│───│",
+                "│   ♯◐    │   │   │   │",
+                "│─────────│───│───│───│",
+                "",
+                "       ─●─",
+                "",
+            ]
+        );
+        // The fifth measure starts a new system, on a staff of its own height
+        assert_eq!(lines[14..16], ["M5", "  ○"]);
+        assert_eq!(lines.len(), 25);
+    }
+}
@@ -1,34 +1,265 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
 
-/// Loads the SVG content for a handpan scale based on the number of notes.
+/// The handpan layouts and rest symbols read so far, by file name, so each file is read from disk only once.
 ///
-/// This function:
+/// Entries are shared through an `Arc`, so a reader can let go of the lock before copying or decompressing one.
+static SVG_CACHE: Lazy<Mutex<HashMap<String, Arc<CachedSvg>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// An SVG file kept in `SVG_CACHE`.
+///
+/// - **`Plain`**: The content as read.
+/// - **`Gzip`**: The gzip-compressed content, used when `HANDFLOW_CACHE_COMPRESS` is set so large layouts take less
+///   memory; it is decompressed on every use.
+pub enum CachedSvg {
+    Plain(String),
+    Gzip(Vec<u8>),
+}
+
+impl CachedSvg {
+    /// Wraps SVG content for the cache, compressing it when `compress` is `true`.
+    pub fn new(content: String, compress: bool) -> io::Result<Self> {
+        if !compress {
+            return Ok(CachedSvg::Plain(content));
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes())?;
+        Ok(CachedSvg::Gzip(encoder.finish()?))
+    }
+
+    /// Returns the original SVG content, decompressing it if needed.
+    pub fn content(&self) -> io::Result<String> {
+        match self {
+            CachedSvg::Plain(content) => Ok(content.clone()),
+            CachedSvg::Gzip(bytes) => {
+                let mut content = String::new();
+                GzDecoder::new(&bytes[..]).read_to_string(&mut content)?;
+                Ok(content)
+            }
+        }
+    }
+}
+
+/// Reads an SVG file through `SVG_CACHE`, loading it from disk the first time it is requested.
 ///
-/// 1. **Generates the File Name**: Constructs the file name based on the scale length.
-/// 2. **Opens the SVG File**: Opens the corresponding SVG file from the `static/img` directory.
-/// 3. **Reads the Content**: Reads the content of the SVG file into a string.
+/// The cache is only locked to look the entry up; a compressed entry is decompressed after the lock is released, so
+/// concurrent renders don't wait on each other's decompression.
 ///
 /// # Parameters
-/// - `scale_len`: The number of notes in the scale.
+/// - `file_name`: The path of the SVG file.
 ///
 /// # Returns
 /// An `io::Result<String>` containing the SVG content.
-pub fn load_svg_for_scale(scale_len: usize) -> io::Result<String> {
-    let file_name = format!("static/img/hand-{}.svg", scale_len);
+fn read_cached_svg(file_name: &str) -> io::Result<String> {
+    let cached = SVG_CACHE.lock().unwrap().get(file_name).cloned();
+    if let Some(cached) = cached {
+        return cached.content();
+    }
+
     let mut file = File::open(file_name)?;
     let mut svg_content = String::new();
     file.read_to_string(&mut svg_content)?;
+
+    let cached = CachedSvg::new(
+        svg_content.clone(),
+        crate::utils::config::config().cache_compress,
+    )?;
+    SVG_CACHE
+        .lock()
+        .unwrap()
+        .insert(file_name.to_string(), Arc::new(cached));
     Ok(svg_content)
 }
 
+/// The start of the group `mirror_svg` wraps a layout in, followed by the x coordinate of its mirror axis times two.
+const MIRROR_GROUP_START: &str = r#"<g class="mirrored" transform="matrix(-1 0 0 1 "#;
+
+/// The error returned by `load_svg_for_scale`.
+///
+/// - **`Missing`**: There is no layout file for the note count; `supported` lists the note counts that have one.
+/// - **`Io`**: The layout file exists but couldn't be read.
+#[derive(Debug)]
+pub enum ScaleSvgError {
+    Missing {
+        file_name: String,
+        supported: Vec<usize>,
+    },
+    Io {
+        file_name: String,
+        source: io::Error,
+    },
+}
+
+impl std::fmt::Display for ScaleSvgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScaleSvgError::Missing {
+                file_name,
+                supported,
+            } => write!(
+                f,
+                "The handpan layout {} is missing; supported note counts are {}",
+                file_name,
+                supported
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            ScaleSvgError::Io { file_name, source } => {
+                write!(
+                    f,
+                    "Failed to read the handpan layout {}: {}",
+                    file_name, source
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScaleSvgError {}
+
+/// Lists the note counts that have a handpan layout in `static/img` (`hand-{count}.svg`), in ascending order.
+pub fn supported_scale_layouts() -> Vec<usize> {
+    let mut counts = std::fs::read_dir("static/img")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_prefix("hand-")?
+                .strip_suffix(".svg")?
+                .parse::<usize>()
+                .ok()
+        })
+        .collect::<Vec<usize>>();
+    counts.sort_unstable();
+    counts
+}
+
+/// The directory holding the default handpan layouts and one subdirectory per layout set.
+const LAYOUT_DIR: &str = "static/img";
+
+/// Returns the file name of a handpan layout, inside the layout set's directory when one is given.
+///
+/// # Parameters
+/// - `layout_dir`: The directory holding the layouts, normally `LAYOUT_DIR`.
+/// - `scale_len`: The number of notes in the scale.
+/// - `layout_set`: An optional alternate layout set, already checked against the allowlist.
+fn layout_file_name(layout_dir: &str, scale_len: usize, layout_set: Option<&str>) -> String {
+    match layout_set {
+        Some(set) => format!("{}/{}/hand-{}.svg", layout_dir, set, scale_len),
+        None => format!("{}/hand-{}.svg", layout_dir, scale_len),
+    }
+}
+
+/// Picks the layout file of a note count: the layout set's own file when the set is allowlisted and has one, the
+/// default layout otherwise.
+///
+/// # Parameters
+/// - `layout_dir`: The directory holding the layouts, normally `LAYOUT_DIR`.
+/// - `scale_len`: The number of notes in the scale.
+/// - `layout_set`: The requested layout set, if any.
+/// - `allowed_sets`: The allowlisted layout sets, normally `layout_sets()`.
+fn select_layout_file(
+    layout_dir: &str,
+    scale_len: usize,
+    layout_set: Option<&str>,
+    allowed_sets: &[String],
+) -> String {
+    let layout_set = match layout_set {
+        Some(set)
+            if allowed_sets.iter().any(|allowed| allowed == set)
+                && std::path::Path::new(&layout_file_name(layout_dir, scale_len, Some(set)))
+                    .is_file() =>
+        {
+            Some(set)
+        }
+        Some(set) => {
+            log::warn!(
+                "Layout set {} has no {}-note layout; using the default one",
+                set,
+                scale_len
+            );
+            None
+        }
+        None => None,
+    };
+    layout_file_name(layout_dir, scale_len, layout_set)
+}
+
+/// Returns whether a layout set is on the configured allowlist (`HANDFLOW_LAYOUT_SETS`).
+pub fn is_layout_set(name: &str) -> bool {
+    crate::utils::config::layout_sets()
+        .iter()
+        .any(|set| set == name)
+}
+
+/// Returns whether an allowlisted layout set has its own layout for a note count.
+///
+/// # Parameters
+/// - `layout_set`: The name of the layout set.
+/// - `scale_len`: The number of notes in the scale.
+pub fn layout_set_has_scale(layout_set: &str, scale_len: usize) -> bool {
+    is_layout_set(layout_set)
+        && std::path::Path::new(&layout_file_name(LAYOUT_DIR, scale_len, Some(layout_set)))
+            .is_file()
+}
+
+/// Loads the SVG content for a handpan scale based on the number of notes.
+///
+/// This function:
+///
+/// 1. **Picks the Layout Set**: Uses the requested set if it is allowlisted and has a layout for the note count,
+///    falling back to the default layouts otherwise.
+/// 2. **Generates the File Name**: Constructs the file name based on the scale length and the layout set.
+/// 3. **Reads the Content**: Reads the corresponding SVG file from the `static/img` directory through the SVG cache.
+///
+/// # Parameters
+/// - `scale_len`: The number of notes in the scale.
+/// - `layout_set`: An optional alternate layout set (e.g. `brandX` for `static/img/brandX/hand-{count}.svg`).
+///
+/// # Returns
+/// - `Ok(String)` containing the SVG content.
+/// - `Err(ScaleSvgError::Missing)` naming the file and the supported note counts if there is no layout for
+///   `scale_len`, or `Err(ScaleSvgError::Io)` if it can't be read.
+pub fn load_svg_for_scale(
+    scale_len: usize,
+    layout_set: Option<&str>,
+) -> Result<String, ScaleSvgError> {
+    let file_name = select_layout_file(
+        LAYOUT_DIR,
+        scale_len,
+        layout_set,
+        crate::utils::config::layout_sets(),
+    );
+    let io_error = |source: io::Error| {
+        if source.kind() == io::ErrorKind::NotFound {
+            ScaleSvgError::Missing {
+                file_name: file_name.clone(),
+                supported: supported_scale_layouts(),
+            }
+        } else {
+            ScaleSvgError::Io {
+                file_name: file_name.clone(),
+                source,
+            }
+        }
+    };
+    read_cached_svg(&file_name).map_err(io_error)
+}
+
 /// Loads the SVG content for a rest symbol based on its duration.
 ///
 /// This function:
 ///
 /// 1. **Generates the File Name**: Constructs the file name based on the rest duration (e.g., "quarter", "half").
-/// 2. **Opens the SVG File**: Opens the corresponding SVG file from the `static/img` directory.
-/// 3. **Reads the Content**: Reads the content of the SVG file into a string.
+/// 2. **Reads the Content**: Reads the corresponding SVG file from the `static/img` directory through the SVG cache.
 ///
 /// # Parameters
 /// - `duration`: The duration of the rest (e.g., "quarter", "half").
@@ -36,11 +267,7 @@ pub fn load_svg_for_scale(scale_len: usize) -> io::Result<String> {
 /// # Returns
 /// An `io::Result<String>` containing the SVG content.
 pub fn load_svg_for_rest(duration: &str) -> io::Result<String> {
-    let file_name = format!("static/img/rest-{}.svg", duration);
-    let mut file = File::open(file_name)?;
-    let mut svg_content = String::new();
-    file.read_to_string(&mut svg_content)?;
-    Ok(svg_content)
+    read_cached_svg(&format!("static/img/rest-{}.svg", duration))
 }
 
 /// Modifies the color of a note or rest in the SVG content.
@@ -91,3 +318,376 @@ pub fn modify_svg_note_color(svg_content: &str, note_idx: usize, duration: &str)
 
     modified_svg
 }
+
+/// Memoizes the colored handpan SVGs of one render.
+///
+/// Every chord colors the same base SVG, so chords playing the same fields with the same durations end up with the
+/// same string. The colorings of a chord (its `(note_idx, duration)` pairs, in order) are the cache key, and only the
+/// first chord of each combination goes through `modify_svg_note_color`.
+///
+/// A cache is created per `generate_measures_html` call and never shared between requests, so it needs no locking.
+pub struct SvgColorCache<'a> {
+    base_svg: &'a str,
+    colored: HashMap<Vec<(usize, String)>, String>,
+    hits: usize,
+}
+
+impl<'a> SvgColorCache<'a> {
+    /// Creates an empty cache for colorings of `base_svg`.
+    pub fn new(base_svg: &'a str) -> Self {
+        SvgColorCache {
+            base_svg,
+            colored: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    /// Returns the base SVG with every `(note_idx, duration)` coloring applied in order, reusing an earlier result
+    /// for the same colorings.
+    pub fn colored_svg(&mut self, colorings: &[(usize, String)]) -> String {
+        if let Some(svg) = self.colored.get(colorings) {
+            self.hits += 1;
+            return svg.clone();
+        }
+
+        let svg = colorings
+            .iter()
+            .fold(self.base_svg.to_string(), |svg, (note_idx, duration)| {
+                modify_svg_note_color(&svg, *note_idx, duration)
+            });
+        self.colored.insert(colorings.to_vec(), svg.clone());
+        svg
+    }
+
+    /// The number of colorings served from the cache so far.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+/// Reads a numeric attribute (e.g. `cx="315.85"`) from a single SVG element tag.
+fn parse_svg_attribute(element: &str, attribute: &str) -> Option<f64> {
+    let pattern = format!(r#" {}=""#, attribute);
+    let start = element.find(&pattern)? + pattern.len();
+    let end = element[start..].find('"')? + start;
+    element[start..end].trim().parse().ok()
+}
+
+/// Computes the center of a handpan field in the SVG's coordinate space.
+///
+/// This function:
+///
+/// 1. **Finds the Field**: Looks up the `note_shadow_{idx}` ellipse, falling back to the `note_{idx}` ellipse.
+/// 2. **Reads the Center**: Extracts the `cx`/`cy` attributes of the ellipse.
+/// 3. **Applies the Transform**: Applies a `translate(tx ty) rotate(a)` transform, as exported by the layout files, to the center.
+///
+/// # Parameters
+/// - `svg_content`: The handpan SVG content.
+/// - `note_idx`: The index of the field.
+///
+/// # Returns
+/// An `Option<(f64, f64)>` containing the field center, or `None` if the field has no ellipse.
+pub fn field_center(svg_content: &str, note_idx: usize) -> Option<(f64, f64)> {
+    let element = [
+        format!(r#"id="note_shadow_{}""#, note_idx),
+        format!(r#"id="note_{}""#, note_idx),
+    ]
+    .iter()
+    .filter_map(|id| {
+        let pos = svg_content.find(id.as_str())?;
+        let start = svg_content[..pos].rfind('<')?;
+        let end = svg_content[pos..].find('>')? + pos;
+        let element = &svg_content[start..end];
+        element.starts_with("<ellipse").then_some(element)
+    })
+    .next()?;
+
+    let cx = parse_svg_attribute(element, "cx")?;
+    let cy = parse_svg_attribute(element, "cy")?;
+
+    let (mut x, mut y) = (cx, cy);
+    if let Some(transform_start) = element.find(r#"transform=""#) {
+        let transform = &element[transform_start..];
+        let numbers_in = |function: &str| -> Vec<f64> {
+            transform
+                .find(function)
+                .and_then(|start| {
+                    let args = &transform[start + function.len()..];
+                    args.find(')').map(|end| &args[..end])
+                })
+                .map(|args| {
+                    args.split([' ', ','])
+                        .filter_map(|n| n.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        if let Some(&angle) = numbers_in("rotate(").first() {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            (x, y) = (cx * cos - cy * sin, cx * sin + cy * cos);
+        }
+        if let [tx, ty, ..] = numbers_in("translate(")[..] {
+            x += tx;
+            y += ty;
+        }
+    }
+
+    if let Some(width) = mirror_width(svg_content) {
+        x = width - x;
+    }
+
+    Some((x, y))
+}
+
+/// Reads the width `mirror_svg` flipped a layout across, or `None` if the layout isn't mirrored.
+fn mirror_width(svg_content: &str) -> Option<f64> {
+    let start = svg_content.find(MIRROR_GROUP_START)? + MIRROR_GROUP_START.len();
+    let end = svg_content[start..].find(' ')? + start;
+    svg_content[start..end].parse().ok()
+}
+
+/// Mirrors a handpan layout horizontally, for left-handed or mirror-tuned instruments.
+///
+/// This function:
+///
+/// 1. **Reads the Width**: Takes the horizontal extent of the drawing from the `viewBox` of the `<svg>` element.
+/// 2. **Flips the Drawing**: Wraps the whole content in a group flipping it across the vertical center line, so every
+///    `note_{idx}` field keeps its id and coloring but moves to the mirrored position.
+///
+/// Labels written afterwards by `label_svg_fields` are placed outside the group, on the mirrored field centers
+/// reported by `field_center`, so they stay readable.
+///
+/// # Parameters
+/// - `svg_content`: The handpan SVG content, as returned by `load_svg_for_scale`.
+///
+/// # Returns
+/// A `String` containing the mirrored SVG, or the content unchanged if it has no `viewBox` or is already mirrored.
+pub fn mirror_svg(svg_content: &str) -> String {
+    if mirror_width(svg_content).is_some() {
+        return svg_content.to_string();
+    }
+    let Some(svg_start) = svg_content.find("<svg") else {
+        return svg_content.to_string();
+    };
+    let Some(tag_end) = svg_content[svg_start..]
+        .find('>')
+        .map(|end| svg_start + end + 1)
+    else {
+        return svg_content.to_string();
+    };
+    let view_box: Vec<f64> = svg_content[svg_start..tag_end]
+        .split_once(r#"viewBox=""#)
+        .and_then(|(_, rest)| rest.split('"').next())
+        .map(|values| {
+            values
+                .split([' ', ','])
+                .filter_map(|n| n.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    let (Some(min_x), Some(width), Some(close)) = (
+        view_box.first(),
+        view_box.get(2),
+        svg_content.rfind("</svg>"),
+    ) else {
+        return svg_content.to_string();
+    };
+
+    format!(
+        "{}{}{} 0)\">{}</g>{}",
+        &svg_content[..tag_end],
+        MIRROR_GROUP_START,
+        2.0 * min_x + width,
+        &svg_content[tag_end..close],
+        &svg_content[close..]
+    )
+}
+
+/// Labels each field of a handpan SVG with the given text (typically its note name).
+///
+/// # Parameters
+/// - `svg_content`: The handpan SVG content.
+/// - `labels`: The label of each field, indexed like the `note_{idx}` elements. Empty labels are skipped.
+///
+/// # Returns
+/// A `String` containing the SVG with a `<text>` element centered on every labeled field.
+pub fn label_svg_fields(svg_content: &str, labels: &[String]) -> String {
+    let mut label_elements = String::new();
+    for (note_idx, label) in labels.iter().enumerate() {
+        if label.is_empty() {
+            continue;
+        }
+        if let Some((x, y)) = field_center(svg_content, note_idx) {
+            label_elements.push_str(&format!(
+                r#"<text class="field-label" x="{:.2}" y="{:.2}" text-anchor="middle" dominant-baseline="central" font-family="sans-serif" font-size="22" fill="white" stroke="black" stroke-width="0.5">{}</text>"#,
+                x,
+                y,
+                crate::templates::html::sanitize_html(label)
+            ));
+        }
+    }
+
+    match svg_content.rfind("</svg>") {
+        Some(pos) => {
+            let mut labeled_svg = String::from(svg_content);
+            labeled_svg.insert_str(pos, &label_elements);
+            labeled_svg
+        }
+        None => svg_content.to_string(),
+    }
+}
+
+/// Colors the played fields of a handpan SVG, e.g. to show the notes of a single measure.
+///
+/// Each field is colored once with `modify_svg_note_color`, by the duration it is first played with.
+///
+/// # Parameters
+/// - `svg_content`: The handpan SVG content.
+/// - `fields`: The `(note_idx, duration)` pairs of the played notes, in play order.
+///
+/// # Returns
+/// A `String` containing the SVG with every played field colored; the other fields are left as they are.
+pub fn color_svg_fields(svg_content: &str, fields: &[(usize, String)]) -> String {
+    let mut colored: HashSet<usize> = HashSet::new();
+    let mut colored_svg = svg_content.to_string();
+    for (note_idx, duration) in fields {
+        if colored.insert(*note_idx) {
+            colored_svg = modify_svg_note_color(&colored_svg, *note_idx, duration);
+        }
+    }
+    colored_svg
+}
+
+/// Numbers the fields of a handpan SVG in play order, for a "follow the numbers" sheet.
+///
+/// This function:
+///
+/// 1. **Numbers the Steps**: Gives every step (a chord) its 1-based position; all fields of a chord share its number.
+/// 2. **Colors the Fields**: Colors each played field with `modify_svg_note_color`, using the duration of its first step.
+/// 3. **Labels the Fields**: Writes the numbers of each field with `label_svg_fields`. A field played several times
+///    gets all its numbers in ascending order (e.g. `"1, 4"`).
+///
+/// # Parameters
+/// - `svg_content`: The handpan SVG content.
+/// - `steps`: The `(note_idx, duration)` pairs of each step, in play order (see `first_phrase`).
+///
+/// # Returns
+/// A `String` containing the numbered SVG.
+pub fn number_svg_fields(svg_content: &str, steps: &[Vec<(usize, String)>]) -> String {
+    let mut numbers: Vec<Vec<String>> = Vec::new();
+    let mut colored_svg = svg_content.to_string();
+    for (step, fields) in steps.iter().enumerate() {
+        for (note_idx, duration) in fields {
+            if numbers.len() <= *note_idx {
+                numbers.resize(note_idx + 1, Vec::new());
+            }
+            let field_numbers = &mut numbers[*note_idx];
+            if field_numbers.is_empty() {
+                colored_svg = modify_svg_note_color(&colored_svg, *note_idx, duration);
+            }
+            let number = (step + 1).to_string();
+            if field_numbers.last() != Some(&number) {
+                field_numbers.push(number);
+            }
+        }
+    }
+
+    let labels: Vec<String> = numbers.iter().map(|n| n.join(", ")).collect();
+    label_svg_fields(&colored_svg, &labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_layout_names_the_file_and_the_supported_counts() {
+        let error = load_svg_for_scale(4, None).unwrap_err();
+        let ScaleSvgError::Missing {
+            file_name,
+            supported,
+        } = &error
+        else {
+            panic!("expected a missing layout, got {:?}", error);
+        };
+        assert_eq!(file_name, "static/img/hand-4.svg");
+        assert_eq!(supported, &supported_scale_layouts());
+        assert!(supported.contains(&9) && !supported.contains(&4));
+        assert!(error.to_string().contains("static/img/hand-4.svg"));
+    }
+
+    #[test]
+    fn repeated_colorings_come_from_the_cache() {
+        let base = r#"<svg><ellipse id="note_1" cx="1" cy="1"/><ellipse id="note_2" cx="2" cy="2"/></svg>"#;
+        let colorings = vec![(1, "quarter".to_string()), (2, "half".to_string())];
+        let mut cache = SvgColorCache::new(base);
+
+        let first = cache.colored_svg(&colorings);
+        assert_ne!(first, base);
+        assert_eq!(cache.hits(), 0);
+        let second = cache.colored_svg(&colorings);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(first, second);
+        assert_eq!(
+            first,
+            modify_svg_note_color(&modify_svg_note_color(base, 1, "quarter"), 2, "half")
+        );
+
+        cache.colored_svg(&colorings[..1]);
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn compressed_cache_entry_round_trips() {
+        let layout = std::fs::read_to_string("static/img/hand-9.svg").unwrap();
+
+        let compressed = CachedSvg::new(layout.clone(), true).unwrap();
+        let CachedSvg::Gzip(bytes) = &compressed else {
+            panic!("expected a compressed entry");
+        };
+        assert!(bytes.len() < layout.len());
+        assert_eq!(compressed.content().unwrap(), layout);
+
+        let plain = CachedSvg::new(layout.clone(), false).unwrap();
+        assert!(matches!(plain, CachedSvg::Plain(_)));
+        assert_eq!(plain.content().unwrap(), layout);
+    }
+
+    #[test]
+    fn mirrored_field_moves_across_the_centre_line() {
+        let layout = r#"<svg viewBox="10 0 200 100"><ellipse id="note_1" cx="50" cy="40" rx="5" ry="5"/></svg>"#;
+        assert_eq!(field_center(layout, 1), Some((50.0, 40.0)));
+
+        // The drawing spans x = 10..210, so x = 50 is mirrored onto x = 170
+        let mirrored = mirror_svg(layout);
+        assert_eq!(field_center(&mirrored, 1), Some((170.0, 40.0)));
+        assert_eq!(mirror_svg(&mirrored), mirrored);
+
+        let labeled = label_svg_fields(&mirrored, &["".to_string(), "D4".to_string()]);
+        assert!(labeled.contains(r#"<text class="field-label" x="170.00" y="40.00""#));
+        // The label stays outside the flipped group, so its text isn't mirrored
+        assert!(labeled.contains(r#"</g><text class="field-label""#));
+    }
+
+    #[test]
+    fn allowlisted_layout_set_selects_its_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout_dir = dir.path().to_str().unwrap();
+        std::fs::create_dir(dir.path().join("brandX")).unwrap();
+        std::fs::write(dir.path().join("brandX/hand-9.svg"), "<svg>brandX</svg>").unwrap();
+        let allowed = ["brandX".to_string()];
+
+        let file_name = select_layout_file(layout_dir, 9, Some("brandX"), &allowed);
+        assert_eq!(file_name, format!("{}/brandX/hand-9.svg", layout_dir));
+        assert_eq!(read_cached_svg(&file_name).unwrap(), "<svg>brandX</svg>");
+        // Sets that aren't allowlisted or lack the note count fall back to the default layout
+        assert_eq!(
+            select_layout_file(layout_dir, 9, Some("brandX"), &[]),
+            format!("{}/hand-9.svg", layout_dir)
+        );
+        assert_eq!(
+            select_layout_file(layout_dir, 10, Some("brandX"), &allowed),
+            format!("{}/hand-10.svg", layout_dir)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::templates::parser::{ScoreMeasure, ScoreNote};
+
+/// The short code written for each duration in a text tab.
+const DURATION_CODES: [(&str, &str); 11] = [
+    ("longa", "L"),
+    ("breve", "B"),
+    ("whole", "w"),
+    ("half", "h"),
+    ("quarter", "q"),
+    ("eighth", "e"),
+    ("16th", "s"),
+    ("32nd", "t"),
+    ("64th", "x"),
+    ("128th", "o"),
+    ("measure", "m"),
+];
+
+/// Writes a single note of a chord as a tab token.
+///
+/// # Returns
+/// `"D"` for the ding, the field number for the other in-scale notes, or the note name in parentheses when the
+/// note is not on the handpan.
+fn note_token(note: &ScoreNote) -> String {
+    match note.note_index {
+        Some(0) if note.delta == 0 => "D".to_string(),
+        Some(index) if note.delta == 0 => index.to_string(),
+        _ => format!("({})", note.note),
+    }
+}
+
+/// Builds a plain-text handpan tab from parsed measures.
+///
+/// This function:
+///
+/// 1. **Writes the Header**: Lists the title, part, scale, scale notes and transposition, followed by a legend.
+/// 2. **Writes the Measures**: Writes one line per measure with its number, any time signature change and its
+///    chords. Each chord is written as its fields joined with `+` (`D` for the ding), followed by `/` and the
+///    duration code; rests are written as `-`.
+///
+/// The tab only uses plain characters, so it can be pasted into a message or printed as is.
+///
+/// # Parameters
+/// - `title`: The work title.
+/// - `part_name`: The name of the exported part.
+/// - `scale_name`: The name of the handpan scale.
+/// - `scale_notes`: The scale's note names, comma-separated.
+/// - `transposed_value`: The transposition applied to the part, in semitones.
+/// - `measures`: The measures returned by `parse_mscx_score`.
+/// - `play_only_inscale`: When `true`, notes that are not on the handpan are left out; a chord left without notes is
+///   written as a rest.
+///
+/// # Returns
+/// The tab as a `String`.
+pub fn export_text_tab(
+    title: &str,
+    part_name: &str,
+    scale_name: &str,
+    scale_notes: &str,
+    transposed_value: i32,
+    measures: &[ScoreMeasure],
+    play_only_inscale: bool,
+) -> String {
+    let mut tab = String::new();
+    tab.push_str(&format!("Title: {}\n", title));
+    tab.push_str(&format!("Part: {}\n", part_name));
+    tab.push_str(&format!("Scale: {}\n", scale_name));
+    tab.push_str(&format!("Notes on Scale: {}\n", scale_notes));
+    tab.push_str(&format!("Transpose: {}\n\n", transposed_value));
+    tab.push_str(
+        "Fields: D = ding, 1, 2, ... = tone fields, (note) = not on the handpan, - = rest\n",
+    );
+    tab.push_str(&format!(
+        "Durations: {}\n\n",
+        DURATION_CODES
+            .iter()
+            .map(|(duration, code)| format!("{} = {}", code, duration))
+            .collect::<Vec<String>>()
+            .join(", ")
+    ));
+
+    for measure in measures {
+        if let Some((pass, loop_count)) = measure.repetition {
+            tab.push_str(&format!("-- Repetition {} of {} --\n", pass, loop_count));
+        }
+
+        let mut line = format!("M{}", measure.number);
+        if let Some((numerator, denominator)) = measure.time_signature.split_once('|') {
+            line.push_str(&format!(" [{}/{}]", numerator, denominator));
+        }
+        line.push_str(" |");
+
+        for chord in &measure.chords {
+            let Some(first) = chord.first() else {
+                continue;
+            };
+            let tokens: Vec<String> = chord
+                .iter()
+                .filter(|note| note.is_played(play_only_inscale))
+                .map(note_token)
+                .collect();
+            let fields = if tokens.is_empty() {
+                "-".to_string()
+            } else {
+                tokens.join("+")
+            };
+            let code = DURATION_CODES
+                .iter()
+                .find(|(duration, _)| *duration == first.duration)
+                .map_or("?", |(_, code)| code);
+            line.push_str(&format!(" {}/{}", fields, code));
+        }
+
+        line.push_str(" |\n");
+        tab.push_str(&line);
+    }
+
+    tab
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A note of the given name and duration, on field `note_index` when it is in the scale (`delta == 0`).
+    fn note(name: &str, duration: &str, note_index: usize, delta: i32) -> ScoreNote {
+        ScoreNote {
+            note: name.to_string(),
+            duration: duration.to_string(),
+            note_index: Some(note_index),
+            delta,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn measures_are_written_as_fields_and_duration_codes() {
+        let measures = [ScoreMeasure {
+            number: 1,
+            time_signature: "4|4".to_string(),
+            chords: vec![
+                vec![note("D3", "quarter", 0, 0)],
+                vec![note("D4", "eighth", 4, 0), note("F4", "eighth", 6, 0)],
+                vec![note("E♭4", "eighth", 5, -1)],
+                vec![note("Rest", "quarter", 0, 0)],
+                vec![note("A4", "128th", 8, 0)],
+            ],
+            ..Default::default()
+        }];
+
+        let tab = export_text_tab("Tune", "Flute", "D Kurd", "D3, A3", 2, &measures, false);
+        assert!(tab.starts_with(
+            "Title: Tune\nPart: Flute\nScale: D Kurd\nNotes on Scale: D3, A3\nTranspose: 2\n\n"
+        ));
+        assert!(tab.ends_with("M1 [4/4] | D/q 4+6/e (E♭4)/e -/q 8/o |\n"));
+
+        // Without the out-of-scale note, its chord becomes a rest
+        let in_scale = export_text_tab("Tune", "Flute", "D Kurd", "D3, A3", 2, &measures, true);
+        assert!(in_scale.ends_with("M1 [4/4] | D/q 4+6/e -/e -/q 8/o |\n"));
+    }
+}
@@ -3,6 +3,7 @@ document.addEventListener('DOMContentLoaded', () => {
     initializeForm();
     initializePartSelect();
     initializeScaleSelect();
+    initializeLayoutSelect();
     initializeTransposeToggle();
     initializeControlsAutoScroll();
     initializeDisplayToggles();
@@ -17,6 +18,14 @@ function initializeForm() {
         event.preventDefault();
         generateDisplay();
     });
+
+    // Page navigation of long scores re-generates the display for the chosen page
+    document.getElementById("generate-container").addEventListener("click", event => {
+        const pageLink = event.target.closest(".page-link");
+        if (pageLink) {
+            generateDisplay(pageLink.dataset.page);
+        }
+    });
 }
 
 // Function to initialize the part selection dropdown
@@ -36,19 +45,68 @@ function initializeScaleSelect() {
         updatePartName();
         regenerateDisplayIfNeeded();
     });
+
+    // Narrowing the list to one family hides the other scales and moves the selection into the family
+    const familySelect = document.getElementById('scale_family');
+    familySelect.addEventListener('change', () => {
+        const family = familySelect.value;
+        const options = Array.from(scaleSelect.options);
+        options.forEach(option => {
+            option.hidden = family !== '' && option.dataset.family !== family;
+        });
+        scaleSelect.querySelectorAll('optgroup').forEach(group => {
+            group.hidden = Array.from(group.children).every(option => option.hidden);
+        });
+        const selected = scaleSelect.options[scaleSelect.selectedIndex];
+        if (selected && selected.hidden) {
+            const firstVisible = options.find(option => !option.hidden);
+            if (firstVisible) {
+                scaleSelect.value = firstVisible.value;
+                scaleSelect.dispatchEvent(new Event('change'));
+            }
+        }
+    });
+}
+
+// Function to initialize the measure layout, order, rhythm, note name, out-of-scale and rest merging dropdowns
+function initializeLayoutSelect() {
+    const layoutSelect = document.getElementById('layout');
+    layoutSelect.addEventListener('change', regenerateDisplayIfNeeded);
+    const measureOrderSelect = document.getElementById('measure_order');
+    measureOrderSelect.addEventListener('change', regenerateDisplayIfNeeded);
+    const rhythmSelect = document.getElementById('rhythm');
+    rhythmSelect.addEventListener('change', regenerateDisplayIfNeeded);
+    const noteNamingSelect = document.getElementById('note_naming');
+    noteNamingSelect.addEventListener('change', regenerateDisplayIfNeeded);
+    const roundingSelect = document.getElementById('out_of_scale_rounding');
+    roundingSelect.addEventListener('change', regenerateDisplayIfNeeded);
+    const mergeRestsSelect = document.getElementById('merge_short_rests');
+    mergeRestsSelect.addEventListener('change', regenerateDisplayIfNeeded);
 }
 
 // Function to handle the transpose toggle and related input changes
 function initializeTransposeToggle() {
     const autoTransposeCheckbox = document.getElementById('auto_transpose');
+    const dingTransposeCheckbox = document.getElementById('ding_transpose');
+    const perMeasureTransposeCheckbox = document.getElementById('per_measure_transpose');
     const transposeInput = document.getElementById('transpose');
     const transposeValueDisplay = document.getElementById('transpose_value');
 
-    toggleTransposeSlider(autoTransposeCheckbox.checked);
+    const modeCheckboxes = [autoTransposeCheckbox, dingTransposeCheckbox, perMeasureTransposeCheckbox];
+
+    toggleTransposeSlider(modeCheckboxes.some(checkbox => checkbox.checked));
     updateTransposeDisplay(transposeInput.value, transposeValueDisplay);
 
-    autoTransposeCheckbox.addEventListener('change', () => {
-        toggleTransposeSlider(autoTransposeCheckbox.checked);
+    // Auto-transpose, the ding mode and the per-measure mode each pick the transposition, so only one can be on
+    modeCheckboxes.forEach(checkbox => {
+        checkbox.addEventListener('change', () => {
+            if (checkbox.checked) {
+                modeCheckboxes
+                    .filter(other => other !== checkbox)
+                    .forEach(other => other.checked = false);
+            }
+            toggleTransposeSlider(checkbox.checked);
+        });
     });
 
     transposeInput.addEventListener('input', () => {
@@ -63,11 +121,19 @@ function initializeDisplayToggles() {
     const toggleSwitchShowHandpanSvg = document.getElementById('showSvg');
     const toggleSwitchRestColor = document.getElementById('showRestColor');
     const toggleSwitchPlayInScale = document.getElementById('togglePlayInScale');
+    const toggleSwitchShowRests = document.getElementById('toggleShowRests');
+    const toggleSwitchShowIntervals = document.getElementById('toggleShowIntervals');
+    const toggleSwitchSplitHands = document.getElementById('toggleSplitHands');
+    const toggleSwitchMirror = document.getElementById('toggleMirror');
 
     toggleSwitchInlinedisplay.addEventListener('change', updateFlexDirection);
     toggleSwitchShowHandpanSvg.addEventListener('change', showHandpanSVG);
     toggleSwitchRestColor.addEventListener('change', toggleClassOnRest);
     toggleSwitchPlayInScale.addEventListener('change', togglePlayInScale);
+    toggleSwitchShowRests.addEventListener('change', toggleShowRests);
+    toggleSwitchShowIntervals.addEventListener('change', toggleShowIntervals);
+    toggleSwitchSplitHands.addEventListener('change', toggleSplitHands);
+    toggleSwitchMirror.addEventListener('change', toggleMirror);
 }
 
 // Function to initialize controls for adjusting the SVG size
@@ -173,6 +239,34 @@ function togglePlayInScale() {
     regenerateDisplayIfNeeded();
 }
 
+// Function to toggle the rest cells of the generated view
+function toggleShowRests() {
+    const show_rests = document.getElementById('show_rests');
+    show_rests.value = this.checked ? "1" : "0";
+    regenerateDisplayIfNeeded();
+}
+
+// Function to toggle the interval labels between melody notes
+function toggleShowIntervals() {
+    const show_intervals = document.getElementById('show_intervals');
+    show_intervals.value = this.checked ? "1" : "0";
+    regenerateDisplayIfNeeded();
+}
+
+// Function to toggle the left/right hand coloring of the notes
+function toggleSplitHands() {
+    const split_hands = document.getElementById('split_hands');
+    split_hands.value = this.checked ? "1" : "0";
+    regenerateDisplayIfNeeded();
+}
+
+// Function to toggle the mirrored handpan layout for left-handed players
+function toggleMirror() {
+    const mirror = document.getElementById('mirror');
+    mirror.value = this.checked ? "1" : "0";
+    regenerateDisplayIfNeeded();
+}
+
 // Function to control the font size of text elements
 function textControl(selector, increase) {
     const element = document.querySelector(selector);
@@ -191,9 +285,9 @@ function mapRange(value, inMin, inMax, outMin, outMax) {
 }
 
 // Function to generate the display based on form data
-function generateDisplay() {
+function generateDisplay(page = 1) {
     const form = document.querySelector("form");
-    fetch("/generate", {
+    fetch(`/generate?page=${encodeURIComponent(page)}`, {
         method: "POST",
         headers: {
             "Content-Type": "application/x-www-form-urlencoded"
@@ -378,6 +472,8 @@ function initializeControlsAutoScroll() {
     function noteDuration(type, bpm, sigD) {
         const beatDuration = 60 / bpm; // Duration of a quarter note in seconds
         const durationMapping = {
+            'longa': 16,
+            'breve': 8,
             'whole': 4,
             'half': 2,
             'quarter': 1,
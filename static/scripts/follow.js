@@ -0,0 +1,58 @@
+document.addEventListener('DOMContentLoaded', () => {
+    // Moves a play cursor along the notes of an exported page, using their data-start and data-duration beats
+    const notes = Array.from(document.querySelectorAll('.note[data-start]'));
+    const toggle = document.getElementById('followPlay');
+    const bpmInput = document.getElementById('followBpm');
+    if (!notes.length || !toggle || !bpmInput) {
+        return;
+    }
+
+    let startedAt = null;
+    let current = null;
+
+    function step(now) {
+        if (startedAt === null) {
+            return;
+        }
+        const beat = (now - startedAt) / 60000 * (parseFloat(bpmInput.value) || 120);
+        const note = notes.find(n => {
+            const start = parseFloat(n.dataset.start);
+            return beat >= start && beat < start + parseFloat(n.dataset.duration);
+        });
+        if (note !== current) {
+            if (current) {
+                current.classList.remove('playing');
+            }
+            if (note) {
+                note.classList.add('playing');
+                note.scrollIntoView({ behavior: 'smooth', block: 'center' });
+            }
+            current = note;
+        }
+        const last = notes[notes.length - 1];
+        if (beat >= parseFloat(last.dataset.start) + parseFloat(last.dataset.duration)) {
+            stop();
+            return;
+        }
+        requestAnimationFrame(step);
+    }
+
+    function stop() {
+        startedAt = null;
+        if (current) {
+            current.classList.remove('playing');
+            current = null;
+        }
+        toggle.textContent = '▶ Follow';
+    }
+
+    toggle.addEventListener('click', () => {
+        if (startedAt !== null) {
+            stop();
+            return;
+        }
+        startedAt = performance.now();
+        toggle.textContent = '■ Stop';
+        requestAnimationFrame(step);
+    });
+});
@@ -58,10 +58,50 @@ function validateFile() {
     const fileName = fileInput.files[0].name;
     const fileExtension = fileName.split('.').pop().toLowerCase();
 
-    if (fileExtension !== 'mscz') {
-        alert("Invalid file type. Please upload a .mscz file.");
+    if (!['mscz', 'mid', 'midi'].includes(fileExtension)) {
+        alert("Invalid file type. Please upload a .mscz or .mid file.");
         return false;
     }
 
     return true;
-}
\ No newline at end of file
+}
+
+// Upload the file, then poll its job until the parts page is ready
+function uploadScore(form) {
+    if (!validateFile()) {
+        return false;
+    }
+
+    const fileNameDisplay = document.getElementById("file-name");
+    fileNameDisplay.textContent = "Uploading…";
+    fetch(form.action, { method: "POST", body: new FormData(form) })
+        .then((response) => response.ok
+            ? response.json()
+            : response.text().then((message) => Promise.reject(message)))
+        .then(({ job_id }) => {
+            fileNameDisplay.textContent = "Reading the score…";
+            return pollJob(job_id);
+        })
+        .catch((message) => {
+            fileNameDisplay.textContent = "Upload failed";
+            alert(message);
+        });
+    return false;
+}
+
+// Poll an upload job every half second, showing its parts page once it is done
+function pollJob(jobId) {
+    return fetch(`/api/jobs/${jobId}`)
+        .then((response) => response.json())
+        .then(({ data }) => {
+            if (data.status === "pending") {
+                return new Promise((resolve) => setTimeout(resolve, 500)).then(() => pollJob(jobId));
+            }
+            if (data.status === "error") {
+                return Promise.reject(data.error);
+            }
+            document.open();
+            document.write(data.result);
+            document.close();
+        });
+}